@@ -0,0 +1,29 @@
+//! Picks the country database the `embedded-db` feature embeds:
+//! `data/country.mmdb` if present, else the small fixture locating the
+//! documentation ranges, so the feature builds from a fresh checkout.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// The country database supplied by whoever builds the binary.
+const DATABASE: &str = "data/country.mmdb";
+
+/// Stand-in locating `192.0.2.0/24`, `198.51.100.0/24`, `203.0.113.0/24`
+/// and `2001:db8::/32` to Germany, France, Japan and the United States.
+const FIXTURE: &str = "data/country-fixture.mmdb";
+
+fn main() {
+    // Watching the directory also notices `data/country.mmdb` appearing.
+    println!("cargo:rerun-if-changed=data");
+    if env::var_os("CARGO_FEATURE_EMBEDDED_DB").is_none() {
+        return;
+    }
+    let source = if Path::new(DATABASE).exists() {
+        DATABASE
+    } else {
+        println!("cargo:warning={DATABASE} not found, embedding the test fixture {FIXTURE}");
+        FIXTURE
+    };
+    let out = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR"));
+    fs::copy(source, out.join("country.mmdb")).expect("the country database is copied");
+}
@@ -0,0 +1,161 @@
+use crate::rdap::Rdap;
+use handlebars::Handlebars;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// The bundled plain text abuse report.
+const BUNDLED: &str = include_str!("../data/abuse_report.txt.hbs");
+
+/// Name the template is registered under.
+const NAME: &str = "abuse";
+
+/// An offending IP and its threat count.
+#[derive(Serialize)]
+pub struct Offender {
+    pub ip: IpAddr,
+    pub count: u64,
+}
+
+/// The offending IPs of a netblock, most threats first.
+#[derive(Serialize)]
+pub struct Netblock {
+    pub range: String,
+    pub owner: Option<String>,
+    pub total_count: u64,
+    pub ips: Vec<Offender>,
+}
+
+/// An abuse report to one contact, covering the offending IPs of every
+/// netblock it is the abuse contact of.
+#[derive(Serialize)]
+pub struct Report {
+    pub contact: String,
+    pub ip_count: usize,
+    pub total_count: u64,
+    pub netblocks: Vec<Netblock>,
+}
+
+impl Report {
+    /// Subject of the report when emailed.
+    pub fn subject(&self) -> String {
+        match self.ip_count {
+            1 => "Abuse report: 1 IP in your network".to_string(),
+            count => format!("Abuse report: {count} IPs in your network"),
+        }
+    }
+}
+
+/// Looks up the netblock of each of the `sources` and their counts, and
+/// groups them into a report per abuse contact, ordered by contact. Returns
+/// the reports along with the number of IPs without a known abuse contact.
+pub fn group(rdap: &Rdap, sources: Vec<(IpAddr, u64)>) -> (Vec<Report>, usize) {
+    let mut contacts: BTreeMap<String, BTreeMap<String, Netblock>> = BTreeMap::new();
+    let mut without_contact = 0;
+    for (ip, count) in sources {
+        let Some(registration) = rdap.registration(ip) else {
+            without_contact += 1;
+            continue;
+        };
+        let Some(contact) = registration.abuse_contact else {
+            without_contact += 1;
+            continue;
+        };
+        let netblock = contacts
+            .entry(contact)
+            .or_default()
+            .entry(registration.range.clone())
+            .or_insert_with(|| Netblock {
+                range: registration.range,
+                owner: registration.owner,
+                total_count: 0,
+                ips: Vec::new(),
+            });
+        netblock.total_count += count;
+        netblock.ips.push(Offender { ip, count });
+    }
+    let reports = contacts
+        .into_iter()
+        .map(|(contact, netblocks)| {
+            let netblocks: Vec<Netblock> = netblocks.into_values().collect();
+            Report {
+                contact,
+                ip_count: netblocks.iter().map(|netblock| netblock.ips.len()).sum(),
+                total_count: netblocks.iter().map(|netblock| netblock.total_count).sum(),
+                netblocks,
+            }
+        })
+        .collect();
+    (reports, without_contact)
+}
+
+/// A Handlebars template rendering abuse reports.
+pub struct Template {
+    registry: Handlebars<'static>,
+    /// Extension of the report files.
+    extension: String,
+}
+
+impl Template {
+    /// Loads and compiles the template at `path`, or the bundled plain text
+    /// one. The reports take the extension preceding `.hbs`, e.g.
+    /// `abuse.html.hbs`, and values are only HTML-escaped in HTML reports.
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let mut registry = Handlebars::new();
+        let extension = if let Some(path) = path {
+            registry
+                .register_template_string(NAME, fs::read_to_string(path)?)
+                .map_err(|e| format!("{}: {e}", path.display()))?;
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let name = name.strip_suffix(".hbs").unwrap_or(&name);
+            Path::new(name)
+                .extension()
+                .map_or_else(|| "txt".to_string(), |e| e.to_string_lossy().into_owned())
+        } else {
+            registry.register_template_string(NAME, BUNDLED)?;
+            "txt".to_string()
+        };
+        if !matches!(extension.as_str(), "html" | "htm") {
+            registry.register_escape_fn(handlebars::no_escape);
+        }
+        Ok(Self {
+            registry,
+            extension,
+        })
+    }
+
+    /// Renders `report`. Besides the report's `contact`, `ip_count`,
+    /// `total_count` and `netblocks`, the template sees the `sender` and
+    /// when the report was `generated_at`.
+    pub fn render(
+        &self,
+        report: &Report,
+        sender: Option<&str>,
+        generated_at: &str,
+    ) -> Result<String, Box<dyn Error>> {
+        let mut data = serde_json::to_value(report)?;
+        data["sender"] = json!(sender);
+        data["generated_at"] = json!(generated_at);
+        Ok(self.registry.render(NAME, &data)?)
+    }
+
+    /// Returns the name of the report file for `contact`, its address with
+    /// characters unsafe in file names replaced.
+    pub fn file_name(&self, contact: &str) -> String {
+        let name: String = contact
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '-' | '_' | '+') {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("{name}.{}", self.extension)
+    }
+}
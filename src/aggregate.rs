@@ -0,0 +1,480 @@
+use crate::geo::Location;
+use crate::sketch::CountMinSketch;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::net::IpAddr;
+
+/// Holds aggregated data for cities, including the name and total count of threats.
+///
+/// `weighted_count` sums each count multiplied by its record's severity, with
+/// records lacking a severity weighted as 1. `population` is the city's
+/// population, if known, for per-capita normalization, and `country_weight`
+/// its country's risk multiplier, if country weights are loaded.
+/// `country_code` is the ISO 3166-1 alpha-2 code of the country, if known.
+/// `services` counts the threats per destination service they were aimed at,
+/// if broken down. `anomaly` is set if the location's count spiked far above
+/// its history. `first_seen` is when the location was first observed, if
+/// sightings are tracked, and `new` is set if that was this run. `fields`
+/// holds custom fields computed by the `--script`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct CityData {
+    pub city_name: String,
+    pub country_name: String,
+    #[serde(default)]
+    pub country_code: Option<String>,
+    pub total_count: u32,
+    pub weighted_count: f64,
+    #[serde(default)]
+    pub population: Option<u32>,
+    #[serde(default)]
+    pub country_weight: Option<f64>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub services: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub anomaly: bool,
+    #[serde(default)]
+    pub first_seen: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub new: bool,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, Value>,
+}
+
+impl CityData {
+    /// Creates the data of a group first seen at `location`.
+    fn new(location: &Location, count: u32, weighted_count: f64) -> Self {
+        Self {
+            city_name: location.city_name.clone(),
+            country_name: location.country_name.clone(),
+            country_code: location.country_code.clone(),
+            total_count: count,
+            weighted_count,
+            population: location.population,
+            country_weight: location.country_weight,
+            services: BTreeMap::new(),
+            anomaly: false,
+            first_seen: None,
+            new: false,
+            fields: BTreeMap::new(),
+        }
+    }
+}
+
+/// A struct to use as a key for locations in the `HashMap`, representing
+/// latitude and longitude in hundred-thousandths of a degree.
+#[derive(Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LocationKey {
+    lat: i32,
+    lon: i32,
+}
+
+/// Fixed-point units per degree of a [`LocationKey`].
+const SCALE: f64 = 100_000.0;
+
+impl LocationKey {
+    /// Rounds `lat` and `lon` to 5 decimal places to use them as a hashable
+    /// key.
+    #[allow(clippy::cast_possible_truncation)] // Coordinates fit in degrees * 10^5.
+    pub fn new(lat: f64, lon: f64) -> Self {
+        Self {
+            lat: (lat * SCALE).round() as i32,
+            lon: (lon * SCALE).round() as i32,
+        }
+    }
+
+    /// Returns the rounded latitude.
+    pub fn lat(self) -> f64 {
+        f64::from(self.lat) / SCALE
+    }
+
+    /// Returns the rounded longitude.
+    pub fn lon(self) -> f64 {
+        f64::from(self.lon) / SCALE
+    }
+}
+
+/// The full aggregation key: a location plus any additional grouping dimensions.
+///
+/// `feed` is only populated when aggregating by feed, so without it all feeds
+/// reporting the same location collapse into a single entry, and likewise
+/// `service` and `tag` when aggregating by them. `known_scanner`
+/// and `cloud_provider` are only populated when known scanners are tagged or
+/// cloud ranges are loaded, respectively, `netblock_owner` and
+/// `abuse_contact` with `--rdap`, and `tags` by enrichers.
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct GroupKey {
+    pub location: LocationKey,
+    pub feed: Option<String>,
+    #[serde(default)]
+    pub service: Option<String>,
+    #[serde(default)]
+    pub tag: Option<String>,
+    #[serde(default)]
+    pub known_scanner: Option<String>,
+    #[serde(default)]
+    pub cloud_provider: Option<String>,
+    #[serde(default)]
+    pub netblock_owner: Option<String>,
+    #[serde(default)]
+    pub abuse_contact: Option<String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// The grouping dimensions of a record besides its location, each set only
+/// when aggregating by it.
+#[derive(Clone, Copy, Default)]
+pub struct Group<'a> {
+    pub feed: Option<&'a str>,
+    pub service: Option<&'a str>,
+    pub tag: Option<&'a str>,
+}
+
+/// Aggregates threat counts by location and the enabled grouping dimensions.
+///
+/// Serializes to its entries so partial aggregates can be checkpointed.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Aggregator {
+    #[serde(with = "entries")]
+    locations: HashMap<GroupKey, CityData>,
+    weighted: bool,
+    #[serde(skip)]
+    approximate: Option<HeavyHitters>,
+    /// Source IPs of the located records, with `--track-ips`.
+    #[serde(default, skip_serializing_if = "HashSet::is_empty")]
+    ips: HashSet<IpAddr>,
+    /// Count per source IP of the located records, with `--host-intel`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    sources: HashMap<IpAddr, u64>,
+}
+
+/// Returns the `count` IPs of `sources` with the most threats and their
+/// counts, highest first, ties broken by IP.
+pub fn top_sources(sources: &HashMap<IpAddr, u64>, count: usize) -> Vec<(IpAddr, u64)> {
+    let mut sources: Vec<(IpAddr, u64)> = sources.iter().map(|(&ip, &count)| (ip, count)).collect();
+    sources.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    sources.truncate(count);
+    sources
+}
+
+/// Approximate aggregation in bounded memory: every group is counted in a
+/// count-min sketch, and only the `top` groups with the highest estimated
+/// counts are kept, with those estimates as their counts.
+struct HeavyHitters {
+    sketch: CountMinSketch,
+    top: usize,
+    /// No kept group has a lower count than this, so groups estimated at or
+    /// below it can be turned away without looking for the lowest.
+    floor: u32,
+}
+
+impl Aggregator {
+    /// Creates an aggregator that only keeps the `top` groups by count,
+    /// estimated with a count-min sketch of `depth` rows of `width`
+    /// counters, so its memory use stays bounded however many groups it
+    /// sees.
+    pub fn approximate(top: usize, width: usize, depth: usize) -> Self {
+        Self {
+            approximate: Some(HeavyHitters {
+                sketch: CountMinSketch::new(width, depth),
+                top: top.max(1),
+                floor: 0,
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Adds `count` threats observed at `location`, optionally attributed to
+    /// the `feed`, `service` and `tag` of `group` and weighted by `severity`.
+    /// The threats are also counted towards `breakdown` in the location's
+    /// per-service breakdown, if given. Counts saturate at `u32::MAX`.
+    pub fn add(
+        &mut self,
+        location: &Location,
+        group: Group,
+        breakdown: Option<&str>,
+        count: u32,
+        severity: Option<f64>,
+    ) {
+        self.weighted |= severity.is_some();
+        let weighted_count = f64::from(count) * severity.unwrap_or(1.0);
+        let key = GroupKey {
+            location: LocationKey::new(location.lat, location.lon),
+            feed: group.feed.map(str::to_string),
+            service: group.service.map(str::to_string),
+            tag: group.tag.map(str::to_string),
+            known_scanner: location.known_scanner.clone(),
+            cloud_provider: location.cloud_provider.clone(),
+            netblock_owner: location.netblock_owner.clone(),
+            abuse_contact: location.abuse_contact.clone(),
+            tags: location.tags.clone(),
+        };
+        if let Some(heavy) = &mut self.approximate {
+            let (estimate, weighted_estimate) = heavy.sketch.add(&key, count, weighted_count);
+            let estimate = u32::try_from(estimate).unwrap_or(u32::MAX);
+            if let Some(data) = self.locations.get_mut(&key) {
+                data.total_count = estimate;
+                data.weighted_count = weighted_estimate;
+                if let Some(service) = breakdown {
+                    let services = data.services.entry(service.to_string()).or_default();
+                    *services = services.saturating_add(count);
+                }
+                return;
+            }
+            if self.locations.len() >= heavy.top {
+                if estimate <= heavy.floor {
+                    return;
+                }
+                let Some((lowest, floor)) = self
+                    .locations
+                    .iter()
+                    .min_by_key(|(_, data)| data.total_count)
+                    .map(|(key, data)| (key.clone(), data.total_count))
+                else {
+                    return;
+                };
+                heavy.floor = floor;
+                if estimate <= floor {
+                    return;
+                }
+                self.locations.remove(&lowest);
+            }
+            let mut data = CityData::new(location, estimate, weighted_estimate);
+            if let Some(service) = breakdown {
+                data.services.insert(service.to_string(), count);
+            }
+            self.locations.insert(key, data);
+            return;
+        }
+        // Aggregate counts for each unique location.
+        let data = self
+            .locations
+            .entry(key)
+            .and_modify(|e| {
+                e.total_count = e.total_count.saturating_add(count);
+                e.weighted_count += weighted_count;
+            })
+            .or_insert_with(|| CityData::new(location, count, weighted_count));
+        if let Some(service) = breakdown {
+            let services = data.services.entry(service.to_string()).or_default();
+            *services = services.saturating_add(count);
+        }
+    }
+
+    /// Records `ip` as the source of located records.
+    pub fn add_ip(&mut self, ip: IpAddr) {
+        self.ips.insert(ip);
+    }
+
+    /// Returns the source IPs recorded since the last call.
+    pub fn take_ips(&mut self) -> HashSet<IpAddr> {
+        std::mem::take(&mut self.ips)
+    }
+
+    /// Adds `count` threats to the count of the source `ip`.
+    pub fn add_source(&mut self, ip: IpAddr, count: u32) {
+        *self.sources.entry(ip).or_default() += u64::from(count);
+    }
+
+    /// Returns the `count` source IPs with the most threats and their
+    /// counts, highest first.
+    pub fn top_sources(&self, count: usize) -> Vec<(IpAddr, u64)> {
+        top_sources(&self.sources, count)
+    }
+
+    /// Adds the counts of every group of `other`, and its source IPs.
+    pub fn merge(&mut self, other: &Self) {
+        self.weighted |= other.weighted;
+        self.ips.extend(&other.ips);
+        for (&ip, &count) in &other.sources {
+            *self.sources.entry(ip).or_default() += count;
+        }
+        for (key, data) in &other.locations {
+            let Some(merged) = self.locations.get_mut(key) else {
+                self.locations.insert(key.clone(), data.clone());
+                continue;
+            };
+            merged.total_count = merged.total_count.saturating_add(data.total_count);
+            merged.weighted_count += data.weighted_count;
+            for (service, count) in &data.services {
+                let merged = merged.services.entry(service.clone()).or_default();
+                *merged = merged.saturating_add(*count);
+            }
+        }
+    }
+
+    /// Takes the counts of every group of `other`, previously merged in, back
+    /// out, dropping groups left without any.
+    pub fn subtract(&mut self, other: &Self) {
+        for (ip, count) in &other.sources {
+            if let Some(merged) = self.sources.get_mut(ip) {
+                *merged = merged.saturating_sub(*count);
+            }
+        }
+        self.sources.retain(|_, count| *count > 0);
+        for (key, data) in &other.locations {
+            let Some(merged) = self.locations.get_mut(key) else {
+                continue;
+            };
+            merged.total_count = merged.total_count.saturating_sub(data.total_count);
+            merged.weighted_count -= data.weighted_count;
+            for (service, count) in &data.services {
+                if let Some(merged_count) = merged.services.get_mut(service) {
+                    *merged_count = merged_count.saturating_sub(*count);
+                }
+            }
+            merged.services.retain(|_, count| *count > 0);
+            if merged.total_count == 0 {
+                self.locations.remove(key);
+            }
+        }
+    }
+
+    /// Flags the groups at each of `locations` as anomalies, and clears the
+    /// flag of all others.
+    #[cfg(feature = "duckdb")]
+    pub fn flag_anomalies(&mut self, locations: &std::collections::HashSet<LocationKey>) {
+        for (key, data) in &mut self.locations {
+            data.anomaly = locations.contains(&key.location);
+        }
+    }
+
+    /// Returns `true` if any aggregated record carried a severity.
+    pub const fn is_weighted(&self) -> bool {
+        self.weighted
+    }
+
+    /// Returns the total count of all aggregated threats, including those of
+    /// groups an approximate aggregator didn't keep.
+    pub fn total_count(&self) -> u64 {
+        if let Some(heavy) = &self.approximate {
+            return heavy.sketch.total();
+        }
+        self.locations
+            .values()
+            .map(|data| u64::from(data.total_count))
+            .sum()
+    }
+
+    /// Returns how far the counts of an approximate aggregator may exceed
+    /// the true counts, or `None` if the counts are exact.
+    pub fn count_error(&self) -> Option<u64> {
+        self.approximate
+            .as_ref()
+            .map(|heavy| heavy.sketch.error_bound())
+    }
+
+    /// Returns the number of aggregated groups.
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Yields each group and its aggregated data.
+    pub fn entries(&self) -> impl Iterator<Item = (&GroupKey, &CityData)> {
+        self.locations.iter()
+    }
+
+    /// Yields each group and its aggregated data, to update.
+    pub fn entries_mut(&mut self) -> impl Iterator<Item = (&GroupKey, &mut CityData)> {
+        self.locations.iter_mut()
+    }
+}
+
+/// (De)serializes the aggregated groups as a list of entries, as JSON objects
+/// can't have structured keys.
+mod entries {
+    use super::{CityData, GroupKey};
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::collections::HashMap;
+
+    pub fn serialize<S: Serializer>(
+        locations: &HashMap<GroupKey, CityData>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(locations)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<GroupKey, CityData>, D::Error> {
+        Vec::<(GroupKey, CityData)>::deserialize(deserializer)
+            .map(|entries| entries.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::{Aggregator, Group};
+    use crate::geo::Location;
+    use std::collections::BTreeMap;
+
+    /// Returns a location in Berlin.
+    pub fn berlin() -> Location {
+        Location {
+            city_name: "Berlin".into(),
+            country_name: "Germany".into(),
+            lat: 52.52,
+            lon: 13.405,
+            geoname_id: None,
+            population: None,
+            country_code: Some("DE".into()),
+            country_weight: None,
+            known_scanner: None,
+            cloud_provider: None,
+            netblock_owner: None,
+            abuse_contact: None,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the total and per-service counts of the only group of
+    /// `aggregator`.
+    fn counts(aggregator: &Aggregator) -> (u32, Vec<(String, u32)>) {
+        let mut entries = aggregator.entries();
+        let (_, data) = entries.next().expect("one group");
+        assert!(entries.next().is_none());
+        let services = data.services.clone().into_iter().collect();
+        (data.total_count, services)
+    }
+
+    #[test]
+    fn adding_saturates_at_the_maximum_count() {
+        let mut aggregator = Aggregator::default();
+        for _ in 0..2 {
+            aggregator.add(
+                &berlin(),
+                Group::default(),
+                Some("22/tcp"),
+                3_000_000_000,
+                None,
+            );
+        }
+        assert_eq!(
+            counts(&aggregator),
+            (u32::MAX, vec![("22/tcp".into(), u32::MAX)])
+        );
+    }
+
+    #[test]
+    fn merging_saturates_at_the_maximum_count() {
+        let mut aggregator = Aggregator::default();
+        let mut other = Aggregator::default();
+        for aggregator in [&mut aggregator, &mut other] {
+            aggregator.add(
+                &berlin(),
+                Group::default(),
+                Some("22/tcp"),
+                u32::MAX - 1,
+                None,
+            );
+        }
+        aggregator.merge(&other);
+        assert_eq!(
+            counts(&aggregator),
+            (u32::MAX, vec![("22/tcp".into(), u32::MAX)])
+        );
+        assert_eq!(aggregator.total_count(), u64::from(u32::MAX));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::aggregate::{Aggregator, CityData, LocationKey};
+use duckdb::{params, Connection, Statement};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+use std::path::Path;
+
+/// Table the aggregated locations of every run are appended to, stamped with
+/// the run's UTC time.
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS locations (
+    generated_at TIMESTAMP NOT NULL,
+    city VARCHAR NOT NULL,
+    country VARCHAR NOT NULL,
+    count UBIGINT NOT NULL,
+    weighted_count DOUBLE NOT NULL,
+    lat DOUBLE NOT NULL,
+    lon DOUBLE NOT NULL,
+    feed VARCHAR,
+    known_scanner VARCHAR,
+    cloud_provider VARCHAR
+)";
+
+/// Appends the aggregated locations to the `locations` table of the DuckDB
+/// database at `path`, stamped with `generated_at`, creating both if needed.
+pub fn append(
+    path: &Path,
+    aggregator: &Aggregator,
+    generated_at: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare(
+            "INSERT INTO locations VALUES (CAST(? AS TIMESTAMP), ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )?;
+        for (key, data) in aggregator.entries() {
+            insert.execute(params![
+                generated_at,
+                data.city_name,
+                data.country_name,
+                data.total_count,
+                data.weighted_count,
+                key.location.lat(),
+                key.location.lon(),
+                key.feed,
+                key.known_scanner,
+                key.cloud_provider,
+            ])?;
+        }
+    }
+    transaction.commit()?;
+    Ok(())
+}
+
+/// Fewest earlier runs a location's baseline is taken from; with fewer, no
+/// anomalies are flagged.
+const MIN_RUNS: usize = 3;
+
+/// Scales a median absolute deviation to the standard deviation of normally
+/// distributed counts.
+const MAD_SCALE: f64 = 1.4826;
+
+/// A location whose count spiked far above its history.
+#[derive(Serialize)]
+pub struct Anomaly {
+    #[serde(skip)]
+    pub location: LocationKey,
+    pub city: String,
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+    pub count: u64,
+    /// Median count of the location over the earlier runs.
+    pub median: f64,
+    /// Median absolute deviation of those counts from the median.
+    pub mad: f64,
+    /// Deviation of the count from the median, in robust standard
+    /// deviations.
+    pub score: f64,
+}
+
+/// Compares each aggregated location's count to its counts in the last
+/// `runs` runs in the DuckDB database at `path`, counting runs it was
+/// missing from as 0, and returns those whose count lies more than
+/// `threshold` robust standard deviations above the median, highest score
+/// first.
+///
+/// The robust standard deviation is taken as at least 1, so locations with
+/// a perfectly steady history are only flagged for a real jump.
+pub fn anomalies(
+    path: &Path,
+    aggregator: &Aggregator,
+    runs: usize,
+    threshold: f64,
+) -> Result<Vec<Anomaly>, Box<dyn Error>> {
+    let connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    let mut statement = connection.prepare(
+        "SELECT DISTINCT generated_at::VARCHAR AS run FROM locations ORDER BY run DESC LIMIT ?",
+    )?;
+    let earlier: Vec<String> = statement
+        .query_map(params![runs], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    if earlier.len() < MIN_RUNS {
+        return Ok(Vec::new());
+    }
+    let mut history: HashMap<LocationKey, Vec<u64>> = HashMap::new();
+    let mut statement = connection.prepare(
+        "SELECT lat, lon, generated_at::VARCHAR AS run, sum(count)::UBIGINT FROM locations
+         WHERE generated_at >= CAST(? AS TIMESTAMP) GROUP BY ALL",
+    )?;
+    let mut rows = statement.query(params![earlier[earlier.len() - 1]])?;
+    while let Some(row) = rows.next()? {
+        let location = LocationKey::new(row.get(0)?, row.get(1)?);
+        let run: String = row.get(2)?;
+        let Some(i) = earlier.iter().position(|earlier| *earlier == run) else {
+            continue;
+        };
+        history
+            .entry(location)
+            .or_insert_with(|| vec![0; earlier.len()])[i] += row.get::<_, u64>(3)?;
+    }
+
+    let mut current: HashMap<LocationKey, (u64, &CityData)> = HashMap::new();
+    for (key, data) in aggregator.entries() {
+        current.entry(key.location).or_insert((0, data)).0 += u64::from(data.total_count);
+    }
+    let mut anomalies = Vec::new();
+    for (location, (count, data)) in current {
+        let counts = history
+            .remove(&location)
+            .unwrap_or_else(|| vec![0; earlier.len()]);
+        #[allow(clippy::cast_precision_loss)] // Counts are far below 2^52.
+        let (count_f, counts): (f64, Vec<f64>) =
+            (count as f64, counts.into_iter().map(|c| c as f64).collect());
+        let median = median(counts.clone());
+        let mad = self::median(counts.iter().map(|c| (c - median).abs()).collect());
+        let score = (count_f - median) / (MAD_SCALE * mad).max(1.0);
+        if score > threshold {
+            anomalies.push(Anomaly {
+                location,
+                city: data.city_name.clone(),
+                country: data.country_name.clone(),
+                lat: location.lat(),
+                lon: location.lon(),
+                count,
+                median,
+                mad,
+                score,
+            });
+        }
+    }
+    anomalies.sort_by(|a, b| b.score.total_cmp(&a.score));
+    Ok(anomalies)
+}
+
+/// Returns the median of a non-empty list of values.
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// Runs the `SELECT` query `sql` against the DuckDB database at `path` and
+/// prints its result to stdout as CSV with a header row.
+pub fn query(path: &Path, sql: &str) -> Result<(), Box<dyn Error>> {
+    let connection = Connection::open(path)?;
+    let sql = sql.trim().trim_end_matches(';');
+    // Cast every column to text so values print the way DuckDB shows them.
+    let mut statement = connection
+        .prepare(&format!("SELECT COLUMNS(*)::VARCHAR FROM ({sql})"))
+        .map_err(|e| format!("--sql: {e}"))?;
+    let mut rows = statement.query([])?;
+    let names = rows
+        .as_ref()
+        .map(Statement::column_names)
+        .unwrap_or_default();
+    let mut writer = csv::Writer::from_writer(io::stdout().lock());
+    writer.write_record(&names)?;
+    while let Some(row) = rows.next()? {
+        let values = (0..names.len())
+            .map(|i| row.get::<_, Option<String>>(i))
+            .collect::<Result<Vec<_>, _>>()?;
+        writer.write_record(
+            values
+                .iter()
+                .map(|value| value.as_deref().unwrap_or_default()),
+        )?;
+    }
+    writer.flush()?;
+    Ok(())
+}
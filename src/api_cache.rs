@@ -0,0 +1,194 @@
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A cached answer of an external API and when it was fetched, in seconds
+/// since the Unix epoch.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    fetched_at: u64,
+    value: Value,
+}
+
+/// The entries of the cache and whether they changed since it was loaded or
+/// last saved.
+#[derive(Default)]
+struct State {
+    entries: BTreeMap<String, Entry>,
+    changed: bool,
+}
+
+/// The `--cache` file: answers of the external APIs the enrichments call,
+/// such as RDAP and Shodan, kept across runs. Each answer is stored under
+/// its service and key, e.g. `rdap:9.0.0.0`, and reused until it is older
+/// than its service's time to live.
+///
+/// Answers are kept in memory and written back with [`ApiCache::save`].
+pub struct ApiCache {
+    path: PathBuf,
+    ttls: HashMap<String, Duration>,
+    state: Mutex<State>,
+}
+
+impl ApiCache {
+    /// Loads the cache from `path`, or starts an empty one if it doesn't
+    /// exist yet, with the time to live of each service's answers. Services
+    /// without one keep their answers forever.
+    pub fn open(path: &Path, ttls: HashMap<String, Duration>) -> Result<Self, Box<dyn Error>> {
+        let entries = match fs::read(path) {
+            Ok(bytes) => {
+                serde_json::from_slice(&bytes).map_err(|e| format!("{}: {e}", path.display()))?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            ttls,
+            state: Mutex::new(State {
+                entries,
+                changed: false,
+            }),
+        })
+    }
+
+    /// Returns the answer of `service` for `key` if it is still fresh.
+    pub fn get(&self, service: &str, key: &str) -> Option<Value> {
+        let now = now();
+        self.state()
+            .entries
+            .get(&format!("{service}:{key}"))
+            .filter(|entry| self.is_fresh(service, entry, now))
+            .map(|entry| entry.value.clone())
+    }
+
+    /// Returns the keys and answers of `service` that are still fresh.
+    pub fn fresh(&self, service: &str) -> Vec<(String, Value)> {
+        let now = now();
+        self.state()
+            .entries
+            .iter()
+            .filter_map(|(name, entry)| {
+                let key = name.strip_prefix(service)?.strip_prefix(':')?;
+                self.is_fresh(service, entry, now)
+                    .then(|| (key.to_string(), entry.value.clone()))
+            })
+            .collect()
+    }
+
+    /// Caches `value` as the answer of `service` for `key`, fetched now.
+    pub fn insert(&self, service: &str, key: &str, value: Value) {
+        let mut state = self.state();
+        state.entries.insert(
+            format!("{service}:{key}"),
+            Entry {
+                fetched_at: now(),
+                value,
+            },
+        );
+        state.changed = true;
+    }
+
+    /// Writes the number of fresh and expired answers of each service as
+    /// CSV to `out`, or with a `service`, each of its answers with when it
+    /// was fetched, whether it is still fresh and its JSON value.
+    pub fn write(&self, service: Option<&str>, out: impl Write) -> Result<(), Box<dyn Error>> {
+        let now = now();
+        let state = self.state();
+        let entries = state.entries.iter().map(|(name, entry)| {
+            let (service, key) = name.split_once(':').unwrap_or((name, ""));
+            (service, key, entry, self.is_fresh(service, entry, now))
+        });
+        let mut writer = csv::Writer::from_writer(out);
+        if let Some(service) = service {
+            writer.write_record(["Key", "Fetched At", "Fresh", "Value"])?;
+            for (_, key, entry, fresh) in entries.filter(|(s, ..)| *s == service) {
+                let fetched_at = i64::try_from(entry.fetched_at)
+                    .ok()
+                    .and_then(|secs| DateTime::from_timestamp(secs, 0))
+                    .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+                    .unwrap_or_default();
+                writer.write_record([
+                    key,
+                    &fetched_at,
+                    &fresh.to_string(),
+                    &entry.value.to_string(),
+                ])?;
+            }
+        } else {
+            let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+            for (service, _, _, fresh) in entries {
+                let count = counts.entry(service).or_default();
+                if fresh {
+                    count.0 += 1;
+                } else {
+                    count.1 += 1;
+                }
+            }
+            writer.write_record(["Service", "Fresh", "Expired"])?;
+            for (service, (fresh, expired)) in counts {
+                writer.write_record([service, &fresh.to_string(), &expired.to_string()])?;
+            }
+        }
+        drop(state);
+        Ok(writer.flush()?)
+    }
+
+    /// Removes the entries of `service`, or of every service, only those
+    /// that are no longer fresh if `expired`. Returns how many were removed.
+    pub fn purge(&self, service: Option<&str>, expired: bool) -> usize {
+        let now = now();
+        let mut state = self.state();
+        let before = state.entries.len();
+        state.entries.retain(|name, entry| {
+            let entry_service = name.split_once(':').map_or(name.as_str(), |(s, _)| s);
+            let selected = service.map_or(true, |service| service == entry_service);
+            !selected || (expired && self.is_fresh(entry_service, entry, now))
+        });
+        let removed = before - state.entries.len();
+        state.changed |= removed > 0;
+        removed
+    }
+
+    /// Writes the cache back to its file, replacing it atomically, if it
+    /// changed.
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let mut state = self.state();
+        if !state.changed {
+            return Ok(());
+        }
+        let mut temporary = self.path.as_os_str().to_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_vec(&state.entries)?)?;
+        state.changed = false;
+        drop(state);
+        Ok(fs::rename(&temporary, &self.path)?)
+    }
+
+    /// Returns whether `entry` of `service` isn't older than its time to
+    /// live at `now`.
+    fn is_fresh(&self, service: &str, entry: &Entry, now: u64) -> bool {
+        self.ttls.get(service).map_or(true, |ttl| {
+            now.saturating_sub(entry.fetched_at) < ttl.as_secs()
+        })
+    }
+
+    /// Locks the state, which stays usable even if a panic poisoned it.
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
@@ -0,0 +1,111 @@
+use crate::geo::Location;
+use lru::LruCache;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+
+/// A least-recently-used cache of IP lookup results whose entries expire
+/// after a time to live, so the collector doesn't look up the same sources
+/// over and over while still noticing changed reference data.
+///
+/// IPs that couldn't be located are cached too.
+pub struct LookupCache {
+    entries: LruCache<IpAddr, (Instant, Option<Location>)>,
+    ttl: Duration,
+}
+
+impl LookupCache {
+    /// Creates a cache of up to `capacity` entries, or `None` if `capacity`
+    /// is zero.
+    pub fn new(capacity: usize, ttl: Duration) -> Option<Self> {
+        let entries = LruCache::new(NonZeroUsize::new(capacity)?);
+        Some(Self { entries, ttl })
+    }
+
+    /// Returns the cached lookup result of `ip`, unless it is missing or
+    /// expired.
+    pub fn get(&mut self, ip: IpAddr) -> Option<&Option<Location>> {
+        let expired = self.entries.peek(&ip)?.0.elapsed() >= self.ttl;
+        if expired {
+            self.entries.pop(&ip);
+            return None;
+        }
+        self.entries.get(&ip).map(|(_, location)| location)
+    }
+
+    /// Drops all cached lookup results, e.g. after the database changed.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Caches the lookup result of `ip`, evicting the least recently used
+    /// entry if the cache is full.
+    pub fn insert(&mut self, ip: IpAddr, location: Option<Location>) {
+        self.entries.put(ip, (Instant::now(), location));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LookupCache;
+    use crate::aggregate::tests::berlin;
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    /// Returns the address `192.0.2.<n>`.
+    fn ip(n: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, n])
+    }
+
+    /// Returns whether `cache` holds a result for `ip`, and if so whether it
+    /// was located.
+    fn cached(cache: &mut LookupCache, ip: IpAddr) -> Option<bool> {
+        cache.get(ip).map(Option::is_some)
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_entry_at_capacity() {
+        let mut cache = LookupCache::new(2, Duration::from_secs(60)).expect("a cache");
+        cache.insert(ip(1), Some(berlin()));
+        cache.insert(ip(2), None);
+        // Looking up the first entry makes the second the least recently used.
+        assert_eq!(cached(&mut cache, ip(1)), Some(true));
+        cache.insert(ip(3), Some(berlin()));
+        assert_eq!(cached(&mut cache, ip(2)), None);
+        assert_eq!(cached(&mut cache, ip(1)), Some(true));
+        assert_eq!(cached(&mut cache, ip(3)), Some(true));
+        cache.insert(ip(4), None);
+        assert_eq!(cached(&mut cache, ip(1)), None);
+        assert_eq!(cached(&mut cache, ip(4)), Some(false));
+    }
+
+    #[test]
+    fn expires_entries_after_the_ttl() {
+        let ttl = Duration::from_secs(60);
+        let mut cache = LookupCache::new(8, ttl).expect("a cache");
+        for n in 1..=3 {
+            cache.insert(ip(n), Some(berlin()));
+        }
+        let now = Instant::now();
+        for (n, age) in [(1, ttl), (2, Duration::from_secs(55))] {
+            let entry = cache.entries.peek_mut(&ip(n)).expect("a cached entry");
+            entry.0 = now.checked_sub(age).expect("an earlier instant");
+        }
+        assert_eq!(cached(&mut cache, ip(1)), None);
+        assert_eq!(cached(&mut cache, ip(2)), Some(true));
+        assert_eq!(cached(&mut cache, ip(3)), Some(true));
+        // The expired entry is dropped rather than kept around.
+        assert_eq!(cache.entries.len(), 2);
+        cache.insert(ip(1), None);
+        assert_eq!(cached(&mut cache, ip(1)), Some(false));
+    }
+
+    #[test]
+    fn clears_every_entry_and_refuses_zero_capacity() {
+        let mut cache = LookupCache::new(2, Duration::from_secs(60)).expect("a cache");
+        cache.insert(ip(1), None);
+        cache.clear();
+        assert_eq!(cached(&mut cache, ip(1)), None);
+        assert!(LookupCache::new(0, Duration::from_secs(60)).is_none());
+    }
+}
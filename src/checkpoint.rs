@@ -0,0 +1,138 @@
+use crate::aggregate::Aggregator;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Identifies the run a checkpoint belongs to, so a checkpoint is never
+/// resumed with different inputs or settings.
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct Run {
+    pub version: String,
+    pub inputs: Vec<PathBuf>,
+    pub input_format: String,
+    pub chunk_size: usize,
+    pub by_feed: bool,
+    #[serde(default)]
+    pub by_service: bool,
+    #[serde(default)]
+    pub by_tag: bool,
+    #[serde(default)]
+    pub sample: Option<f64>,
+    /// Fingerprint of the other options that affect what is aggregated and
+    /// of the files they name, such as the database and `--filter-script`.
+    #[serde(default)]
+    pub options: String,
+}
+
+/// How far a run has got.
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+pub struct Progress {
+    /// Inputs fully aggregated.
+    pub inputs_done: usize,
+    /// Chunks of the next input already aggregated.
+    pub chunks_done: usize,
+    /// Source IP records aggregated so far, for `--max-ips`.
+    pub records: usize,
+}
+
+/// The checkpoint file as written.
+#[derive(Serialize)]
+struct Saved<'a> {
+    run: &'a Run,
+    progress: Progress,
+    aggregator: &'a Aggregator,
+}
+
+/// The checkpoint file as read back.
+#[derive(Deserialize)]
+struct Loaded {
+    run: Run,
+    progress: Progress,
+    aggregator: Aggregator,
+}
+
+/// Periodically persists a run's progress and partial aggregates so an
+/// interrupted run can resume where it left off.
+pub struct Checkpoint {
+    path: PathBuf,
+    run: Run,
+    interval: Duration,
+    last_saved: Instant,
+}
+
+impl Checkpoint {
+    /// Creates a checkpoint for `run` at `path`, saved at most every
+    /// `interval` by [`Self::save_due`].
+    pub fn new(path: &Path, run: Run, interval: Duration) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            run,
+            interval,
+            last_saved: Instant::now(),
+        }
+    }
+
+    /// Loads the progress and partial aggregates of a previous attempt at
+    /// this run, or `None` if there is no checkpoint to resume from.
+    pub fn load(&self) -> Result<Option<(Progress, Aggregator)>, Box<dyn Error>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let loaded: Loaded = serde_json::from_slice(&contents)?;
+        if loaded.run != self.run {
+            return Err(format!(
+                "checkpoint {} belongs to a run with different inputs or settings; \
+                 delete it to start over",
+                self.path.display()
+            )
+            .into());
+        }
+        Ok(Some((loaded.progress, loaded.aggregator)))
+    }
+
+    /// Saves `progress` and `aggregator` if the interval has passed since the
+    /// last save.
+    pub fn save_due(
+        &mut self,
+        progress: Progress,
+        aggregator: &Aggregator,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.last_saved.elapsed() >= self.interval {
+            self.save(progress, aggregator)?;
+        }
+        Ok(())
+    }
+
+    /// Saves `progress` and `aggregator`, replacing the previous checkpoint
+    /// atomically so a crash mid-write leaves the old one intact.
+    pub fn save(
+        &mut self,
+        progress: Progress,
+        aggregator: &Aggregator,
+    ) -> Result<(), Box<dyn Error>> {
+        let saved = Saved {
+            run: &self.run,
+            progress,
+            aggregator,
+        };
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_vec(&saved)?)?;
+        fs::rename(&temporary, &self.path)?;
+        self.last_saved = Instant::now();
+        Ok(())
+    }
+
+    /// Removes the checkpoint once the run has completed.
+    pub fn remove(&self) -> Result<(), Box<dyn Error>> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
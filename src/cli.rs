@@ -0,0 +1,1030 @@
+use crate::checkpoint;
+use crate::dns;
+use crate::feeds::Feed;
+use crate::input::{self, InputFormat};
+use crate::intel;
+use crate::logging::LogFormat;
+use crate::nearest;
+use crate::output::OutputFormat;
+use crate::rdap;
+use crate::remote::{self, UploadMethod};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use clap_mangen::Man;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use std::{fs, io};
+
+/// Prefix of the environment variables every option can also be set
+/// through, e.g. `DLM_OUTPUT` for `--output`.
+const ENV_PREFIX: &str = "DLM_";
+
+/// Command line options for the dashboard location mapper.
+///
+/// Every option defaults to the paths the tool has always used, so running the
+/// binary without arguments keeps the original behavior.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// Threat source file to read, or an `http(s)://` URL or `s3://bucket/key`
+    /// object to download it from. May be given more than once to merge
+    /// several feeds; records without a feed label are attributed to the file
+    /// stem of the input they came from.
+    #[arg(
+        short,
+        long = "input",
+        value_name = "PATH",
+        default_value = "threat_sources.json"
+    )]
+    pub inputs: Vec<PathBuf>,
+
+    /// Format of the input files.
+    #[arg(long, value_enum, default_value_t)]
+    pub input_format: InputFormat,
+
+    /// Extra `Name: value` header sent when fetching inputs over HTTP, e.g.
+    /// `Authorization: Bearer TOKEN`. May be repeated.
+    #[arg(long = "header", value_name = "HEADER", value_parser = remote::parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Refuse any input larger than this, on disk, downloaded or after gzip
+    /// decompression. Accepts `K`, `M` and `G` suffixes (e.g. `512M`).
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_input_size: Option<u64>,
+
+    /// Abort once the inputs hold more than this many source IP records in
+    /// total.
+    #[arg(long, value_name = "N")]
+    pub max_ips: Option<usize>,
+
+    /// Aggregate only this fraction of source IPs, between 0 and 1 (e.g.
+    /// `0.1`), scaling up their counts to match. Sources are chosen by a hash
+    /// of their IP, so the same ones are kept on every run.
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub sample: Option<f64>,
+
+    /// Read and aggregate inputs in chunks of at most this many source IP
+    /// records, keeping memory use flat regardless of input size.
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    pub chunk_size: usize,
+
+    /// Look up the records of each chunk on this many threads; 0 uses every
+    /// CPU available to the process, as limited by its affinity mask and
+    /// cgroup CPU quota.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub threads: usize,
+
+    /// Periodically save progress and partial aggregates to this file, and
+    /// resume from it if it exists. It is removed once the run completes.
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Seconds between checkpoint saves; a checkpoint is also saved after
+    /// each input.
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    pub checkpoint_interval: u64,
+
+    /// Only count `netfilter` LOG lines containing this log prefix (e.g. the
+    /// `--log-prefix` of your drop rule).
+    #[arg(long, value_name = "PREFIX")]
+    pub log_prefix: Option<String>,
+
+    /// Only count `cowrie` sessions that logged one of these event types,
+    /// given as `eventid` prefixes with or without the leading `cowrie.`
+    /// (e.g. `login.failed,command`). By default every session is counted.
+    #[arg(long, value_delimiter = ',', value_name = "EVENTS")]
+    pub cowrie_events: Vec<String>,
+
+    /// Only count `pcap` packets matching this BPF-style filter, e.g.
+    /// `tcp and dst port 22`.
+    #[arg(long, value_name = "EXPR")]
+    pub pcap_filter: Option<String>,
+
+    /// Field holding the source IP in `ndjson` events. Nested fields are
+    /// addressed with dotted paths, e.g. `client.ip`.
+    #[arg(long, value_name = "FIELD", default_value = "ip")]
+    pub ip_field: String,
+
+    /// Only count `ndjson` events where FIELD equals VALUE. May be repeated;
+    /// all conditions must match.
+    #[arg(long = "where", value_name = "FIELD=VALUE", value_parser = parse_condition)]
+    pub conditions: Vec<(String, String)>,
+
+    /// Field holding the feed label in `ndjson` events.
+    #[arg(long, value_name = "FIELD")]
+    pub feed_field: Option<String>,
+
+    /// Field holding a pre-aggregated count in `ndjson` events; without it
+    /// every event counts once.
+    #[arg(long, value_name = "FIELD")]
+    pub count_field: Option<String>,
+
+    /// Field holding the destination port in `ndjson` events, for
+    /// `--group-by city,service`.
+    #[arg(long, value_name = "FIELD")]
+    pub port_field: Option<String>,
+
+    /// Field holding the protocol, e.g. `tcp`, in `ndjson` events.
+    #[arg(long, value_name = "FIELD")]
+    pub protocol_field: Option<String>,
+
+    /// Field holding a category in `ndjson` events, such as the kind of
+    /// attack, for `--group-by city,tag`.
+    #[arg(long, value_name = "FIELD")]
+    pub tag_field: Option<String>,
+
+    /// Run as a NetFlow v5/v9 and IPFIX collector on this UDP address instead
+    /// of reading input files, rewriting the output every flush interval.
+    /// Flows are attributed to the exporter's address as their feed.
+    #[arg(long, value_name = "ADDR")]
+    pub listen: Option<SocketAddr>,
+
+    /// Seconds between output rewrites in collector mode.
+    #[arg(long, value_name = "SECS", default_value_t = 60)]
+    pub flush_interval: u64,
+
+    /// Only cover this much recent time in collector mode, e.g. `1h`, rather
+    /// than everything since the collector started. Accepts `s`, `m`, `h`
+    /// and `d` suffixes.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub window: Option<Duration>,
+
+    /// Move the `--window` forward this often, e.g. `5m`; by default it
+    /// starts over once it is full. The window must be a whole number of
+    /// slides.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration, requires = "window")]
+    pub slide: Option<Duration>,
+
+    /// Maximum number of received datagrams waiting to be aggregated in
+    /// collector mode. Once it is reached, receiving pauses until aggregation
+    /// catches up, keeping memory use bounded during slow output uploads.
+    #[arg(long, value_name = "N", default_value_t = 1024)]
+    pub queue_size: usize,
+
+    /// Resolve sources that are hostnames, such as the entries of domain
+    /// blocklists, to their first A or AAAA record through the system
+    /// resolver before looking them up. Each hostname is resolved once per
+    /// run.
+    #[arg(long)]
+    pub resolve_hostnames: bool,
+
+    /// Give up resolving a hostname after this long, e.g. `5` or `1m`.
+    #[arg(long, value_name = "DURATION", default_value = "5", value_parser = parse_duration, requires = "resolve_hostnames")]
+    pub resolve_timeout: Duration,
+
+    /// Resolve up to this many hostnames at once.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 32,
+        requires = "resolve_hostnames"
+    )]
+    pub resolve_concurrency: usize,
+
+    /// How long addresses resolved by `--resolve-hostnames` are reused from
+    /// the `--cache`, e.g. `30m` or `1d`.
+    #[arg(long, value_name = "DURATION", default_value = "1h", value_parser = parse_duration)]
+    pub resolve_ttl: Duration,
+
+    /// Number of IP lookup results cached across flushes in collector mode.
+    /// 0 disables the cache.
+    #[arg(long, value_name = "N", default_value_t = 100_000)]
+    pub lookup_cache_size: usize,
+
+    /// Seconds a cached IP lookup result stays valid in collector mode.
+    #[arg(long, value_name = "SECS", default_value_t = 3600)]
+    pub lookup_cache_ttl: u64,
+
+    /// Aggregate approximately in bounded memory, for unbounded streams:
+    /// counts are estimated with a count-min sketch and only the top this
+    /// many groups are kept, with the estimates' error bound as an extra
+    /// column.
+    #[arg(long, value_name = "N")]
+    pub approximate: Option<usize>,
+
+    /// Counters per row of the `--approximate` sketch; estimates exceed true
+    /// counts by at most e / WIDTH of the total count.
+    #[arg(long, value_name = "WIDTH", default_value_t = 4096)]
+    pub sketch_width: usize,
+
+    /// Rows of the `--approximate` sketch; estimates stay within their error
+    /// bound with a probability of 1 - e^-DEPTH.
+    #[arg(long, value_name = "DEPTH", default_value_t = 5)]
+    pub sketch_depth: usize,
+
+    /// City `.mmdb` database used for IP geolocation lookups.
+    #[arg(short, long, value_name = "PATH", default_value = "geoip2/city.mmdb")]
+    pub database: PathBuf,
+
+    /// Look up IPs in the country-level database embedded in the binary
+    /// instead of `--database`, placing each at the centre of its country.
+    /// Requires the `embedded-db` feature.
+    #[arg(long)]
+    pub embedded_db: bool,
+
+    /// Where IPs are located: a `MaxMind` database, or the `--mock-map`
+    /// file, to run without one, e.g. in CI.
+    #[arg(long, value_enum, default_value_t = Provider::Maxmind, conflicts_with = "embedded_db")]
+    pub provider: Provider,
+
+    /// JSON file mapping IPs and CIDRs to locations, for `--provider mock`,
+    /// e.g. `{"203.0.113.0/24": {"city": "Paris", "country": "France",
+    /// "country_code": "FR", "lat": 48.86, "lon": 2.35}}`. `geoname_id` and
+    /// `time_zone` are optional.
+    #[arg(long, value_name = "PATH", required_if_eq("provider", "mock"))]
+    pub mock_map: Option<PathBuf>,
+
+    /// Additional `.mmdb` database consulted before `--database`, e.g. an
+    /// internal database mapping private ranges to office locations. May be
+    /// repeated; earlier databases take precedence.
+    #[arg(long = "overlay-database", value_name = "PATH")]
+    pub overlay_databases: Vec<PathBuf>,
+
+    /// CSV of `network,city,country,lat,lon` lines pinning IPs or CIDRs to
+    /// fixed locations, e.g. known partner or internal egress addresses.
+    /// Takes precedence over all databases.
+    #[arg(long, value_name = "PATH")]
+    pub overrides: Option<PathBuf>,
+
+    /// File the aggregated locations are written to, or an `http(s)://` URL
+    /// or `s3://bucket/key` object to upload them to.
+    #[arg(short, long, value_name = "PATH", default_value = "locations.csv")]
+    pub output: PathBuf,
+
+    /// GeoNames dump (e.g. `cities500.txt`) or `geoname_id,population` file
+    /// to add a per-capita `Threats per 100k` column from, so hotspots aren't
+    /// just the biggest cities.
+    #[arg(long, value_name = "PATH")]
+    pub population: Option<PathBuf>,
+
+    /// File of `CC,multiplier` lines weighting counts by ISO country code
+    /// (`*` for unlisted countries), adding a `Risk Weighted Count` column
+    /// the output is sorted by.
+    #[arg(long, value_name = "PATH")]
+    pub country_weights: Option<PathBuf>,
+
+    /// Drop threats from known benign internet scanners (Censys, Shodan,
+    /// ...), so the map reflects real threats rather than research scanning.
+    #[arg(long, conflicts_with = "tag_known_scanners")]
+    pub exclude_known_scanners: bool,
+
+    /// Keep threats from known scanners but split them into their own rows,
+    /// labeled in a `Known Scanner` column.
+    #[arg(long)]
+    pub tag_known_scanners: bool,
+
+    /// File of `CIDR name` lines to use instead of the bundled list of known
+    /// scanners.
+    #[arg(long, value_name = "PATH")]
+    pub known_scanners: Option<PathBuf>,
+
+    /// Published cloud IP ranges (AWS `ip-ranges.json`, Google Cloud
+    /// `cloud.json` or Azure `ServiceTags_Public.json`) used to label each
+    /// location's threats per provider in a `Cloud Provider` column. May be
+    /// repeated.
+    #[arg(long, value_name = "PATH")]
+    pub cloud_ranges: Vec<PathBuf>,
+
+    /// JSON file caching the answers of external APIs across runs: the
+    /// addresses of `--resolve-hostnames`, the netblocks of `--rdap` and
+    /// `report-abuse` and the hosts of `--host-intel`. Each is reused until
+    /// it is older than its `--*-ttl`. Created if it doesn't exist; see the
+    /// `cache` command to inspect and purge it.
+    #[arg(long, value_name = "PATH")]
+    pub cache: Option<PathBuf>,
+
+    /// Look up the netblock of each IP over RDAP, splitting each location's
+    /// threats per netblock owner in `Netblock Owner` and `Abuse Contact`
+    /// columns. Netblocks are looked up once per run.
+    #[arg(long)]
+    pub rdap: bool,
+
+    /// RDAP service `--rdap` and `report-abuse` query, e.g. a regional
+    /// registry's. Defaults to rdap.org, which redirects each query to the
+    /// right registry.
+    #[arg(long, value_name = "URL")]
+    pub rdap_url: Option<String>,
+
+    /// Most RDAP requests to make per second.
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub rdap_rate: u32,
+
+    /// How long netblocks looked up over RDAP are reused from the `--cache`,
+    /// e.g. `7d`.
+    #[arg(long, value_name = "DURATION", default_value = "7d", value_parser = parse_duration)]
+    pub rdap_ttl: Duration,
+
+    /// Custom enrichment step to run on each located IP, as `NAME` or
+    /// `NAME=ARG`, e.g. `asn=geoip2/asn.mmdb`. Enrichers are compiled in
+    /// behind cargo features. May be repeated; they run in order.
+    #[arg(long = "enricher", value_name = "NAME[=ARG]")]
+    pub enrichers: Vec<String>,
+
+    /// Rhai script defining `filter(record)`, called with each located record
+    /// to keep it (`true`), drop it (`false`) or return it modified.
+    #[arg(long, value_name = "PATH")]
+    pub filter_script: Option<PathBuf>,
+
+    /// Rhai script defining `transform(rows)`, called with the aggregated
+    /// rows before they are written and returning the rows to write, e.g. to
+    /// rename cities, merge locations or add custom fields.
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<PathBuf>,
+
+    /// Format of the output file.
+    #[arg(short, long, value_enum, default_value_t)]
+    pub format: OutputFormat,
+
+    /// Handlebars template rendering the output instead of `--format`, e.g.
+    /// a custom HTML, Markdown or XML report.
+    #[arg(long, value_name = "PATH")]
+    pub template: Option<PathBuf>,
+
+    /// Title of Markdown and PDF reports.
+    #[arg(long, value_name = "TEXT", default_value = "Threat Locations Report")]
+    pub report_title: String,
+
+    /// JPEG logo shown in the header of PDF reports.
+    #[arg(long, value_name = "PATH")]
+    pub report_logo: Option<PathBuf>,
+
+    /// Look up the top offending source IPs in a host intelligence service
+    /// and list them with their open ports and tags in Markdown and PDF
+    /// reports.
+    #[arg(long, value_enum, value_name = "SERVICE")]
+    pub host_intel: Option<intel::Service>,
+
+    /// Number of top offending IPs to look up with `--host-intel`.
+    #[arg(long, value_name = "N", default_value_t = 10, requires = "host_intel")]
+    pub host_intel_top: usize,
+
+    /// API key of the `--host-intel` service: a Shodan API key or a Censys
+    /// personal access token.
+    #[arg(long, value_name = "KEY")]
+    pub host_intel_key: Option<String>,
+
+    /// How long `--host-intel` answers are reused from the `--cache`, e.g.
+    /// `12h` or `7d`.
+    #[arg(long, value_name = "DURATION", default_value = "1d", value_parser = parse_duration)]
+    pub host_intel_ttl: Duration,
+
+    /// API base URL of the `--host-intel` service, e.g. a proxy.
+    #[arg(long, value_name = "URL", requires = "host_intel")]
+    pub host_intel_url: Option<String>,
+
+    /// Abort before making any request if the API enrichments (`--rdap`,
+    /// `--host-intel` and `report-abuse`) would make more than this many
+    /// API calls, as estimated from the inputs after deduplication and
+    /// caching.
+    #[arg(long, value_name = "N")]
+    pub max_api_calls: Option<usize>,
+
+    /// Print the estimated number of API calls per service and exit without
+    /// making any.
+    #[arg(long)]
+    pub estimate_api_calls: bool,
+
+    /// Map zoom levels to cluster locations for with `--format clusters`, or
+    /// to generate vector tiles for with `--format mvt` and `pmtiles`, e.g.
+    /// `0-6`.
+    #[arg(long, value_name = "MIN-MAX", default_value = "0-6", value_parser = parse_zoom)]
+    pub zoom: RangeInclusive<u8>,
+
+    /// Location the `--format arcs` lines run to, as `LAT,LON`, e.g. the
+    /// data center the threats are aimed at.
+    #[arg(long, value_name = "LAT,LON", value_parser = parse_coordinates)]
+    pub target: Option<(f64, f64)>,
+
+    /// Spread locations sharing the same coordinates, e.g. one per feed,
+    /// evenly on a circle of this many meters around them, so dense city
+    /// centers don't render as a single pin.
+    #[arg(long, value_name = "METERS")]
+    pub jitter: Option<f64>,
+
+    /// Add a `Label` column with a flag emoji, the place and its count, e.g.
+    /// `🇩🇪 Berlin, Germany (1,234)`, for chat notifications and simple HTML
+    /// tables.
+    #[arg(long)]
+    pub labels: bool,
+
+    /// Add a `Top Services` column listing the N destination services, e.g.
+    /// `22/tcp`, each location's threats were most often aimed at, with
+    /// their counts. Only inputs recording the service fill it, see
+    /// `--group-by`.
+    #[arg(long, value_name = "N")]
+    pub top_services: Option<usize>,
+
+    /// Embed run metadata (tool version, generation time, database build
+    /// date) in the output: as `#` comments in CSV, a top-level `metadata`
+    /// object in JSON, or a `metadata` foreign member in GeoJSON.
+    #[arg(long)]
+    pub metadata: bool,
+
+    /// Also write a `<output>.manifest.json` sidecar with the output's SHA-256
+    /// hash and row count, the generation time, the database build date and
+    /// the tool version.
+    #[arg(long)]
+    pub manifest: bool,
+
+    /// DuckDB database the aggregated locations of each run are appended to,
+    /// in a `locations` table stamped with the run's time, building up a
+    /// history to query with `--sql`. Requires the `duckdb` feature.
+    #[arg(long, value_name = "PATH")]
+    pub duckdb: Option<PathBuf>,
+
+    /// SQL `SELECT` query run against the `--duckdb` database once the run's
+    /// locations are added, printing its result to stdout as CSV.
+    #[arg(long, value_name = "QUERY", requires = "duckdb")]
+    pub sql: Option<String>,
+
+    /// JSON file recording when each location and country was first and
+    /// last observed, across runs, adding `First Seen` and `New` columns to
+    /// the output. Created if it doesn't exist.
+    #[arg(long, value_name = "PATH")]
+    pub seen_state: Option<PathBuf>,
+
+    /// Also record when each source IP was first and last observed in the
+    /// `--seen-state`.
+    #[arg(long, requires = "seen_state")]
+    pub track_ips: bool,
+
+    /// Only output locations never observed before, according to the
+    /// `--seen-state`.
+    #[arg(long, requires = "seen_state")]
+    pub only_new: bool,
+
+    /// Flag locations whose count spikes far above their history in the
+    /// `--duckdb` database in an `anomaly` column, and write them to this
+    /// file as a JSON alerts feed.
+    #[arg(long, value_name = "PATH", requires = "duckdb")]
+    pub anomalies: Option<PathBuf>,
+
+    /// Number of earlier runs in the `--duckdb` history each location's
+    /// baseline is taken from.
+    #[arg(long, value_name = "N", default_value_t = 30)]
+    pub anomaly_runs: usize,
+
+    /// Deviations from the baseline median, in robust standard deviations
+    /// (1.4826 median absolute deviations), above which a count is flagged
+    /// as an anomaly.
+    #[arg(long, value_name = "SCORE", default_value_t = 3.5)]
+    pub anomaly_threshold: f64,
+
+    /// HTTP method used to upload the output to an `http(s)://` URL.
+    #[arg(long, value_enum, default_value_t)]
+    pub upload_method: UploadMethod,
+
+    /// Extra `Name: value` header sent when uploading the output over HTTP.
+    /// May be repeated.
+    #[arg(long = "upload-header", value_name = "HEADER", value_parser = remote::parse_header)]
+    pub upload_headers: Vec<(String, String)>,
+
+    /// Number of times a failed upload is retried.
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub upload_retries: u32,
+
+    /// Email the output as an attachment to this address after the run. May
+    /// be repeated.
+    #[arg(long, value_name = "ADDR", requires = "email_from")]
+    pub email_to: Vec<String>,
+
+    /// Sender address of emailed outputs.
+    #[arg(long, value_name = "ADDR")]
+    pub email_from: Option<String>,
+
+    /// SMTP server emailed outputs are sent through, e.g.
+    /// `smtps://smtp.example.com`. Defaults to `$SMTP_URL`, then
+    /// `smtp://localhost`; credentials are read from `$SMTP_USERNAME` and
+    /// `$SMTP_PASSWORD`.
+    #[arg(long, value_name = "URL")]
+    pub smtp_url: Option<String>,
+
+    /// Slack incoming webhook URL to post a summary of the run to: total
+    /// threats, top countries and a link to the output.
+    #[arg(long, value_name = "URL")]
+    pub slack_webhook: Option<String>,
+
+    /// Microsoft Teams workflow webhook URL to post a summary of the run to.
+    #[arg(long, value_name = "URL")]
+    pub teams_webhook: Option<String>,
+
+    /// TOML file of alerting rules, checked against the aggregated counts
+    /// each time the output is written, e.g. a country's count rising above
+    /// a threshold or a new country appearing. Alerts are posted to the
+    /// rules' own webhooks, or to `--slack-webhook` and `--teams-webhook`.
+    #[arg(long, value_name = "PATH")]
+    pub rules: Option<PathBuf>,
+
+    /// Comma separated list of dimensions to aggregate by. `city` is always
+    /// required; add `feed` to break each location down per threat feed,
+    /// `service` per destination port and protocol and `tag` per record
+    /// category.
+    #[arg(long, value_delimiter = ',', default_value = "city")]
+    pub group_by: Vec<GroupBy>,
+
+    /// Log per-phase timings, skip reasons and counters to stderr, as text
+    /// lines or one JSON object per event. Nothing is logged by default.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Export OpenTelemetry spans and metrics to this OTLP/HTTP collector
+    /// (e.g. `http://localhost:4318`). Defaults to the standard
+    /// `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable.
+    #[arg(long, value_name = "URL")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Serve `/healthz` and `/readyz` HTTP probes on this TCP address in
+    /// collector mode, e.g. for Kubernetes liveness and readiness checks.
+    #[arg(long, value_name = "ADDR", requires = "listen")]
+    pub health_listen: Option<SocketAddr>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands, run instead of aggregating unless noted.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print a shell completion script to stdout, e.g. for
+    /// `/etc/bash_completion.d/`.
+    Completions {
+        /// Shell to generate the script for.
+        shell: Shell,
+    },
+    /// Print a man page in roff format to stdout.
+    Man,
+    /// Aggregate as usual while showing a live dashboard of the top
+    /// locations, record counts and a world heatmap in the terminal. Press
+    /// `q` to quit.
+    Tui,
+    /// Print everything known about a single IP: its location, subdivisions,
+    /// time zone and the labels of any `--enricher`s, e.g. its ASN with
+    /// `--enricher asn`, looked up with the same databases and reference
+    /// data as an aggregation run.
+    Lookup {
+        /// IP address to look up.
+        ip: IpAddr,
+        /// Print the record as JSON instead of text.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Look up every IP of a list, one per line, and write a CSV row for
+    /// each with its location and enrichments, without aggregating, e.g.
+    /// `resolve --input ips.txt --output resolved.csv`.
+    Resolve {
+        /// List of IPs to look up, optionally gzipped.
+        #[arg(short, long, value_name = "PATH")]
+        input: PathBuf,
+        /// Where to write the CSV, a local path or remote location; stdout
+        /// if not given.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Print the aggregated locations nearest to the given coordinates, e.g.
+    /// a point clicked on a map, to find the data rows behind it. The inputs
+    /// are read and aggregated as usual, but no output is written.
+    Nearest {
+        /// Coordinates to start from.
+        #[arg(value_name = "LAT,LON", value_parser = parse_coordinates, allow_hyphen_values = true)]
+        coordinates: (f64, f64),
+        /// Number of locations to print.
+        #[arg(short = 'n', long, default_value_t = 1)]
+        count: usize,
+        /// Look among the bundled list of capitals and other major cities
+        /// instead of the aggregated locations, without reading any inputs
+        /// or databases.
+        #[arg(long)]
+        cities: bool,
+    },
+    /// Aggregate the IP indicators of a public threat feed, fetched from its
+    /// API, instead of the inputs, e.g. `fetch-feed threatfox --days 7`.
+    /// Output and everything else work as in a regular run.
+    FetchFeed {
+        /// Feed to fetch.
+        #[arg(value_enum)]
+        feed: Feed,
+        /// Only fetch indicators reported in the last N days.
+        #[arg(long, value_name = "N", default_value_t = 7)]
+        days: u32,
+        /// API key of the feed: the OTX API key or the abuse.ch Auth-Key.
+        #[arg(
+            long,
+            value_name = "KEY",
+            env = "DLM_FEED_API_KEY",
+            hide_env_values = true
+        )]
+        api_key: Option<String>,
+        /// Fetch from this URL instead of the feed's own endpoint, e.g. a
+        /// mirror.
+        #[arg(long, value_name = "URL")]
+        url: Option<String>,
+    },
+    /// Group the offending source IPs of the inputs by the abuse contact of
+    /// their netblock, looked up over RDAP, and write an abuse report for
+    /// each contact listing its IPs and their counts, per netblock.
+    /// Filters and exclusions apply as in a regular run.
+    ReportAbuse {
+        /// Directory to write the reports to, a file per contact named after
+        /// its address. Created if it doesn't exist.
+        #[arg(long, value_name = "DIR", default_value = "abuse_reports")]
+        output_dir: PathBuf,
+        /// Handlebars template of the reports, instead of the bundled plain
+        /// text email. It sees `contact`, `ip_count`, `total_count`,
+        /// `netblocks` (each with `range`, `owner`, `total_count` and `ips`
+        /// of `ip` and `count`), `sender` and `generated_at`.
+        #[arg(long, value_name = "PATH")]
+        template: Option<PathBuf>,
+        /// Also email each report to its contact, from `--email-from`
+        /// through the `--smtp-url` server.
+        #[arg(long)]
+        send: bool,
+    },
+    /// Inspect or purge the `--cache` of external API answers, without
+    /// reading any inputs or databases.
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Check that the `--database`, or the one `--embedded-db` or
+    /// `--provider mock` use instead, can be used: open it, print its type,
+    /// build date, node count and languages, and look up a sample of IPs
+    /// spread over the address space to verify they decode as City records
+    /// with a location. Exits with an error if none can be placed on the
+    /// map, which is why every IP of a run would be skipped.
+    CheckDb {
+        /// Number of IPs to sample from each address family.
+        #[arg(long, value_name = "N", default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+        samples: u32,
+    },
+    /// Flag impossible travel in authentication logs: read the inputs as
+    /// NDJSON logins, geolocate each user's consecutive logins and print
+    /// those too far apart for the time between them to stdout, as CSV or
+    /// JSON lines. `--ip-field` and `--where` apply as for `ndjson` inputs.
+    Travel {
+        /// Field holding the user name of a login.
+        #[arg(long, value_name = "FIELD", default_value = "user")]
+        user_field: String,
+        /// Field holding the time of a login, as RFC 3339 or seconds or
+        /// milliseconds since the Unix epoch.
+        #[arg(long, value_name = "FIELD", default_value = "timestamp")]
+        time_field: String,
+        /// Fastest plausible travel between logins, in km/h.
+        #[arg(long, value_name = "KMH", default_value_t = 1000.0)]
+        max_speed: f64,
+        /// Never flag logins closer together than this many km, as city
+        /// level geolocation can't tell nearby places apart.
+        #[arg(long, value_name = "KM", default_value_t = 500.0)]
+        min_distance: f64,
+        /// Print findings as JSON lines instead of CSV.
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// What the `cache` command does.
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    /// Print the number of fresh and expired answers per service, or with a
+    /// service, each of its answers, e.g. `cache show rdap`.
+    Show {
+        /// Service whose answers to print: `dns`, `rdap`, `shodan` or
+        /// `censys`.
+        service: Option<String>,
+    },
+    /// Remove cached answers, of every service or only of the given one,
+    /// e.g. `cache purge shodan --expired`.
+    Purge {
+        /// Service whose answers to remove: `dns`, `rdap`, `shodan` or
+        /// `censys`.
+        service: Option<String>,
+        /// Only remove answers older than their service's `--*-ttl`.
+        #[arg(long)]
+        expired: bool,
+    },
+}
+
+/// Sources of IP locations.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    /// The `--database` or, with `--embedded-db`, the embedded database.
+    Maxmind,
+    /// The locations of the `--mock-map` file.
+    Mock,
+}
+
+/// Dimensions that threat counts can be aggregated by.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Aggregate by city location (latitude and longitude).
+    City,
+    /// Additionally split each location by the feed that reported it.
+    Feed,
+    /// Additionally split each location by the destination port and protocol
+    /// its threats were aimed at, e.g. `22/tcp`.
+    Service,
+    /// Additionally split each location by the category its records were
+    /// tagged with, e.g. `ssh-bruteforce`.
+    Tag,
+}
+
+impl Command {
+    /// Returns whether the command runs without the databases, see
+    /// [`Cli::run`].
+    pub const fn is_standalone(&self) -> bool {
+        matches!(
+            self,
+            Self::Completions { .. } | Self::Man | Self::Nearest { cities: true, .. }
+        )
+    }
+}
+
+impl Cli {
+    /// Parses the command line, falling back to a `DLM_<OPTION>` environment
+    /// variable for each option that isn't given, e.g. `DLM_FLUSH_INTERVAL`
+    /// for `--flush-interval`. Exits with usage on errors, like
+    /// [`Parser::parse`].
+    pub fn parse_with_env() -> Self {
+        let mut command = Self::command_with_env();
+        let matches = command.get_matches_mut();
+        Self::from_arg_matches(&matches).unwrap_or_else(|e| e.format(&mut command).exit())
+    }
+
+    /// Builds the command with each option also read from its `DLM_`
+    /// environment variable.
+    fn command_with_env() -> clap::Command {
+        Self::command().mut_args(|arg| {
+            let name = arg
+                .get_long()
+                .unwrap_or_else(|| arg.get_id().as_str())
+                .replace('-', "_")
+                .to_uppercase();
+            // Values may be secrets such as webhook URLs, so keep them out
+            // of `--help`.
+            arg.env(format!("{ENV_PREFIX}{name}")).hide_env_values(true)
+        })
+    }
+
+    /// Returns the number of lookup threads to use.
+    pub fn threads(&self) -> usize {
+        if self.threads > 0 {
+            return self.threads;
+        }
+        thread::available_parallelism().map_or(1, NonZeroUsize::get)
+    }
+
+    /// Runs `command`, writing the generated completion script or man page to
+    /// stdout.
+    pub fn run(command: &Command) -> io::Result<()> {
+        let mut cli = Self::command_with_env();
+        let mut stdout = io::stdout().lock();
+        match command {
+            Command::Completions { shell } => {
+                let name = cli.get_name().to_string();
+                clap_complete::generate(*shell, &mut cli, name, &mut stdout);
+                Ok(())
+            }
+            Command::Man => Man::new(cli).render(&mut stdout),
+            Command::Nearest {
+                coordinates,
+                count,
+                cities: true,
+            } => nearest::write(*coordinates, nearest::cities(), *count, &mut stdout),
+            // Need the databases, see `main`.
+            _ => Ok(()),
+        }
+    }
+
+    /// Collects the options for uploading outputs to remote locations.
+    pub fn upload(&self) -> remote::Upload {
+        remote::Upload {
+            method: self.upload_method,
+            headers: self.upload_headers.clone(),
+            retries: self.upload_retries,
+        }
+    }
+
+    /// Collects the format specific input options.
+    pub fn input_options(&self) -> input::Options {
+        input::Options {
+            log_prefix: self.log_prefix.clone(),
+            cowrie_events: self.cowrie_events.clone(),
+            pcap_filter: self.pcap_filter.clone(),
+            field_mapping: input::FieldMapping {
+                ip_field: self.ip_field.clone(),
+                conditions: self.conditions.clone(),
+                feed_field: self.feed_field.clone(),
+                count_field: self.count_field.clone(),
+                port_field: self.port_field.clone(),
+                protocol_field: self.protocol_field.clone(),
+                tag_field: self.tag_field.clone(),
+            },
+            headers: self.headers.clone(),
+            max_input_size: self.max_input_size,
+        }
+    }
+
+    /// Identifies this run for `--checkpoint`.
+    pub fn checkpoint_run(&self) -> Result<checkpoint::Run, Box<dyn Error>> {
+        Ok(checkpoint::Run {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            inputs: self.inputs.clone(),
+            input_format: format!("{:?}", self.input_format),
+            chunk_size: self.chunk_size,
+            by_feed: self.groups_by(GroupBy::Feed),
+            by_service: self.groups_by(GroupBy::Service),
+            by_tag: self.groups_by(GroupBy::Tag),
+            sample: self.sample,
+            options: self.options_fingerprint()?,
+        })
+    }
+
+    /// Returns the SHA-256 hash of every other option that affects what is
+    /// aggregated: how inputs are parsed and IPs located, filtered, enriched
+    /// and grouped, including the contents of the files they name.
+    fn options_fingerprint(&self) -> Result<String, Box<dyn Error>> {
+        let file = |path: &Path| -> Result<_, Box<dyn Error>> {
+            let contents = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+            Ok(json!([path, format!("{:x}", Sha256::digest(contents))]))
+        };
+        let files = |paths: &[PathBuf]| -> Result<Vec<_>, Box<dyn Error>> {
+            paths.iter().map(|path| file(path)).collect()
+        };
+        let optional = |path: &Option<PathBuf>| path.as_deref().map(file).transpose();
+        let database = match (self.provider, self.embedded_db) {
+            (Provider::Maxmind, false) => Some(file(&self.database)?),
+            _ => None,
+        };
+        let options = json!({
+            "provider": format!("{:?}", self.provider),
+            "embedded_db": self.embedded_db,
+            "database": database,
+            "mock_map": optional(&self.mock_map)?,
+            "overlay_databases": files(&self.overlay_databases)?,
+            "overrides": optional(&self.overrides)?,
+            "max_ips": self.max_ips,
+            "log_prefix": self.log_prefix,
+            "cowrie_events": self.cowrie_events,
+            "pcap_filter": self.pcap_filter,
+            "ip_field": self.ip_field,
+            "conditions": self.conditions,
+            "feed_field": self.feed_field,
+            "count_field": self.count_field,
+            "port_field": self.port_field,
+            "protocol_field": self.protocol_field,
+            "tag_field": self.tag_field,
+            "resolve_hostnames": self.resolve_hostnames,
+            "filter_script": optional(&self.filter_script)?,
+            "exclude_known_scanners": self.exclude_known_scanners,
+            "tag_known_scanners": self.tag_known_scanners,
+            "known_scanners": optional(&self.known_scanners)?,
+            "cloud_ranges": files(&self.cloud_ranges)?,
+            "enrichers": self.enrichers,
+            "rdap": self.rdap,
+            "rdap_url": self.rdap_url,
+            "population": optional(&self.population)?,
+            "country_weights": optional(&self.country_weights)?,
+            "top_services": self.top_services,
+            "group_by": format!("{:?}", self.group_by),
+        });
+        Ok(format!("{:x}", Sha256::digest(options.to_string())))
+    }
+
+    /// Returns `true` if the count of each source IP should be kept, for
+    /// `--host-intel` or `report-abuse`.
+    pub const fn tracks_sources(&self) -> bool {
+        self.host_intel.is_some() || matches!(self.command, Some(Command::ReportAbuse { .. }))
+    }
+
+    /// Returns `true` if an enrichment calling a rate limited API is enabled:
+    /// `--rdap`, `--host-intel` or `report-abuse`.
+    pub const fn uses_apis(&self) -> bool {
+        self.rdap || self.tracks_sources()
+    }
+
+    /// Returns how long the answers of each service are reused from the
+    /// `--cache`.
+    pub fn cache_ttls(&self) -> HashMap<String, Duration> {
+        [
+            (dns::CACHE_NAME, self.resolve_ttl),
+            (rdap::CACHE_NAME, self.rdap_ttl),
+            (intel::Service::Shodan.cache_name(), self.host_intel_ttl),
+            (intel::Service::Censys.cache_name(), self.host_intel_ttl),
+        ]
+        .into_iter()
+        .map(|(service, ttl)| (service.to_string(), ttl))
+        .collect()
+    }
+
+    /// Returns the path of the database IPs are looked up in, `embedded`
+    /// with `--embedded-db` or the `--mock-map` with `--provider mock`.
+    pub fn database_path(&self) -> &Path {
+        match (self.provider, &self.mock_map) {
+            (Provider::Mock, Some(path)) => path,
+            _ if self.embedded_db => Path::new("embedded"),
+            _ => &self.database,
+        }
+    }
+
+    /// Returns whether IPs are looked up in the `--database` file, which a
+    /// running collector reloads when it is replaced.
+    pub fn uses_database_file(&self) -> bool {
+        self.provider == Provider::Maxmind && !self.embedded_db
+    }
+
+    /// Returns `true` if aggregation should be split by `dimension`.
+    pub fn groups_by(&self, dimension: GroupBy) -> bool {
+        self.group_by.contains(&dimension)
+    }
+}
+
+/// Parses a `FIELD=VALUE` condition.
+fn parse_condition(condition: &str) -> Result<(String, String), String> {
+    condition
+        .split_once('=')
+        .map(|(field, value)| (field.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected FIELD=VALUE, got `{condition}`"))
+}
+
+/// Parses a sampling rate above 0 and at most 1.
+fn parse_rate(rate: &str) -> Result<f64, String> {
+    match rate.trim().parse::<f64>() {
+        Ok(parsed) if parsed > 0.0 && parsed <= 1.0 => Ok(parsed),
+        _ => Err(format!(
+            "expected a rate above 0 and at most 1 such as `0.1`, got `{rate}`"
+        )),
+    }
+}
+
+/// Parses a whole number of seconds, minutes, hours or days such as `90s`,
+/// `5m`, `1h` or `7d`, or of seconds without a suffix.
+fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let trimmed = duration.trim();
+    let (digits, unit) = match trimmed.chars().last().map(|c| c.to_ascii_lowercase()) {
+        Some('s') => (&trimmed[..trimmed.len() - 1], 1),
+        Some('m') => (&trimmed[..trimmed.len() - 1], 60),
+        Some('h') => (&trimmed[..trimmed.len() - 1], 3600),
+        Some('d') => (&trimmed[..trimmed.len() - 1], 86_400),
+        _ => (trimmed, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|&seconds| seconds > 0)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("expected a duration such as `300`, `5m` or `1h`, got `{duration}`"))
+}
+
+/// Highest zoom level accepted by `--zoom`.
+const MAX_ZOOM: u8 = 22;
+
+/// Parses a zoom level range such as `0-6`, or a single level such as `4`.
+fn parse_zoom(zoom: &str) -> Result<RangeInclusive<u8>, String> {
+    let (min, max) = zoom.split_once('-').unwrap_or((zoom, zoom));
+    match (min.trim().parse::<u8>(), max.trim().parse::<u8>()) {
+        (Ok(min), Ok(max)) if min <= max && max <= MAX_ZOOM => Ok(min..=max),
+        _ => Err(format!(
+            "expected zoom levels up to {MAX_ZOOM} such as `0-6` or `4`, got `{zoom}`"
+        )),
+    }
+}
+
+/// Parses a latitude and longitude such as `50.11,8.68`.
+fn parse_coordinates(coordinates: &str) -> Result<(f64, f64), String> {
+    let parsed = coordinates
+        .split_once(',')
+        .and_then(|(lat, lon)| Some((lat.trim().parse().ok()?, lon.trim().parse().ok()?)));
+    match parsed {
+        Some((lat, lon)) if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon) => {
+            Ok((lat, lon))
+        }
+        _ => Err(format!(
+            "expected a latitude and longitude such as `50.11,8.68`, got `{coordinates}`"
+        )),
+    }
+}
+
+/// Parses a byte size with an optional binary `K`, `M` or `G` suffix.
+fn parse_size(size: &str) -> Result<u64, String> {
+    let trimmed = size.trim();
+    let (digits, shift) = match trimmed.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&trimmed[..trimmed.len() - 1], 10),
+        Some('M') => (&trimmed[..trimmed.len() - 1], 20),
+        Some('G') => (&trimmed[..trimmed.len() - 1], 30),
+        _ => (trimmed, 0),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| format!("expected a size such as `1048576` or `512M`, got `{size}`"))
+}
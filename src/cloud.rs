@@ -0,0 +1,67 @@
+use crate::input::open_text;
+use crate::ranges::RangeMap;
+use ipnetwork::IpNetwork;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::PathBuf;
+
+/// Loads the published IP ranges of cloud providers, labeling each range
+/// with its provider.
+///
+/// Each file may be AWS `ip-ranges.json`, Google Cloud `cloud.json` or an
+/// Azure `ServiceTags_Public.json`, detected from its layout, and may be
+/// gzipped.
+pub fn load(paths: &[PathBuf]) -> Result<RangeMap, Box<dyn Error>> {
+    let mut networks = Vec::new();
+    for path in paths {
+        let json: Value = serde_json::from_reader(open_text(path)?)?;
+        let (provider, prefixes) = prefixes(&json).ok_or_else(|| {
+            format!(
+                "{} is not an AWS, GCP or Azure IP ranges file",
+                path.display()
+            )
+        })?;
+        // Azure lists the same prefix under several service tags.
+        let unique: HashSet<&str> = prefixes.into_iter().collect();
+        for prefix in unique {
+            let network: IpNetwork = prefix
+                .parse()
+                .map_err(|e| format!("{}: invalid prefix `{prefix}`: {e}", path.display()))?;
+            networks.push((network, provider.to_string()));
+        }
+    }
+    Ok(networks.into_iter().collect())
+}
+
+/// Detects the provider of an IP ranges file and collects its prefixes.
+fn prefixes(json: &Value) -> Option<(&'static str, Vec<&str>)> {
+    if json.get("ipv6_prefixes").is_some() {
+        let mut prefixes = strings(&json["prefixes"], "ip_prefix");
+        prefixes.extend(strings(&json["ipv6_prefixes"], "ipv6_prefix"));
+        return Some(("aws", prefixes));
+    }
+    if json.get("prefixes").is_some() {
+        let mut prefixes = strings(&json["prefixes"], "ipv4Prefix");
+        prefixes.extend(strings(&json["prefixes"], "ipv6Prefix"));
+        return Some(("gcp", prefixes));
+    }
+    let values = json.get("values")?.as_array()?;
+    let prefixes = values
+        .iter()
+        .filter_map(|value| value["properties"]["addressPrefixes"].as_array())
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect();
+    Some(("azure", prefixes))
+}
+
+/// Collects the string `field` of each object in the `entries` array.
+fn strings<'a>(entries: &'a Value, field: &str) -> Vec<&'a str> {
+    entries
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry[field].as_str())
+        .collect()
+}
@@ -0,0 +1,177 @@
+use crate::geo;
+use chrono::{DateTime, SecondsFormat};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+/// First and last IPv4 address sampled: the unicast space from `1.0.0.0`
+/// up to the multicast block.
+const IPV4_SAMPLED: (u32, u32) = (0x0100_0000, 0xDFFF_FFFF);
+
+/// First and last IPv6 address sampled: the global unicast block
+/// `2000::/3`.
+const IPV6_SAMPLED: (u128, u128) = (0x2000 << 112, (0x4000 << 112) - 1);
+
+/// How the IPs sampled from one address family fared.
+#[derive(Default)]
+pub struct Samples {
+    pub looked_up: usize,
+    /// IPs the database has a record for.
+    pub found: usize,
+    /// IPs whose record can be placed on the map: with English city and
+    /// country names and coordinates, or for country-level databases, a
+    /// country with a known centre.
+    pub located: usize,
+    /// IPs whose record failed to decode as a City record.
+    pub errors: usize,
+    pub first_error: Option<String>,
+}
+
+/// What `check-db` found out about a database.
+pub struct Check {
+    pub database_type: String,
+    pub build_epoch: u64,
+    pub ip_version: u16,
+    pub record_size: u16,
+    pub node_count: u32,
+    pub languages: Vec<String>,
+    pub ipv4: Samples,
+    /// Samples of IPv6 databases.
+    pub ipv6: Option<Samples>,
+}
+
+/// Reads the metadata of the database behind `reader` and looks up
+/// `samples` IPs spread evenly over the IPv4 unicast space, and as many over
+/// the IPv6 one if the database has IPv6 records. IPs are located like in a
+/// run, at their country's centre with `centres`.
+pub fn check<S: AsRef<[u8]>>(
+    reader: &Reader<S>,
+    samples: u32,
+    centres: Option<&HashMap<String, (f64, f64)>>,
+) -> Check {
+    let metadata = &reader.metadata;
+    let (first, last) = IPV4_SAMPLED;
+    let step = (last - first) / samples.max(1);
+    let ipv4 = (0..samples).map(|i| IpAddr::V4(Ipv4Addr::from(first + step * i + step / 2)));
+    let ipv6 = (metadata.ip_version == 6).then(|| {
+        let (first, last) = IPV6_SAMPLED;
+        let step = (last - first) / u128::from(samples.max(1));
+        let ips = (0..u128::from(samples))
+            .map(move |i| IpAddr::V6(Ipv6Addr::from(first + step * i + step / 2)));
+        look_up(reader, ips, centres)
+    });
+    Check {
+        database_type: metadata.database_type.clone(),
+        build_epoch: metadata.build_epoch,
+        ip_version: metadata.ip_version,
+        record_size: metadata.record_size,
+        node_count: metadata.node_count,
+        languages: metadata.languages.clone(),
+        ipv4: look_up(reader, ipv4, centres),
+        ipv6,
+    }
+}
+
+/// Looks up each of `ips` as a City record, counting how they fared.
+fn look_up<S: AsRef<[u8]>>(
+    reader: &Reader<S>,
+    ips: impl Iterator<Item = IpAddr>,
+    centres: Option<&HashMap<String, (f64, f64)>>,
+) -> Samples {
+    let mut samples = Samples::default();
+    for ip in ips {
+        samples.looked_up += 1;
+        match reader.lookup::<geoip2::City>(ip) {
+            Ok(_) => {
+                samples.found += 1;
+                let located = geo::lookup(reader, ip).is_some()
+                    || centres
+                        .is_some_and(|centres| geo::lookup_country(reader, centres, ip).is_some());
+                if located {
+                    samples.located += 1;
+                }
+            }
+            Err(MaxMindDBError::AddressNotFoundError(_)) => {}
+            Err(e) => {
+                samples.errors += 1;
+                samples
+                    .first_error
+                    .get_or_insert_with(|| format!("{ip}: {e}"));
+            }
+        }
+    }
+    samples
+}
+
+impl Check {
+    /// Returns what is wrong with the database, if anything: records that
+    /// fail to decode, or no sampled IP of either family that could be
+    /// placed on the map, which would skip every IP of a run.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let families = [("IPv4", Some(&self.ipv4)), ("IPv6", self.ipv6.as_ref())];
+        let families = families
+            .into_iter()
+            .filter_map(|(family, samples)| Some((family, samples?)));
+        let (mut found, mut located) = (0, 0);
+        for (family, samples) in families {
+            if let Some(error) = &samples.first_error {
+                problems.push(format!(
+                    "{} {family} records failed to decode as City records, e.g. {error}",
+                    samples.errors
+                ));
+            }
+            found += samples.found;
+            located += samples.located;
+        }
+        if found == 0 {
+            problems.push("none of the sampled IPs was found".to_string());
+        } else if located == 0 {
+            problems.push(format!(
+                "none of the {found} records found has a city location; is it a City database?"
+            ));
+        }
+        problems
+    }
+
+    /// Writes the report on the database at `path` as aligned text, ending
+    /// with its problems, if any.
+    pub fn write(&self, path: &Path, mut out: impl Write) -> io::Result<()> {
+        let build_date = i64::try_from(self.build_epoch)
+            .ok()
+            .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+            .map_or_else(
+                || self.build_epoch.to_string(),
+                |date| date.to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+        let sampled = |samples: &Samples| {
+            format!(
+                "{} looked up, {} found, {} located, {} errors",
+                samples.looked_up, samples.found, samples.located, samples.errors
+            )
+        };
+        let fields = [
+            ("database", Some(path.display().to_string())),
+            ("type", Some(self.database_type.clone())),
+            ("build date", Some(build_date)),
+            ("ip version", Some(self.ip_version.to_string())),
+            ("record size", Some(format!("{} bits", self.record_size))),
+            ("node count", Some(self.node_count.to_string())),
+            ("languages", Some(self.languages.join(", "))),
+            ("ipv4 samples", Some(sampled(&self.ipv4))),
+            ("ipv6 samples", self.ipv6.as_ref().map(sampled)),
+        ];
+        let problems = self.problems();
+        let problems = problems
+            .iter()
+            .map(|problem| ("problem", Some(problem.clone())));
+        for (field, value) in fields.into_iter().chain(problems) {
+            if let Some(value) = value {
+                writeln!(out, "{:<17}{value}", format!("{field}:"))?;
+            }
+        }
+        Ok(())
+    }
+}
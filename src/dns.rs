@@ -0,0 +1,160 @@
+use crate::api_cache::ApiCache;
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Service resolved addresses are cached under, by hostname.
+pub const CACHE_NAME: &str = "dns";
+
+/// Resolves hostnames to IP addresses through the system resolver, several
+/// at a time, giving up on each after a timeout and remembering every
+/// answer for the rest of the run. Addresses are also kept in the `--cache`,
+/// if given; failures aren't, as they may be transient.
+pub struct Resolver {
+    timeout: Duration,
+    concurrency: usize,
+    /// The address each hostname resolved to, or `None` if it didn't.
+    cache: Mutex<HashMap<String, Option<IpAddr>>>,
+    api_cache: Option<Arc<ApiCache>>,
+}
+
+impl Resolver {
+    /// Creates a resolver waiting up to `timeout` per hostname with up to
+    /// `concurrency` lookups in flight, reusing the addresses still fresh
+    /// in `api_cache`.
+    pub fn new(timeout: Duration, concurrency: usize, api_cache: Option<Arc<ApiCache>>) -> Self {
+        Self {
+            timeout,
+            concurrency: concurrency.max(1),
+            cache: Mutex::new(HashMap::new()),
+            api_cache,
+        }
+    }
+
+    /// Resolves `hosts` that haven't been already and returns the address
+    /// of each that resolved: its first A or AAAA record, in the order the
+    /// system resolver prefers them.
+    pub fn resolve<'a>(
+        &self,
+        hosts: impl IntoIterator<Item = &'a str>,
+    ) -> HashMap<&'a str, IpAddr> {
+        let hosts: BTreeSet<&str> = hosts.into_iter().collect();
+        let mut pending = Vec::new();
+        let mut cache = self.cache();
+        for &host in &hosts {
+            if cache.contains_key(host) {
+                continue;
+            }
+            if let Some(ip) = self.cached(host) {
+                cache.insert(host.to_string(), Some(ip));
+            } else {
+                pending.push(host.to_string());
+            }
+        }
+        drop(cache);
+        let answers = self.look_up(pending);
+        if let Some(api_cache) = &self.api_cache {
+            for (host, ip) in &answers {
+                if let Some(ip) = ip {
+                    api_cache.insert(CACHE_NAME, host, json!(ip));
+                }
+            }
+        }
+        let mut cache = self.cache();
+        cache.extend(answers);
+        let addresses = hosts
+            .into_iter()
+            .filter_map(|host| Some((host, (*cache.get(host)?)?)))
+            .collect();
+        drop(cache);
+        addresses
+    }
+
+    /// Resolves every host, up to `concurrency` at a time, answering `None`
+    /// for those that fail or time out.
+    ///
+    /// The system resolver can't be cancelled, so a lookup that times out
+    /// is left to finish on its own thread, and its answer ignored.
+    fn look_up(&self, hosts: Vec<String>) -> Vec<(String, Option<IpAddr>)> {
+        let mut pending = hosts.into_iter();
+        let mut answers = Vec::new();
+        let (sender, receiver) = mpsc::channel();
+        let mut in_flight: HashMap<String, Instant> = HashMap::new();
+        loop {
+            while in_flight.len() < self.concurrency {
+                let Some(host) = pending.next() else {
+                    break;
+                };
+                let sender = sender.clone();
+                in_flight.insert(host.clone(), Instant::now());
+                thread::spawn(move || {
+                    let ip = (host.as_str(), 0)
+                        .to_socket_addrs()
+                        .ok()
+                        .and_then(|mut addrs| addrs.next())
+                        .map(|addr| addr.ip());
+                    let _ = sender.send((host, ip));
+                });
+            }
+            let Some(started) = in_flight.values().min().copied() else {
+                break;
+            };
+            let wait = (started + self.timeout).saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(wait) {
+                Ok((host, ip)) => {
+                    if in_flight.remove(&host).is_some() {
+                        answers.push((host, ip));
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    in_flight.retain(|host, started| {
+                        let waiting = now.duration_since(*started) < self.timeout;
+                        if !waiting {
+                            answers.push((host.clone(), None));
+                        }
+                        waiting
+                    });
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        answers
+    }
+
+    /// Returns the address of `host` if it is still fresh in the
+    /// `--cache`.
+    fn cached(&self, host: &str) -> Option<IpAddr> {
+        let ip = self.api_cache.as_ref()?.get(CACHE_NAME, host)?;
+        serde_json::from_value(ip).ok()
+    }
+
+    /// Locks the cache, which stays usable even if a panic poisoned it.
+    fn cache(&self) -> MutexGuard<'_, HashMap<String, Option<IpAddr>>> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns whether `name` looks like a DNS hostname, such as
+/// `evil.example.com`, rather than an IP address or some other text: dot
+/// separated labels of letters, digits and hyphens, ending in a label that
+/// isn't all digits. A trailing dot is allowed.
+pub fn is_hostname(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    let labels: Vec<&str> = name.split('.').collect();
+    name.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| !tld.chars().all(|c| c.is_ascii_digit()))
+}
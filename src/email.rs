@@ -0,0 +1,87 @@
+use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use std::env;
+use std::error::Error;
+
+/// Sends the output of a run as an email attachment over SMTP.
+pub struct Mailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Mailer {
+    /// Configures delivery from `from` to each of `to` through the SMTP
+    /// server at `url` (e.g. `smtps://smtp.example.com` or
+    /// `smtp://smtp.example.com:587?tls=required`), falling back to the
+    /// `SMTP_URL` environment variable and then `smtp://localhost`.
+    ///
+    /// Credentials are taken from `SMTP_USERNAME` and `SMTP_PASSWORD`, unless
+    /// the URL already includes them.
+    pub fn new(url: Option<&str>, from: &str, to: &[String]) -> Result<Self, Box<dyn Error>> {
+        let url = url.map_or_else(
+            || env::var("SMTP_URL").unwrap_or_else(|_| "smtp://localhost".to_string()),
+            str::to_string,
+        );
+        let mut builder = SmtpTransport::from_url(&url)?;
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+        {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(Self {
+            transport: builder.build(),
+            from: from
+                .parse()
+                .map_err(|e| format!("invalid --email-from `{from}`: {e}"))?,
+            to: to
+                .iter()
+                .map(|to| {
+                    to.parse()
+                        .map_err(|e| format!("invalid --email-to `{to}`: {e}"))
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Sends a plain text email with `subject` and `text` to `to` alone,
+    /// instead of the configured recipients.
+    pub fn send_text(&self, to: &str, subject: &str, text: String) -> Result<(), Box<dyn Error>> {
+        let to: Mailbox = to
+            .parse()
+            .map_err(|e| format!("invalid address `{to}`: {e}"))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .singlepart(SinglePart::plain(text))?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+
+    /// Sends an email with `subject` and `text`, attaching `body` as
+    /// `filename`.
+    pub fn send(
+        &self,
+        subject: &str,
+        text: String,
+        filename: String,
+        body: &[u8],
+        content_type: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut message = Message::builder().from(self.from.clone()).subject(subject);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let attachment =
+            Attachment::new(filename).body(body.to_vec(), ContentType::parse(content_type)?);
+        let message = message.multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(text))
+                .singlepart(attachment),
+        )?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
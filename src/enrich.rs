@@ -0,0 +1,60 @@
+#[cfg(feature = "asn")]
+mod asn;
+
+use crate::geo::Location;
+use std::error::Error;
+use std::net::IpAddr;
+
+/// A custom per-IP enrichment step, run after the built-in lookups and
+/// reference data, e.g. an internal CMDB lookup or custom scoring.
+///
+/// Enrichers are compiled in, each behind its own cargo feature, and
+/// registered in [`registry`]; `--enricher` selects which ones run. They
+/// run on the `--threads` lookup threads, so must be `Send` and `Sync`.
+pub trait Enricher: Send + Sync {
+    /// Enriches the location of `ip`, typically by adding
+    /// [`Location::tags`], which split the aggregated locations and are
+    /// written to a `Tags` column. Returning `None` drops the IP.
+    fn enrich(&self, ip: IpAddr, location: Location) -> Option<Location>;
+}
+
+/// Creates an enricher from the argument given after `=` in `--enricher`.
+type Factory = fn(Option<&str>) -> Result<Box<dyn Enricher>, Box<dyn Error>>;
+
+/// The enrichers compiled into this build, by name.
+fn registry() -> Vec<(&'static str, Factory)> {
+    vec![
+        #[cfg(feature = "asn")]
+        ("asn", asn::create),
+    ]
+}
+
+/// Creates the enrichers selected by `NAME[=ARG]` specs, in order.
+pub fn load(specs: &[String]) -> Result<Vec<Box<dyn Enricher>>, Box<dyn Error>> {
+    let registry = registry();
+    specs
+        .iter()
+        .map(|spec| {
+            let (name, argument) = spec
+                .split_once('=')
+                .map_or((spec.as_str(), None), |(name, argument)| {
+                    (name, Some(argument))
+                });
+            let (_, create) = registry
+                .iter()
+                .find(|(registered, _)| *registered == name)
+                .ok_or_else(|| {
+                    let available: Vec<&str> = registry.iter().map(|(name, _)| *name).collect();
+                    format!(
+                        "unknown enricher `{name}`, this build has: {}",
+                        if available.is_empty() {
+                            "none".to_string()
+                        } else {
+                            available.join(", ")
+                        }
+                    )
+                })?;
+            create(argument)
+        })
+        .collect()
+}
@@ -0,0 +1,36 @@
+use super::Enricher;
+use crate::geo::Location;
+use maxminddb::{geoip2, Reader};
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Default location of the `GeoLite2` ASN database.
+const DEFAULT_DATABASE: &str = "geoip2/asn.mmdb";
+
+/// Tags each IP with its autonomous system from a `MaxMind` ASN database,
+/// e.g. `asn=AS13335 Cloudflare, Inc.`.
+struct Asn {
+    reader: Reader<Vec<u8>>,
+}
+
+/// Opens the ASN database at `path`, or `geoip2/asn.mmdb`.
+pub fn create(path: Option<&str>) -> Result<Box<dyn Enricher>, Box<dyn Error>> {
+    let path = path.unwrap_or(DEFAULT_DATABASE);
+    let reader = Reader::open_readfile(path).map_err(|e| format!("{path}: {e}"))?;
+    Ok(Box::new(Asn { reader }))
+}
+
+impl Enricher for Asn {
+    fn enrich(&self, ip: IpAddr, mut location: Location) -> Option<Location> {
+        if let Ok(asn) = self.reader.lookup::<geoip2::Asn>(ip) {
+            if let Some(number) = asn.autonomous_system_number {
+                let organization = asn.autonomous_system_organization.unwrap_or_default();
+                let tag = format!("AS{number} {organization}");
+                location
+                    .tags
+                    .insert("asn".to_string(), tag.trim_end().to_string());
+            }
+        }
+        Some(location)
+    }
+}
@@ -0,0 +1,165 @@
+use crate::input::{self, ThreatRecord};
+use chrono::{Duration, Utc};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Public threat feeds `fetch-feed` can pull indicators from.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feed {
+    /// IP indicators of the OTX pulses the API key's account is subscribed
+    /// to. Requires an API key.
+    Otx,
+    /// `ip:port` IOCs reported to abuse.ch, at most 7 days back. Requires an
+    /// abuse.ch Auth-Key.
+    Threatfox,
+    /// Botnet C2 servers on the abuse.ch Feodo Tracker IP blocklist.
+    Feodo,
+}
+
+impl Feed {
+    /// The feed's default endpoint.
+    const fn url(self) -> &'static str {
+        match self {
+            Self::Otx => "https://otx.alienvault.com/api/v1/pulses/subscribed",
+            Self::Threatfox => "https://threatfox-api.abuse.ch/api/v1/",
+            Self::Feodo => "https://feodotracker.abuse.ch/downloads/ipblocklist.json",
+        }
+    }
+
+    /// The feed label of its records.
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Otx => "otx",
+            Self::Threatfox => "threatfox",
+            Self::Feodo => "feodo",
+        }
+    }
+}
+
+/// Most OTX pages followed, as a guard against a `next` link that never
+/// ends.
+const MAX_OTX_PAGES: usize = 1000;
+
+/// Fetches the IP indicators `feed` reported in the last `days` days from
+/// `url`, or the feed's own endpoint, as one record each, labeled with the
+/// feed and tagged with the malware family where the feed names it.
+/// Indicators other than IPs, such as domains and hashes, are skipped.
+pub fn fetch(
+    feed: Feed,
+    days: u32,
+    api_key: Option<&str>,
+    url: Option<&str>,
+) -> Result<Vec<ThreatRecord>, Box<dyn Error>> {
+    let url = url.unwrap_or_else(|| feed.url());
+    let since = Utc::now() - Duration::days(i64::from(days));
+    let require_key =
+        || api_key.ok_or_else(|| format!("the {} feed needs --api-key", feed.label()));
+    let indicators = match feed {
+        Feed::Otx => otx(
+            url,
+            require_key()?,
+            &since.format("%Y-%m-%dT%H:%M:%S").to_string(),
+        )?,
+        Feed::Threatfox => threatfox(url, require_key()?, days)?,
+        Feed::Feodo => feodo(url, &since.format("%Y-%m-%d").to_string())?,
+    };
+    Ok(indicators
+        .into_iter()
+        .map(|(source, port, tag)| ThreatRecord {
+            source,
+            count: 1,
+            feed: feed.label().to_string(),
+            severity: None,
+            service: input::service(None, port),
+            tag,
+        })
+        .collect())
+}
+
+/// An IP indicator: the IP, the port it was seen on and its malware family.
+type Indicator = (String, Option<u16>, Option<String>);
+
+/// Fetches the IPv4 and IPv6 indicators of the subscribed OTX pulses
+/// modified since `since`, following the result pages.
+fn otx(url: &str, api_key: &str, since: &str) -> Result<Vec<Indicator>, Box<dyn Error>> {
+    let mut indicators = Vec::new();
+    let mut page = Some(format!("{url}?limit=50&modified_since={since}"));
+    for _ in 0..MAX_OTX_PAGES {
+        let Some(url) = page.take() else {
+            break;
+        };
+        let response = json_body(ureq::get(&url).set("X-OTX-API-KEY", api_key).call()?)?;
+        for pulse in response["results"].as_array().into_iter().flatten() {
+            let family = pulse["malware_families"][0]["display_name"]
+                .as_str()
+                .or_else(|| pulse["malware_families"][0].as_str())
+                .map(str::to_string);
+            for indicator in pulse["indicators"].as_array().into_iter().flatten() {
+                if matches!(indicator["type"].as_str(), Some("IPv4" | "IPv6")) {
+                    if let Some(ip) = indicator["indicator"].as_str() {
+                        indicators.push((ip.to_string(), None, family.clone()));
+                    }
+                }
+            }
+        }
+        page = response["next"].as_str().map(str::to_string);
+    }
+    Ok(indicators)
+}
+
+/// Fetches the `ip:port` IOCs reported in the last `days` days.
+fn threatfox(url: &str, api_key: &str, days: u32) -> Result<Vec<Indicator>, Box<dyn Error>> {
+    let query = json!({"query": "get_iocs", "days": days});
+    let response = json_body(
+        ureq::post(url)
+            .set("Auth-Key", api_key)
+            .set("Content-Type", "application/json")
+            .send_string(&query.to_string())?,
+    )?;
+    match response["query_status"].as_str() {
+        Some("ok") => {}
+        Some("no_result") => return Ok(Vec::new()),
+        status => return Err(format!("ThreatFox answered {}", status.unwrap_or("nothing")).into()),
+    }
+    let iocs = response["data"].as_array().into_iter().flatten();
+    Ok(iocs
+        .filter(|ioc| ioc["ioc_type"].as_str() == Some("ip:port"))
+        .filter_map(|ioc| {
+            let (ip, port) = ioc["ioc"].as_str()?.rsplit_once(':')?;
+            let ip = ip.trim_matches(['[', ']']);
+            ip.parse::<IpAddr>().ok()?;
+            let family = ioc["malware_printable"].as_str().map(str::to_string);
+            Some((ip.to_string(), port.parse().ok(), family))
+        })
+        .collect())
+}
+
+/// Fetches the Feodo Tracker blocklist entries last online on or after
+/// `since`, a `YYYY-MM-DD` date. Entries without a date are kept.
+fn feodo(url: &str, since: &str) -> Result<Vec<Indicator>, Box<dyn Error>> {
+    let entries = json_body(ureq::get(url).call()?)?;
+    Ok(entries
+        .as_array()
+        .ok_or("the Feodo Tracker blocklist isn't a JSON array")?
+        .iter()
+        .filter(|entry| {
+            entry["last_online"]
+                .as_str()
+                .map_or(true, |last_online| last_online >= since)
+        })
+        .filter_map(|entry| {
+            let ip = entry["ip_address"].as_str()?.to_string();
+            let port = entry["port"]
+                .as_u64()
+                .and_then(|port| u16::try_from(port).ok());
+            Some((ip, port, entry["malware"].as_str().map(str::to_string)))
+        })
+        .collect())
+}
+
+/// Parses the body of `response` as JSON.
+fn json_body(response: ureq::Response) -> Result<Value, Box<dyn Error>> {
+    Ok(serde_json::from_reader(response.into_reader())?)
+}
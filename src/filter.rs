@@ -0,0 +1,176 @@
+use crate::geo::Location;
+use crate::input::ThreatRecord;
+use crate::script::Script;
+use rhai::{Dynamic, Map, FLOAT, INT};
+use std::error::Error;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A user-defined record filter: a Rhai script defining `filter(record)`,
+/// called with each located record as a map of `ip`, `city`, `country`,
+/// `country_code`, `lat`, `lon`, `feed`, `service`, `tag`, `count`,
+/// `severity`, `known_scanner`, `cloud_provider`, `netblock_owner`,
+/// `abuse_contact` and `tags`.
+///
+/// `filter` returns `true` to keep the record, `false` to drop it, or the
+/// (modified) map to keep the record with its changed `city`, `country`,
+/// `lat`, `lon`, `feed`, `service`, `tag`, `count`, `severity` and `tags`.
+pub struct Filter {
+    script: Script,
+}
+
+impl Filter {
+    /// Compiles the script at `path` and runs its top-level statements.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let script = Script::load(path, "filter")?;
+        Ok(Self { script })
+    }
+
+    /// Runs `filter` on `record`, located at `location`, applying any
+    /// changes the script made to either. Returns `false` if the record is
+    /// dropped.
+    pub fn apply(
+        &self,
+        ip: IpAddr,
+        record: &mut ThreatRecord,
+        location: &mut Location,
+    ) -> Result<bool, Box<dyn Error>> {
+        let decision = self.script.call(to_map(ip, record, location).into())?;
+        if let Ok(keep) = decision.as_bool() {
+            return Ok(keep);
+        }
+        let Some(map) = decision.try_cast::<Map>() else {
+            let error = "`filter` must return a bool or the record map";
+            return Err(self.script.error(error).into());
+        };
+        from_map(&map, record, location).map_err(|e| self.script.error(e))?;
+        Ok(true)
+    }
+}
+
+/// Builds the map `filter` is called with.
+fn to_map(ip: IpAddr, record: &ThreatRecord, location: &Location) -> Map {
+    let optional = |value: Option<&str>| value.map_or(Dynamic::UNIT, Into::into);
+    let tags: Map = location
+        .tags
+        .iter()
+        .map(|(key, value)| (key.into(), value.clone().into()))
+        .collect();
+    Map::from([
+        ("ip".into(), ip.to_string().into()),
+        ("city".into(), location.city_name.clone().into()),
+        ("country".into(), location.country_name.clone().into()),
+        (
+            "country_code".into(),
+            optional(location.country_code.as_deref()),
+        ),
+        ("lat".into(), location.lat.into()),
+        ("lon".into(), location.lon.into()),
+        ("feed".into(), record.feed.clone().into()),
+        ("service".into(), optional(record.service.as_deref())),
+        ("tag".into(), optional(record.tag.as_deref())),
+        ("count".into(), INT::from(record.count).into()),
+        (
+            "severity".into(),
+            record.severity.map_or(Dynamic::UNIT, Dynamic::from),
+        ),
+        (
+            "known_scanner".into(),
+            optional(location.known_scanner.as_deref()),
+        ),
+        (
+            "cloud_provider".into(),
+            optional(location.cloud_provider.as_deref()),
+        ),
+        (
+            "netblock_owner".into(),
+            optional(location.netblock_owner.as_deref()),
+        ),
+        (
+            "abuse_contact".into(),
+            optional(location.abuse_contact.as_deref()),
+        ),
+        ("tags".into(), tags.into()),
+    ])
+}
+
+/// Applies the fields of a map returned by `filter` to `record` and
+/// `location`.
+#[allow(clippy::cast_precision_loss)] // Coordinates given as integers.
+fn from_map(
+    map: &Map,
+    record: &mut ThreatRecord,
+    location: &mut Location,
+) -> Result<(), Box<dyn Error>> {
+    let invalid = |field: &str, expected: &str| format!("`{field}` must be {expected}");
+    let string = |field: &str| -> Result<Option<String>, String> {
+        map.get(field)
+            .map(|value| {
+                value
+                    .clone()
+                    .into_string()
+                    .map_err(|_| invalid(field, "a string"))
+            })
+            .transpose()
+    };
+    let number = |field: &str| -> Result<Option<f64>, String> {
+        map.get(field)
+            .map(|value| {
+                value
+                    .as_float()
+                    .or_else(|_| value.as_int().map(|n| n as FLOAT))
+                    .map_err(|_| invalid(field, "a number"))
+            })
+            .transpose()
+    };
+    if let Some(city) = string("city")? {
+        location.city_name = city;
+    }
+    if let Some(country) = string("country")? {
+        location.country_name = country;
+    }
+    if let Some(lat) = number("lat")? {
+        location.lat = lat;
+    }
+    if let Some(lon) = number("lon")? {
+        location.lon = lon;
+    }
+    if let Some(feed) = string("feed")? {
+        record.feed = feed;
+    }
+    if let Some(service) = map.get("service") {
+        record.service = if service.is_unit() {
+            None
+        } else {
+            string("service")?
+        };
+    }
+    if let Some(tag) = map.get("tag") {
+        record.tag = if tag.is_unit() { None } else { string("tag")? };
+    }
+    if let Some(count) = map.get("count") {
+        record.count = count
+            .as_int()
+            .ok()
+            .and_then(|count| u32::try_from(count).ok())
+            .ok_or_else(|| invalid("count", "a non-negative integer"))?;
+    }
+    if let Some(severity) = map.get("severity") {
+        record.severity = if severity.is_unit() {
+            None
+        } else {
+            number("severity")?
+        };
+    }
+    if let Some(tags) = map.get("tags") {
+        let tags = tags
+            .clone()
+            .try_cast::<Map>()
+            .ok_or_else(|| invalid("tags", "a map"))?;
+        location.tags = tags
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+    }
+    Ok(())
+}
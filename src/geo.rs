@@ -0,0 +1,273 @@
+use crate::api_cache::ApiCache;
+use crate::dns::Resolver;
+use crate::enrich::Enricher;
+use crate::population::Populations;
+use crate::ranges::RangeMap;
+use crate::rdap::Rdap;
+use crate::risk::CountryWeights;
+use maxminddb::{geoip2, Metadata, Reader};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Approximate centres of countries, by ISO code.
+const COUNTRY_CENTRES: &str = include_str!("../data/countries.txt");
+
+/// The country database embedded at build time, for `--embedded-db`:
+/// `data/country.mmdb`, or the test fixture without one, as copied by the
+/// build script.
+#[cfg(feature = "embedded-db")]
+const EMBEDDED_DB: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/country.mmdb"));
+
+/// The geographical location an IP address resolved to.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub city_name: String,
+    pub country_name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// GeoNames ID of the city.
+    pub geoname_id: Option<u32>,
+    /// Population of the city, if known from `--population` data.
+    pub population: Option<u32>,
+    /// ISO 3166-1 alpha-2 code of the country.
+    pub country_code: Option<String>,
+    /// Risk multiplier of the country, if `--country-weights` are loaded.
+    pub country_weight: Option<f64>,
+    /// Name of the known scanner the IP belongs to, if scanners are loaded.
+    pub known_scanner: Option<String>,
+    /// Cloud provider the IP belongs to, if cloud ranges are loaded.
+    pub cloud_provider: Option<String>,
+    /// Owner of the netblock the IP belongs to, with `--rdap`.
+    pub netblock_owner: Option<String>,
+    /// Abuse contact email of the IP's netblock, with `--rdap`.
+    pub abuse_contact: Option<String>,
+    /// Labels added by `--enricher`s or the `--filter-script`.
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Resolves IP addresses to locations, enriched with any loaded reference
+/// data.
+pub struct Locator<S: AsRef<[u8]>> {
+    pub reader: Reader<S>,
+    /// Databases from `--overlay-database`, consulted before `reader` in
+    /// order.
+    pub overlays: Vec<Reader<S>>,
+    /// Fixed locations from `--overrides`, taking precedence over all
+    /// databases.
+    pub overrides: Option<RangeMap<Location>>,
+    /// City populations from `--population`.
+    pub populations: Option<Populations>,
+    /// Country risk multipliers from `--country-weights`.
+    pub weights: Option<CountryWeights>,
+    /// Known scanner ranges, if they are excluded or tagged.
+    pub scanners: Option<RangeMap>,
+    /// Cloud provider ranges from `--cloud-ranges`.
+    pub clouds: Option<RangeMap>,
+    /// Custom enrichment steps from `--enricher`, run in order.
+    pub enrichers: Vec<Box<dyn Enricher>>,
+    /// Resolves hostname sources to IPs, with `--resolve-hostnames`.
+    pub resolver: Option<Resolver>,
+    /// Looks up netblock registrations, with `--rdap`.
+    pub rdap: Option<Rdap>,
+    /// Answers of external APIs kept across runs, with `--cache`.
+    pub api_cache: Option<Arc<ApiCache>>,
+    /// Centres of the countries IPs that `reader` only locates to their
+    /// country are placed at, with `--embedded-db`.
+    pub country_centres: Option<HashMap<String, (f64, f64)>>,
+}
+
+impl<S: AsRef<[u8]>> Locator<S> {
+    /// Metadata of the `MaxMind` database.
+    pub const fn metadata(&self) -> &Metadata {
+        &self.reader.metadata
+    }
+
+    /// Returns the name of the known scanner `ip` belongs to, if scanners
+    /// are loaded.
+    pub fn known_scanner(&self, ip: IpAddr) -> Option<&str> {
+        self.scanners.as_ref()?.get(ip).map(String::as_str)
+    }
+
+    /// Looks up the enriched location of `ip`: its override if one is
+    /// loaded, else the location found by [`lookup`] in the first database
+    /// that locates it, else with country centres loaded, the centre of its
+    /// country.
+    pub fn locate(&self, ip: IpAddr) -> Option<Location> {
+        let overridden = self
+            .overrides
+            .as_ref()
+            .and_then(|overrides| overrides.get(ip));
+        let mut location = match overridden {
+            Some(location) => location.clone(),
+            None => self
+                .overlays
+                .iter()
+                .chain([&self.reader])
+                .find_map(|reader| lookup(reader, ip))
+                .or_else(|| lookup_country(&self.reader, self.country_centres.as_ref()?, ip))?,
+        };
+        if let (Some(populations), Some(id)) = (&self.populations, location.geoname_id) {
+            location.population = populations.get(id);
+        }
+        if let Some(weights) = &self.weights {
+            location.country_weight = Some(weights.get(location.country_code.as_deref()));
+        }
+        location.known_scanner = self.known_scanner(ip).map(str::to_string);
+        location.cloud_provider = self
+            .clouds
+            .as_ref()
+            .and_then(|clouds| clouds.get(ip))
+            .cloned();
+        if let Some(registration) = self.rdap.as_ref().and_then(|rdap| rdap.registration(ip)) {
+            location.netblock_owner = registration.owner;
+            location.abuse_contact = registration.abuse_contact;
+        }
+        self.enrichers
+            .iter()
+            .try_fold(location, |location, enricher| enricher.enrich(ip, location))
+    }
+}
+
+/// Parts of an IP's database record that aggregation has no use for.
+#[derive(Default)]
+pub struct Details {
+    /// English names of the subdivisions, from largest to smallest.
+    pub subdivisions: Vec<String>,
+    pub time_zone: Option<String>,
+    /// Radius in km around the coordinates the IP is likely within.
+    pub accuracy_radius: Option<u16>,
+    pub postal_code: Option<String>,
+}
+
+impl<S: AsRef<[u8]>> Locator<S> {
+    /// Returns the details of the record of `ip` in the first database that
+    /// has one, for the `lookup` command.
+    pub fn details(&self, ip: IpAddr) -> Details {
+        let Some(city) = self
+            .overlays
+            .iter()
+            .chain([&self.reader])
+            .find_map(|reader| reader.lookup::<geoip2::City>(ip).ok())
+        else {
+            return Details::default();
+        };
+        let location = city.location.as_ref();
+        Details {
+            subdivisions: city
+                .subdivisions
+                .iter()
+                .flatten()
+                .filter_map(|s| s.names.as_ref()?.get("en").map(|n| (*n).to_string()))
+                .collect(),
+            time_zone: location.and_then(|l| l.time_zone).map(str::to_string),
+            accuracy_radius: location.and_then(|l| l.accuracy_radius),
+            postal_code: city.postal.and_then(|p| p.code).map(str::to_string),
+        }
+    }
+}
+
+/// Mean radius of the Earth in kilometres.
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Returns the great circle distance in kilometres between two points given
+/// as latitude and longitude, by the haversine formula.
+pub fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let half_lat = (lat2 - lat1) / 2.0;
+    let half_lon = (to.1 - from.1).to_radians() / 2.0;
+    let a = (lat1.cos() * lat2.cos()).mul_add(half_lon.sin().powi(2), half_lat.sin().powi(2));
+    2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+}
+
+/// Opens the country database embedded at build time.
+#[cfg(feature = "embedded-db")]
+pub fn embedded_db() -> Result<Reader<Vec<u8>>, Box<dyn Error>> {
+    Ok(Reader::from_source(EMBEDDED_DB.to_vec())?)
+}
+
+/// Opens the country database embedded at build time.
+#[cfg(not(feature = "embedded-db"))]
+pub fn embedded_db() -> Result<Reader<Vec<u8>>, Box<dyn Error>> {
+    Err("--embedded-db requires building with `--features embedded-db`".into())
+}
+
+/// Returns the bundled approximate centres of countries, by ISO code.
+pub fn country_centres() -> HashMap<String, (f64, f64)> {
+    COUNTRY_CENTRES
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let lat = fields.next()?.parse().ok()?;
+            let lon = fields.next()?.parse().ok()?;
+            Some((fields.next()?.to_string(), (lat, lon)))
+        })
+        .collect()
+}
+
+/// Looks up the country of `ip` in a country-level database and places it
+/// at the country's centre, named after the country in place of a city.
+///
+/// Returns `None` if the IP is not in the database, or its record is
+/// missing an ISO code or English country name, or has no known centre.
+pub fn lookup_country<S: AsRef<[u8]>>(
+    reader: &Reader<S>,
+    centres: &HashMap<String, (f64, f64)>,
+    ip: IpAddr,
+) -> Option<Location> {
+    let country = reader.lookup::<geoip2::Country>(ip).ok()?.country?;
+    let country_code = country.iso_code?;
+    let country_name = country.names?.get("en").copied()?;
+    let &(lat, lon) = centres.get(country_code)?;
+    Some(Location {
+        city_name: country_name.to_string(),
+        country_name: country_name.to_string(),
+        lat,
+        lon,
+        geoname_id: None,
+        population: None,
+        country_code: Some(country_code.to_string()),
+        country_weight: None,
+        known_scanner: None,
+        cloud_provider: None,
+        netblock_owner: None,
+        abuse_contact: None,
+        tags: BTreeMap::new(),
+    })
+}
+
+/// Looks up the city location of `ip` in the `MaxMind` database.
+///
+/// Returns `None` if the IP is not in the database or the record is missing
+/// an English city name, country name, or coordinates.
+pub fn lookup<S: AsRef<[u8]>>(reader: &Reader<S>, ip: IpAddr) -> Option<Location> {
+    let city = reader.lookup::<geoip2::City>(ip).ok()?;
+    let geoname_id = city.city.as_ref().and_then(|c| c.geoname_id);
+    let city_name = city
+        .city
+        .and_then(|c| c.names)
+        .and_then(|n| n.get("en").copied())?;
+    let country_code = city.country.as_ref().and_then(|c| c.iso_code);
+    let country_name = city
+        .country
+        .and_then(|c| c.names)
+        .and_then(|n| n.get("en").copied())?;
+    let location = city.location?;
+    let (lat, lon) = (location.latitude?, location.longitude?);
+    Some(Location {
+        city_name: city_name.to_string(),
+        country_name: country_name.to_string(),
+        lat,
+        lon,
+        geoname_id,
+        population: None,
+        country_code: country_code.map(str::to_string),
+        country_weight: None,
+        known_scanner: None,
+        cloud_provider: None,
+        netblock_owner: None,
+        abuse_contact: None,
+        tags: BTreeMap::new(),
+    })
+}
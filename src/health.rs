@@ -0,0 +1,51 @@
+use crate::shutdown;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Set while the collector is receiving flows.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Serves the `/healthz` and `/readyz` probes on `addr` from a background
+/// thread.
+///
+/// `/healthz` answers `200` as long as the process runs. `/readyz` answers
+/// `200` once the collector is ready to receive flows, and `503` before that
+/// and after a shutdown has been requested, so no new traffic is routed to a
+/// stopping instance.
+pub fn serve(addr: SocketAddr) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A misbehaving prober must not take the endpoint down.
+            let _ = respond(stream);
+        }
+    });
+    Ok(())
+}
+
+/// Marks the collector as ready, or not, to receive flows.
+pub fn set_ready(ready: bool) {
+    READY.store(ready, Ordering::SeqCst);
+}
+
+/// Answers a single probe request.
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path {
+        "/healthz" => ("200 OK", "ok"),
+        "/readyz" if READY.load(Ordering::SeqCst) && !shutdown::requested() => ("200 OK", "ready"),
+        "/readyz" => ("503 Service Unavailable", "not ready"),
+        _ => ("404 Not Found", "not found"),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
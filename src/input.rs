@@ -0,0 +1,568 @@
+mod cowrie;
+mod csv;
+#[cfg(feature = "evtx")]
+mod evtx;
+mod filterlog;
+mod json;
+mod list;
+mod ndjson;
+mod netfilter;
+mod netflow;
+mod pcap;
+mod text;
+mod vpcflow;
+
+pub use ndjson::{read_logins, FieldMapping, Login};
+pub use netflow::Collector;
+
+use crate::dns;
+use crate::logging;
+use crate::refang::refang;
+use crate::remote::Remote;
+use crate::url;
+use clap::ValueEnum;
+use flate2::read::MultiGzDecoder;
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::IpAddr;
+use std::path::Path;
+use std::{env, process};
+
+/// Number of bytes, decompressed, looked at to tell the format of an input.
+const SNIFF_BYTES: u64 = 64 * 1024;
+
+/// Supported input file formats.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Tell the format from the contents of each input: the threat sources
+    /// JSON, a JSON array of objects, NDJSON (including Cloudflare and
+    /// Cowrie logs), CSV or a plain list of IPs.
+    #[default]
+    Auto,
+    /// Legacy `{"Threat Sources": {"Count": [...], "Source": [...]}}` JSON.
+    Json,
+    /// A JSON array of objects, mapped with `--ip-field` and friends like
+    /// `ndjson` events.
+    JsonArray,
+    /// CSV with a header row, mapped with `--ip-field` and friends naming
+    /// columns.
+    Csv,
+    /// A plain list of IPs, one per line; each line counts once.
+    List,
+    /// Arbitrary text, such as email headers or paste dumps; every IPv4 and
+    /// IPv6 address found in it is counted each time it occurs.
+    TextScan,
+    /// pfSense/OPNsense `filterlog` or pfBlockerNG `ip_block.log` lines;
+    /// blocked packets are counted per source IP.
+    Filterlog,
+    /// Kernel `iptables`/`nftables` LOG lines from plain log files or a
+    /// journald export; each line is counted against its `SRC=` address.
+    #[value(alias = "iptables", alias = "nftables")]
+    Netfilter,
+    /// Windows Event Log files; failed logons (event 4625) are counted per
+    /// source IP. Requires the `evtx` feature.
+    Evtx,
+    /// Cowrie honeypot JSON logs; sessions are counted per `src_ip`.
+    Cowrie,
+    /// IPFIX files or captured NetFlow v5 export packets; flows are counted
+    /// per external source IP.
+    #[value(alias = "ipfix")]
+    Netflow,
+    /// pcap or pcapng packet captures; packets are counted per external
+    /// source IP, optionally narrowed with `--pcap-filter`.
+    Pcap,
+    /// AWS VPC Flow Logs, plain or gzipped as downloaded from S3; rejected
+    /// flows are counted per `srcaddr`.
+    #[value(alias = "vpc-flow-logs")]
+    Vpcflow,
+    /// Cloudflare Logpush NDJSON; blocked requests are counted per `ClientIP`.
+    Cloudflare,
+    /// Generic NDJSON events, mapped with `--ip-field` and friends.
+    Ndjson,
+}
+
+/// Format specific options for the input adapters.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Only count netfilter LOG lines containing this log prefix.
+    pub log_prefix: Option<String>,
+    /// Only count Cowrie sessions with events of these types.
+    pub cowrie_events: Vec<String>,
+    /// BPF-style expression selecting which captured packets to count.
+    pub pcap_filter: Option<String>,
+    /// Where to find the source IP and related values in generic NDJSON.
+    pub field_mapping: FieldMapping,
+    /// Extra headers sent when fetching inputs over HTTP.
+    pub headers: Vec<(String, String)>,
+    /// Refuse inputs larger than this many bytes, downloaded or decompressed.
+    pub max_input_size: Option<u64>,
+}
+
+/// A single threat source entry: an IP address string, its threat count, the
+/// feed it was attributed to and its severity, if the input provided one.
+/// `service` is the destination port and protocol the traffic was aimed at,
+/// e.g. `22/tcp`, for inputs that record them, and `tag` a free-form
+/// category, such as the kind of attack, for inputs that provide one.
+#[derive(Debug)]
+pub struct ThreatRecord {
+    pub source: String,
+    pub count: u32,
+    pub feed: String,
+    pub severity: Option<f64>,
+    pub service: Option<String>,
+    pub tag: Option<String>,
+}
+
+/// Hands the records read from an input on in chunks of at most `size`
+/// records, so inputs of any size can be aggregated without holding all of
+/// their records at once.
+pub struct Chunks<'a> {
+    size: usize,
+    sink: &'a mut dyn FnMut(Vec<ThreatRecord>) -> Result<(), Box<dyn Error>>,
+}
+
+impl<'a> Chunks<'a> {
+    /// Creates chunks of at most `size` records passed to `sink`. Errors
+    /// returned by `sink` abort reading the input.
+    pub fn new(
+        size: usize,
+        sink: &'a mut dyn FnMut(Vec<ThreatRecord>) -> Result<(), Box<dyn Error>>,
+    ) -> Self {
+        Self {
+            size: size.max(1),
+            sink,
+        }
+    }
+
+    /// Passes `records` on to the sink in chunks.
+    fn send(
+        &mut self,
+        records: impl IntoIterator<Item = ThreatRecord>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut records = records.into_iter().peekable();
+        while records.peek().is_some() {
+            (self.sink)(records.by_ref().take(self.size).collect())?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the threat records from the file at `path` in the given `format`,
+/// passing them on in `chunks`.
+///
+/// `path` may also be an `http(s)://` URL or an `s3://bucket/key` object,
+/// which is downloaded to a temporary file first.
+pub fn read(
+    path: &Path,
+    format: InputFormat,
+    options: &Options,
+    chunks: Chunks,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(remote) = path.to_str().and_then(Remote::parse) {
+        return read_remote(&remote, format, options, chunks);
+    }
+    if let Some(limit) = options.max_input_size {
+        check_size(path, limit)?;
+    }
+    let format = match format {
+        InputFormat::Auto => sniff(path)?,
+        format => format,
+    };
+    match format {
+        InputFormat::Auto | InputFormat::Json => json::read(path, chunks),
+        InputFormat::JsonArray => ndjson::read_array(path, &options.field_mapping, chunks),
+        InputFormat::Csv => csv::read(path, &options.field_mapping, chunks),
+        InputFormat::List => list::read(path, chunks),
+        InputFormat::TextScan => text::read(path, chunks),
+        InputFormat::Filterlog => filterlog::read(path, chunks),
+        InputFormat::Netfilter => netfilter::read(path, options.log_prefix.as_deref(), chunks),
+        InputFormat::Cowrie => cowrie::read(path, &options.cowrie_events, chunks),
+        InputFormat::Netflow => netflow::read(path, chunks),
+        InputFormat::Pcap => pcap::read(path, options.pcap_filter.as_deref(), chunks),
+        InputFormat::Vpcflow => vpcflow::read(path, chunks),
+        InputFormat::Cloudflare => ndjson::read_cloudflare(path, chunks),
+        InputFormat::Ndjson => ndjson::read_mapped(path, &options.field_mapping, chunks),
+        #[cfg(feature = "evtx")]
+        InputFormat::Evtx => evtx::read(path, chunks),
+        #[cfg(not(feature = "evtx"))]
+        InputFormat::Evtx => Err("EVTX input requires building with `--features evtx`".into()),
+    }
+}
+
+/// Tells the format of the input at `path` from its first
+/// [`SNIFF_BYTES`], as [`detect`] does.
+fn sniff(path: &Path) -> Result<InputFormat, Box<dyn Error>> {
+    let mut head = Vec::new();
+    open_text(path)?.take(SNIFF_BYTES).read_to_end(&mut head)?;
+    let Some(format) = detect(&String::from_utf8_lossy(&head)) else {
+        return Err(format!(
+            "{}: can't tell the input format, set --input-format",
+            path.display()
+        )
+        .into());
+    };
+    logging::info(
+        "input format detected",
+        &[
+            ("input", json!(path.display().to_string())),
+            (
+                "format",
+                json!(format.to_possible_value().map(|v| v.get_name().to_string())),
+            ),
+        ],
+    );
+    Ok(format)
+}
+
+/// Tells the format of an input starting with `head`: a JSON object
+/// spanning the first line is NDJSON, told apart from the threat sources
+/// JSON by its keys and recognized as a Cloudflare or Cowrie log by theirs,
+/// and a `[` starts a JSON array. A first line starting with an IP or URL,
+/// or holding nothing but a hostname, is a list, or else with a comma a CSV
+/// header. They may be defanged. Returns `None` for input without any line
+/// but comments.
+fn detect(head: &str) -> Option<InputFormat> {
+    let text = head.trim_start_matches('\u{feff}').trim_start();
+    let first_line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.starts_with('#'))
+        .unwrap_or_default();
+    if text.starts_with('{') {
+        return Some(match serde_json::from_str(first_line) {
+            Ok(serde_json::Value::Object(event)) if event.contains_key("Threat Sources") => {
+                InputFormat::Json
+            }
+            Ok(serde_json::Value::Object(event)) if event.contains_key("ClientIP") => {
+                InputFormat::Cloudflare
+            }
+            Ok(serde_json::Value::Object(event)) if event.contains_key("eventid") => {
+                InputFormat::Cowrie
+            }
+            Ok(serde_json::Value::Object(_)) => InputFormat::Ndjson,
+            _ => InputFormat::Json,
+        });
+    }
+    if text.starts_with('[') {
+        return Some(InputFormat::JsonArray);
+    }
+    let listed = list::first_token(first_line)
+        .map(refang)
+        .is_some_and(|token| {
+            let host = url::host(&token).unwrap_or(&token);
+            host.parse::<IpAddr>().is_ok()
+                || (dns::is_hostname(host) && !first_line.contains([',', ';']))
+        });
+    if listed {
+        Some(InputFormat::List)
+    } else if first_line.contains(',') {
+        Some(InputFormat::Csv)
+    } else {
+        None
+    }
+}
+
+/// Downloads a remote input into a temporary directory and reads it from
+/// there. The downloaded file keeps the remote file name so the default feed
+/// label matches that of a local copy.
+fn read_remote(
+    remote: &Remote,
+    format: InputFormat,
+    options: &Options,
+    chunks: Chunks,
+) -> Result<(), Box<dyn Error>> {
+    let contents = remote.fetch(&options.headers, options.max_input_size)?;
+    let dir = env::temp_dir().join(format!("dashboard_location_mapper-{}", process::id()));
+    fs::create_dir_all(&dir)?;
+    let name = Some(remote.file_name()).filter(|name| !name.is_empty());
+    let path = dir.join(name.unwrap_or("input"));
+    fs::write(&path, contents)?;
+    let result = read(&path, format, options, chunks);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+/// Writes `contents` to a temporary file called `name`, reads it with `read`
+/// in chunks of 2 records and returns the records as `(source, feed,
+/// service, count)`, sorted and with the counts of each summed up again.
+#[cfg(test)]
+fn read_test_input(
+    name: &str,
+    contents: &[u8],
+    read: impl FnOnce(&Path, Chunks) -> Result<(), Box<dyn Error>>,
+) -> Vec<(String, String, Option<String>, u32)> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir().join(format!("dashboard_location_mapper-{}-{run}", process::id()));
+    fs::create_dir_all(&dir).expect("the temporary directory is created");
+    let path = dir.join(name);
+    fs::write(&path, contents).expect("the input is written");
+    let mut records = Vec::new();
+    let mut sink = |chunk: Vec<ThreatRecord>| {
+        records.extend(chunk);
+        Ok(())
+    };
+    let result = read(&path, Chunks::new(2, &mut sink));
+    let _ = fs::remove_dir_all(&dir);
+    result.expect("the input is read");
+    let mut counts = std::collections::BTreeMap::new();
+    for record in records {
+        let count: &mut u32 = counts
+            .entry((record.source, record.feed, record.service))
+            .or_default();
+        *count = count.saturating_add(record.count);
+    }
+    counts
+        .into_iter()
+        .map(|((source, feed, service), count)| (source, feed, service, count))
+        .collect()
+}
+
+/// Fails if the file at `path`, or its decompressed contents if it is
+/// gzipped, is larger than `limit` bytes.
+///
+/// Gzipped files are streamed through the decoder and discarded, so a
+/// decompression bomb is caught without holding any of it in memory.
+fn check_size(path: &Path, limit: u64) -> Result<(), Box<dyn Error>> {
+    let exceeded = || {
+        format!(
+            "{} exceeds --max-input-size of {limit} bytes",
+            path.display()
+        )
+    };
+    if fs::metadata(path)?.len() > limit {
+        return Err(exceeded().into());
+    }
+    let mut file = BufReader::new(File::open(path)?);
+    if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        let mut decoder = MultiGzDecoder::new(file).take(limit + 1);
+        if io::copy(&mut decoder, &mut io::sink())? > limit {
+            return Err(exceeded().into());
+        }
+    }
+    Ok(())
+}
+
+/// The feed label used for records that don't name one: the file stem of the
+/// input they were read from.
+fn default_feed(path: &Path) -> String {
+    path.file_stem()
+        .map_or_else(String::new, |s| s.to_string_lossy().into_owned())
+}
+
+/// Opens a text input for line-by-line reading, transparently decompressing
+/// it if it is gzipped, as rotated logs and S3 log exports usually are.
+pub fn open_text(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        return Ok(Box::new(BufReader::new(MultiGzDecoder::new(file))));
+    }
+    Ok(Box::new(file))
+}
+
+/// Counts occurrences of source IPs per feed, service and tag, for log
+/// formats where each line is a single event rather than a pre-aggregated
+/// count.
+///
+/// Once a chunk's worth of distinct sources has been tallied the counts are
+/// passed on and the tally starts over, so a source may appear in several
+/// chunks; aggregation sums them up again.
+struct Tally<'a> {
+    counts: HashMap<(String, String, Option<String>, Option<String>), u32>,
+    chunks: Chunks<'a>,
+}
+
+impl<'a> Tally<'a> {
+    /// Creates an empty tally passing its counts on in `chunks`.
+    fn new(chunks: Chunks<'a>) -> Self {
+        Self {
+            counts: HashMap::new(),
+            chunks,
+        }
+    }
+
+    /// Records one event from `source` reported by `feed`.
+    fn add(&mut self, source: &str, feed: &str) -> Result<(), Box<dyn Error>> {
+        self.add_count(source, feed, None, None, 1)
+    }
+
+    /// Records one event from `source` reported by `feed`, aimed at
+    /// `service`.
+    fn add_service(
+        &mut self,
+        source: &str,
+        feed: &str,
+        service: Option<String>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.add_count(source, feed, service, None, 1)
+    }
+
+    /// Records `count` events from `source` reported by `feed`, aimed at
+    /// `service` and tagged with `tag`.
+    fn add_count(
+        &mut self,
+        source: &str,
+        feed: &str,
+        service: Option<String>,
+        tag: Option<String>,
+        count: u32,
+    ) -> Result<(), Box<dyn Error>> {
+        let tally = self
+            .counts
+            .entry((source.to_string(), feed.to_string(), service, tag))
+            .or_default();
+        *tally = tally.saturating_add(count);
+        if self.counts.len() >= self.chunks.size {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Passes the remaining counts on once the input has been read.
+    fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.flush()
+    }
+
+    /// Passes the tallied counts on as threat records and clears them.
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        let records = self
+            .counts
+            .drain()
+            .map(|((source, feed, service, tag), count)| ThreatRecord {
+                source,
+                count,
+                feed,
+                severity: None,
+                service,
+                tag,
+            });
+        self.chunks.send(records)
+    }
+}
+
+/// Formats the destination port and protocol of an event as its service,
+/// e.g. `22/tcp`, or as whichever of them is known. Port 0 and the port
+/// fields of ICMP, which some formats fill with the message type, are
+/// ignored.
+pub fn service(protocol: Option<&str>, port: Option<u16>) -> Option<String> {
+    let protocol = protocol.map(str::to_ascii_lowercase);
+    let icmp = protocol.as_deref().is_some_and(|p| p.starts_with("icmp"));
+    let port = port.filter(|&port| port != 0 && !icmp);
+    match (protocol, port) {
+        (Some(protocol), Some(port)) => Some(format!("{port}/{protocol}")),
+        (Some(protocol), None) => Some(protocol),
+        (None, Some(port)) => Some(port.to_string()),
+        (None, None) => None,
+    }
+}
+
+/// Returns the name of an IANA protocol number, or the number itself for
+/// uncommon protocols.
+fn protocol_name(number: u8) -> String {
+    match number {
+        1 => "icmp".to_string(),
+        6 => "tcp".to_string(),
+        17 => "udp".to_string(),
+        58 => "icmp6".to_string(),
+        132 => "sctp".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Returns `true` unless `ip` is a private, loopback, link-local or otherwise
+/// non-routable address, i.e. an internal host rather than an external source.
+const fn is_external(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let shared = v4.octets()[0] == 100 && v4.octets()[1] & 0xc0 == 64;
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_broadcast()
+                || v4.is_unspecified()
+                || v4.is_multicast()
+                || shared)
+        }
+        IpAddr::V6(v6) => {
+            let segment = v6.segments()[0];
+            let unique_local = segment & 0xfe00 == 0xfc00;
+            let link_local = segment & 0xffc0 == 0xfe80;
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                || unique_local
+                || link_local)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{detect, InputFormat};
+
+    #[test]
+    fn detects_json_variants() {
+        let cases = [
+            (r#"{"Threat Sources": []}"#, InputFormat::Json),
+            ("{\n  \"Threat Sources\": []\n}", InputFormat::Json),
+            (
+                r#"{"ClientIP":"203.0.113.5","SecurityAction":"block"}"#,
+                InputFormat::Cloudflare,
+            ),
+            (
+                r#"{"eventid":"cowrie.session.connect","src_ip":"203.0.113.5"}"#,
+                InputFormat::Cowrie,
+            ),
+            (r#"{"ip":"203.0.113.5"}"#, InputFormat::Ndjson),
+            (
+                "\u{feff}  [{\"ip\":\"203.0.113.5\"}]",
+                InputFormat::JsonArray,
+            ),
+        ];
+        for (head, format) in cases {
+            assert_eq!(detect(head), Some(format), "{head}");
+        }
+    }
+
+    #[test]
+    fn detects_lists() {
+        let cases = [
+            "203.0.113.5\n198.51.100.7",
+            "# blocked today\n2001:db8::5",
+            "203[.]0[.]113[.]5, ssh",
+            "hxxps://203.0.113.5/login",
+            "scanner.example.com\n203.0.113.5",
+        ];
+        for head in cases {
+            assert_eq!(detect(head), Some(InputFormat::List), "{head}");
+        }
+    }
+
+    #[test]
+    fn detects_csv_headers() {
+        let cases = [
+            "ip,action\n203.0.113.5,block",
+            "source.ip,action\n203.0.113.5,block",
+            "client.address;port,count",
+        ];
+        for head in cases {
+            assert_eq!(detect(head), Some(InputFormat::Csv), "{head}");
+        }
+    }
+
+    #[test]
+    fn cannot_tell_empty_or_unknown_input() {
+        for head in [
+            "",
+            "  \n\n",
+            "# only a comment\n# and another",
+            "hello world",
+        ] {
+            assert_eq!(detect(head), None, "{head:?}");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use super::{default_feed, open_text, Chunks, Tally};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// The fields of a Cowrie log event used for counting sessions.
+#[derive(Deserialize)]
+struct Event {
+    eventid: String,
+    src_ip: String,
+    session: String,
+}
+
+/// Reads a Cowrie honeypot JSON log and counts sessions per source IP.
+///
+/// Each line holds one event. A session is counted once no matter how many
+/// events it logged; with `events` given, only sessions that logged an event
+/// whose `eventid` starts with one of them are counted. Lines that aren't
+/// valid events, such as a partially written last line, are skipped.
+///
+/// Sessions are only handed on in chunks once the whole log has been read,
+/// as a session's events may be spread across it.
+pub fn read(path: &Path, events: &[String], chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let prefixes: Vec<String> = events
+        .iter()
+        .map(|event| {
+            if event.starts_with("cowrie.") {
+                event.clone()
+            } else {
+                format!("cowrie.{event}")
+            }
+        })
+        .collect();
+
+    // Ordered, so chunks come out the same on every read for --checkpoint.
+    let mut sessions = BTreeSet::new();
+    for line in open_text(path)?.lines() {
+        let Ok(event) = serde_json::from_str::<Event>(&line?) else {
+            continue;
+        };
+        if prefixes.is_empty() || prefixes.iter().any(|p| event.eventid.starts_with(p)) {
+            sessions.insert((event.src_ip, event.session));
+        }
+    }
+
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    for (source, _) in &sessions {
+        tally.add(source, &feed)?;
+    }
+    tally.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::read;
+    use crate::input::read_test_input;
+
+    /// A session from an IPv4 source that logged in, another from the same
+    /// source that didn't, and one from an IPv6 source, with a partially
+    /// written last line.
+    const LOG: &str = r#"{"eventid":"cowrie.session.connect","src_ip":"203.0.113.5","session":"a1"}
+{"eventid":"cowrie.login.success","src_ip":"203.0.113.5","session":"a1"}
+{"eventid":"cowrie.command.input","src_ip":"203.0.113.5","session":"a1"}
+{"eventid":"cowrie.session.connect","src_ip":"203.0.113.5","session":"b2"}
+{"eventid":"cowrie.login.failed","src_ip":"203.0.113.5","session":"b2"}
+{"eventid":"cowrie.session.connect","src_ip":"2001:db8::5","session":"c3"}
+{"eventid":"cowrie.login.success","src_ip":"2001:db8::5","ses"#;
+
+    /// Returns the sources and session counts read from [`LOG`] with `events`.
+    fn sessions(events: &[&str]) -> Vec<(String, u32)> {
+        let events: Vec<String> = events.iter().map(ToString::to_string).collect();
+        read_test_input("cowrie.json", LOG.as_bytes(), |path, chunks| {
+            read(path, &events, chunks)
+        })
+        .into_iter()
+        .map(|(source, _, _, count)| (source, count))
+        .collect()
+    }
+
+    #[test]
+    fn counts_each_session_once() {
+        let expected = vec![("2001:db8::5".into(), 1), ("203.0.113.5".into(), 2)];
+        assert_eq!(sessions(&[]), expected);
+    }
+
+    #[test]
+    fn counts_only_sessions_with_the_given_events() {
+        // The IPv6 session's login is on the truncated line.
+        assert_eq!(
+            sessions(&["login.success"]),
+            vec![("203.0.113.5".into(), 1)]
+        );
+        assert_eq!(sessions(&["cowrie.login"]), vec![("203.0.113.5".into(), 2)]);
+    }
+
+    #[test]
+    fn skips_lines_that_are_not_events() {
+        let log = "\n[]\n{\"eventid\":\"cowrie.session.connect\"}\nnot json\n";
+        let records = read_test_input("cowrie.json", log.as_bytes(), |path, chunks| {
+            read(path, &[], chunks)
+        });
+        assert_eq!(records, Vec::new());
+    }
+}
@@ -0,0 +1,34 @@
+use super::ndjson::{add_event, FieldMapping};
+use super::{default_feed, open_text, Chunks, Tally};
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::path::Path;
+
+/// Reads CSV with a header row, counting the rows matching all of the
+/// mapping's conditions against the IP in the `ip_field` column, like the
+/// events of NDJSON input. Fields name columns by their header.
+pub fn read(path: &Path, mapping: &FieldMapping, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let default_feed = default_feed(path);
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(open_text(path)?);
+    let headers = reader.headers()?.clone();
+    if !headers.iter().any(|header| header == mapping.ip_field) {
+        return Err(format!(
+            "{}: no `{}` column, set --ip-field to the column holding the source IP",
+            path.display(),
+            mapping.ip_field
+        )
+        .into());
+    }
+    let mut tally = Tally::new(chunks);
+    for row in reader.records() {
+        let row: Map<String, Value> = headers
+            .iter()
+            .zip(&row?)
+            .map(|(header, value)| (header.to_string(), Value::from(value.trim())))
+            .collect();
+        add_event(&mut tally, mapping, &Value::Object(row), &default_feed)?;
+    }
+    tally.finish()
+}
@@ -0,0 +1,36 @@
+use super::{default_feed, Chunks, Tally};
+use evtx::EvtxParser;
+use serde_json::Value;
+use std::error::Error;
+use std::path::Path;
+
+/// Security log event ID for "An account failed to log on".
+const FAILED_LOGON: u64 = 4625;
+
+/// Reads a Windows Event Log file and counts failed logon events per source IP.
+///
+/// Logons without a network source (`IpAddress` of `-`, as for local console
+/// logons) are skipped, as are records the parser can't decode.
+pub fn read(path: &Path, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    let mut parser = EvtxParser::from_path(path)?;
+    for record in parser.records_json_value().flatten() {
+        let event = &record.data["Event"];
+        if event_id(&event["System"]["EventID"]) != Some(FAILED_LOGON) {
+            continue;
+        }
+        if let Some(source) = event["EventData"]["IpAddress"].as_str() {
+            if source != "-" {
+                tally.add(source, &feed)?;
+            }
+        }
+    }
+    tally.finish()
+}
+
+/// Reads the event ID, which is rendered as a plain number or, when the
+/// element carries attributes such as `Qualifiers`, as `{"#text": id}`.
+fn event_id(value: &Value) -> Option<u64> {
+    value.as_u64().or_else(|| value["#text"].as_u64())
+}
@@ -0,0 +1,176 @@
+use super::{default_feed, open_text, service, Chunks, Tally};
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Reads a pfSense/OPNsense firewall log and counts inbound blocked packets
+/// per source IP and the service given by their protocol and destination
+/// port.
+///
+/// Two line layouts are understood:
+/// - `filterlog` CSV, either bare or behind a BSD or RFC 5424 syslog header.
+/// - pfBlockerNG `ip_block.log`, whose feed name column is used as the feed
+///   label so per-list hotspots can be broken down with `--group-by city,feed`.
+///
+/// Lines in any other shape, passed packets and outbound blocks are ignored.
+pub fn read(path: &Path, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let default_feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    for line in open_text(path)?.lines() {
+        let line = line?;
+        if let Some(Block {
+            source,
+            feed,
+            service,
+        }) = parse_line(&line)
+        {
+            tally.add_service(source, feed.unwrap_or(&default_feed), service)?;
+        }
+    }
+    tally.finish()
+}
+
+/// An inbound block or reject: its source IP, the pfBlockerNG feed name if
+/// present, and the service it was aimed at.
+struct Block<'a> {
+    source: &'a str,
+    feed: Option<&'a str>,
+    service: Option<String>,
+}
+
+/// Parses an inbound block or reject log line.
+fn parse_line(line: &str) -> Option<Block<'_>> {
+    // The filterlog payload never contains spaces, so whatever syslog header
+    // precedes it is dropped by taking the last whitespace-separated token.
+    if line.contains("filterlog") {
+        return parse_filterlog(line.split_whitespace().last()?);
+    }
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.get(5) == Some(&"match") {
+        return parse_filterlog(line);
+    }
+    parse_pfblockerng(&fields)
+}
+
+/// Parses a `filterlog` CSV payload: `rule,subrule,anchor,tracker,interface,
+/// reason,action,direction,ipversion,...`, where the positions of the
+/// protocol, the source address and the ports following it depend on the IP
+/// version.
+fn parse_filterlog(payload: &str) -> Option<Block<'_>> {
+    let fields: Vec<&str> = payload.split(',').collect();
+    if !is_inbound_block(fields.get(6)?, fields.get(7)?) {
+        return None;
+    }
+    let (protocol, source) = match *fields.get(8)? {
+        "4" => (16, 18),
+        "6" => (12, 15),
+        _ => return None,
+    };
+    let port = fields.get(source + 3).and_then(|port| port.parse().ok());
+    Some(Block {
+        source: fields.get(source)?,
+        feed: None,
+        service: service(fields.get(protocol).copied(), port),
+    })
+}
+
+/// Parses a pfBlockerNG `ip_block.log` line: `date,rule,interface,
+/// friendly_name,action,ipversion,protocol_id,protocol,src,dst,src_port,
+/// dst_port,direction,geoip,alias,evaluated,feed,...`.
+///
+/// The protocol may carry TCP flags, as in `TCP-S`.
+fn parse_pfblockerng<'a>(fields: &[&'a str]) -> Option<Block<'a>> {
+    if !is_inbound_block(fields.get(4)?, fields.get(12)?) {
+        return None;
+    }
+    let protocol = fields
+        .get(7)
+        .and_then(|protocol| protocol.split('-').next());
+    let port = fields.get(11).and_then(|port| port.parse().ok());
+    Some(Block {
+        source: fields.get(8)?,
+        feed: fields.get(16).copied().filter(|f| !f.is_empty()),
+        service: service(protocol.filter(|p| !p.is_empty()), port),
+    })
+}
+
+/// Returns `true` for blocked or rejected inbound traffic.
+fn is_inbound_block(action: &str, direction: &str) -> bool {
+    matches!(action, "block" | "reject") && direction == "in"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_line;
+
+    /// An inbound IPv4 TCP block of port 22 from `203.0.113.5`.
+    const IPV4: &str = "5,,,1000000103,igb0,match,block,in,4,0x0,,64,12345,0,none,6,tcp,60,\
+                        203.0.113.5,198.51.100.10,54321,22,0,S,1234567890,,64240,,mss";
+
+    /// An inbound IPv6 UDP block of port 53 from `2001:db8::5`.
+    const IPV6: &str = "5,,,1000000103,igb0,match,block,in,6,0x00,0x00000,64,udp,17,40,\
+                        2001:db8::5,2001:db8::10,5353,53,40";
+
+    /// Returns the source, feed and service of the block on `line`.
+    fn parse(line: &str) -> Option<(&str, Option<&str>, Option<String>)> {
+        parse_line(line).map(|block| (block.source, block.feed, block.service))
+    }
+
+    #[test]
+    fn parses_bare_ipv4_blocks() {
+        let expected = Some(("203.0.113.5", None, Some("22/tcp".to_string())));
+        assert_eq!(parse(IPV4), expected);
+    }
+
+    #[test]
+    fn parses_ipv4_blocks_behind_syslog_headers() {
+        let bsd = format!("Jan  5 10:00:00 fw filterlog[1234]: {IPV4}");
+        let rfc5424 = format!("<134>1 2024-01-05T10:00:00Z fw filterlog 1234 - - {IPV4}");
+        for line in [bsd, rfc5424] {
+            assert_eq!(parse(&line).map(|(source, ..)| source), Some("203.0.113.5"));
+        }
+    }
+
+    #[test]
+    fn parses_ipv6_blocks() {
+        let expected = Some(("2001:db8::5", None, Some("53/udp".to_string())));
+        assert_eq!(parse(IPV6), expected);
+    }
+
+    #[test]
+    fn parses_pfblockerng_blocks() {
+        let line = "Jan 5 10:00:00,1770000000,igb0,WAN,block,4,6,TCP-S,203.0.113.7,\
+                    198.51.100.10,40000,443,in,CN,pfB_Top_v4,203.0.0.0/8,Spamhaus_DROP,+";
+        let expected = Some((
+            "203.0.113.7",
+            Some("Spamhaus_DROP"),
+            Some("443/tcp".to_string()),
+        ));
+        assert_eq!(parse(line), expected);
+    }
+
+    #[test]
+    fn ignores_passed_and_outbound_packets() {
+        assert_eq!(parse(&IPV4.replacen(",block,", ",pass,", 1)), None);
+        assert_eq!(parse(&IPV4.replacen(",in,", ",out,", 1)), None);
+    }
+
+    #[test]
+    fn ignores_short_lines() {
+        for line in [
+            "",
+            "filterlog:",
+            "5,,,1000000103,igb0,match,block,in",
+            "5,,,1000000103,igb0,match,block,in,4,0x0,,64,12345,0,none,6,tcp,60",
+            "5,,,1000000103,igb0,match,block,in,6,0x00,0x00000,64,udp,17,40",
+            "Jan 5 10:00:00,1770000000,igb0,WAN,block",
+        ] {
+            assert_eq!(parse(line), None, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn ignores_unknown_ip_versions() {
+        assert_eq!(parse(&IPV4.replacen(",in,4,", ",in,5,", 1)), None);
+    }
+}
@@ -0,0 +1,67 @@
+use super::{default_feed, Chunks, ThreatRecord};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// The input document; any other top-level keys are ignored.
+#[derive(Deserialize)]
+struct Document {
+    #[serde(rename = "Threat Sources")]
+    threat_sources: ThreatSources,
+}
+
+/// Represents the structure of threat sources loaded from a JSON file.
+///
+/// `Feed` is optional and, when present, runs parallel to `Source` labeling the
+/// threat feed each IP was reported by. `Source Name` is accepted as an alias.
+/// `Severity` is likewise optional and holds a numeric weight per IP, and
+/// `Tag` a category per IP, such as the kind of attack.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug)]
+struct ThreatSources {
+    Count: Vec<u32>,
+    Source: Vec<String>,
+    #[serde(default, alias = "Source Name")]
+    Feed: Option<Vec<String>>,
+    #[serde(default)]
+    Severity: Option<Vec<f64>>,
+    #[serde(default)]
+    Tag: Option<Vec<String>>,
+}
+
+/// Reads the threat sources from the JSON file at `path`.
+///
+/// Records without an explicit feed label are attributed to the file stem of
+/// `path`, so merging several single-feed inputs still yields a per-feed
+/// breakdown.
+///
+/// The parallel `Count` and `Source` arrays can't be paired up without
+/// holding both, so the document is parsed whole and only its records are
+/// handed on in chunks. It is deserialized straight into the arrays, which
+/// are then moved into the records, so the document is only held once.
+pub fn read(path: &Path, mut chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let file = BufReader::new(File::open(path)?);
+    let Document { threat_sources } = serde_json::from_reader(file)?;
+
+    let default_feed = default_feed(path);
+    let mut feeds = threat_sources.Feed.unwrap_or_default().into_iter();
+    let mut severities = threat_sources.Severity.unwrap_or_default().into_iter();
+    let mut tags = threat_sources.Tag.unwrap_or_default().into_iter();
+
+    chunks.send(
+        threat_sources
+            .Source
+            .into_iter()
+            .zip(threat_sources.Count)
+            .map(|(source, count)| ThreatRecord {
+                source,
+                count,
+                feed: feeds.next().unwrap_or_else(|| default_feed.clone()),
+                severity: severities.next(),
+                service: None,
+                tag: tags.next().filter(|tag| !tag.is_empty()),
+            }),
+    )
+}
@@ -0,0 +1,29 @@
+use super::{default_feed, open_text, Chunks, Tally};
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Reads a plain list of IPs, counting every line against the IP it starts
+/// with, so repeated IPs count once per line. Anything after the IP, such as
+/// a comment, is ignored, as are blank lines and `#` comments. Defanged IPs
+/// are refanged on lookup.
+pub fn read(path: &Path, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    for line in open_text(path)?.lines() {
+        let line = line?;
+        let Some(source) = first_token(&line) else {
+            continue;
+        };
+        tally.add(source, &feed)?;
+    }
+    tally.finish()
+}
+
+/// Returns the first token of a list line, or `None` for blank lines and
+/// comments.
+pub(super) fn first_token(line: &str) -> Option<&str> {
+    line.split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .find(|token| !token.is_empty())
+        .filter(|token| !token.starts_with('#'))
+}
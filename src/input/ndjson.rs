@@ -0,0 +1,275 @@
+use super::{default_feed, open_text, service, Chunks, Tally};
+use serde_json::Value;
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Cloudflare fields holding the action taken on a request: `SecurityAction`
+/// in the HTTP requests dataset, `WAFAction` in its older schema and `Action`
+/// in the firewall events dataset.
+const CLOUDFLARE_ACTION_FIELDS: [&str; 3] = ["SecurityAction", "WAFAction", "Action"];
+
+/// Describes where to find the relevant values in arbitrary NDJSON events.
+///
+/// Field names are dotted paths into nested objects, e.g. `client.ip`.
+#[derive(Debug, Default)]
+pub struct FieldMapping {
+    /// Field holding the source IP.
+    pub ip_field: String,
+    /// `(field, value)` pairs that must all match for an event to be counted.
+    pub conditions: Vec<(String, String)>,
+    /// Field holding the feed label; defaults to the input's file stem.
+    pub feed_field: Option<String>,
+    /// Field holding a pre-aggregated count; each event counts once without it.
+    pub count_field: Option<String>,
+    /// Fields holding the destination port and protocol of the event.
+    pub port_field: Option<String>,
+    pub protocol_field: Option<String>,
+    /// Field holding a category of the event, such as the kind of attack.
+    pub tag_field: Option<String>,
+}
+
+/// Reads Cloudflare Logpush NDJSON (HTTP requests or firewall events) and
+/// counts blocked requests per `ClientIP`.
+///
+/// Blank or malformed lines, such as a partially written last line, are
+/// skipped here and in [`read_mapped`].
+pub fn read_cloudflare(path: &Path, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    for line in open_text(path)?.lines() {
+        let Ok(event) = serde_json::from_str::<Value>(&line?) else {
+            continue;
+        };
+        let blocked = CLOUDFLARE_ACTION_FIELDS
+            .iter()
+            .any(|field| event[field].as_str() == Some("block"));
+        if let (true, Some(source)) = (blocked, event["ClientIP"].as_str()) {
+            tally.add(source, &feed)?;
+        }
+    }
+    tally.finish()
+}
+
+/// Reads arbitrary NDJSON events, counting those matching all of the
+/// mapping's conditions against the IP in its `ip_field`.
+pub fn read_mapped(
+    path: &Path,
+    mapping: &FieldMapping,
+    chunks: Chunks,
+) -> Result<(), Box<dyn Error>> {
+    let default_feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    for line in open_text(path)?.lines() {
+        let Ok(event) = serde_json::from_str::<Value>(&line?) else {
+            continue;
+        };
+        add_event(&mut tally, mapping, &event, &default_feed)?;
+    }
+    tally.finish()
+}
+
+/// Reads a JSON array of objects, such as `[{"ip": "192.0.2.1"}]`, counting
+/// those matching all of the mapping's conditions against the IP in its
+/// `ip_field`, like the events of [`read_mapped`].
+pub fn read_array(
+    path: &Path,
+    mapping: &FieldMapping,
+    chunks: Chunks,
+) -> Result<(), Box<dyn Error>> {
+    let default_feed = default_feed(path);
+    let events: Vec<Value> = serde_json::from_reader(open_text(path)?)
+        .map_err(|e| format!("{}: expected a JSON array of objects: {e}", path.display()))?;
+    let mut tally = Tally::new(chunks);
+    for event in &events {
+        add_event(&mut tally, mapping, event, &default_feed)?;
+    }
+    tally.finish()
+}
+
+/// Counts `event` if it matches all of the mapping's conditions, against
+/// the IP, feed, count, service and tag in the mapped fields. Counts may be
+/// numbers or numeric strings.
+pub(super) fn add_event(
+    tally: &mut Tally,
+    mapping: &FieldMapping,
+    event: &Value,
+    default_feed: &str,
+) -> Result<(), Box<dyn Error>> {
+    let matches = mapping
+        .conditions
+        .iter()
+        .all(|(field, value)| text(lookup(event, field)).as_deref() == Some(value.as_str()));
+    let Some(source) = text(lookup(event, &mapping.ip_field)).filter(|_| matches) else {
+        return Ok(());
+    };
+    let field = |field: &Option<String>| field.as_ref().map(|field| lookup(event, field));
+    let feed = field(&mapping.feed_field)
+        .and_then(text)
+        .unwrap_or_else(|| default_feed.to_string());
+    let count = field(&mapping.count_field).map_or(1, |count| {
+        text(count).and_then(|c| c.parse().ok()).unwrap_or(0)
+    });
+    let port = field(&mapping.port_field).and_then(|port| text(port)?.parse().ok());
+    let protocol = field(&mapping.protocol_field).and_then(text);
+    let service = service(protocol.as_deref(), port);
+    let tag = field(&mapping.tag_field).and_then(text);
+    tally.add_count(&source, &feed, service, tag, count)
+}
+
+/// A login from an authentication log.
+pub struct Login {
+    pub user: String,
+    pub source: String,
+    /// Seconds since the Unix epoch.
+    pub time: f64,
+}
+
+/// Reads the logins of NDJSON authentication events matching all of the
+/// mapping's conditions, with the user in `user_field`, the IP in the
+/// mapping's `ip_field` and the time in `time_field`, as RFC 3339 or seconds
+/// or milliseconds since the Unix epoch. Events missing any are skipped.
+pub fn read_logins(
+    path: &Path,
+    mapping: &FieldMapping,
+    user_field: &str,
+    time_field: &str,
+) -> Result<Vec<Login>, Box<dyn Error>> {
+    let mut logins = Vec::new();
+    for line in open_text(path)?.lines() {
+        let Ok(event) = serde_json::from_str::<Value>(&line?) else {
+            continue;
+        };
+        let matches = mapping
+            .conditions
+            .iter()
+            .all(|(field, value)| text(lookup(&event, field)).as_deref() == Some(value.as_str()));
+        let user = text(lookup(&event, user_field));
+        let source = text(lookup(&event, &mapping.ip_field));
+        let time = timestamp(lookup(&event, time_field));
+        if let (true, Some(user), Some(source), Some(time)) = (matches, user, source, time) {
+            logins.push(Login { user, source, time });
+        }
+    }
+    Ok(logins)
+}
+
+/// Parses an RFC 3339 time, or seconds since the Unix epoch, or milliseconds
+/// if too large to be seconds, as seconds since the epoch.
+#[allow(clippy::cast_precision_loss)] // Millisecond precision is plenty.
+fn timestamp(value: &Value) -> Option<f64> {
+    if let Some(number) = value.as_f64() {
+        return Some(if number > 1e11 {
+            number / 1000.0
+        } else {
+            number
+        });
+    }
+    let time = chrono::DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+    Some(time.timestamp_millis() as f64 / 1000.0)
+}
+
+/// Resolves a dotted field path such as `client.ip` within `event`.
+fn lookup<'a>(event: &'a Value, path: &str) -> &'a Value {
+    path.split('.').fold(event, |value, key| &value[key])
+}
+
+/// Renders a scalar JSON value as text for matching and labeling.
+fn text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_cloudflare, read_mapped, Chunks, FieldMapping};
+    use crate::input::read_test_input;
+    use std::error::Error;
+    use std::path::Path;
+
+    /// Returns the sources and counts read from `contents` with `read`.
+    fn counts(
+        contents: &str,
+        read: impl FnOnce(&Path, Chunks) -> Result<(), Box<dyn Error>>,
+    ) -> Vec<(String, String, Option<String>, u32)> {
+        read_test_input("events.ndjson", contents.as_bytes(), read)
+    }
+
+    /// Returns a record of `count` events from `source` of `feed`, aimed at
+    /// `service`.
+    fn record(
+        source: &str,
+        feed: &str,
+        service: Option<&str>,
+        count: u32,
+    ) -> (String, String, Option<String>, u32) {
+        (source.into(), feed.into(), service.map(Into::into), count)
+    }
+
+    #[test]
+    fn counts_blocked_cloudflare_requests() {
+        let log = r#"{"ClientIP":"203.0.113.5","SecurityAction":"block"}
+{"ClientIP":"203.0.113.5","WAFAction":"block"}
+{"ClientIP":"2001:db8::5","Action":"block"}
+{"ClientIP":"198.51.100.7","SecurityAction":"allow"}
+{"SecurityAction":"block"}
+
+{"ClientIP":"198.51.100.8","Secu"#;
+        let expected = vec![
+            record("2001:db8::5", "events", None, 1),
+            record("203.0.113.5", "events", None, 2),
+        ];
+        assert_eq!(counts(log, read_cloudflare), expected);
+    }
+
+    #[test]
+    fn counts_mapped_events_matching_all_conditions() {
+        let mapping = FieldMapping {
+            ip_field: "client.ip".into(),
+            conditions: vec![
+                ("verdict".into(), "deny".into()),
+                ("rule".into(), "7".into()),
+            ],
+            feed_field: Some("sensor".into()),
+            count_field: Some("hits".into()),
+            port_field: Some("dst.port".into()),
+            protocol_field: Some("dst.proto".into()),
+            ..FieldMapping::default()
+        };
+        let log = r#"{"client":{"ip":"203.0.113.5"},"verdict":"deny","rule":7,"sensor":"edge","hits":3,"dst":{"port":22,"proto":"tcp"}}
+{"client":{"ip":"203.0.113.5"},"verdict":"deny","rule":"7","sensor":"edge","hits":"2","dst":{"port":"22","proto":"tcp"}}
+{"client":{"ip":"2001:db8::5"},"verdict":"deny","rule":7,"hits":1}
+{"client":{"ip":"198.51.100.7"},"verdict":"allow","rule":7}
+{"client":{"ip":"198.51.100.8"},"verdict":"deny"}
+{"client":"198.51.100.9","verdict":"deny","rule":7}
+[1, 2]
+not json"#;
+        let expected = vec![
+            record("2001:db8::5", "events", None, 1),
+            record("203.0.113.5", "edge", Some("22/tcp"), 5),
+        ];
+        assert_eq!(
+            counts(log, |path, chunks| read_mapped(path, &mapping, chunks)),
+            expected
+        );
+    }
+
+    #[test]
+    fn saturates_counts_near_the_maximum() {
+        let mapping = FieldMapping {
+            ip_field: "ip".into(),
+            count_field: Some("n".into()),
+            ..FieldMapping::default()
+        };
+        let log = r#"{"ip":"203.0.113.5","n":3000000000}
+{"ip":"203.0.113.5","n":3000000000}"#;
+        assert_eq!(
+            counts(log, |path, chunks| read_mapped(path, &mapping, chunks)),
+            vec![record("203.0.113.5", "events", None, u32::MAX)]
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use super::{default_feed, open_text, service, Chunks, Tally};
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Reads kernel `iptables`/`nftables` LOG lines and counts them per source IP
+/// and the service given by their `PROTO=` and `DPT=` fields.
+///
+/// The `SRC=` field is searched for anywhere in the line, so plain kernel logs,
+/// `journalctl` text output and `journalctl -o export`/`-o json` dumps all
+/// work. When `log_prefix` is given only lines containing it are counted,
+/// which selects the drop rules' LOG targets when other rules log as well.
+pub fn read(path: &Path, log_prefix: Option<&str>, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    for line in open_text(path)?.lines() {
+        let line = line?;
+        if log_prefix.is_some_and(|prefix| !line.contains(prefix)) {
+            continue;
+        }
+        if let Some(source) = field(&line, "SRC=") {
+            let port = field(&line, "DPT=").and_then(|port| port.parse().ok());
+            tally.add_service(source, &feed, service(field(&line, "PROTO="), port))?;
+        }
+    }
+    tally.finish()
+}
+
+/// Extracts the value of a field such as `SRC=` from a LOG line.
+fn field<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    line.split_whitespace()
+        .find_map(|field| field.strip_prefix(name))
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field, read};
+    use crate::input::read_test_input;
+
+    /// Returns the records read from a kernel log holding `contents`.
+    fn records(
+        contents: &str,
+        log_prefix: Option<&str>,
+    ) -> Vec<(String, String, Option<String>, u32)> {
+        read_test_input("kern.log", contents.as_bytes(), |path, chunks| {
+            read(path, log_prefix, chunks)
+        })
+    }
+
+    /// Returns a record of `count` events from `source` aimed at `service`.
+    fn record(source: &str, service: &str, count: u32) -> (String, String, Option<String>, u32) {
+        (source.into(), "kern".into(), Some(service.into()), count)
+    }
+
+    #[test]
+    fn counts_sources_per_service() {
+        let log = "\
+Jan  5 10:00:00 fw kernel: [123.456] DROP IN=eth0 OUT= SRC=203.0.113.5 DST=198.51.100.10 LEN=60 PROTO=TCP SPT=54321 DPT=22
+Jan  5 10:00:01 fw kernel: [123.457] DROP IN=eth0 OUT= SRC=203.0.113.5 DST=198.51.100.10 LEN=60 PROTO=TCP SPT=54322 DPT=22
+Jan  5 10:00:02 fw kernel: [123.458] DROP IN=eth0 OUT= SRC=2001:0db8:0000:0000:0000:0000:0000:0005 DST=2001:0db8:0000:0000:0000:0000:0000:0010 PROTO=UDP SPT=5353 DPT=53
+Jan  5 10:00:03 fw kernel: [123.459] DROP IN=eth0 OUT= SRC=198.51.100.7 DST=198.51.100.10 PROTO=ICMP TYPE=8 CODE=0
+";
+        let expected = vec![
+            record("198.51.100.7", "icmp", 1),
+            record("2001:0db8:0000:0000:0000:0000:0000:0005", "53/udp", 1),
+            record("203.0.113.5", "22/tcp", 2),
+        ];
+        assert_eq!(records(log, None), expected);
+    }
+
+    #[test]
+    fn finds_fields_in_journal_exports() {
+        let log = r#"{"MESSAGE":"DROP IN=eth0 OUT= SRC=203.0.113.5 DST=198.51.100.10 PROTO=TCP DPT=443 ","_TRANSPORT":"kernel"}"#;
+        assert_eq!(
+            records(log, None),
+            vec![record("203.0.113.5", "443/tcp", 1)]
+        );
+    }
+
+    #[test]
+    fn counts_only_lines_with_the_log_prefix() {
+        let log = "\
+kernel: DROP IN=eth0 SRC=203.0.113.5 PROTO=TCP DPT=22
+kernel: ACCEPT IN=eth0 SRC=203.0.113.6 PROTO=TCP DPT=22
+";
+        assert_eq!(
+            records(log, Some("DROP")),
+            vec![record("203.0.113.5", "22/tcp", 1)]
+        );
+    }
+
+    #[test]
+    fn skips_lines_without_a_source() {
+        let log = "kernel: DROP IN=eth0 SRC= PROTO=TCP DPT=22\nsshd[1]: Accepted publickey\n\n";
+        assert_eq!(records(log, None), Vec::new());
+    }
+
+    #[test]
+    fn extracts_whole_fields_only() {
+        let line = "IN=eth0 MACSRC=00:11:22:33:44:55 SRC=203.0.113.5 DPT=x";
+        assert_eq!(field(line, "SRC="), Some("203.0.113.5"));
+        assert_eq!(field(line, "DPT="), Some("x"));
+        assert_eq!(field(line, "PROTO="), None);
+    }
+}
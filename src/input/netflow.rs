@@ -0,0 +1,561 @@
+use super::{default_feed, is_external, protocol_name, service, Chunks, Tally, ThreatRecord};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Information element (NetFlow v9 field type) of the IP protocol number.
+const PROTOCOL_IDENTIFIER: u16 = 4;
+/// Information element (NetFlow v9 field type) of the destination port.
+const DESTINATION_TRANSPORT_PORT: u16 = 11;
+/// Information element (NetFlow v9 field type) of the IPv4 source address.
+const SOURCE_IPV4_ADDRESS: u16 = 8;
+/// Information element (NetFlow v9 field type) of the IPv6 source address.
+const SOURCE_IPV6_ADDRESS: u16 = 27;
+/// Field length marking an IPFIX variable-length field.
+const VARIABLE_LENGTH: u16 = 65535;
+
+/// Reads an IPFIX file (RFC 5655) or a stream of NetFlow v5 export packets and
+/// counts flows per external source IP and the service given by their
+/// protocol and destination port.
+///
+/// NetFlow v9 packets carry no total length, so they can only be split reliably
+/// at datagram boundaries; use the collector (`--listen`) for v9 exporters.
+pub fn read(path: &Path, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let data = fs::read(path)?;
+    let feed = default_feed(path);
+    let mut decoder = Decoder::default();
+    let mut tally = Tally::new(chunks);
+    let mut rest = data.as_slice();
+    while !rest.is_empty() {
+        let length = match read_u16(rest, 0) {
+            Some(5) => read_u16(rest, 2).map(|count| 24 + 48 * usize::from(count)),
+            Some(10) => read_u16(rest, 2).map(usize::from),
+            _ => None,
+        }
+        .filter(|&length| length > 0 && length <= rest.len())
+        .ok_or("unsupported or truncated NetFlow/IPFIX message")?;
+        let (message, tail) = rest.split_at(length);
+        for flow in decoder.decode(message, IpAddr::V4(Ipv4Addr::UNSPECIFIED)) {
+            tally.add_service(&flow.source.to_string(), &feed, flow.service())?;
+        }
+        rest = tail;
+    }
+    tally.finish()
+}
+
+/// A UDP collector receiving NetFlow v5/v9 or IPFIX export datagrams.
+pub struct Collector {
+    socket: UdpSocket,
+    decoder: Decoder,
+    buffer: Vec<u8>,
+}
+
+impl Collector {
+    /// Binds the collector to `addr`. Receiving blocks for at most `timeout`
+    /// so the caller gets regular opportunities to flush its output.
+    pub fn bind(addr: SocketAddr, timeout: Duration) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(timeout))?;
+        Ok(Self {
+            socket,
+            decoder: Decoder::default(),
+            buffer: vec![0; usize::from(u16::MAX)],
+        })
+    }
+
+    /// Waits for the next export datagram and returns one record per flow from
+    /// an external source, attributed to the exporter's address as its feed.
+    ///
+    /// Returns an empty list if nothing arrived before the timeout or a signal
+    /// interrupted the wait.
+    pub fn recv(&mut self) -> Result<Vec<ThreatRecord>, Box<dyn Error>> {
+        let (length, exporter) = match self.socket.recv_from(&mut self.buffer) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut | ErrorKind::Interrupted
+                ) =>
+            {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let feed = exporter.ip().to_string();
+        let mut records = Vec::new();
+        let mut collect = |chunk: Vec<ThreatRecord>| {
+            records.extend(chunk);
+            Ok(())
+        };
+        let mut tally = Tally::new(Chunks::new(usize::MAX, &mut collect));
+        for flow in self.decoder.decode(&self.buffer[..length], exporter.ip()) {
+            tally.add_service(&flow.source.to_string(), &feed, flow.service())?;
+        }
+        tally.finish()?;
+        Ok(records)
+    }
+
+    /// Moves receiving and decoding to a background thread that hands the
+    /// records of each datagram on through a queue of at most `capacity`
+    /// datagrams.
+    ///
+    /// While the queue is full, e.g. during a slow output upload, the thread
+    /// waits instead of receiving, leaving further datagrams to the socket's
+    /// receive buffer, so memory use stays bounded however far aggregation
+    /// falls behind. A receive error ends the thread and is queued last.
+    pub fn spawn(mut self, capacity: usize) -> Queue {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        let stalls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&stalls);
+        thread::spawn(move || loop {
+            let datagram = self.recv().map_err(|e| e.to_string());
+            if matches!(&datagram, Ok(records) if records.is_empty()) {
+                continue;
+            }
+            let failed = datagram.is_err();
+            let sent = match sender.try_send(datagram) {
+                Err(TrySendError::Full(datagram)) => {
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    sender.send(datagram).is_ok()
+                }
+                result => result.is_ok(),
+            };
+            if failed || !sent {
+                return;
+            }
+        });
+        Queue { receiver, stalls }
+    }
+}
+
+/// Records received by a [`Collector`] running on a background thread, one
+/// entry per datagram.
+pub struct Queue {
+    pub receiver: Receiver<Result<Vec<ThreatRecord>, String>>,
+    stalls: Arc<AtomicUsize>,
+}
+
+impl Queue {
+    /// Returns how often the receiving thread found the queue full since the
+    /// last call, and resets the count.
+    pub fn take_stalls(&self) -> usize {
+        self.stalls.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// The source address, protocol number and destination port of a flow.
+struct Flow {
+    source: IpAddr,
+    protocol: Option<u8>,
+    port: Option<u16>,
+}
+
+impl Flow {
+    fn service(&self) -> Option<String> {
+        // Exporters leave the protocol 0 if they don't know it.
+        let protocol = self.protocol.filter(|&protocol| protocol != 0);
+        service(protocol.map(protocol_name).as_deref(), self.port)
+    }
+}
+
+/// A template field: its information element and length in bytes.
+#[derive(Clone, Copy)]
+struct Field {
+    element: u16,
+    length: u16,
+}
+
+/// Identifies a template: exporter, source ID / observation domain and
+/// template ID.
+type TemplateKey = (IpAddr, u32, u16);
+
+/// Decodes export packets, remembering the v9 and IPFIX templates announced
+/// by each exporter.
+#[derive(Default)]
+struct Decoder {
+    templates: HashMap<TemplateKey, Vec<Field>>,
+}
+
+impl Decoder {
+    /// Decodes one export packet and returns its flows from external source
+    /// addresses. Data sets whose template hasn't been seen yet are skipped.
+    fn decode(&mut self, packet: &[u8], exporter: IpAddr) -> Vec<Flow> {
+        let mut flows = Vec::new();
+        match read_u16(packet, 0) {
+            Some(5) => decode_v5(packet, &mut flows),
+            Some(9) => self.decode_sets(packet, 20, 0, 1, exporter, &mut flows),
+            Some(10) => self.decode_sets(packet, 16, 2, 3, exporter, &mut flows),
+            _ => {}
+        }
+        flows.retain(|flow| is_external(flow.source));
+        flows
+    }
+
+    /// Walks the flow sets of a v9 or IPFIX packet with a header of
+    /// `header_length` bytes, whose last four bytes hold the source ID.
+    fn decode_sets(
+        &mut self,
+        packet: &[u8],
+        header_length: usize,
+        template_set: u16,
+        options_set: u16,
+        exporter: IpAddr,
+        flows: &mut Vec<Flow>,
+    ) {
+        let Some(domain) = read_u32(packet, header_length - 4) else {
+            return;
+        };
+        let ipfix = template_set == 2;
+        let mut offset = header_length;
+        while let (Some(set_id), Some(length)) =
+            (read_u16(packet, offset), read_u16(packet, offset + 2))
+        {
+            let length = usize::from(length);
+            let Some(body) = packet
+                .get(offset + 4..offset + length)
+                .filter(|_| length >= 4)
+            else {
+                return;
+            };
+            if set_id == template_set {
+                self.read_templates(body, (exporter, domain), ipfix);
+            } else if set_id > 255 && set_id != options_set {
+                if let Some(fields) = self.templates.get(&(exporter, domain, set_id)) {
+                    decode_records(body, fields, flows);
+                }
+            }
+            offset += length;
+        }
+    }
+
+    /// Stores the templates of a template set.
+    fn read_templates(&mut self, mut body: &[u8], (exporter, domain): (IpAddr, u32), ipfix: bool) {
+        while let (Some(id), Some(count)) = (read_u16(body, 0), read_u16(body, 2)) {
+            // Template IDs start at 256; anything lower is trailing padding.
+            if id < 256 {
+                return;
+            }
+            let mut offset = 4;
+            let mut fields = Vec::with_capacity(usize::from(count));
+            for _ in 0..count {
+                let (Some(element), Some(length)) =
+                    (read_u16(body, offset), read_u16(body, offset + 2))
+                else {
+                    return;
+                };
+                offset += 4;
+                // Enterprise-specific IPFIX elements carry a private enterprise number.
+                if ipfix && element & 0x8000 != 0 {
+                    offset += 4;
+                }
+                fields.push(Field { element, length });
+            }
+            self.templates.insert((exporter, domain, id), fields);
+            let Some(rest) = body.get(offset..) else {
+                return;
+            };
+            body = rest;
+        }
+    }
+}
+
+/// Decodes the fixed-layout records of a NetFlow v5 packet, up to the first
+/// record cut short.
+fn decode_v5(packet: &[u8], flows: &mut Vec<Flow>) {
+    let count = read_u16(packet, 2).unwrap_or(0);
+    for i in 0..usize::from(count) {
+        let Some(record) = packet.get(24 + i * 48..24 + (i + 1) * 48) else {
+            return;
+        };
+        if let Some(source) = read_u32(record, 0) {
+            flows.push(Flow {
+                source: IpAddr::V4(Ipv4Addr::from(source)),
+                protocol: record.get(38).copied(),
+                port: read_u16(record, 34),
+            });
+        }
+    }
+}
+
+/// Decodes the records of a data set laid out according to `fields`.
+fn decode_records(mut body: &[u8], fields: &[Field], flows: &mut Vec<Flow>) {
+    // Anything shorter than the smallest possible record is set padding.
+    let min_length: usize = fields
+        .iter()
+        .map(|f| match f.length {
+            VARIABLE_LENGTH => 1,
+            length => usize::from(length),
+        })
+        .sum();
+    if min_length == 0 {
+        return;
+    }
+    while body.len() >= min_length {
+        let mut offset = 0;
+        let (mut source, mut protocol, mut port) = (None, None, None);
+        for field in fields {
+            let mut length = usize::from(field.length);
+            if field.length == VARIABLE_LENGTH {
+                let Some(&short) = body.get(offset) else {
+                    return;
+                };
+                offset += 1;
+                length = usize::from(short);
+                if short == 255 {
+                    let Some(long) = read_u16(body, offset) else {
+                        return;
+                    };
+                    offset += 2;
+                    length = usize::from(long);
+                }
+            }
+            let Some(value) = body.get(offset..offset + length) else {
+                return;
+            };
+            match (
+                field.element,
+                <[u8; 4]>::try_from(value),
+                <[u8; 16]>::try_from(value),
+            ) {
+                (SOURCE_IPV4_ADDRESS, Ok(v4), _) => source = Some(IpAddr::from(v4)),
+                (SOURCE_IPV6_ADDRESS, _, Ok(v6)) => source = Some(IpAddr::from(v6)),
+                (PROTOCOL_IDENTIFIER, ..) => protocol = value.first().copied(),
+                (DESTINATION_TRANSPORT_PORT, ..) => port = read_u16(value, 0),
+                _ => {}
+            }
+            offset += length;
+        }
+        if let Some(source) = source {
+            flows.push(Flow {
+                source,
+                protocol,
+                port,
+            });
+        }
+        body = &body[offset..];
+    }
+}
+
+/// Reads a big-endian `u16` at `offset`.
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Reads a big-endian `u32` at `offset`.
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, Chunks, Decoder};
+    use crate::input::read_test_input;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::{env, fs, process};
+
+    /// Address of the exporter the test packets come from.
+    const EXPORTER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    /// Returns the source and service of each flow decoded from `packet`.
+    fn decode(decoder: &mut Decoder, packet: &[u8]) -> Vec<(String, Option<String>)> {
+        decoder
+            .decode(packet, EXPORTER)
+            .iter()
+            .map(|flow| (flow.source.to_string(), flow.service()))
+            .collect()
+    }
+
+    /// Returns a flow from `source` aimed at `service`.
+    fn flow(source: &str, service: &str) -> (String, Option<String>) {
+        (source.to_string(), Some(service.to_string()))
+    }
+
+    /// Returns a set of `id` holding `body`, with its length.
+    fn set(id: u16, body: &[u8]) -> Vec<u8> {
+        let length = u16::try_from(body.len() + 4).expect("test sets are short");
+        [&id.to_be_bytes()[..], &length.to_be_bytes(), body].concat()
+    }
+
+    /// Returns a NetFlow v5 packet of records from `sources`, each a TCP flow
+    /// to port 22.
+    fn v5(sources: &[[u8; 4]]) -> Vec<u8> {
+        let count = u16::try_from(sources.len()).expect("test packets are short");
+        let mut packet = [&5u16.to_be_bytes()[..], &count.to_be_bytes(), &[0; 20]].concat();
+        for source in sources {
+            let mut record = [0; 48];
+            record[..4].copy_from_slice(source);
+            record[34..36].copy_from_slice(&22u16.to_be_bytes());
+            record[38] = 6;
+            packet.extend(record);
+        }
+        packet
+    }
+
+    /// Returns a NetFlow v9 packet from source ID 7 holding `sets`.
+    fn v9(sets: &[Vec<u8>]) -> Vec<u8> {
+        let count = u16::try_from(sets.len()).expect("test packets are short");
+        let header = [
+            &9u16.to_be_bytes()[..],
+            &count.to_be_bytes(),
+            &[0; 12],
+            &[0, 0, 0, 7],
+        ];
+        [&header.concat()[..], &sets.concat()].concat()
+    }
+
+    /// Returns an IPFIX message from observation domain 7 holding `sets`.
+    fn ipfix(sets: &[Vec<u8>]) -> Vec<u8> {
+        let sets = sets.concat();
+        let length = u16::try_from(sets.len() + 16).expect("test messages are short");
+        let header = [
+            &10u16.to_be_bytes()[..],
+            &length.to_be_bytes(),
+            &[0; 8],
+            &[0, 0, 0, 7],
+        ];
+        [&header.concat()[..], &sets].concat()
+    }
+
+    /// A v9 template set announcing template 256: IPv4 source address,
+    /// protocol and destination port.
+    fn v9_template() -> Vec<u8> {
+        set(0, &[1, 0, 0, 3, 0, 8, 0, 4, 0, 4, 0, 1, 0, 11, 0, 2])
+    }
+
+    /// A v9 data set of template 256 with a UDP flow from `203.0.113.5` to
+    /// port 53, one from a private address, and padding.
+    fn v9_data() -> Vec<u8> {
+        set(
+            256,
+            &[203, 0, 113, 5, 17, 0, 53, 10, 0, 0, 1, 17, 0, 53, 0, 0],
+        )
+    }
+
+    #[test]
+    fn decodes_v5_packets() {
+        let packet = v5(&[[203, 0, 113, 5], [10, 0, 0, 1], [198, 51, 100, 7]]);
+        let expected = vec![
+            flow("203.0.113.5", "22/tcp"),
+            flow("198.51.100.7", "22/tcp"),
+        ];
+        assert_eq!(decode(&mut Decoder::default(), &packet), expected);
+    }
+
+    #[test]
+    fn decodes_v9_data_after_its_template() {
+        let packet = v9(&[v9_template(), v9_data()]);
+        let expected = vec![flow("203.0.113.5", "53/udp")];
+        assert_eq!(decode(&mut Decoder::default(), &packet), expected);
+    }
+
+    #[test]
+    fn skips_data_before_its_template() {
+        let mut decoder = Decoder::default();
+        assert_eq!(decode(&mut decoder, &v9(&[v9_data()])), Vec::new());
+        assert_eq!(decode(&mut decoder, &v9(&[v9_template()])), Vec::new());
+        let expected = vec![flow("203.0.113.5", "53/udp")];
+        assert_eq!(decode(&mut decoder, &v9(&[v9_data()])), expected);
+        // Templates are only used for the exporter that announced them.
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        assert!(decoder.decode(&v9(&[v9_data()]), other).is_empty());
+    }
+
+    #[test]
+    fn decodes_ipfix_enterprise_and_variable_length_fields() {
+        // Template 300: an enterprise element of PEN 9, the IPv6 source
+        // address, a variable-length interface name, the destination port and
+        // the protocol.
+        let template = set(
+            2,
+            &[
+                1, 44, 0, 5, 0x80, 100, 0, 4, 0, 0, 0, 9, 0, 27, 0, 16, 0, 82, 255, 255, 0, 11, 0,
+                2, 0, 4, 0, 1,
+            ],
+        );
+        let source = [0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5];
+        let mut data = Vec::new();
+        // A short variable-length field, then one in the three-byte form.
+        for name in [
+            &[4, b'e', b't', b'h', b'0'][..],
+            &[255, 0, 3, b'p', b'p', b'p'],
+        ] {
+            data.extend([0xde, 0xad, 0xbe, 0xef]);
+            data.extend(source);
+            data.extend(name);
+            data.extend([1, 187, 6]);
+        }
+        let message = ipfix(&[template, set(300, &data)]);
+        let expected = vec![
+            flow("2001:db8::5", "443/tcp"),
+            flow("2001:db8::5", "443/tcp"),
+        ];
+        assert_eq!(decode(&mut Decoder::default(), &message), expected);
+    }
+
+    #[test]
+    fn stops_at_truncated_sets() {
+        let mut decoder = Decoder::default();
+        // A set running past the end of the packet is dropped whole.
+        let packet = v9(&[v9_template()]);
+        assert!(decode(&mut decoder, &packet[..packet.len() - 1]).is_empty());
+        assert!(decode(&mut decoder, &v9(&[v9_data()])).is_empty());
+        // Sets before it are still decoded.
+        let packet = v9(&[v9_template(), v9_data(), v9_data()]);
+        let expected = vec![flow("203.0.113.5", "53/udp")];
+        assert_eq!(decode(&mut decoder, &packet[..packet.len() - 1]), expected);
+        // Within a set, records are decoded up to the first one cut short.
+        let truncated = set(256, &[203, 0, 113, 5, 17, 0, 53, 198, 51, 100]);
+        assert_eq!(decode(&mut decoder, &v9(&[truncated])), expected);
+        // Truncated packet and set headers, and v5 records, are skipped.
+        assert!(decode(&mut decoder, &[0, 9, 0, 1, 0]).is_empty());
+        assert!(decode(&mut decoder, &v9(&[vec![1, 0, 0]])).is_empty());
+        assert!(decode(&mut decoder, &v5(&[[203, 0, 113, 5]])[..40]).is_empty());
+    }
+
+    #[test]
+    fn reads_streams_of_v5_packets_and_ipfix_messages() {
+        let contents = [
+            v5(&[[203, 0, 113, 5], [203, 0, 113, 5]]),
+            ipfix(&[
+                set(2, &[1, 0, 0, 2, 0, 8, 0, 4, 0, 11, 0, 2]),
+                set(256, &[198, 51, 100, 7, 0, 80]),
+            ]),
+        ]
+        .concat();
+        let records = read_test_input("flows.ipfix", &contents, read);
+        let expected = vec![
+            ("198.51.100.7".into(), "flows".into(), Some("80".into()), 1),
+            (
+                "203.0.113.5".into(),
+                "flows".into(),
+                Some("22/tcp".into()),
+                2,
+            ),
+        ];
+        assert_eq!(records, expected);
+    }
+
+    #[test]
+    fn rejects_truncated_streams() {
+        let packet = v5(&[[203, 0, 113, 5]]);
+        let path = env::temp_dir().join(format!(
+            "dashboard_location_mapper-{}-truncated.ipfix",
+            process::id()
+        ));
+        fs::write(&path, &packet[..packet.len() - 1]).expect("the input is written");
+        let mut sink = |_| Ok(());
+        let result = read(&path, Chunks::new(1, &mut sink));
+        let _ = fs::remove_file(&path);
+        assert!(result.is_err());
+    }
+}
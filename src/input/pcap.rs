@@ -0,0 +1,118 @@
+mod filter;
+
+use super::{default_feed, is_external, service, Chunks, Tally};
+use etherparse::{NetSlice, SlicedPacket, TransportSlice};
+use filter::{Filter, Packet, Protocol};
+use pcap_file::pcap::PcapReader;
+use pcap_file::pcapng::{Block, PcapNgReader};
+use pcap_file::DataLink;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Magic number opening a pcapng section header block.
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+/// Reads a pcap or pcapng capture and counts packets per external source IP
+/// and the service given by their protocol and destination port.
+///
+/// Only packets from publicly routable sources are counted, which for a
+/// capture taken at the network edge means inbound traffic. `filter` narrows
+/// this further using BPF-style syntax, e.g. `tcp and dst port 22`. Packets on
+/// unsupported link types or that fail to decode are skipped.
+pub fn read(path: &Path, filter: Option<&str>, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let filter = filter.map(Filter::parse).transpose()?;
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    let mut count = |link: DataLink, data: &[u8]| -> Result<(), Box<dyn Error>> {
+        if let Some(packet) = decode(link, data) {
+            if is_external(packet.source) && filter.as_ref().map_or(true, |f| f.matches(&packet)) {
+                let protocol = packet.protocol.map(Protocol::name);
+                let service = service(protocol, packet.destination_port);
+                tally.add_service(&packet.source.to_string(), &feed, service)?;
+            }
+        }
+        Ok(())
+    };
+
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    if magic == PCAPNG_MAGIC {
+        let mut reader = PcapNgReader::new(file)?;
+        let mut links = Vec::new();
+        while let Some(block) = reader.next_block() {
+            let (interface, data) = match block? {
+                Block::SectionHeader(_) => {
+                    links.clear();
+                    continue;
+                }
+                Block::InterfaceDescription(description) => {
+                    links.push(description.linktype);
+                    continue;
+                }
+                Block::EnhancedPacket(packet) => (packet.interface_id, packet.data),
+                Block::SimplePacket(packet) => (0, packet.data),
+                _ => continue,
+            };
+            if let Some(&link) = usize::try_from(interface).ok().and_then(|i| links.get(i)) {
+                count(link, &data)?;
+            }
+        }
+    } else {
+        let mut reader = PcapReader::new(file)?;
+        let link = reader.header().datalink;
+        while let Some(packet) = reader.next_packet() {
+            count(link, &packet?.data)?;
+        }
+    }
+    tally.finish()
+}
+
+/// Decodes the addresses, protocol and ports of a captured frame.
+fn decode(link: DataLink, data: &[u8]) -> Option<Packet> {
+    let sliced = match link {
+        DataLink::ETHERNET => SlicedPacket::from_ethernet(data),
+        DataLink::LINUX_SLL => SlicedPacket::from_linux_sll(data),
+        DataLink::RAW | DataLink::IPV4 | DataLink::IPV6 => SlicedPacket::from_ip(data),
+        _ => return None,
+    }
+    .ok()?;
+
+    let (source, destination) = match sliced.net? {
+        NetSlice::Ipv4(ip) => (
+            IpAddr::V4(ip.header().source_addr()),
+            IpAddr::V4(ip.header().destination_addr()),
+        ),
+        NetSlice::Ipv6(ip) => (
+            IpAddr::V6(ip.header().source_addr()),
+            IpAddr::V6(ip.header().destination_addr()),
+        ),
+    };
+    let (protocol, source_port, destination_port) = match sliced.transport {
+        Some(TransportSlice::Tcp(tcp)) => (
+            Some(Protocol::Tcp),
+            Some(tcp.source_port()),
+            Some(tcp.destination_port()),
+        ),
+        Some(TransportSlice::Udp(udp)) => (
+            Some(Protocol::Udp),
+            Some(udp.source_port()),
+            Some(udp.destination_port()),
+        ),
+        Some(TransportSlice::Icmpv4(_)) => (Some(Protocol::Icmp), None, None),
+        Some(TransportSlice::Icmpv6(_)) => (Some(Protocol::Icmp6), None, None),
+        None => (None, None, None),
+    };
+    Some(Packet {
+        source,
+        destination,
+        protocol,
+        source_port,
+        destination_port,
+    })
+}
@@ -0,0 +1,290 @@
+use ipnetwork::IpNetwork;
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Transport protocols a filter can match on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Icmp6,
+}
+
+impl Protocol {
+    /// The protocol's name in filter expressions.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+            Self::Icmp => "icmp",
+            Self::Icmp6 => "icmp6",
+        }
+    }
+}
+
+/// The packet fields a filter can match on.
+pub struct Packet {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: Option<Protocol>,
+    pub source_port: Option<u16>,
+    pub destination_port: Option<u16>,
+}
+
+/// Which side of a packet a `host`, `net` or `port` primitive applies to.
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    Source,
+    Destination,
+    Either,
+}
+
+/// A parsed packet filter expression.
+///
+/// Supports the commonly used subset of the BPF (`tcpdump`) filter syntax:
+/// the primitives `ip`, `ip6`, `tcp`, `udp`, `icmp`, `icmp6`, and
+/// `[src|dst] host ADDR`, `[src|dst] net CIDR`, `[src|dst] port N`, combined
+/// with `and`/`&&`, `or`/`||`, `not`/`!` and parentheses.
+#[derive(Debug)]
+pub enum Filter {
+    Ipv4,
+    Ipv6,
+    Protocol(Protocol),
+    Host(Direction, IpAddr),
+    Net(Direction, IpNetwork),
+    Port(Direction, u16),
+    Not(Box<Self>),
+    And(Box<Self>, Box<Self>),
+    Or(Box<Self>, Box<Self>),
+}
+
+impl Filter {
+    /// Parses a filter expression.
+    pub fn parse(expression: &str) -> Result<Self, Box<dyn Error>> {
+        let spaced = expression.replace('(', " ( ").replace(')', " ) ");
+        let tokens: Vec<&str> = spaced.split_whitespace().collect();
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let filter = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected `{token}` in packet filter").into());
+        }
+        Ok(filter)
+    }
+
+    /// Returns `true` if `packet` matches the filter.
+    pub fn matches(&self, packet: &Packet) -> bool {
+        match self {
+            Self::Ipv4 => packet.source.is_ipv4(),
+            Self::Ipv6 => packet.source.is_ipv6(),
+            Self::Protocol(protocol) => packet.protocol == Some(*protocol),
+            Self::Host(direction, host) => {
+                direction.applies(packet.source, packet.destination, |ip| ip == *host)
+            }
+            Self::Net(direction, net) => {
+                direction.applies(packet.source, packet.destination, |ip| net.contains(ip))
+            }
+            Self::Port(direction, port) => {
+                direction.applies(packet.source_port, packet.destination_port, |p| {
+                    p == Some(*port)
+                })
+            }
+            Self::Not(inner) => !inner.matches(packet),
+            Self::And(left, right) => left.matches(packet) && right.matches(packet),
+            Self::Or(left, right) => left.matches(packet) || right.matches(packet),
+        }
+    }
+}
+
+impl Direction {
+    /// Tests the source and/or destination value with `test`.
+    fn applies<T: Copy>(self, source: T, destination: T, test: impl Fn(T) -> bool) -> bool {
+        match self {
+            Self::Source => test(source),
+            Self::Destination => test(destination),
+            Self::Either => test(source) || test(destination),
+        }
+    }
+}
+
+/// A recursive descent parser over the filter's tokens.
+struct Parser<'a> {
+    tokens: Vec<&'a str>,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Result<&'a str, Box<dyn Error>> {
+        let token = self.peek().ok_or("unexpected end of packet filter")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Filter, Box<dyn Error>> {
+        let mut filter = self.and()?;
+        while matches!(self.peek(), Some("or" | "||")) {
+            self.position += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, Box<dyn Error>> {
+        let mut filter = self.not()?;
+        while matches!(self.peek(), Some("and" | "&&")) {
+            self.position += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<Filter, Box<dyn Error>> {
+        if matches!(self.peek(), Some("not" | "!")) {
+            self.position += 1;
+            return Ok(Filter::Not(Box::new(self.not()?)));
+        }
+        self.primitive()
+    }
+
+    fn primitive(&mut self) -> Result<Filter, Box<dyn Error>> {
+        let direction = match self.peek() {
+            Some("src") => Direction::Source,
+            Some("dst") => Direction::Destination,
+            _ => Direction::Either,
+        };
+        if !matches!(direction, Direction::Either) {
+            self.position += 1;
+        }
+        let token = self.next()?;
+        let filter = match token {
+            "(" => {
+                let filter = self.or()?;
+                if self.next()? != ")" {
+                    return Err("missing `)` in packet filter".into());
+                }
+                filter
+            }
+            "ip" => Filter::Ipv4,
+            "ip6" => Filter::Ipv6,
+            "tcp" => Filter::Protocol(Protocol::Tcp),
+            "udp" => Filter::Protocol(Protocol::Udp),
+            "icmp" => Filter::Protocol(Protocol::Icmp),
+            "icmp6" => Filter::Protocol(Protocol::Icmp6),
+            "host" => Filter::Host(direction, self.next()?.parse()?),
+            "net" => Filter::Net(direction, self.next()?.parse()?),
+            "port" => Filter::Port(direction, self.next()?.parse()?),
+            _ => return Err(format!("unsupported packet filter primitive `{token}`").into()),
+        };
+        let takes_direction = matches!(
+            filter,
+            Filter::Host(..) | Filter::Net(..) | Filter::Port(..)
+        );
+        if !takes_direction && !matches!(direction, Direction::Either) {
+            return Err(format!("`{token}` can't be qualified with src/dst").into());
+        }
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, Packet, Protocol};
+
+    /// A TCP packet from `203.0.113.5:40000` to `198.51.100.10:22`.
+    fn ssh() -> Packet {
+        Packet {
+            source: "203.0.113.5".parse().expect("the source is an IP"),
+            destination: "198.51.100.10".parse().expect("the destination is an IP"),
+            protocol: Some(Protocol::Tcp),
+            source_port: Some(40000),
+            destination_port: Some(22),
+        }
+    }
+
+    /// An ICMP for IPv6 ping from `2001:db8::5` to `2001:db8::10`.
+    fn ping6() -> Packet {
+        Packet {
+            source: "2001:db8::5".parse().expect("the source is an IP"),
+            destination: "2001:db8::10".parse().expect("the destination is an IP"),
+            protocol: Some(Protocol::Icmp6),
+            source_port: None,
+            destination_port: None,
+        }
+    }
+
+    /// Returns whether `expression` matches [`ssh`] and [`ping6`].
+    fn matches(expression: &str) -> (bool, bool) {
+        let filter = Filter::parse(expression).expect("the filter parses");
+        (filter.matches(&ssh()), filter.matches(&ping6()))
+    }
+
+    #[test]
+    fn matches_primitives() {
+        assert_eq!(matches("ip"), (true, false));
+        assert_eq!(matches("ip6"), (false, true));
+        assert_eq!(matches("tcp"), (true, false));
+        assert_eq!(matches("udp"), (false, false));
+        assert_eq!(matches("icmp6"), (false, true));
+        assert_eq!(matches("host 198.51.100.10"), (true, false));
+        assert_eq!(matches("net 2001:db8::/32"), (false, true));
+        assert_eq!(matches("port 22"), (true, false));
+    }
+
+    #[test]
+    fn matches_directions() {
+        assert_eq!(matches("src host 203.0.113.5"), (true, false));
+        assert_eq!(matches("dst host 203.0.113.5"), (false, false));
+        assert_eq!(matches("dst net 198.51.100.0/24"), (true, false));
+        assert_eq!(matches("src port 22"), (false, false));
+        assert_eq!(matches("dst port 22"), (true, false));
+        assert_eq!(matches("src net 2001:db8::/64"), (false, true));
+    }
+
+    #[test]
+    fn combines_with_precedence_and_parentheses() {
+        assert_eq!(matches("tcp and dst port 22"), (true, false));
+        assert_eq!(matches("tcp && port 80 || icmp6"), (false, true));
+        assert_eq!(matches("udp or tcp and port 22"), (true, false));
+        assert_eq!(matches("(udp or tcp) and not port 22"), (false, false));
+        assert_eq!(matches("not (tcp or udp)"), (false, true));
+        assert_eq!(matches("! ! ip6"), (false, true));
+        assert_eq!(matches("((ip))and(port 22)"), (true, false));
+    }
+
+    #[test]
+    fn rejects_unsupported_expressions() {
+        for expression in ["arp", "tcp[13] & 2 != 0", "ether host 00:11:22:33:44:55"] {
+            assert!(Filter::parse(expression).is_err(), "{expression}");
+        }
+        let error = Filter::parse("src tcp").expect_err("protocols have no direction");
+        assert_eq!(error.to_string(), "`tcp` can't be qualified with src/dst");
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        for expression in [
+            "",
+            "tcp and",
+            "not",
+            "(tcp",
+            "tcp)",
+            "()",
+            "tcp udp",
+            "host",
+            "host 203.0.113",
+            "net 10.0.0.0/33",
+            "port 65536",
+            "port ssh",
+            "src",
+        ] {
+            assert!(Filter::parse(expression).is_err(), "{expression:?}");
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use super::{default_feed, open_text, Chunks, Tally};
+use crate::refang::refang;
+use std::error::Error;
+use std::io::BufRead;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Reads arbitrary text, such as email headers, paste dumps or reports, and
+/// counts every IPv4 and IPv6 address found in it, each time it occurs.
+///
+/// Defanged addresses, such as `192[.]0[.]2[.]1`, are refanged first.
+/// Invalid UTF-8 is replaced rather than rejected, so text with some binary
+/// in it is still scanned.
+pub fn read(path: &Path, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    let mut text = open_text(path)?;
+    let mut line = Vec::new();
+    while text.read_until(b'\n', &mut line)? > 0 {
+        for ip in scan(&refang(&String::from_utf8_lossy(&line))) {
+            tally.add(&ip.to_string(), &feed)?;
+        }
+        line.clear();
+    }
+    tally.finish()
+}
+
+/// Returns the IP addresses in `text`, in order.
+///
+/// Candidates are runs of hex digits, dots and colons not directly preceded
+/// or followed by other word characters, so `v1.2.3.4` or `Foo::bar` aren't
+/// mistaken for addresses. A trailing port, as in `192.0.2.1:8080`, is
+/// dropped, and unspecified and loopback addresses such as `::` or
+/// `127.0.0.1` are skipped.
+pub fn scan(text: &str) -> Vec<IpAddr> {
+    let is_part = |c: char| c.is_ascii_hexdigit() || c == '.' || c == ':';
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut ips = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(is_part) {
+        let before = rest[..start].chars().next_back();
+        let run = &rest[start..];
+        let end = run.find(|c| !is_part(c)).unwrap_or(run.len());
+        let after = run[end..].chars().next();
+        rest = &run[end..];
+        if before.is_some_and(is_word) || after.is_some_and(is_word) {
+            continue;
+        }
+        let candidate = &run[..end];
+        // Dots and colons around an address are punctuation, unless they're
+        // part of it as in `::1`.
+        let trimmed = candidate.trim_matches(|c| c == '.' || c == ':');
+        if let Some(ip) = address(candidate).or_else(|| address(trimmed)) {
+            ips.push(ip);
+        }
+    }
+    ips
+}
+
+/// Parses a candidate run as an address, or as an IPv4 address with a port.
+fn address(candidate: &str) -> Option<IpAddr> {
+    let ip = candidate.parse::<IpAddr>().ok().or_else(|| {
+        let (host, port) = candidate.rsplit_once(':')?;
+        port.parse::<u16>().ok()?;
+        host.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4)
+    })?;
+    Some(ip).filter(|ip| !ip.is_unspecified() && !ip.is_loopback())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, scan};
+    use crate::input::read_test_input;
+    use std::net::IpAddr;
+
+    /// Returns the addresses found in `text`, formatted.
+    fn found(text: &str) -> Vec<String> {
+        scan(text).iter().map(IpAddr::to_string).collect()
+    }
+
+    #[test]
+    fn finds_addresses_with_ports() {
+        assert_eq!(
+            found("GET from 192.0.2.1:8080 and [2001:db8::1]:443"),
+            ["192.0.2.1", "2001:db8::1"]
+        );
+    }
+
+    #[test]
+    fn finds_addresses_set_off_by_punctuation() {
+        assert_eq!(
+            found("Received: from mx (192.0.2.1), src=198.51.100.7; <2001:db8::5>. Last: 203.0.113.9."),
+            ["192.0.2.1", "198.51.100.7", "2001:db8::5", "203.0.113.9"]
+        );
+        assert_eq!(
+            found("::ffff:192.0.2.1 and 2001:db8::"),
+            ["::ffff:192.0.2.1", "2001:db8::"]
+        );
+    }
+
+    #[test]
+    fn skips_addresses_inside_words() {
+        assert!(found("v1.2.3.4 x192.0.2.1 192.0.2.1x 192.0.2.1_a Foo::bar").is_empty());
+    }
+
+    #[test]
+    fn skips_timestamps_and_version_strings() {
+        assert!(found("at 12:34:56 on 2024-01-02, version 1.2.3.4.5 or 10.0.19045").is_empty());
+        assert!(found("2024-01-02T12:34:56Z 1.2.3.4.5:80 192.0.2.1:99999").is_empty());
+    }
+
+    #[test]
+    fn skips_unspecified_and_loopback_addresses() {
+        assert_eq!(
+            found("0.0.0.0 :: 127.0.0.1 ::1 127.1.2.3 192.0.2.1"),
+            ["192.0.2.1"]
+        );
+    }
+
+    #[test]
+    fn counts_defanged_addresses_each_time_they_occur() {
+        let text = "Block 192[.]0[.]2[.]1 and hxxp://198.51.100[.]7:8080/x\n\
+                    again 192.0.2.1, 2001[:]db8[:][:]5\n";
+        let expected = vec![
+            ("192.0.2.1".into(), "paste".into(), None, 2),
+            ("198.51.100.7".into(), "paste".into(), None, 1),
+            ("2001:db8::5".into(), "paste".into(), None, 1),
+        ];
+        assert_eq!(
+            read_test_input("paste.txt", text.as_bytes(), read),
+            expected
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use super::{default_feed, open_text, protocol_name, service, Chunks, Tally};
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Column of `srcaddr` in the default version 2 record format.
+const DEFAULT_SRCADDR: usize = 3;
+/// Column of `dstport` in the default version 2 record format.
+const DEFAULT_DSTPORT: usize = 6;
+/// Column of `protocol` in the default version 2 record format.
+const DEFAULT_PROTOCOL: usize = 7;
+/// Column of `action` in the default version 2 record format.
+const DEFAULT_ACTION: usize = 12;
+
+/// Reads AWS VPC Flow Log records and counts rejected flows per `srcaddr`
+/// and the service given by their `dstport` and `protocol`.
+///
+/// Records are space-delimited. If the file starts with the header line that
+/// S3 and CloudWatch exports include, the columns are located from it, so
+/// custom log formats work too, with or without the `dstport` and `protocol`
+/// columns; otherwise the default version 2 layout is assumed. Gzipped files
+/// are decompressed transparently.
+pub fn read(path: &Path, chunks: Chunks) -> Result<(), Box<dyn Error>> {
+    let feed = default_feed(path);
+    let mut tally = Tally::new(chunks);
+    let (mut srcaddr, mut action) = (DEFAULT_SRCADDR, DEFAULT_ACTION);
+    let (mut dstport, mut protocol) = (Some(DEFAULT_DSTPORT), Some(DEFAULT_PROTOCOL));
+    for (i, line) in open_text(path)?.lines().enumerate() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if i == 0 && fields.contains(&"srcaddr") {
+            let column = |name| fields.iter().position(|&f| f == name);
+            srcaddr = column("srcaddr").ok_or("VPC flow log header lacks srcaddr")?;
+            action = column("action").ok_or("VPC flow log header lacks action")?;
+            (dstport, protocol) = (column("dstport"), column("protocol"));
+            continue;
+        }
+        if fields.get(action) == Some(&"REJECT") {
+            if let Some(source) = fields.get(srcaddr) {
+                let field = |column: Option<usize>| column.and_then(|column| fields.get(column));
+                let protocol = field(protocol)
+                    .and_then(|p| p.parse().ok())
+                    .map(protocol_name);
+                let port = field(dstport).and_then(|port| port.parse().ok());
+                tally.add_service(source, &feed, service(protocol.as_deref(), port))?;
+            }
+        }
+    }
+    tally.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read, Chunks};
+    use crate::input::read_test_input;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    /// Returns the records read from a flow log holding `contents`.
+    fn records(contents: &[u8]) -> Vec<(String, String, Option<String>, u32)> {
+        read_test_input("vpc.log", contents, read)
+    }
+
+    /// Returns a record of `count` flows from `source` aimed at `service`.
+    fn record(
+        source: &str,
+        service: Option<&str>,
+        count: u32,
+    ) -> (String, String, Option<String>, u32) {
+        (source.into(), "vpc".into(), service.map(Into::into), count)
+    }
+
+    /// Default version 2 records: two rejected IPv4 SSH flows, a rejected
+    /// IPv6 DNS flow, an accepted flow and a short line.
+    const DEFAULT: &str = "\
+2 123456789010 eni-1 203.0.113.5 172.31.0.10 40000 22 6 1 40 1620000000 1620000060 REJECT OK
+2 123456789010 eni-1 203.0.113.5 172.31.0.10 40001 22 6 1 40 1620000000 1620000060 REJECT OK
+2 123456789010 eni-1 2001:db8::5 2001:db8::10 5353 53 17 1 60 1620000000 1620000060 REJECT OK
+2 123456789010 eni-1 198.51.100.7 172.31.0.10 40000 443 6 1 40 1620000000 1620000060 ACCEPT OK
+2 123456789010 eni-1 198.51.100.8 172.31.0.10
+";
+
+    #[test]
+    fn reads_default_records() {
+        let expected = vec![
+            record("2001:db8::5", Some("53/udp"), 1),
+            record("203.0.113.5", Some("22/tcp"), 2),
+        ];
+        assert_eq!(records(DEFAULT.as_bytes()), expected);
+    }
+
+    #[test]
+    fn reads_gzipped_records() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(DEFAULT.as_bytes())
+            .expect("the log is compressed");
+        let gzipped = encoder.finish().expect("the log is compressed");
+        assert_eq!(records(&gzipped), records(DEFAULT.as_bytes()));
+    }
+
+    #[test]
+    fn locates_columns_from_the_header() {
+        let log = "\
+action srcaddr dstport
+REJECT 203.0.113.5 22
+REJECT 2001:db8::5 -
+REJECT
+ACCEPT 198.51.100.7 22
+";
+        let expected = vec![
+            record("2001:db8::5", None, 1),
+            record("203.0.113.5", Some("22"), 1),
+        ];
+        assert_eq!(records(log.as_bytes()), expected);
+    }
+
+    #[test]
+    fn rejects_headers_without_the_needed_columns() {
+        let mut sink = |_| Ok(());
+        let path = env::temp_dir().join(format!(
+            "dashboard_location_mapper-{}-vpc-header.log",
+            process::id()
+        ));
+        fs::write(&path, "version srcaddr dstaddr\n").expect("the log is written");
+        let result = read(&path, Chunks::new(1, &mut sink));
+        let _ = fs::remove_file(&path);
+        let error = result.expect_err("the header lacks action");
+        assert_eq!(error.to_string(), "VPC flow log header lacks action");
+    }
+}
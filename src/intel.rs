@@ -0,0 +1,212 @@
+use crate::api_cache::ApiCache;
+use crate::logging;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Host intelligence services `--host-intel` can look up the top offending
+/// IPs in.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Service {
+    /// The Shodan host API, with a Shodan API key.
+    Shodan,
+    /// The Censys Platform host API, with a personal access token.
+    Censys,
+}
+
+impl Service {
+    /// The service's default API base URL.
+    const fn url(self) -> &'static str {
+        match self {
+            Self::Shodan => "https://api.shodan.io",
+            Self::Censys => "https://api.platform.censys.io",
+        }
+    }
+
+    /// The name of the service, as shown in reports.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Shodan => "Shodan",
+            Self::Censys => "Censys",
+        }
+    }
+
+    /// The name its answers are cached under in the `--cache`.
+    pub const fn cache_name(self) -> &'static str {
+        match self {
+            Self::Shodan => "shodan",
+            Self::Censys => "censys",
+        }
+    }
+}
+
+/// Pause between requests, to stay within the rate limits of the free API
+/// plans.
+const REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a host intelligence service knows about an IP.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HostInfo {
+    /// Open ports, in ascending order.
+    pub ports: Vec<u16>,
+    /// Tags or labels, such as `vpn` or `self-signed`.
+    pub tags: Vec<String>,
+}
+
+impl HostInfo {
+    /// Returns the open ports as a comma separated list.
+    pub fn ports_list(&self) -> String {
+        let ports: Vec<String> = self.ports.iter().map(u16::to_string).collect();
+        ports.join(", ")
+    }
+}
+
+/// One of the top offending IPs, with its aggregated count and what the
+/// service knows about it, if anything.
+pub struct Offender {
+    pub ip: IpAddr,
+    pub count: u64,
+    pub info: Option<HostInfo>,
+}
+
+/// The top offending IPs of a report, most threats first.
+pub struct Offenders {
+    /// Name of the service the host details come from.
+    pub service: &'static str,
+    pub hosts: Vec<Offender>,
+}
+
+/// Looks up IPs in a host intelligence service, keeping the answers in the
+/// `--cache`, if given, until they are older than the time to live.
+pub struct HostIntel {
+    service: Service,
+    api_key: String,
+    url: String,
+    cache: Option<Arc<ApiCache>>,
+}
+
+impl HostIntel {
+    pub fn new(
+        service: Service,
+        api_key: String,
+        url: Option<String>,
+        cache: Option<Arc<ApiCache>>,
+    ) -> Self {
+        Self {
+            service,
+            api_key,
+            url: url.unwrap_or_else(|| service.url().to_string()),
+            cache,
+        }
+    }
+
+    /// Looks up each of the `top` IPs and their counts, using cached
+    /// answers that are still fresh and caching the new ones. IPs the
+    /// service knows nothing about are kept without details.
+    pub fn look_up(&self, top: Vec<(IpAddr, u64)>) -> Result<Offenders, Box<dyn Error>> {
+        let (mut requests, mut cached) = (0, 0);
+        let mut hosts = Vec::new();
+        for (ip, count) in top {
+            let info = if let Some(info) = self.cached(ip) {
+                cached += 1;
+                info
+            } else {
+                if requests > 0 {
+                    thread::sleep(REQUEST_INTERVAL);
+                }
+                requests += 1;
+                let info = self.fetch(ip)?;
+                if let Some(cache) = &self.cache {
+                    cache.insert(self.service.cache_name(), &ip.to_string(), json!(info));
+                }
+                info
+            };
+            hosts.push(Offender { ip, count, info });
+        }
+        logging::info(
+            "host intel looked up",
+            &[
+                ("service", json!(self.service.name())),
+                ("requests", json!(requests)),
+                ("cached", json!(cached)),
+            ],
+        );
+        Ok(Offenders {
+            service: self.service.name(),
+            hosts,
+        })
+    }
+
+    /// Returns how many of `ips` have no fresh answer cached, and so would
+    /// be looked up.
+    pub fn pending(&self, ips: impl IntoIterator<Item = IpAddr>) -> usize {
+        ips.into_iter()
+            .filter(|&ip| self.cached(ip).is_none())
+            .count()
+    }
+
+    /// Returns the fresh cached answer about `ip`, if any: the host's details
+    /// or `None` if the service had no record of it.
+    #[allow(clippy::option_option)] // Not cached, or cached as not found.
+    fn cached(&self, ip: IpAddr) -> Option<Option<HostInfo>> {
+        let value = self
+            .cache
+            .as_ref()?
+            .get(self.service.cache_name(), &ip.to_string())?;
+        serde_json::from_value(value).ok()
+    }
+
+    /// Asks the service about `ip`, returning `None` if it has no record of
+    /// it.
+    fn fetch(&self, ip: IpAddr) -> Result<Option<HostInfo>, Box<dyn Error>> {
+        let request = match self.service {
+            Service::Shodan => ureq::get(&format!("{}/shodan/host/{ip}", self.url))
+                .query("key", &self.api_key)
+                .query("minify", "true"),
+            Service::Censys => ureq::get(&format!("{}/v3/global/asset/host/{ip}", self.url))
+                .set("Authorization", &format!("Bearer {}", self.api_key)),
+        };
+        let host: Value = match request.call() {
+            Ok(response) => serde_json::from_reader(response.into_reader())?,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(format!("{} lookup of {ip}: {e}", self.service.name()).into()),
+        };
+        let (ports, tags) = match self.service {
+            Service::Shodan => (host["ports"].clone(), host["tags"].clone()),
+            Service::Censys => {
+                let resource = &host["result"]["resource"];
+                let ports = resource["services"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|service| service["port"].clone())
+                    .collect();
+                (ports, resource["labels"].clone())
+            }
+        };
+        let ports: BTreeSet<u16> = ports
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|port| u16::try_from(port.as_u64()?).ok())
+            .collect();
+        // Censys labels are objects with a `value`, Shodan tags strings.
+        let tags: BTreeSet<String> = tags
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|tag| tag.as_str().or_else(|| tag["value"].as_str()))
+            .map(str::to_string)
+            .collect();
+        Ok(Some(HostInfo {
+            ports: ports.into_iter().collect(),
+            tags: tags.into_iter().collect(),
+        }))
+    }
+}
@@ -0,0 +1,87 @@
+use crate::systemd;
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Formats of the log events written to stderr.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One human-readable `key=value` line per event.
+    Text,
+    /// One JSON object per event, for log pipelines.
+    Json,
+    /// Native journald entries with every field as a structured journal
+    /// field, for running as a systemd service. Falls back to `text` if the
+    /// journal can't be reached.
+    Journald,
+}
+
+/// The format selected with `--log-format`; logging is off until it is set.
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+
+/// Enables logging in `format`, or leaves it off if `format` is `None`.
+pub fn init(format: Option<LogFormat>) {
+    if let Some(format) = format {
+        // Only the first call takes effect; the format never changes mid-run.
+        let _ = FORMAT.set(format);
+    }
+}
+
+/// Logs an informational event with the given fields.
+pub fn info(message: &str, fields: &[(&str, Value)]) {
+    emit("info", message, fields);
+}
+
+/// Logs a warning event with the given fields.
+pub fn warn(message: &str, fields: &[(&str, Value)]) {
+    emit("warn", message, fields);
+}
+
+/// Returns the milliseconds elapsed since `start`, as a log field value.
+pub fn elapsed_ms(start: Instant) -> Value {
+    Value::from(start.elapsed().as_secs_f64() * 1000.0)
+}
+
+/// Writes one event to stderr, or the journal, in the selected format.
+fn emit(level: &str, message: &str, fields: &[(&str, Value)]) {
+    let Some(format) = FORMAT.get() else {
+        return;
+    };
+    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+    match format {
+        LogFormat::Text => text(&timestamp, level, message, fields),
+        LogFormat::Json => {
+            let mut event = Map::new();
+            event.insert("timestamp".into(), timestamp.into());
+            event.insert("level".into(), level.into());
+            event.insert("message".into(), message.into());
+            for (key, value) in fields {
+                event.insert((*key).into(), value.clone());
+            }
+            eprintln!("{}", Value::Object(event));
+        }
+        LogFormat::Journald => {
+            let priority = if level == "warn" { 4 } else { 6 };
+            if systemd::journal(priority, message, fields).is_err() {
+                text(&timestamp, level, message, fields);
+            }
+        }
+    }
+}
+
+/// Writes one event to stderr as a `key=value` text line.
+fn text(timestamp: &str, level: &str, message: &str, fields: &[(&str, Value)]) {
+    let fields = fields.iter().fold(String::new(), |mut line, (key, value)| {
+        line.push(' ');
+        line.push_str(key);
+        line.push('=');
+        match value {
+            Value::String(text) => line.push_str(text),
+            other => line.push_str(&other.to_string()),
+        }
+        line
+    });
+    eprintln!("{timestamp} {} {message}{fields}", level.to_uppercase());
+}
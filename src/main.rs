@@ -1,48 +1,102 @@
-use maxminddb::geoip2;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+mod abuse;
+mod aggregate;
+#[cfg(feature = "duckdb")]
+mod analytics;
+mod api_cache;
+mod cache;
+mod checkpoint;
+mod cli;
+mod cloud;
+mod dbcheck;
+mod dns;
+mod email;
+mod enrich;
+mod feeds;
+mod filter;
+mod geo;
+mod health;
+mod input;
+mod intel;
+mod logging;
+mod manifest;
+mod mock;
+mod nearest;
+mod notify;
+mod output;
+mod overrides;
+mod population;
+mod ranges;
+mod rdap;
+mod record;
+mod refang;
+mod reload;
+mod remote;
+mod resolve;
+mod risk;
+mod rules;
+mod sample;
+mod scanners;
+mod script;
+mod seen;
+mod shutdown;
+mod sketch;
+mod systemd;
+mod telemetry;
+mod transform;
+mod travel;
+mod tui;
+mod url;
+mod window;
+
+use aggregate::{Aggregator, CityData, Group, GroupKey};
+use api_cache::ApiCache;
+use cache::LookupCache;
+use checkpoint::{Checkpoint, Progress};
+use cli::{CacheAction, Cli, Command, GroupBy, Provider};
+use dns::Resolver;
+use email::Mailer;
+use filter::Filter;
+use geo::{Location, Locator};
+use input::{Chunks, Collector, ThreatRecord};
+use intel::{HostIntel, Offenders};
+use manifest::{Manifest, OutputFile, Provenance};
+use output::{Logo, OutputFormat, Report, Template};
+use population::Populations;
+use rdap::Rdap;
+use record::Record;
+use refang::refang;
+use reload::DatabaseWatcher;
+use remote::Remote;
+use risk::CountryWeights;
+use rules::Rules;
+use sample::Sampler;
+use seen::SeenState;
+use serde_json::json;
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
-use std::hash::Hash;
-use std::net::IpAddr;
-
-/// Represents the structure of threat sources loaded from a JSON file.
-#[allow(non_snake_case)]
-#[derive(Serialize, Deserialize, Debug)]
-struct ThreatSources {
-    Count: Vec<u32>,
-    Source: Vec<String>,
-}
-
-/// Holds aggregated data for cities, including the name and total count of threats.
-#[derive(Default)]
-struct CityData {
-    city_name: String,
-    country_name: String,
-    total_count: u32,
-}
-
-/// A struct to use as a key for locations in the `HashMap`, representing latitude and longitude.
-#[derive(Hash, PartialEq, Eq)]
-struct LocationKey {
-    lat: String,
-    lon: String,
-}
+use std::fs;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use transform::Transform;
+use tui::Dashboard;
+use window::Window;
 
 /// The main entry point for the IP geolocation aggregation tool.
 ///
 /// This function performs several key operations:
-/// 1. Reads a list of source IP addresses and their associated threat counts
-///    from a JSON file named `threat_sources.json`.
-/// 2. Uses the `maxminddb` crate to lookup geographical locations (city, country,
-///    latitude, and longitude) for each IP address using the `MaxMind GeoLite2`
-///    City database (`city.mmdb`).
-/// 3. Aggregates threat counts by city, summing counts for IPs mapping to the
-///    same city location.
-/// 4. Outputs the aggregated data to a CSV file named "locations.csv", with
-///    each row representing a unique city location and including the city name,
-///    country name, total aggregated count, latitude, and longitude.
+/// 1. Reads the source IP addresses and their threat counts from the
+///    `--input` files, or collects them live with `--listen`.
+/// 2. Looks up the geographical location (city, country, latitude and
+///    longitude) of each IP address in the database.
+/// 3. Aggregates threat counts by city location and any other `--group-by`
+///    dimensions.
+/// 4. Writes the aggregated data in the `--format` chosen to the `--output`
+///    (`locations.csv` by default).
 ///
 /// IPs with indeterminable geographical locations or missing city names in the
 /// database are skipped.
@@ -51,77 +105,1522 @@ struct LocationKey {
 /// - Propagates errors using Rust's `Result` type for graceful error handling.
 /// - Failures to open files or parse JSON content result in program termination
 ///   with an appropriate error message.
-///
-/// Note:
-/// This function expects `threat_sources.json` and `city.mmdb` to be present and
-/// accessible in the working directory before running.
 fn main() -> Result<(), Box<dyn Error>> {
-    // Open and read the JSON file containing the threat sources.
-    let file = File::open("threat_sources.json")?;
-    let json: Value = serde_json::from_reader(file)?;
-    let threat_sources: ThreatSources = serde_json::from_value(json["Threat Sources"].clone())?;
+    let started = Instant::now();
+    let cli = Cli::parse_with_env();
+    if let Some(command) = cli.command.as_ref().filter(|c| c.is_standalone()) {
+        return Ok(Cli::run(command)?);
+    }
+    logging::init(cli.log_format);
+    telemetry::init(cli.otlp_endpoint.clone());
+    shutdown::install()?;
+    if let Some(addr) = cli.health_listen {
+        health::serve(addr)?;
+    }
+    check_options(&cli)?;
+    if let Some(result) = run_tool(&cli) {
+        return result;
+    }
+    let mailer = (!cli.email_to.is_empty())
+        .then(|| open_mailer(&cli, &cli.email_to))
+        .transpose()?;
+    let mut locator = open_locator(&cli)?;
+    if let Some(result) = run_lookups(&cli, &locator) {
+        return result;
+    }
+    if limit_api_calls(&cli, &locator)? {
+        return Ok(());
+    }
+    let filter = cli.filter_script.as_deref().map(Filter::load).transpose()?;
+    let mut rules = load_rules(&cli)?;
 
-    // Open the MaxMind DB for IP geolocation lookup.
-    let reader = maxminddb::Reader::open_readfile("geoip2/city.mmdb")?;
-
-    // Initialize the CSV writer to write the aggregated location data.
-    let mut wtr = csv::Writer::from_path("locations.csv")?;
-    wtr.write_record(["City Name", "Country Name", "Count", "Lat", "Lon"])?;
-
-    // Use a HashMap to aggregate counts by city location (lat, lon).
-    let mut locations: HashMap<LocationKey, CityData> = HashMap::new();
-
-    // Iterate through each source IP to lookup its geographical location and aggregate counts.
-    for (i, ip_str) in threat_sources.Source.iter().enumerate() {
-        if let Ok(ip) = ip_str.parse::<IpAddr>() {
-            if let Ok(city) = reader.lookup::<geoip2::City>(ip) {
-                if let Some(city_name) = city
-                    .city
-                    .and_then(|c| c.names)
-                    .and_then(|n| n.get("en").copied())
-                {
-                    if let Some(country_name) = city
-                        .country
-                        .and_then(|c| c.names)
-                        .and_then(|n| n.get("en").copied())
-                    {
-                        if let Some(location) = city.location {
-                            if let (Some(lat), Some(lon)) = (location.latitude, location.longitude)
-                            {
-                                // Round lat and lon to 5 decimal places and use as hashable key.
-                                let key = LocationKey {
-                                    lat: format!("{lat:.5}"),
-                                    lon: format!("{lon:.5}"),
-                                };
-                                let count = threat_sources.Count[i];
-                                // Aggregate counts for each unique location.
-                                locations
-                                    .entry(key)
-                                    .and_modify(|e| e.total_count += count)
-                                    .or_insert_with(|| CityData {
-                                        city_name: city_name.to_string(),
-                                        country_name: country_name.to_string(),
-                                        total_count: count,
-                                    });
-                            }
-                        }
-                    }
+    // Aggregate counts by city location (lat, lon) and the requested dimensions.
+    let mut aggregator = cli.approximate.map_or_else(Aggregator::default, |top| {
+        Aggregator::approximate(top, cli.sketch_width, cli.sketch_depth)
+    });
+
+    let mut dashboard = matches!(cli.command, Some(Command::Tui))
+        .then(Dashboard::start)
+        .transpose()?;
+
+    if let Some(addr) = cli.listen {
+        return collect(
+            &cli,
+            addr,
+            &mut locator,
+            filter.as_ref(),
+            &mut aggregator,
+            rules.as_mut(),
+            dashboard.as_mut(),
+        );
+    }
+
+    let (mut checkpoint, progress) = resume(&cli, &mut aggregator)?;
+
+    let partial = read_inputs(
+        &cli,
+        &locator,
+        filter.as_ref(),
+        &mut aggregator,
+        checkpoint.as_mut(),
+        progress,
+        dashboard.as_mut(),
+    )?;
+
+    if matches!(cli.command, Some(Command::Nearest { .. })) {
+        return print_nearest(&cli, &aggregator);
+    }
+    if matches!(cli.command, Some(Command::ReportAbuse { .. })) {
+        return report_abuse(&cli, &mut locator, &aggregator);
+    }
+    let seen = load_sightings(&cli, &mut aggregator)?;
+    #[cfg(feature = "duckdb")]
+    if let (Some(path), Some(feed)) = (&cli.duckdb, &cli.anomalies) {
+        detect_anomalies(&cli, path, feed, &mut aggregator)?;
+    }
+    let (body, content_type) = write_output(&cli, &aggregator, &locator, partial)?;
+    save_sightings(&cli, seen.as_ref())?;
+    if let Some(mailer) = &mailer {
+        email_output(&cli, mailer, &aggregator, partial, &body, content_type)?;
+    }
+    notify(&cli, &aggregator, partial)?;
+    if let Some(rules) = rules.as_mut() {
+        alert(&cli, rules, &aggregator)?;
+    }
+    #[cfg(feature = "duckdb")]
+    if let Some(path) = &cli.duckdb {
+        analyze(&cli, path, &aggregator)?;
+    }
+    if let Some(checkpoint) = checkpoint.as_ref().filter(|_| !partial) {
+        checkpoint.remove()?;
+    }
+    if let Some(dashboard) = dashboard.as_mut().filter(|_| !partial) {
+        dashboard.wait(format!("finished, wrote {}", cli.output.display()))?;
+    }
+    logging::info(
+        "run finished",
+        &[
+            ("inputs", json!(cli.inputs.len())),
+            ("groups", json!(aggregator.len())),
+            ("partial", json!(partial)),
+            ("elapsed_ms", logging::elapsed_ms(started)),
+        ],
+    );
+    telemetry::export("run");
+    if partial {
+        return Err("interrupted before all inputs were aggregated, wrote partial output".into());
+    }
+    Ok(())
+}
+
+/// Opens the `--checkpoint`, if given, and resumes from it if it was saved
+/// by an earlier attempt at the same run, restoring its partial aggregates
+/// into `aggregator`. Returns the checkpoint and the progress to resume from.
+fn resume(
+    cli: &Cli,
+    aggregator: &mut Aggregator,
+) -> Result<(Option<Checkpoint>, Progress), Box<dyn Error>> {
+    let checkpoint = cli
+        .checkpoint
+        .as_deref()
+        .map(|path| -> Result<_, Box<dyn Error>> {
+            let interval = Duration::from_secs(cli.checkpoint_interval);
+            Ok(Checkpoint::new(path, cli.checkpoint_run()?, interval))
+        })
+        .transpose()?;
+    let mut progress = Progress::default();
+    if let Some((saved, partial)) = checkpoint
+        .as_ref()
+        .map(Checkpoint::load)
+        .transpose()?
+        .flatten()
+    {
+        logging::info(
+            "resuming from checkpoint",
+            &[
+                ("inputs_done", json!(saved.inputs_done)),
+                ("chunks_done", json!(saved.chunks_done)),
+            ],
+        );
+        progress = saved;
+        *aggregator = partial;
+    }
+    Ok((checkpoint, progress))
+}
+
+/// Estimates the API calls the enrichments would make for the inputs, per
+/// service, before any is made: a pass over the inputs counts the threats
+/// of each source IP, then
+/// - RDAP is called at most once per IP outside the netblocks in the
+///   `--cache`, fewer when IPs share a netblock;
+/// - the `--host-intel` service is called for those of its top IPs without
+///   a fresh cached answer.
+///
+/// IPs that turn out not to be located or are filtered out aren't looked up
+/// either, so the estimate is an upper bound.
+fn estimate_api_calls<S: AsRef<[u8]> + Sync>(
+    cli: &Cli,
+    locator: &Locator<S>,
+) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    let start = Instant::now();
+    let sources = count_sources(cli, locator)?;
+    let mut estimate = Vec::new();
+    if cli.rdap || matches!(cli.command, Some(Command::ReportAbuse { .. })) {
+        let ips = || sources.keys().copied();
+        let pending = locator.rdap.as_ref().map_or_else(
+            || {
+                let rdap = Rdap::new(None, 1, locator.api_cache.clone());
+                rdap.pending(ips())
+            },
+            |rdap| rdap.pending(ips()),
+        );
+        estimate.push((rdap::CACHE_NAME.to_string(), pending));
+    }
+    if let (Some(service), Some(intel)) =
+        (cli.host_intel, host_intel(cli, locator.api_cache.as_ref()))
+    {
+        let top = aggregate::top_sources(&sources, cli.host_intel_top);
+        let pending = intel.pending(top.into_iter().map(|(ip, _)| ip));
+        estimate.push((service.cache_name().to_string(), pending));
+    }
+    let mut fields = vec![("ips", json!(sources.len()))];
+    fields.extend(
+        estimate
+            .iter()
+            .map(|(service, calls)| (service.as_str(), json!(calls))),
+    );
+    fields.push((
+        "total",
+        json!(estimate.iter().map(|(_, calls)| calls).sum::<usize>()),
+    ));
+    finish_phase("API calls estimated", start, fields);
+    Ok(estimate)
+}
+
+/// Reads the inputs and counts the threats of each source IP, as they would
+/// be aggregated, without locating them.
+fn count_sources<S: AsRef<[u8]> + Sync>(
+    cli: &Cli,
+    locator: &Locator<S>,
+) -> Result<HashMap<IpAddr, u64>, Box<dyn Error>> {
+    let input_options = cli.input_options();
+    let mut sources: HashMap<IpAddr, u64> = HashMap::new();
+    for path in &cli.inputs {
+        let mut sink = |mut records: Vec<ThreatRecord>| -> Result<(), Box<dyn Error>> {
+            prepare(cli, locator, &mut records, &mut AggregateStats::default());
+            for record in records {
+                let Ok(ip) = refang(&record.source).parse::<IpAddr>() else {
+                    continue;
+                };
+                if !(cli.exclude_known_scanners && locator.known_scanner(ip).is_some()) {
+                    *sources.entry(ip).or_default() += u64::from(record.count);
                 }
             }
+            Ok(())
+        };
+        let chunks = Chunks::new(cli.chunk_size, &mut sink);
+        input::read(path, cli.input_format, &input_options, chunks)?;
+    }
+    Ok(sources)
+}
+
+/// Prints the API calls estimated per service and in total, for
+/// `--estimate-api-calls`.
+fn print_estimate(estimate: &[(String, usize)]) -> Result<(), Box<dyn Error>> {
+    let mut stdout = std::io::stdout().lock();
+    for (service, calls) in estimate {
+        writeln!(stdout, "{service}: at most {calls}")?;
+    }
+    let total: usize = estimate.iter().map(|(_, calls)| calls).sum();
+    writeln!(stdout, "total: at most {total}")?;
+    Ok(())
+}
+
+/// Estimates the API calls the enrichments would make, if any are enabled,
+/// and fails if they exceed `--max-api-calls`. Returns `true` if the
+/// estimate was printed for `--estimate-api-calls` and the run is over.
+fn limit_api_calls<S: AsRef<[u8]> + Sync>(
+    cli: &Cli,
+    locator: &Locator<S>,
+) -> Result<bool, Box<dyn Error>> {
+    if !cli.uses_apis() || matches!(cli.command, Some(Command::FetchFeed { .. })) {
+        return Ok(false);
+    }
+    let estimate = estimate_api_calls(cli, locator)?;
+    if cli.estimate_api_calls {
+        print_estimate(&estimate)?;
+        return Ok(true);
+    }
+    let total: usize = estimate.iter().map(|(_, calls)| calls).sum();
+    match cli.max_api_calls {
+        Some(max) if total > max => Err(format!(
+            "the API enrichments could make up to {total} calls, more than --max-api-calls {max}"
+        )
+        .into()),
+        _ => Ok(false),
+    }
+}
+
+/// Runs the commands that look up IPs without aggregating, returning `None`
+/// for the others, then saves the `--cache`.
+fn run_lookups<S: AsRef<[u8]>>(
+    cli: &Cli,
+    locator: &Locator<S>,
+) -> Option<Result<(), Box<dyn Error>>> {
+    let result = match &cli.command {
+        Some(Command::Lookup { ip, json }) => print_record(locator, *ip, *json),
+        Some(Command::Resolve { input, output }) => {
+            resolve_list(cli, locator, input, output.as_deref())
+        }
+        Some(Command::Travel { .. }) => find_travel(cli, locator),
+        _ => return None,
+    };
+    Some(result.and_then(|()| save_api_cache(locator)))
+}
+
+/// Prints the aggregated locations nearest to the given coordinates, for the
+/// `nearest` command.
+fn print_nearest(cli: &Cli, aggregator: &Aggregator) -> Result<(), Box<dyn Error>> {
+    let Some(Command::Nearest {
+        coordinates, count, ..
+    }) = &cli.command
+    else {
+        return Ok(());
+    };
+    let places = nearest::locations(aggregator);
+    let stdout = std::io::stdout().lock();
+    Ok(nearest::write(*coordinates, places, *count, stdout)?)
+}
+
+/// Groups the offending source IPs by the abuse contact of their netblock
+/// and writes a report for each, emailing it to the contact with `--send`,
+/// for the `report-abuse` command. The netblocks are looked up with the
+/// `--rdap` client if enabled, so those already known aren't looked up
+/// again.
+fn report_abuse<S: AsRef<[u8]>>(
+    cli: &Cli,
+    locator: &mut Locator<S>,
+    aggregator: &Aggregator,
+) -> Result<(), Box<dyn Error>> {
+    let Some(Command::ReportAbuse {
+        output_dir,
+        template,
+        send,
+    }) = &cli.command
+    else {
+        return Ok(());
+    };
+    let start = Instant::now();
+    let template = abuse::Template::load(template.as_deref())?;
+    let mailer = send.then(|| open_mailer(cli, &[])).transpose()?;
+    let rdap = locator.rdap.take().unwrap_or_else(|| {
+        Rdap::new(
+            cli.rdap_url.clone(),
+            cli.rdap_rate,
+            locator.api_cache.clone(),
+        )
+    });
+    let sources = aggregator.top_sources(usize::MAX);
+    let ips = sources.len();
+    let (reports, without_contact) = abuse::group(&rdap, sources);
+    fs::create_dir_all(output_dir)?;
+    let generated_at = chrono::Utc::now()
+        .format("%Y-%m-%d %H:%M:%S UTC")
+        .to_string();
+    for report in &reports {
+        let text = template.render(report, cli.email_from.as_deref(), &generated_at)?;
+        fs::write(output_dir.join(template.file_name(&report.contact)), &text)?;
+        if let Some(mailer) = &mailer {
+            mailer.send_text(&report.contact, &report.subject(), text)?;
+        }
+    }
+    finish_phase(
+        "abuse reports written",
+        start,
+        vec![
+            ("path", json!(output_dir)),
+            ("ips", json!(ips)),
+            ("contacts", json!(reports.len())),
+            ("ips_without_contact", json!(without_contact)),
+            ("emailed", json!(send)),
+        ],
+    );
+    save_api_cache(locator)
+}
+
+/// Runs the commands that work on the `--cache` or the database itself
+/// rather than on inputs, returning `None` for the others.
+fn run_tool(cli: &Cli) -> Option<Result<(), Box<dyn Error>>> {
+    match &cli.command {
+        Some(Command::Cache { action }) => Some(manage_cache(cli, action)),
+        Some(Command::CheckDb { samples }) => Some(check_database(cli, *samples)),
+        _ => None,
+    }
+}
+
+/// Checks the `--database` and prints what was found, for the `check-db`
+/// command. Fails if it can't be opened or has problems.
+fn check_database(cli: &Cli, samples: u32) -> Result<(), Box<dyn Error>> {
+    let path = cli.database_path();
+    let reader = open_database(cli).map_err(|e| format!("{}: {e}", path.display()))?;
+    let centres = cli.embedded_db.then(geo::country_centres);
+    let check = dbcheck::check(&reader, samples, centres.as_ref());
+    check.write(path, std::io::stdout().lock())?;
+    if check.problems().is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} has problems", path.display()).into())
+    }
+}
+
+/// Prints or purges the `--cache` entries, for the `cache` command.
+fn manage_cache(cli: &Cli, action: &CacheAction) -> Result<(), Box<dyn Error>> {
+    let path = cli.cache.as_deref().ok_or("cache requires --cache")?;
+    let cache = ApiCache::open(path, cli.cache_ttls())?;
+    match action {
+        CacheAction::Show { service } => cache.write(service.as_deref(), std::io::stdout().lock()),
+        CacheAction::Purge { service, expired } => {
+            let removed = cache.purge(service.as_deref(), *expired);
+            cache.save()?;
+            println!("purged {removed} cached answers");
+            Ok(())
+        }
+    }
+}
+
+/// Writes the answers of external APIs looked up so far back to the
+/// `--cache`, if given.
+fn save_api_cache<S: AsRef<[u8]>>(locator: &Locator<S>) -> Result<(), Box<dyn Error>> {
+    locator
+        .api_cache
+        .as_ref()
+        .map_or(Ok(()), |cache| cache.save())
+}
+
+/// Prints everything known about `ip`, for the `lookup` command.
+fn print_record<S: AsRef<[u8]>>(
+    locator: &Locator<S>,
+    ip: IpAddr,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let location = locator
+        .locate(ip)
+        .ok_or_else(|| format!("{ip} is not in the database"))?;
+    let record = Record::new(ip, location, locator.details(ip));
+    record.write(json, std::io::stdout().lock())
+}
+
+/// Looks up every IP listed in `input` and writes them with their locations
+/// to `output`, or stdout, for the `resolve` command.
+fn resolve_list<S: AsRef<[u8]>>(
+    cli: &Cli,
+    locator: &Locator<S>,
+    input: &Path,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn Error>> {
+    let start = Instant::now();
+    let list = input::open_text(input)?;
+    let (listed, located) = match output {
+        Some(path) => {
+            let mut body = Vec::new();
+            let counts = resolve::write(locator, list, &mut body)?;
+            deliver(cli, path, &body, "text/csv")?;
+            counts
+        }
+        None => resolve::write(locator, list, std::io::stdout().lock())?,
+    };
+    finish_phase(
+        "resolve",
+        start,
+        vec![("listed", json!(listed)), ("located", json!(located))],
+    );
+    Ok(())
+}
+
+/// Reads the inputs as authentication logs and prints the impossible travel
+/// found in them, for the `travel` command.
+fn find_travel<S: AsRef<[u8]>>(cli: &Cli, locator: &Locator<S>) -> Result<(), Box<dyn Error>> {
+    let Some(Command::Travel {
+        user_field,
+        time_field,
+        max_speed,
+        min_distance,
+        json,
+    }) = &cli.command
+    else {
+        return Ok(());
+    };
+    let start = Instant::now();
+    let mapping = cli.input_options().field_mapping;
+    let mut logins = Vec::new();
+    for path in &cli.inputs {
+        logins.extend(input::read_logins(path, &mapping, user_field, time_field)?);
+    }
+    let read = logins.len();
+    let limits = travel::Limits {
+        max_speed: *max_speed,
+        min_distance: *min_distance,
+    };
+    let findings = travel::find(locator, logins, &limits);
+    travel::write(&findings, *json, std::io::stdout().lock())?;
+    finish_phase(
+        "travel",
+        start,
+        vec![("logins", json!(read)), ("findings", json!(findings.len()))],
+    );
+    Ok(())
+}
+
+/// Rejects invalid option combinations, and fails on a broken template,
+/// script or logo before aggregating anything.
+fn check_options(cli: &Cli) -> Result<(), Box<dyn Error>> {
+    if !cli.groups_by(GroupBy::City) {
+        return Err("--group-by must include `city`".into());
+    }
+    if let Some(path) = &cli.template {
+        Template::load(path)?;
+    }
+    if let Some(path) = &cli.script {
+        Transform::load(path)?;
+    }
+    if let Some(path) = &cli.report_logo {
+        Logo::load(path)?;
+    }
+    load_rules(cli)?;
+    if let Some(path) = &cli.seen_state {
+        SeenState::load(path)?;
+    }
+    if cli.format == OutputFormat::Arcs && cli.target.is_none() {
+        return Err("--format arcs requires --target".into());
+    }
+    if matches!(cli.command, Some(Command::Nearest { .. })) && cli.listen.is_some() {
+        return Err("nearest can't be combined with --listen".into());
+    }
+    if matches!(cli.command, Some(Command::FetchFeed { .. })) && cli.listen.is_some() {
+        return Err("fetch-feed can't be combined with --listen".into());
+    }
+    if cli.estimate_api_calls && !cli.uses_apis() {
+        return Err("--estimate-api-calls requires --rdap, --host-intel or report-abuse".into());
+    }
+    if cli.max_api_calls.is_some() || cli.estimate_api_calls {
+        if cli.listen.is_some() {
+            return Err(
+                "--max-api-calls and --estimate-api-calls can't be combined with --listen".into(),
+            );
+        }
+        if matches!(cli.command, Some(Command::FetchFeed { .. })) {
+            return Err(
+                "--max-api-calls and --estimate-api-calls can't be combined with fetch-feed".into(),
+            );
+        }
+    }
+    if let Some(Command::ReportAbuse { template, send, .. }) = &cli.command {
+        if cli.listen.is_some() {
+            return Err("report-abuse can't be combined with --listen".into());
+        }
+        if *send && cli.email_from.is_none() {
+            return Err("report-abuse --send requires --email-from".into());
+        }
+        abuse::Template::load(template.as_deref())?;
+    }
+    if cli.host_intel.is_some() {
+        if !matches!(cli.format, OutputFormat::Markdown | OutputFormat::Pdf)
+            || cli.template.is_some()
+        {
+            return Err("--host-intel requires --format markdown or pdf".into());
+        }
+        if cli.host_intel_key.is_none() {
+            return Err("--host-intel requires --host-intel-key".into());
+        }
+        if cli.approximate.is_some() {
+            return Err("--host-intel can't be combined with --approximate".into());
+        }
+    }
+    if cli.window.is_some() && cli.listen.is_none() {
+        return Err("--window only applies to the collector (--listen)".into());
+    }
+    if cli.window.is_some() && cli.approximate.is_some() {
+        return Err("--window can't be combined with --approximate".into());
+    }
+    if let (Some(window), Some(slide)) = (cli.window, cli.slide) {
+        if slide > window {
+            return Err("--slide can't be longer than --window".into());
+        }
+        if window.as_secs() % slide.as_secs() != 0 {
+            return Err("--window must be a whole number of --slide".into());
+        }
+    }
+    if cli.approximate.is_some() && cli.checkpoint.is_some() {
+        return Err("--approximate can't be combined with --checkpoint".into());
+    }
+    if cfg!(not(feature = "embedded-db")) && cli.embedded_db {
+        return Err("--embedded-db requires building with `--features embedded-db`".into());
+    }
+    if cfg!(not(feature = "duckdb")) && cli.duckdb.is_some() {
+        return Err("--duckdb requires building with `--features duckdb`".into());
+    }
+    if cfg!(not(feature = "arrow")) && cli.format == OutputFormat::Arrow {
+        return Err("--format arrow requires building with `--features arrow`".into());
+    }
+    if cfg!(not(feature = "mvt")) && cli.format == OutputFormat::Mvt {
+        return Err("--format mvt requires building with `--features mvt`".into());
+    }
+    Ok(())
+}
+
+/// Loads the `--rules`, if given, for the collector or a single run.
+fn load_rules(cli: &Cli) -> Result<Option<Rules>, Box<dyn Error>> {
+    cli.rules
+        .as_deref()
+        .map(|path| Rules::load(path, cli.listen.is_some()))
+        .transpose()
+}
+
+/// Opens the database IPs are looked up in: the `--database`, the embedded
+/// one, or one built from the `--mock-map`.
+fn open_database(cli: &Cli) -> Result<maxminddb::Reader<Vec<u8>>, Box<dyn Error>> {
+    match (cli.provider, &cli.mock_map) {
+        (Provider::Mock, Some(path)) => mock::load(path),
+        _ if cli.embedded_db => geo::embedded_db(),
+        _ => Ok(maxminddb::Reader::open_readfile(&cli.database)?),
+    }
+}
+
+/// Opens the `MaxMind` databases and loads the reference data enabled on the
+/// command line.
+fn open_locator(cli: &Cli) -> Result<Locator<Vec<u8>>, Box<dyn Error>> {
+    // Open the MaxMind DB for IP geolocation lookup.
+    let reader = open_database(cli)?;
+    let overlays = cli
+        .overlay_databases
+        .iter()
+        .map(maxminddb::Reader::open_readfile)
+        .collect::<Result<_, _>>()?;
+    let overrides = cli.overrides.as_deref().map(overrides::load).transpose()?;
+    let populations = cli
+        .population
+        .as_deref()
+        .map(Populations::load)
+        .transpose()?;
+    let weights = cli
+        .country_weights
+        .as_deref()
+        .map(CountryWeights::load)
+        .transpose()?;
+    let scanners = (cli.exclude_known_scanners || cli.tag_known_scanners)
+        .then(|| scanners::load(cli.known_scanners.as_deref()))
+        .transpose()?;
+    let clouds = (!cli.cloud_ranges.is_empty())
+        .then(|| cloud::load(&cli.cloud_ranges))
+        .transpose()?;
+    let api_cache = cli
+        .cache
+        .as_deref()
+        .map(|path| ApiCache::open(path, cli.cache_ttls()))
+        .transpose()?
+        .map(Arc::new);
+    Ok(Locator {
+        reader,
+        overlays,
+        overrides,
+        populations,
+        weights,
+        scanners,
+        clouds,
+        enrichers: enrich::load(&cli.enrichers)?,
+        resolver: cli.resolve_hostnames.then(|| {
+            let api_cache = api_cache.clone();
+            Resolver::new(cli.resolve_timeout, cli.resolve_concurrency, api_cache)
+        }),
+        rdap: cli.rdap.then(|| {
+            let api_cache = api_cache.clone();
+            Rdap::new(cli.rdap_url.clone(), cli.rdap_rate, api_cache)
+        }),
+        api_cache,
+        country_centres: cli.embedded_db.then(geo::country_centres),
+    })
+}
+
+/// Reads and aggregates the inputs, starting from `progress` and saving it
+/// to `checkpoint` as it goes. Returns `true` if a shutdown was requested
+/// before all inputs were aggregated.
+///
+/// Inputs are read and aggregated chunk by chunk, so only one chunk of
+/// records is held at a time. Chunks aggregated before a resumed checkpoint
+/// are read again but skipped.
+fn read_inputs<S: AsRef<[u8]> + Sync>(
+    cli: &Cli,
+    locator: &Locator<S>,
+    filter: Option<&Filter>,
+    aggregator: &mut Aggregator,
+    mut checkpoint: Option<&mut Checkpoint>,
+    mut progress: Progress,
+    mut dashboard: Option<&mut Dashboard>,
+) -> Result<bool, Box<dyn Error>> {
+    if let Some(Command::FetchFeed { .. }) = &cli.command {
+        return fetch_feed(cli, locator, filter, aggregator).map(|()| false);
+    }
+    let input_options = cli.input_options();
+    for path in cli.inputs.iter().skip(progress.inputs_done) {
+        let phase = Instant::now();
+        let mut stats = AggregateStats::default();
+        let skip = progress.chunks_done;
+        let mut chunk = 0;
+        let mut sink = |records: Vec<ThreatRecord>| -> Result<(), Box<dyn Error>> {
+            if shutdown::requested() {
+                return Err("interrupted".into());
+            }
+            chunk += 1;
+            if chunk <= skip {
+                return Ok(());
+            }
+            progress.records += records.len();
+            if let Some(max) = cli.max_ips.filter(|&max| progress.records > max) {
+                return Err(
+                    format!("inputs hold more than --max-ips {max} source IP records").into(),
+                );
+            }
+            logging::info(
+                "chunk aggregated",
+                &[
+                    ("path", json!(path)),
+                    ("chunk", json!(chunk)),
+                    ("records", json!(records.len())),
+                ],
+            );
+            aggregate(cli, aggregator, locator, filter, None, records, &mut stats)?;
+            progress.chunks_done = chunk;
+            if let Some(dashboard) = dashboard.as_deref_mut() {
+                let phase = format!("reading {} chunk {chunk}", path.display());
+                dashboard.show(phase, aggregator, stats.counts())?;
+            }
+            if let Some(checkpoint) = checkpoint.as_deref_mut() {
+                checkpoint.save_due(progress, aggregator)?;
+            }
+            Ok(())
+        };
+        let chunks = Chunks::new(cli.chunk_size, &mut sink);
+        let result = input::read(path, cli.input_format, &input_options, chunks);
+        stats.report(
+            "input processed",
+            phase,
+            vec![("path", json!(path)), ("chunks", json!(chunk))],
+        );
+        if shutdown::requested() {
+            // Stop at the chunk boundary, keeping what has been aggregated.
+            logging::warn(
+                "interrupted, writing partial output",
+                &[("path", json!(path))],
+            );
+            if let Some(checkpoint) = checkpoint {
+                checkpoint.save(progress, aggregator)?;
+            }
+            return Ok(true);
+        }
+        result?;
+
+        progress.inputs_done += 1;
+        progress.chunks_done = 0;
+        if let Some(checkpoint) = checkpoint.as_deref_mut() {
+            checkpoint.save(progress, aggregator)?;
+        }
+    }
+    Ok(false)
+}
+
+/// Fetches the indicators of the feed selected by the `fetch-feed` command
+/// and aggregates them in place of the inputs.
+fn fetch_feed<S: AsRef<[u8]> + Sync>(
+    cli: &Cli,
+    locator: &Locator<S>,
+    filter: Option<&Filter>,
+    aggregator: &mut Aggregator,
+) -> Result<(), Box<dyn Error>> {
+    let Some(Command::FetchFeed {
+        feed,
+        days,
+        api_key,
+        url,
+    }) = &cli.command
+    else {
+        return Ok(());
+    };
+    let phase = Instant::now();
+    let records = feeds::fetch(*feed, *days, api_key.as_deref(), url.as_deref())?;
+    let mut stats = AggregateStats::default();
+    aggregate(cli, aggregator, locator, filter, None, records, &mut stats)?;
+    stats.report(
+        "feed fetched",
+        phase,
+        vec![("feed", json!(format!("{feed:?}").to_lowercase()))],
+    );
+    Ok(())
+}
+
+/// Logs the end of a pipeline phase that started at `start` and records it
+/// as a telemetry span.
+fn finish_phase(name: &str, start: Instant, mut fields: Vec<(&str, serde_json::Value)>) {
+    telemetry::span(name, start, &fields);
+    fields.push(("elapsed_ms", logging::elapsed_ms(start)));
+    logging::info(name, &fields);
+}
+
+/// Counts of how the records of an aggregation pass were handled.
+#[derive(Default)]
+struct AggregateStats {
+    records: usize,
+    located: usize,
+    /// Records whose source isn't a valid IP address.
+    invalid_ip: usize,
+    /// Records whose IP has no city location in the database.
+    not_located: usize,
+    /// Records from known scanners, with `--exclude-known-scanners`.
+    known_scanner: usize,
+    /// Records dropped by the `--filter-script`.
+    filtered: usize,
+    /// Records left out by `--sample`, which are never looked up.
+    sampled_out: usize,
+    /// Records whose hostname source was resolved to an IP.
+    resolved: usize,
+    lookups: telemetry::Lookups,
+    /// Lookups answered from and missing in the collector's lookup cache.
+    cache_hits: usize,
+    cache_misses: usize,
+}
+
+impl AggregateStats {
+    /// Returns how many records had each outcome.
+    const fn outcomes(&self) -> [(&'static str, usize); 6] {
+        [
+            ("located", self.located),
+            ("invalid_ip", self.invalid_ip),
+            ("not_located", self.not_located),
+            ("known_scanner", self.known_scanner),
+            ("filtered", self.filtered),
+            ("sampled_out", self.sampled_out),
+        ]
+    }
+
+    /// Returns the number of records along with their outcomes, for the
+    /// dashboard.
+    fn counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts = vec![("records", self.records)];
+        counts.extend(self.outcomes());
+        counts
+    }
+
+    /// Finishes the phase `name` that started at `start`, logging the
+    /// counters along with `fields`, and adds them to the telemetry metrics.
+    fn report(&self, name: &str, start: Instant, mut fields: Vec<(&str, serde_json::Value)>) {
+        for (outcome, count) in self.outcomes() {
+            telemetry::count_records(outcome, count as u64);
+        }
+        telemetry::record_lookups(&self.lookups);
+        fields.extend([
+            ("records", json!(self.records)),
+            ("located", json!(self.located)),
+            ("skipped_invalid_ip", json!(self.invalid_ip)),
+            ("skipped_not_located", json!(self.not_located)),
+            ("skipped_known_scanner", json!(self.known_scanner)),
+            ("skipped_filtered", json!(self.filtered)),
+            ("skipped_sampled_out", json!(self.sampled_out)),
+        ]);
+        if self.resolved > 0 {
+            fields.push(("hostnames_resolved", json!(self.resolved)));
+        }
+        if self.cache_hits + self.cache_misses > 0 {
+            telemetry::count_cache_lookups("hit", self.cache_hits as u64);
+            telemetry::count_cache_lookups("miss", self.cache_misses as u64);
+            fields.extend([
+                ("cache_hits", json!(self.cache_hits)),
+                ("cache_misses", json!(self.cache_misses)),
+            ]);
+        }
+        finish_phase(name, start, fields);
+    }
+}
+
+/// Looks up the geographical location of each record's source IP and adds its
+/// counts to `aggregator`, skipping IPs that are invalid or can't be located,
+/// and known scanners if they are excluded. Located records are passed
+/// through `filter`, if given, which may drop or modify them. With `--sample`,
+/// records of sources left out are dropped before being looked up and the
+/// counts of the rest scaled up. Sources that are URLs are replaced by their
+/// host first, and hostnames resolved with `--resolve-hostnames`. Lookup
+/// results are reused from `cache`, if given. How each record was handled is
+/// counted in `stats`.
+fn aggregate<S: AsRef<[u8]> + Sync>(
+    cli: &Cli,
+    aggregator: &mut Aggregator,
+    locator: &Locator<S>,
+    filter: Option<&Filter>,
+    cache: Option<&mut LookupCache>,
+    mut records: Vec<ThreatRecord>,
+    stats: &mut AggregateStats,
+) -> Result<(), Box<dyn Error>> {
+    let by_feed = cli.groups_by(GroupBy::Feed);
+    let by_service = cli.groups_by(GroupBy::Service);
+    let by_tag = cli.groups_by(GroupBy::Tag);
+    stats.records += records.len();
+    prepare(cli, locator, &mut records, stats);
+    let lookups = lookup_all(cli, locator, cache, &records, stats);
+    for (mut record, lookup) in records.into_iter().zip(lookups) {
+        let (ip, mut location) = match lookup {
+            Lookup::InvalidIp => {
+                stats.invalid_ip += 1;
+                continue;
+            }
+            Lookup::KnownScanner => {
+                stats.known_scanner += 1;
+                continue;
+            }
+            Lookup::NotLocated => {
+                stats.not_located += 1;
+                continue;
+            }
+            Lookup::Located(ip, location) => (ip, location),
+        };
+        if let Some(filter) = filter {
+            if !filter.apply(ip, &mut record, &mut location)? {
+                stats.filtered += 1;
+                continue;
+            }
+        }
+        let group = Group {
+            feed: by_feed.then_some(record.feed.as_str()),
+            service: record.service.as_deref().filter(|_| by_service),
+            tag: record.tag.as_deref().filter(|_| by_tag),
+        };
+        let breakdown = record
+            .service
+            .as_deref()
+            .filter(|_| cli.top_services.is_some());
+        aggregator.add(&location, group, breakdown, record.count, record.severity);
+        if cli.track_ips {
+            aggregator.add_ip(ip);
+        }
+        if cli.tracks_sources() {
+            aggregator.add_source(ip, record.count);
+        }
+        stats.located += 1;
+    }
+    Ok(())
+}
+
+/// Prepares `records` for lookup: replaces URL sources with their host,
+/// samples them with `--sample` and resolves hostnames with
+/// `--resolve-hostnames`, counting them in `stats`.
+fn prepare<S: AsRef<[u8]>>(
+    cli: &Cli,
+    locator: &Locator<S>,
+    records: &mut Vec<ThreatRecord>,
+    stats: &mut AggregateStats,
+) {
+    for record in records.iter_mut() {
+        if let Some(host) = url::host(&refang(&record.source)).map(str::to_string) {
+            record.source = host;
+        }
+    }
+    if let Some(sampler) = cli.sample.map(Sampler::new) {
+        let total = records.len();
+        records.retain(|record| sampler.keeps(&record.source));
+        stats.sampled_out += total - records.len();
+        for record in records.iter_mut() {
+            record.count = sampler.scale(record.count);
+        }
+    }
+    if let Some(resolver) = &locator.resolver {
+        resolve_hostnames(resolver, records, stats);
+    }
+}
+
+/// Replaces the sources of records that are hostnames with the addresses
+/// they resolve to, counting them in `stats`. Hostnames that don't resolve
+/// are left to be skipped as invalid IPs.
+fn resolve_hostnames(
+    resolver: &Resolver,
+    records: &mut [ThreatRecord],
+    stats: &mut AggregateStats,
+) {
+    let names: Vec<String> = records
+        .iter()
+        .map(|record| refang(record.source.trim()).into_owned())
+        .collect();
+    let addresses = resolver.resolve(
+        names
+            .iter()
+            .map(String::as_str)
+            .filter(|name| dns::is_hostname(name)),
+    );
+    for (record, name) in records.iter_mut().zip(&names) {
+        if let Some(ip) = addresses.get(name.as_str()) {
+            record.source = ip.to_string();
+            stats.resolved += 1;
+        }
+    }
+}
+
+/// The outcome of looking up a record's source IP.
+#[allow(clippy::large_enum_variant)] // Most records are located.
+enum Lookup {
+    InvalidIp,
+    KnownScanner,
+    NotLocated,
+    Located(IpAddr, Location),
+}
+
+/// Fewest records worth handing to a lookup thread of their own.
+const MIN_RECORDS_PER_THREAD: usize = 1024;
+
+/// Looks up the source IPs of `records`, in order. Without a `cache`, the
+/// records are split among up to `--threads` threads; with one, as in the
+/// collector, they're looked up on this thread so it can be updated.
+fn lookup_all<S: AsRef<[u8]> + Sync>(
+    cli: &Cli,
+    locator: &Locator<S>,
+    mut cache: Option<&mut LookupCache>,
+    records: &[ThreatRecord],
+    stats: &mut AggregateStats,
+) -> Vec<Lookup> {
+    let per_thread = records
+        .len()
+        .div_ceil(cli.threads())
+        .max(MIN_RECORDS_PER_THREAD);
+    if cache.is_some() || records.len() <= per_thread {
+        return records
+            .iter()
+            .map(|record| lookup(cli, locator, cache.as_deref_mut(), record, stats))
+            .collect();
+    }
+    thread::scope(|scope| {
+        let workers: Vec<_> = records
+            .chunks(per_thread)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut stats = AggregateStats::default();
+                    let lookups: Vec<Lookup> = chunk
+                        .iter()
+                        .map(|record| lookup(cli, locator, None, record, &mut stats))
+                        .collect();
+                    (lookups, stats.lookups)
+                })
+            })
+            .collect();
+        let mut lookups = Vec::with_capacity(records.len());
+        for worker in workers {
+            let (chunk, latencies) = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            lookups.extend(chunk);
+            stats.lookups.merge(&latencies);
+        }
+        lookups
+    })
+}
+
+/// Looks up the source IP of `record`, refanged if it was defanged, through
+/// `cache` if given.
+fn lookup<S: AsRef<[u8]>>(
+    cli: &Cli,
+    locator: &Locator<S>,
+    cache: Option<&mut LookupCache>,
+    record: &ThreatRecord,
+    stats: &mut AggregateStats,
+) -> Lookup {
+    let Ok(ip) = refang(&record.source).parse::<IpAddr>() else {
+        return Lookup::InvalidIp;
+    };
+    if cli.exclude_known_scanners && locator.known_scanner(ip).is_some() {
+        return Lookup::KnownScanner;
+    }
+    locate(locator, cache, ip, stats)
+        .map_or(Lookup::NotLocated, |location| Lookup::Located(ip, location))
+}
+
+/// Looks up the location of `ip`, through `cache` if given, counting the
+/// lookup in `stats`.
+fn locate<S: AsRef<[u8]>>(
+    locator: &Locator<S>,
+    cache: Option<&mut LookupCache>,
+    ip: IpAddr,
+    stats: &mut AggregateStats,
+) -> Option<Location> {
+    let Some(cache) = cache else {
+        let lookup = Instant::now();
+        let location = locator.locate(ip);
+        stats.lookups.observe(lookup.elapsed());
+        return location;
+    };
+    if let Some(location) = cache.get(ip) {
+        stats.cache_hits += 1;
+        return location.clone();
+    }
+    stats.cache_misses += 1;
+    let location = locate(locator, None, ip, stats);
+    cache.insert(ip, location.clone());
+    location
+}
+
+/// Writes the aggregated data, transformed by the `--script` if given, to the
+/// output file in the selected format, followed by its manifest sidecar if
+/// requested, then saves the external API answers looked up so far to the
+/// `--cache`. `partial` marks the output of an interrupted run in the
+/// manifest. Returns the output and its MIME type.
+fn write_output<S: AsRef<[u8]>>(
+    cli: &Cli,
+    aggregator: &Aggregator,
+    locator: &Locator<S>,
+    partial: bool,
+) -> Result<(Vec<u8>, &'static str), Box<dyn Error>> {
+    // Reloaded on every write so script edits show up in collector mode.
+    let transformed = match &cli.script {
+        Some(path) => Some(Transform::load(path)?.apply(aggregator.entries())?),
+        None => None,
+    };
+    let mut entries: Vec<(&GroupKey, &CityData)> = transformed.as_ref().map_or_else(
+        || aggregator.entries().collect(),
+        |transformed| transformed.iter().map(|(key, data)| (key, data)).collect(),
+    );
+    if cli.only_new {
+        entries.retain(|(_, data)| data.new);
+    }
+    // Most threats first, then by group, so the same data always gives the
+    // same output.
+    entries.sort_by(|(a_key, a), (b_key, b)| {
+        b.total_count
+            .cmp(&a.total_count)
+            .then_with(|| a_key.cmp(b_key))
+    });
+    let columns = output::Columns {
+        weighted: aggregator.is_weighted(),
+        feed: cli.groups_by(GroupBy::Feed),
+        service: cli.groups_by(GroupBy::Service),
+        tag: cli.groups_by(GroupBy::Tag),
+        per_capita: cli.population.is_some(),
+        risk: cli.country_weights.is_some(),
+        known_scanner: cli.tag_known_scanners,
+        cloud_provider: !cli.cloud_ranges.is_empty(),
+        rdap: cli.rdap,
+        tags: !cli.enrichers.is_empty()
+            || cli.filter_script.is_some()
+            || entries.iter().any(|(key, _)| !key.tags.is_empty()),
+        label: cli.labels,
+        top_services: cli.top_services,
+        count_error: aggregator.count_error(),
+        anomaly: cli.anomalies.is_some(),
+        seen: cli.seen_state.is_some(),
+    };
+    let rows = entries.len();
+    let phase = Instant::now();
+    let provenance = Provenance::new(cli.database_path(), locator.metadata());
+    let embedded = cli.metadata.then_some(&provenance);
+    let mut body = Vec::new();
+    let content_type = if let Some(path) = &cli.template {
+        // Reloaded on every write so template edits show up in collector mode.
+        let template = Template::load(path)?;
+        output::render(
+            &mut body,
+            &template,
+            entries,
+            columns,
+            cli.jitter,
+            &provenance,
+        )?;
+        template.content_type()
+    } else {
+        let logo = cli.report_logo.as_deref().map(Logo::load).transpose()?;
+        let offenders = top_offenders(cli, locator.api_cache.as_ref(), aggregator)?;
+        let report = Report {
+            title: &cli.report_title,
+            logo: logo.as_ref(),
+            zoom: cli.zoom.clone(),
+            target: cli.target,
+            offenders: offenders.as_ref(),
+        };
+        output::write(
+            &mut body, cli.format, entries, columns, cli.jitter, &report, embedded,
+        )?;
+        cli.format.content_type()
+    };
+    deliver(cli, &cli.output, &body, content_type)?;
+    finish_phase(
+        "output written",
+        phase,
+        vec![
+            ("path", json!(cli.output)),
+            ("rows", json!(rows)),
+            ("bytes", json!(body.len())),
+        ],
+    );
+
+    if cli.manifest {
+        let manifest = Manifest {
+            provenance: &provenance,
+            partial,
+            outputs: vec![OutputFile::new(&cli.output, &body, rows)],
+        };
+        let sidecar = manifest::sidecar_path(&cli.output);
+        deliver(
+            cli,
+            &sidecar,
+            &serde_json::to_vec_pretty(&manifest)?,
+            "application/json",
+        )?;
+    }
+    save_api_cache(locator)?;
+    Ok((body, content_type))
+}
+
+/// Looks up the `--host-intel-top` source IPs with the most threats in the
+/// `--host-intel` service, if one is selected.
+fn top_offenders(
+    cli: &Cli,
+    api_cache: Option<&Arc<ApiCache>>,
+    aggregator: &Aggregator,
+) -> Result<Option<Offenders>, Box<dyn Error>> {
+    host_intel(cli, api_cache)
+        .map(|intel| intel.look_up(aggregator.top_sources(cli.host_intel_top)))
+        .transpose()
+}
+
+/// Creates the client of the `--host-intel` service, if one is selected,
+/// caching its answers in `api_cache`.
+fn host_intel(cli: &Cli, api_cache: Option<&Arc<ApiCache>>) -> Option<HostIntel> {
+    let (Some(service), Some(api_key)) = (cli.host_intel, &cli.host_intel_key) else {
+        return None;
+    };
+    Some(HostIntel::new(
+        service,
+        api_key.clone(),
+        cli.host_intel_url.clone(),
+        api_cache.cloned(),
+    ))
+}
+
+/// Creates the mailer sending from `--email-from` to `to` through the
+/// `--smtp-url` server.
+fn open_mailer(cli: &Cli, to: &[String]) -> Result<Mailer, Box<dyn Error>> {
+    let from = cli.email_from.as_deref().unwrap_or_default();
+    Mailer::new(cli.smtp_url.as_deref(), from, to)
+}
+
+/// Emails the output `body` of a run with a short summary.
+fn email_output(
+    cli: &Cli,
+    mailer: &Mailer,
+    aggregator: &Aggregator,
+    partial: bool,
+    body: &[u8],
+    content_type: &str,
+) -> Result<(), Box<dyn Error>> {
+    let (total, locations) = (aggregator.total_count(), aggregator.len());
+    let filename = cli.output.file_name().map_or_else(
+        || "locations".to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let mut subject = format!(
+        "{}: {total} threats from {locations} locations",
+        cli.report_title
+    );
+    let mut text = format!(
+        "{total} threats were aggregated from {locations} locations.\n\nThe output is attached as {filename}.\n"
+    );
+    if partial {
+        subject.push_str(" (partial)");
+        text.push_str("\nThe run was interrupted, so the output only covers part of the inputs.\n");
+    }
+    mailer.send(&subject, text, filename, body, content_type)?;
+    logging::info("output emailed", &[("to", json!(cli.email_to))]);
+    Ok(())
+}
+
+/// Posts a summary of the run to the configured chat webhooks.
+fn notify(cli: &Cli, aggregator: &Aggregator, partial: bool) -> Result<(), Box<dyn Error>> {
+    let output = cli.output.to_string_lossy();
+    let summary = notify::Summary::new(&cli.report_title, aggregator, &output, partial);
+    if let Some(url) = &cli.slack_webhook {
+        notify::slack(url, &summary, &cli.upload())?;
+        logging::info("summary posted", &[("webhook", json!("slack"))]);
+    }
+    if let Some(url) = &cli.teams_webhook {
+        notify::teams(url, &summary, &cli.upload())?;
+        logging::info("summary posted", &[("webhook", json!("teams"))]);
+    }
+    Ok(())
+}
+
+/// Checks the `--rules` against the aggregated counts, logging the alerts of
+/// those that fired and posting them to the rule's webhooks, or to the
+/// `--slack-webhook` and `--teams-webhook` if it has none.
+fn alert(cli: &Cli, rules: &mut Rules, aggregator: &Aggregator) -> Result<(), Box<dyn Error>> {
+    for alert in rules.check(aggregator) {
+        let rule = alert.rule;
+        logging::warn(
+            "alert fired",
+            &[
+                ("rule", json!(rule.name)),
+                ("message", json!(alert.message)),
+            ],
+        );
+        let (slack, teams) =
+            if rule.slack.is_some() || rule.teams.is_some() || rule.webhook.is_some() {
+                (rule.slack.as_deref(), rule.teams.as_deref())
+            } else {
+                (cli.slack_webhook.as_deref(), cli.teams_webhook.as_deref())
+            };
+        let targets = [
+            slack.map(notify::AlertTarget::Slack),
+            teams.map(notify::AlertTarget::Teams),
+            rule.webhook.as_deref().map(notify::AlertTarget::Webhook),
+        ];
+        for target in targets.iter().flatten() {
+            notify::alert(target, &cli.report_title, &alert, &cli.upload())?;
         }
     }
+    Ok(())
+}
 
-    // Write the aggregated data to the CSV file.
-    for (key, data) in locations {
-        wtr.write_record([
-            &data.city_name,
-            &data.country_name,
-            &data.total_count.to_string(),
-            &key.lat,
-            &key.lon,
-        ])?;
+/// Writes `body` to the local file at `location`, or uploads it if
+/// `location` is remote.
+///
+/// Local files are replaced atomically, so an interrupted or failed write
+/// never leaves a truncated output behind.
+fn deliver(
+    cli: &Cli,
+    location: &Path,
+    body: &[u8],
+    content_type: &str,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(remote) = location.to_str().and_then(Remote::parse) {
+        return remote.upload(body, content_type, &cli.upload());
     }
+    let mut temporary = location.as_os_str().to_os_string();
+    temporary.push(".tmp");
+    fs::write(&temporary, body)?;
+    Ok(fs::rename(&temporary, location)?)
+}
+
+/// Appends the aggregated locations to the `--duckdb` database and runs the
+/// `--sql` query against it, if given.
+#[cfg(feature = "duckdb")]
+fn analyze(cli: &Cli, path: &Path, aggregator: &Aggregator) -> Result<(), Box<dyn Error>> {
+    let phase = Instant::now();
+    let generated_at = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    analytics::append(path, aggregator, &generated_at)?;
+    finish_phase(
+        "history appended",
+        phase,
+        vec![("path", json!(path)), ("rows", json!(aggregator.len()))],
+    );
+    if let Some(sql) = &cli.sql {
+        analytics::query(path, sql)?;
+    }
+    Ok(())
+}
+
+/// Loads the `--seen-state`, if given, and records the aggregated sightings
+/// in it.
+fn load_sightings(
+    cli: &Cli,
+    aggregator: &mut Aggregator,
+) -> Result<Option<SeenState>, Box<dyn Error>> {
+    let Some(path) = &cli.seen_state else {
+        return Ok(None);
+    };
+    let mut seen = SeenState::load(path)?;
+    track_sightings(path, &mut seen, aggregator);
+    Ok(Some(seen))
+}
 
-    wtr.flush()?;
+/// Saves the `--seen-state`, once the output flagging the new sightings has
+/// been delivered, so they aren't lost to a failed write.
+fn save_sightings(cli: &Cli, seen: Option<&SeenState>) -> Result<(), Box<dyn Error>> {
+    if let (Some(path), Some(seen)) = (&cli.seen_state, seen) {
+        seen.save(path)?;
+    }
     Ok(())
 }
+
+/// Records the aggregated locations, countries and tracked source IPs as seen
+/// now in the `--seen-state` loaded from `path`, flagging those never seen
+/// before.
+fn track_sightings(path: &Path, seen: &mut SeenState, aggregator: &mut Aggregator) {
+    let phase = Instant::now();
+    let now = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let new = seen.update(aggregator, &now);
+    finish_phase(
+        "sightings recorded",
+        phase,
+        vec![
+            ("path", json!(path)),
+            ("new_locations", json!(new.locations)),
+            ("new_countries", json!(new.countries)),
+            ("new_ips", json!(new.ips)),
+        ],
+    );
+}
+
+/// Flags the locations whose count spiked far above their history in the
+/// `--duckdb` database, before this run is appended to it, and writes them to
+/// `feed` as JSON.
+#[cfg(feature = "duckdb")]
+fn detect_anomalies(
+    cli: &Cli,
+    path: &Path,
+    feed: &Path,
+    aggregator: &mut Aggregator,
+) -> Result<(), Box<dyn Error>> {
+    let phase = Instant::now();
+    let anomalies =
+        analytics::anomalies(path, aggregator, cli.anomaly_runs, cli.anomaly_threshold)?;
+    let locations = anomalies.iter().map(|anomaly| anomaly.location).collect();
+    aggregator.flag_anomalies(&locations);
+    let body = serde_json::to_vec_pretty(&anomalies)?;
+    deliver(cli, feed, &body, "application/json")?;
+    finish_phase(
+        "anomalies detected",
+        phase,
+        vec![("path", json!(feed)), ("anomalies", json!(anomalies.len()))],
+    );
+    Ok(())
+}
+
+/// Loads a replaced database file noticed by `watcher` in place of the
+/// current one, clearing the lookup `cache`, or keeps the current one if the
+/// new file can't be opened.
+fn reload_database(
+    cli: &Cli,
+    watcher: &mut DatabaseWatcher,
+    locator: &mut Locator<Vec<u8>>,
+    cache: Option<&mut LookupCache>,
+) {
+    match watcher.poll() {
+        Ok(Some(reader)) => {
+            locator.reader = reader;
+            if let Some(cache) = cache {
+                cache.clear();
+            }
+            logging::info(
+                "database reloaded",
+                &[
+                    ("path", json!(cli.database_path())),
+                    ("build_epoch", json!(locator.metadata().build_epoch)),
+                ],
+            );
+        }
+        Err(error) => logging::warn(
+            "database reload failed, keeping the current database",
+            &[
+                ("path", json!(cli.database_path())),
+                ("error", json!(error.to_string())),
+            ],
+        ),
+        Ok(None) => {}
+    }
+}
+
+/// Runs the NetFlow/IPFIX collector on `addr`, aggregating flows as they
+/// arrive and rewriting the output with the running totals every
+/// `--flush-interval` seconds, covering only the last `--window` if given, and
+/// checking the `--rules` after each rewrite. A
+/// replaced database file is loaded in place of
+/// the current one between datagrams. Returns after a final flush once SIGINT
+/// or SIGTERM is received, or on a socket or output error.
+fn collect(
+    cli: &Cli,
+    addr: SocketAddr,
+    locator: &mut Locator<Vec<u8>>,
+    filter: Option<&Filter>,
+    aggregator: &mut Aggregator,
+    mut rules: Option<&mut Rules>,
+    mut dashboard: Option<&mut Dashboard>,
+) -> Result<(), Box<dyn Error>> {
+    let interval = Duration::from_secs(cli.flush_interval.max(1));
+    // Wake up at least every second to notice shutdown requests.
+    let wake = interval.min(Duration::from_secs(1));
+    let queue = Collector::bind(addr, wake)?.spawn(cli.queue_size.max(1));
+    logging::info("collector listening", &[("addr", json!(addr))]);
+    health::set_ready(true);
+    systemd::notify("READY=1");
+    let mut watchdog = systemd::Watchdog::new();
+    let mut next_flush = Instant::now() + interval;
+    let mut since = Instant::now();
+    let mut stats = AggregateStats::default();
+    let ttl = Duration::from_secs(cli.lookup_cache_ttl);
+    let mut cache = LookupCache::new(cli.lookup_cache_size, ttl);
+    let mut watcher = DatabaseWatcher::new(cli.database.clone());
+    let mut seen = cli.seen_state.as_deref().map(SeenState::load).transpose()?;
+    let mut window = cli
+        .window
+        .map(|length| Window::new(length, cli.slide.unwrap_or(length), Instant::now()));
+    loop {
+        watchdog.ping();
+        if cli.uses_database_file() {
+            reload_database(cli, &mut watcher, locator, cache.as_mut());
+        }
+        let records = match queue.receiver.recv_timeout(wake) {
+            Ok(received) => received?,
+            Err(RecvTimeoutError::Timeout) => Vec::new(),
+            Err(RecvTimeoutError::Disconnected) => return Err("collector stopped receiving".into()),
+        };
+        // With a window, flows are aggregated on their own first so they can
+        // be added to its newest bucket as well as the totals.
+        let mut batch = Aggregator::default();
+        if let Some(window) = window.as_mut() {
+            window.advance(Instant::now(), aggregator);
+        }
+        aggregate(
+            cli,
+            if window.is_some() {
+                &mut batch
+            } else {
+                &mut *aggregator
+            },
+            locator,
+            filter,
+            cache.as_mut(),
+            records,
+            &mut stats,
+        )?;
+        if let Some(window) = window.as_mut() {
+            window.add(&batch, aggregator);
+        }
+        if let Some(dashboard) = dashboard.as_deref_mut() {
+            let phase = format!("listening on {addr}");
+            dashboard.show(phase, aggregator, stats.counts())?;
+        }
+        let stopping = shutdown::requested();
+        if stopping {
+            systemd::notify("STOPPING=1");
+        }
+        if stopping || Instant::now() >= next_flush {
+            let stalls = queue.take_stalls();
+            stats.report(
+                "records aggregated",
+                since,
+                vec![("queue_stalls", json!(stalls))],
+            );
+            if let (Some(path), Some(seen)) = (&cli.seen_state, seen.as_mut()) {
+                track_sightings(path, seen, aggregator);
+            }
+            write_output(cli, aggregator, locator, false)?;
+            save_sightings(cli, seen.as_ref())?;
+            if let Some(rules) = rules.as_deref_mut() {
+                alert(cli, rules, aggregator)?;
+            }
+            telemetry::export("flush");
+            systemd::notify(&format!(
+                "STATUS={} threats at {} locations",
+                aggregator.total_count(),
+                aggregator.len()
+            ));
+            next_flush = Instant::now() + interval;
+            since = Instant::now();
+            stats = AggregateStats::default();
+        }
+        if stopping {
+            logging::info("collector stopped", &[("addr", json!(addr))]);
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use maxminddb::Metadata;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Where and when a run's outputs came from.
+#[derive(Serialize)]
+pub struct Provenance {
+    pub tool: &'static str,
+    pub version: &'static str,
+    /// RFC 3339 time the outputs were generated at.
+    pub generated_at: String,
+    pub database: Database,
+}
+
+/// A sidecar describing a run's outputs, letting dashboards detect stale or
+/// corrupted data files.
+#[derive(Serialize)]
+pub struct Manifest<'a> {
+    #[serde(flatten)]
+    pub provenance: &'a Provenance,
+    /// Set if the run was interrupted, so the outputs only cover what had
+    /// been aggregated by then.
+    pub partial: bool,
+    pub outputs: Vec<OutputFile>,
+}
+
+/// The `MaxMind` database the outputs were generated with.
+#[derive(Serialize)]
+pub struct Database {
+    pub path: PathBuf,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub build_epoch: u64,
+    /// RFC 3339 rendering of `build_epoch`.
+    pub build_date: Option<String>,
+}
+
+/// A single output file and the information needed to verify it.
+#[derive(Serialize)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub bytes: usize,
+    /// Number of data rows, excluding any header.
+    pub rows: usize,
+}
+
+impl Provenance {
+    /// Describes a run generating outputs now with the database at `path`
+    /// described by `metadata`.
+    pub fn new(path: &Path, metadata: &Metadata) -> Self {
+        let build_date = i64::try_from(metadata.build_epoch)
+            .ok()
+            .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+            .map(|date| date.to_rfc3339_opts(SecondsFormat::Secs, true));
+        Self {
+            tool: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            generated_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            database: Database {
+                path: path.to_path_buf(),
+                kind: metadata.database_type.clone(),
+                build_epoch: metadata.build_epoch,
+                build_date,
+            },
+        }
+    }
+}
+
+impl OutputFile {
+    /// Describes the output written to `path` with contents `body`.
+    pub fn new(path: &Path, body: &[u8], rows: usize) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            sha256: format!("{:x}", Sha256::digest(body)),
+            bytes: body.len(),
+            rows,
+        }
+    }
+}
+
+/// The sidecar location for `output`: the same path with `.manifest.json`
+/// appended, e.g. `locations.csv.manifest.json`.
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    let mut path = OsString::from(output);
+    path.push(".manifest.json");
+    PathBuf::from(path)
+}
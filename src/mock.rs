@@ -0,0 +1,345 @@
+use ipnetwork::IpNetwork;
+use maxminddb::Reader;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Type of the database built from a `--mock-map`, as shown in manifests and
+/// by `check-db`.
+const DATABASE_TYPE: &str = "Mock-City";
+
+/// Marks the start of the metadata section of a `MaxMind` database.
+const METADATA_MARKER: &[u8] = b"\xAB\xCD\xEFMaxMind.com";
+
+/// Bits per record of the search tree: two 32-bit records per node.
+const RECORD_SIZE: u32 = 32;
+
+/// Size of the zeroed separator between the search tree and the data section.
+const SEPARATOR_SIZE: usize = 16;
+
+/// Location of an IP or CIDR in a `--mock-map`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MockLocation {
+    city: String,
+    country: String,
+    country_code: Option<String>,
+    lat: f64,
+    lon: f64,
+    geoname_id: Option<u32>,
+    time_zone: Option<String>,
+}
+
+impl MockLocation {
+    /// Returns the location as a `GeoIP2` City record.
+    fn record(self) -> Value {
+        let mut city = json!({ "names": { "en": self.city } });
+        if let Some(id) = self.geoname_id {
+            city["geoname_id"] = json!(id);
+        }
+        let mut country = json!({ "names": { "en": self.country } });
+        if let Some(code) = self.country_code {
+            country["iso_code"] = json!(code);
+        }
+        let mut location = json!({ "latitude": self.lat, "longitude": self.lon });
+        if let Some(time_zone) = self.time_zone {
+            location["time_zone"] = json!(time_zone);
+        }
+        json!({ "city": city, "country": country, "location": location })
+    }
+}
+
+/// A record of a search tree node: another node, no data, or the data at an
+/// offset of the data section.
+#[derive(Clone, Copy)]
+enum Child {
+    Node(u32),
+    Empty,
+    Data(u32),
+}
+
+/// Loads a `--mock-map` of IPs and CIDRs to locations and builds an
+/// in-memory City database from it, so IPs are looked up exactly as in a
+/// `MaxMind` database. Where networks overlap, the most specific one wins.
+///
+/// The database's build date is the Unix epoch, keeping outputs and
+/// manifests of the same inputs identical across runs.
+pub fn load(path: &Path) -> Result<Reader<Vec<u8>>, Box<dyn Error>> {
+    let map: BTreeMap<String, MockLocation> =
+        serde_json::from_slice(&fs::read(path)?).map_err(|e| format!("{}: {e}", path.display()))?;
+    let mut networks = Vec::new();
+    for (network, location) in map {
+        let parsed: IpNetwork = network
+            .parse()
+            .map_err(|e| format!("{}: {network}: {e}", path.display()))?;
+        networks.push((parsed, location.record()));
+    }
+    Ok(Reader::from_source(build(networks)?)?)
+}
+
+/// Builds an IPv6 `MaxMind` database with 32-bit records holding `networks`
+/// and their records, with IPv4 networks in the `::/96` subtree, where
+/// lookups of IPv4 addresses start.
+fn build(networks: Vec<(IpNetwork, Value)>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut networks: Vec<(u128, u8, Value)> = networks
+        .into_iter()
+        .map(|(network, record)| match network {
+            IpNetwork::V4(v4) => (
+                u128::from(u32::from(v4.network())),
+                96 + v4.prefix(),
+                record,
+            ),
+            IpNetwork::V6(v6) => (u128::from(v6.network()), v6.prefix(), record),
+        })
+        .collect();
+    // Less specific networks first, so more specific ones split them.
+    networks.sort_by_key(|&(_, prefix, _)| prefix);
+    let mut nodes = vec![[Child::Empty; 2]];
+    let mut data = Vec::new();
+    for (bits, prefix, record) in networks {
+        let offset = u32::try_from(data.len())?;
+        encode(&record, &mut data);
+        insert(&mut nodes, bits, prefix, Child::Data(offset))?;
+    }
+    let node_count = u32::try_from(nodes.len())?;
+    let mut database = Vec::new();
+    for node in &nodes {
+        for child in node {
+            let record = match *child {
+                Child::Node(index) => index,
+                Child::Empty => node_count,
+                Child::Data(offset) => node_count + 16 + offset,
+            };
+            database.extend(record.to_be_bytes());
+        }
+    }
+    database.extend([0; SEPARATOR_SIZE]);
+    database.extend(data);
+    database.extend(METADATA_MARKER);
+    let metadata = json!({
+        "binary_format_major_version": 2,
+        "binary_format_minor_version": 0,
+        "build_epoch": 0,
+        "database_type": DATABASE_TYPE,
+        "description": { "en": "Locations of a --mock-map" },
+        "ip_version": 6,
+        "languages": ["en"],
+        "node_count": node_count,
+        "record_size": RECORD_SIZE,
+    });
+    encode(&metadata, &mut database);
+    Ok(database)
+}
+
+/// Points the search tree path of the network of `prefix` bits starting at
+/// `bits` to `data`, splitting any less specific network on the way.
+fn insert(
+    nodes: &mut Vec<[Child; 2]>,
+    bits: u128,
+    prefix: u8,
+    data: Child,
+) -> Result<(), Box<dyn Error>> {
+    if prefix == 0 {
+        nodes[0] = [data; 2];
+        return Ok(());
+    }
+    let mut node = 0;
+    for depth in 0..prefix {
+        let bit = usize::from(bits >> (127 - depth) & 1 == 1);
+        if depth + 1 == prefix {
+            nodes[node][bit] = data;
+            break;
+        }
+        node = match nodes[node][bit] {
+            Child::Node(index) => index as usize,
+            child => {
+                let index = nodes.len();
+                nodes.push([child; 2]);
+                nodes[node][bit] = Child::Node(u32::try_from(index)?);
+                index
+            }
+        };
+    }
+    Ok(())
+}
+
+/// Appends `value` to `out` in the `MaxMind` DB data format. Integers are
+/// stored as 32 or 64-bit unsigned integers, other numbers as doubles, and
+/// null object fields and array items are left out.
+fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::String(string) => {
+            header(2, string.len(), out);
+            out.extend(string.as_bytes());
+        }
+        Value::Number(number) => {
+            if let Some(integer) = number.as_u64() {
+                let bytes = integer.to_be_bytes();
+                let skipped = integer.leading_zeros() as usize / 8;
+                header(
+                    if integer > u64::from(u32::MAX) { 9 } else { 6 },
+                    8 - skipped,
+                    out,
+                );
+                out.extend(&bytes[skipped..]);
+            } else {
+                header(3, 8, out);
+                out.extend(number.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        }
+        Value::Bool(bool) => header(14, usize::from(*bool), out),
+        Value::Array(items) => {
+            let items: Vec<&Value> = items.iter().filter(|item| !item.is_null()).collect();
+            header(11, items.len(), out);
+            for item in items {
+                encode(item, out);
+            }
+        }
+        Value::Object(fields) => encode_map(fields, out),
+        Value::Null => {}
+    }
+}
+
+/// Appends the non-null fields of a map to `out`.
+fn encode_map(fields: &Map<String, Value>, out: &mut Vec<u8>) {
+    let fields: Vec<(&String, &Value)> = fields.iter().filter(|(_, v)| !v.is_null()).collect();
+    header(7, fields.len(), out);
+    for (key, value) in fields {
+        header(2, key.len(), out);
+        out.extend(key.as_bytes());
+        encode(value, out);
+    }
+}
+
+/// Appends the control byte of a field of `kind` and `size` to `out`, with
+/// the extended type and size bytes it needs.
+#[allow(clippy::cast_possible_truncation)] // Each size is within its range.
+fn header(kind: u8, size: usize, out: &mut Vec<u8>) {
+    let (size_bits, extra): (u8, Vec<u8>) = match size {
+        0..=28 => (size as u8, Vec::new()),
+        29..=284 => (29, vec![(size - 29) as u8]),
+        285..=65_820 => (30, ((size - 285) as u16).to_be_bytes().to_vec()),
+        _ => (31, ((size - 65_821) as u32).to_be_bytes()[1..].to_vec()),
+    };
+    if kind > 7 {
+        out.extend([size_bits, kind - 7]);
+    } else {
+        out.push(kind << 5 | size_bits);
+    }
+    out.extend(extra);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, DATABASE_TYPE};
+    use maxminddb::{geoip2, MaxMindDBError, Reader};
+    use serde_json::{json, Value};
+    use std::error::Error;
+    use std::net::IpAddr;
+    use std::{env, fs, process};
+
+    /// A map of IPv4 and IPv6 networks, a single IP, and a network nested
+    /// in another.
+    const MAP: &str = r#"{
+        "203.0.113.0/24": {"city": "Paris", "country": "France", "country_code": "FR",
+                           "lat": 48.86, "lon": 2.35},
+        "203.0.113.128/25": {"city": "Lyon", "country": "France", "lat": 45.76, "lon": 4.84,
+                             "geoname_id": 2996944},
+        "198.51.100.7": {"city": "Tokyo", "country": "Japan", "lat": 35.68, "lon": 139.69,
+                         "time_zone": "Asia/Tokyo"},
+        "2001:db8::/32": {"city": "Berlin", "country": "Germany", "country_code": "DE",
+                          "lat": 52.52, "lon": 13.4}
+    }"#;
+
+    /// Writes `map` to a temporary file called `name` and loads it.
+    fn load_map(name: &str, map: &str) -> Result<Reader<Vec<u8>>, Box<dyn Error>> {
+        let path = env::temp_dir().join(format!(
+            "dashboard_location_mapper-{}-{name}",
+            process::id()
+        ));
+        fs::write(&path, map).expect("the map is written");
+        let reader = load(&path);
+        let _ = fs::remove_file(&path);
+        reader
+    }
+
+    /// Looks up the record of `ip`.
+    fn lookup(reader: &Reader<Vec<u8>>, ip: &str) -> Result<Value, MaxMindDBError> {
+        reader.lookup(ip.parse::<IpAddr>().expect("the IP is valid"))
+    }
+
+    #[test]
+    fn reads_every_entry_back() {
+        let reader = load_map("mock.json", MAP).expect("the map loads");
+        assert_eq!(reader.metadata.database_type, DATABASE_TYPE);
+        assert_eq!(reader.metadata.ip_version, 6);
+
+        let paris = json!({
+            "city": { "names": { "en": "Paris" } },
+            "country": { "names": { "en": "France" }, "iso_code": "FR" },
+            "location": { "latitude": 48.86, "longitude": 2.35 },
+        });
+        let lyon = json!({
+            "city": { "names": { "en": "Lyon" }, "geoname_id": 2_996_944 },
+            "country": { "names": { "en": "France" } },
+            "location": { "latitude": 45.76, "longitude": 4.84 },
+        });
+        let tokyo = json!({
+            "city": { "names": { "en": "Tokyo" } },
+            "country": { "names": { "en": "Japan" } },
+            "location": { "latitude": 35.68, "longitude": 139.69, "time_zone": "Asia/Tokyo" },
+        });
+        let berlin = json!({
+            "city": { "names": { "en": "Berlin" } },
+            "country": { "names": { "en": "Germany" }, "iso_code": "DE" },
+            "location": { "latitude": 52.52, "longitude": 13.4 },
+        });
+        for (ip, expected) in [
+            ("203.0.113.0", &paris),
+            ("203.0.113.127", &paris),
+            ("203.0.113.128", &lyon),
+            ("203.0.113.255", &lyon),
+            ("198.51.100.7", &tokyo),
+            ("2001:db8::1", &berlin),
+            ("2001:db8:ffff::1", &berlin),
+        ] {
+            assert_eq!(lookup(&reader, ip).as_ref(), Ok(expected), "{ip}");
+        }
+        let ip = "203.0.113.200".parse::<IpAddr>().expect("the IP is valid");
+        let city: geoip2::City = reader.lookup(ip).expect("records decode as City records");
+        assert_eq!(city.city.and_then(|city| city.geoname_id), Some(2_996_944));
+
+        for miss in ["198.51.100.8", "192.0.2.1", "2001:db9::1", "::1"] {
+            assert!(
+                matches!(
+                    lookup(&reader, miss),
+                    Err(MaxMindDBError::AddressNotFoundError(_))
+                ),
+                "{miss}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_maps() {
+        for (name, map) in [
+            (
+                "network",
+                r#"{"203.0.113.0/33": {"city": "A", "country": "B", "lat": 0, "lon": 0}}"#,
+            ),
+            (
+                "field",
+                r#"{"192.0.2.1": {"city": "A", "country": "B", "lat": 0, "lon": 0, "x": 1}}"#,
+            ),
+            (
+                "location",
+                r#"{"192.0.2.1": {"city": "A", "lat": 0, "lon": 0}}"#,
+            ),
+        ] {
+            assert!(load_map(name, map).is_err(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use crate::aggregate::Aggregator;
+use crate::geo::distance_km;
+use std::io::{self, Write};
+
+/// The bundled list of capitals and other major cities.
+const BUNDLED: &str = include_str!("../data/cities.txt");
+
+/// A place that coordinates can be matched to: an aggregated location, or a
+/// city from the bundled list.
+pub struct Place {
+    pub city: String,
+    /// Name of the country, or its ISO code for bundled cities.
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Aggregated threat count, for aggregated locations.
+    pub count: Option<u32>,
+}
+
+/// Returns the cities of the bundled list.
+pub fn cities() -> Vec<Place> {
+    BUNDLED
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, ' ');
+            let lat = fields.next()?.parse().ok()?;
+            let lon = fields.next()?.parse().ok()?;
+            let country = fields.next()?.to_string();
+            let city = fields.next()?.to_string();
+            Some(Place {
+                city,
+                country,
+                lat,
+                lon,
+                count: None,
+            })
+        })
+        .collect()
+}
+
+/// Returns the aggregated groups as places.
+pub fn locations(aggregator: &Aggregator) -> Vec<Place> {
+    aggregator
+        .entries()
+        .map(|(key, data)| Place {
+            city: data.city_name.clone(),
+            country: data.country_name.clone(),
+            lat: key.location.lat(),
+            lon: key.location.lon(),
+            count: Some(data.total_count),
+        })
+        .collect()
+}
+
+/// Writes the `count` places nearest to `target` as CSV, nearest first,
+/// with their distance from it in km.
+pub fn write(
+    target: (f64, f64),
+    mut places: Vec<Place>,
+    count: usize,
+    out: impl Write,
+) -> io::Result<()> {
+    let distance = |place: &Place| distance_km(target, (place.lat, place.lon));
+    places.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(["Distance km", "City Name", "Country", "Count", "Lat", "Lon"])?;
+    for place in places.iter().take(count) {
+        writer.write_record([
+            &format!("{:.1}", distance(place)),
+            &place.city,
+            &place.country,
+            &place
+                .count
+                .map(|count| count.to_string())
+                .unwrap_or_default(),
+            &place.lat.to_string(),
+            &place.lon.to_string(),
+        ])?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,196 @@
+use crate::aggregate::Aggregator;
+use crate::remote::{Remote, Upload, UploadMethod};
+use crate::rules::Alert;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Number of countries listed in notifications.
+const TOP_COUNTRIES: usize = 5;
+
+/// The highlights of a run posted to chat webhooks.
+pub struct Summary<'a> {
+    pub title: &'a str,
+    pub total: u64,
+    pub locations: usize,
+    /// Countries with the most threats and their counts, highest first.
+    pub countries: Vec<(&'a str, u64)>,
+    /// Where the output can be found: a URL, or a path or S3 location shown
+    /// as text.
+    pub output: &'a str,
+    pub partial: bool,
+}
+
+impl<'a> Summary<'a> {
+    /// Summarizes the aggregated threats of a run whose output went to
+    /// `output`.
+    pub fn new(title: &'a str, aggregator: &'a Aggregator, output: &'a str, partial: bool) -> Self {
+        let mut countries: HashMap<&str, u64> = HashMap::new();
+        for (_, data) in aggregator.entries() {
+            *countries.entry(&data.country_name).or_default() += u64::from(data.total_count);
+        }
+        let mut countries: Vec<(&str, u64)> = countries.into_iter().collect();
+        countries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+        countries.truncate(TOP_COUNTRIES);
+        Self {
+            title,
+            total: aggregator.total_count(),
+            locations: aggregator.len(),
+            countries,
+            output,
+            partial,
+        }
+    }
+
+    /// One line stating the total threats and locations.
+    fn headline(&self) -> String {
+        let mut headline = format!("{} threats from {} locations", self.total, self.locations);
+        if self.partial {
+            headline.push_str(" (partial: the run was interrupted)");
+        }
+        headline
+    }
+
+    /// Returns `count` as a percentage of all threats.
+    fn share(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        // Counts are far below 2^52, so the conversion is exact.
+        #[allow(clippy::cast_precision_loss)]
+        let share = count as f64 * 100.0 / self.total as f64;
+        share
+    }
+
+    /// Returns the output location if it is a web link.
+    fn link(&self) -> Option<&str> {
+        matches!(Remote::parse(self.output), Some(Remote::Http(_))).then_some(self.output)
+    }
+}
+
+/// Posts the summary to a Slack incoming webhook.
+pub fn slack(url: &str, summary: &Summary, upload: &Upload) -> Result<(), Box<dyn Error>> {
+    let mut lines = vec![format!("*{}*: {}", summary.title, summary.headline())];
+    if !summary.countries.is_empty() {
+        lines.push("Top countries:".to_string());
+    }
+    for &(country, count) in &summary.countries {
+        lines.push(format!(
+            "• {country}: {count} ({:.1}%)",
+            summary.share(count)
+        ));
+    }
+    lines.push(summary.link().map_or_else(
+        || format!("Output: `{}`", summary.output),
+        |link| format!("<{link}|Open output>"),
+    ));
+    let text = lines.join("\n");
+    post(url, &json!({ "text": text }), upload)
+}
+
+/// Posts the summary to a Microsoft Teams workflow webhook as an Adaptive
+/// Card.
+pub fn teams(url: &str, summary: &Summary, upload: &Upload) -> Result<(), Box<dyn Error>> {
+    let facts: Vec<Value> = summary
+        .countries
+        .iter()
+        .map(|&(country, count)| {
+            json!({
+                "title": country,
+                "value": format!("{count} ({:.1}%)", summary.share(count)),
+            })
+        })
+        .collect();
+    let mut card = json!({
+        "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+        "type": "AdaptiveCard",
+        "version": "1.4",
+        "body": [
+            { "type": "TextBlock", "text": summary.title, "weight": "Bolder", "size": "Medium" },
+            { "type": "TextBlock", "text": summary.headline(), "wrap": true },
+            { "type": "FactSet", "facts": facts },
+        ],
+    });
+    match summary.link() {
+        Some(link) => {
+            card["actions"] =
+                json!([{ "type": "Action.OpenUrl", "title": "Open output", "url": link }]);
+        }
+        None => {
+            if let Some(body) = card["body"].as_array_mut() {
+                body.push(json!({
+                    "type": "TextBlock",
+                    "text": format!("Output: {}", summary.output),
+                    "wrap": true,
+                }));
+            }
+        }
+    }
+    let message = json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": card,
+        }],
+    });
+    post(url, &message, upload)
+}
+
+/// Where to send an alert: a Slack, Teams or plain JSON webhook.
+pub enum AlertTarget<'a> {
+    Slack(&'a str),
+    Teams(&'a str),
+    Webhook(&'a str),
+}
+
+/// Posts an alert fired by a `--rules` rule. Plain webhooks get a JSON
+/// object with the `title`, `rule` and `message`.
+pub fn alert(
+    target: &AlertTarget,
+    title: &str,
+    alert: &Alert,
+    upload: &Upload,
+) -> Result<(), Box<dyn Error>> {
+    let (rule, message) = (&alert.rule.name, &alert.message);
+    match *target {
+        AlertTarget::Slack(url) => post(
+            url,
+            &json!({ "text": format!(":rotating_light: *{title}* alert `{rule}`: {message}") }),
+            upload,
+        ),
+        AlertTarget::Teams(url) => {
+            let card = json!({
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "body": [
+                    { "type": "TextBlock", "text": format!("{title} alert: {rule}"), "weight": "Bolder", "size": "Medium", "color": "Attention" },
+                    { "type": "TextBlock", "text": message, "wrap": true },
+                ],
+            });
+            let message = json!({
+                "type": "message",
+                "attachments": [{
+                    "contentType": "application/vnd.microsoft.card.adaptive",
+                    "content": card,
+                }],
+            });
+            post(url, &message, upload)
+        }
+        AlertTarget::Webhook(url) => post(
+            url,
+            &json!({ "title": title, "rule": rule, "message": message }),
+            upload,
+        ),
+    }
+}
+
+/// Posts a JSON `message` to a webhook, retrying like output uploads.
+fn post(url: &str, message: &Value, upload: &Upload) -> Result<(), Box<dyn Error>> {
+    let upload = Upload {
+        method: UploadMethod::Post,
+        headers: Vec::new(),
+        retries: upload.retries,
+    };
+    Remote::Http(url).upload(&serde_json::to_vec(message)?, "application/json", &upload)
+}
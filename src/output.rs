@@ -0,0 +1,405 @@
+mod arcs;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod clusters;
+mod csv;
+mod geojson;
+mod json;
+mod markdown;
+#[cfg(feature = "mvt")]
+mod mbtiles;
+mod mvt;
+mod pdf;
+mod pmtiles;
+mod summary;
+mod template;
+mod termmap;
+mod tiles;
+
+use crate::aggregate::{CityData, GroupKey};
+use crate::intel::Offenders;
+use crate::manifest::Provenance;
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::f64::consts::TAU;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+pub use pdf::Logo;
+pub use template::Template;
+
+/// Supported output formats.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Comma separated values with a header row.
+    #[default]
+    Csv,
+    /// A JSON array of location objects.
+    Json,
+    /// A GeoJSON `FeatureCollection` of points.
+    Geojson,
+    /// A human-readable Markdown report with summary statistics, the top
+    /// locations and a per-country breakdown.
+    Markdown,
+    /// A PDF report with summary statistics, a map of the locations, the top
+    /// locations and a per-country breakdown.
+    Pdf,
+    /// An Arrow IPC file (Feather v2) for loading into pandas, Polars or R
+    /// without parsing. Requires the `arrow` feature.
+    Arrow,
+    /// A Braille world map of the locations in ANSI colors, to view in a
+    /// terminal.
+    Termmap,
+    /// JSON clusters of the locations per map tile at each `--zoom` level,
+    /// for web maps to draw without clustering client-side.
+    Clusters,
+    /// Mapbox Vector Tiles of the locations at each `--zoom` level, packaged
+    /// as an MBTiles file. Requires the `mvt` feature.
+    Mvt,
+    /// The vector tiles of `mvt` packaged as a PMTiles archive, for static
+    /// file hosts to serve with HTTP range requests.
+    Pmtiles,
+    /// A GeoJSON `FeatureCollection` of great circle arcs from each location
+    /// to `--target`, for attack flow maps.
+    Arcs,
+}
+
+impl OutputFormat {
+    /// The MIME type of the format, used when uploading outputs.
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Json | Self::Clusters => "application/json",
+            Self::Geojson | Self::Arcs => "application/geo+json",
+            Self::Markdown => "text/markdown",
+            Self::Pdf => "application/pdf",
+            Self::Arrow => "application/vnd.apache.arrow.file",
+            Self::Termmap => "text/plain; charset=utf-8",
+            Self::Mvt => "application/vnd.sqlite3",
+            Self::Pmtiles => "application/vnd.pmtiles",
+        }
+    }
+}
+
+/// Optional columns appended after the original five.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Default, Clone, Copy)]
+pub struct Columns {
+    /// Severity-weighted total count.
+    pub weighted: bool,
+    /// Feed the location was reported by.
+    pub feed: bool,
+    /// Destination port and protocol the threats were aimed at.
+    pub service: bool,
+    /// Category the records were tagged with.
+    pub tag: bool,
+    /// Threats per 100,000 inhabitants of the city.
+    pub per_capita: bool,
+    /// Count multiplied by the country's risk weight; rows are sorted by it.
+    pub risk: bool,
+    /// Known scanner the threats came from.
+    pub known_scanner: bool,
+    /// Cloud provider the threats came from.
+    pub cloud_provider: bool,
+    /// Netblock owner and abuse contact from RDAP.
+    pub rdap: bool,
+    /// Labels added by enrichers.
+    pub tags: bool,
+    /// Flag, place and count, formatted for display.
+    pub label: bool,
+    /// Most frequent destination services, at most this many.
+    pub top_services: Option<usize>,
+    /// How far approximate counts may exceed the true counts.
+    pub count_error: Option<u64>,
+    /// Whether the location's count spiked far above its history.
+    pub anomaly: bool,
+    /// When the location was first observed, and whether it was new.
+    pub seen: bool,
+}
+
+/// Presentation options of the report and map formats.
+pub struct Report<'a> {
+    pub title: &'a str,
+    /// Logo shown in the header of PDF reports.
+    pub logo: Option<&'a Logo>,
+    /// Zoom levels to cluster locations for.
+    pub zoom: RangeInclusive<u8>,
+    /// Latitude and longitude the arcs run to.
+    pub target: Option<(f64, f64)>,
+    /// Top offending IPs listed in Markdown and PDF reports.
+    pub offenders: Option<&'a Offenders>,
+}
+
+/// A single output row: one aggregated location and its enabled columns.
+#[derive(Serialize)]
+pub struct Row<'a> {
+    pub city_name: &'a str,
+    pub country_name: &'a str,
+    pub count: u32,
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weighted_count: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count_error: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub feed: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threats_per_100k: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk_weighted_count: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub known_scanner: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_provider: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netblock_owner: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abuse_contact: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<&'a BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_services: Option<Vec<ServiceCount<'a>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anomaly: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<bool>,
+    /// Custom fields computed by the `--script`.
+    #[serde(flatten)]
+    pub fields: &'a BTreeMap<String, Value>,
+}
+
+/// How many of a location's threats were aimed at a destination service.
+#[derive(Serialize)]
+pub struct ServiceCount<'a> {
+    pub service: &'a str,
+    pub count: u32,
+}
+
+impl<'a> Row<'a> {
+    /// Builds the row for an aggregated entry, keeping only enabled columns.
+    fn new(key: &'a GroupKey, data: &'a CityData, columns: Columns) -> Self {
+        Self {
+            city_name: &data.city_name,
+            country_name: &data.country_name,
+            count: data.total_count,
+            lat: key.location.lat(),
+            lon: key.location.lon(),
+            weighted_count: columns.weighted.then_some(data.weighted_count),
+            count_error: columns.count_error,
+            feed: key.feed.as_deref().filter(|_| columns.feed),
+            service: key.service.as_deref().filter(|_| columns.service),
+            tag: key.tag.as_deref().filter(|_| columns.tag),
+            threats_per_100k: data
+                .population
+                .filter(|_| columns.per_capita)
+                .map(|population| per_100k(data.total_count, population)),
+            risk_weighted_count: data
+                .country_weight
+                .filter(|_| columns.risk)
+                .map(|weight| f64::from(data.total_count) * weight),
+            known_scanner: key
+                .known_scanner
+                .as_deref()
+                .filter(|_| columns.known_scanner),
+            cloud_provider: key
+                .cloud_provider
+                .as_deref()
+                .filter(|_| columns.cloud_provider),
+            netblock_owner: key.netblock_owner.as_deref().filter(|_| columns.rdap),
+            abuse_contact: key.abuse_contact.as_deref().filter(|_| columns.rdap),
+            tags: columns.tags.then_some(&key.tags),
+            label: columns.label.then(|| label(data)),
+            top_services: columns.top_services.map(|n| top_services(data, n)),
+            anomaly: columns.anomaly.then_some(data.anomaly),
+            first_seen: data.first_seen.as_deref().filter(|_| columns.seen),
+            new: columns.seen.then_some(data.new),
+            fields: &data.fields,
+        }
+    }
+}
+
+/// Formats `data` for display, e.g. `🇩🇪 Berlin, Germany (1,234)`, with the
+/// flag left out if the country code isn't known.
+fn label(data: &CityData) -> String {
+    let count = data.total_count.to_string();
+    let mut grouped = String::new();
+    for (i, digit) in count.chars().enumerate() {
+        if i > 0 && (count.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    let flag = data
+        .country_code
+        .as_deref()
+        .and_then(flag)
+        .map(|flag| flag + " ")
+        .unwrap_or_default();
+    format!(
+        "{flag}{}, {} ({grouped})",
+        data.city_name, data.country_name
+    )
+}
+
+/// Returns the `n` services most of the location's threats were aimed at,
+/// most frequent first.
+fn top_services(data: &CityData, n: usize) -> Vec<ServiceCount<'_>> {
+    let mut services: Vec<ServiceCount> = data
+        .services
+        .iter()
+        .map(|(service, &count)| ServiceCount { service, count })
+        .collect();
+    services.sort_by_key(|service| Reverse(service.count));
+    services.truncate(n);
+    services
+}
+
+/// Returns the flag emoji of an ISO 3166-1 alpha-2 country code, spelled
+/// with regional indicator symbols.
+fn flag(code: &str) -> Option<String> {
+    if code.len() != 2 || !code.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    code.bytes()
+        .map(|b| char::from_u32(0x1F1E6 + u32::from(b.to_ascii_uppercase() - b'A')))
+        .collect()
+}
+
+/// Returns `count` per 100,000 of `population`, rounded to three decimals.
+fn per_100k(count: u32, population: u32) -> f64 {
+    (f64::from(count) * 100_000.0 / f64::from(population) * 1000.0).round() / 1000.0
+}
+
+/// Writes the aggregated locations to `writer` in the given `format`,
+/// highest risk-weighted count first if that column is enabled.
+///
+/// With `provenance` set, run metadata is embedded as `#` comment lines
+/// before the CSV header, a top-level `metadata` object wrapping the JSON
+/// `locations` array, or a `metadata` foreign member of the GeoJSON
+/// `FeatureCollection`.
+///
+/// With `jitter` set, rows sharing the same coordinates are spread around
+/// them, see [`spread`].
+pub fn write<'a>(
+    writer: impl Write,
+    format: OutputFormat,
+    entries: impl IntoIterator<Item = (&'a GroupKey, &'a CityData)>,
+    columns: Columns,
+    jitter: Option<f64>,
+    report: &Report,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let rows = rows(entries, columns, jitter).into_iter();
+    match format {
+        OutputFormat::Csv => csv::write(writer, rows, columns, provenance),
+        OutputFormat::Json => json::write(writer, rows, provenance),
+        OutputFormat::Geojson => geojson::write(writer, rows, provenance),
+        OutputFormat::Markdown => markdown::write(writer, rows, report, provenance),
+        OutputFormat::Pdf => pdf::write(writer, rows, report, provenance),
+        OutputFormat::Termmap => termmap::write(writer, rows),
+        OutputFormat::Clusters => clusters::write(writer, rows, report.zoom.clone(), provenance),
+        #[cfg(feature = "mvt")]
+        OutputFormat::Mvt => mbtiles::write(writer, rows, report.zoom.clone(), provenance),
+        #[cfg(not(feature = "mvt"))]
+        OutputFormat::Mvt => Err("MVT output requires building with `--features mvt`".into()),
+        OutputFormat::Pmtiles => pmtiles::write(writer, rows, report.zoom.clone(), provenance),
+        OutputFormat::Arcs => {
+            let target = report.target.ok_or("--format arcs requires --target")?;
+            arcs::write(writer, rows, target, provenance)
+        }
+        #[cfg(feature = "arrow")]
+        OutputFormat::Arrow => arrow::write(writer, rows, columns, provenance),
+        #[cfg(not(feature = "arrow"))]
+        OutputFormat::Arrow => Err("Arrow output requires building with `--features arrow`".into()),
+    }
+}
+
+/// Renders the aggregated locations through a user-supplied `template`,
+/// ordered as [`write`] orders them.
+pub fn render<'a>(
+    writer: impl Write,
+    template: &Template,
+    entries: impl IntoIterator<Item = (&'a GroupKey, &'a CityData)>,
+    columns: Columns,
+    jitter: Option<f64>,
+    provenance: &Provenance,
+) -> Result<(), Box<dyn Error>> {
+    template.write(
+        writer,
+        rows(entries, columns, jitter).into_iter(),
+        provenance,
+    )
+}
+
+/// Builds the output rows, highest risk-weighted count first if that column
+/// is enabled, spread by `jitter` meters if given.
+fn rows<'a>(
+    entries: impl IntoIterator<Item = (&'a GroupKey, &'a CityData)>,
+    columns: Columns,
+    jitter: Option<f64>,
+) -> Vec<Row<'a>> {
+    let mut rows: Vec<Row> = entries
+        .into_iter()
+        .map(|(key, data)| Row::new(key, data, columns))
+        .collect();
+    if columns.risk {
+        let risk = |row: &Row| row.risk_weighted_count.unwrap_or_default();
+        rows.sort_by(|a, b| risk(b).total_cmp(&risk(a)));
+    }
+    if let Some(meters) = jitter {
+        spread(&mut rows, meters);
+    }
+    rows
+}
+
+/// Moves rows sharing the same coordinates, e.g. one per feed, onto a circle
+/// of `meters` radius around them, evenly spaced, so each renders as its own
+/// pin. Rows are placed in an order that doesn't depend on aggregation
+/// order, so repeated runs put them in the same spots.
+#[allow(clippy::cast_precision_loss)] // Far fewer rows than 2^52.
+fn spread<'a>(rows: &mut [Row<'a>], meters: f64) {
+    /// Length of a degree of latitude, and of longitude at the equator.
+    const METERS_PER_DEGREE: f64 = 111_320.0;
+
+    let mut groups: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+    for (i, row) in rows.iter().enumerate() {
+        let point = (row.lat.to_bits(), row.lon.to_bits());
+        groups.entry(point).or_default().push(i);
+    }
+    for mut group in groups.into_values().filter(|group| group.len() > 1) {
+        let order = |row: &Row<'a>| {
+            let dimensions = (row.feed, row.service, row.tag);
+            let registration = (row.netblock_owner, row.abuse_contact);
+            let key = (
+                dimensions,
+                row.known_scanner,
+                row.cloud_provider,
+                registration,
+                row.tags,
+            );
+            (key, row.city_name, row.count)
+        };
+        group.sort_by(|&a, &b| order(&rows[a]).cmp(&order(&rows[b])));
+        let step = TAU / group.len() as f64;
+        for (n, i) in group.into_iter().enumerate() {
+            let row = &mut rows[i];
+            let angle = step * n as f64;
+            let lon_meters = METERS_PER_DEGREE * row.lat.to_radians().cos();
+            row.lon += meters * angle.sin() / lon_meters;
+            row.lat += meters * angle.cos() / METERS_PER_DEGREE;
+        }
+    }
+}
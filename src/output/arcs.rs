@@ -0,0 +1,75 @@
+use super::Row;
+use crate::manifest::Provenance;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::Write;
+
+/// Number of straight segments approximating each arc.
+const SEGMENTS: u32 = 32;
+
+/// Writes the rows as a GeoJSON `FeatureCollection` of `LineString` features
+/// running along the great circle from each location to `target`, given as
+/// latitude and longitude, with every column as a feature property.
+/// Provenance, if given, is added as a `metadata` foreign member of the
+/// collection.
+pub fn write<'a>(
+    writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    target: (f64, f64),
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let features: Vec<Value> = rows
+        .map(|row| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "LineString", "coordinates": arc((row.lat, row.lon), target) },
+                "properties": row,
+            })
+        })
+        .collect();
+    let mut collection = json!({ "type": "FeatureCollection", "features": features });
+    if let Some(metadata) = provenance {
+        collection["metadata"] = serde_json::to_value(metadata)?;
+    }
+    serde_json::to_writer(writer, &collection)?;
+    Ok(())
+}
+
+/// Returns the `[lon, lat]` points of the great circle arc from `from` to
+/// `to`, both given as latitude and longitude.
+///
+/// Longitudes are kept continuous rather than wrapped to ±180, so arcs
+/// crossing the antimeridian are drawn across it instead of around the
+/// world.
+fn arc(from: (f64, f64), to: (f64, f64)) -> Vec<[f64; 2]> {
+    let (start, end) = (unit(from), unit(to));
+    let dot = start.iter().zip(&end).map(|(a, b)| a * b).sum::<f64>();
+    let angle = dot.clamp(-1.0, 1.0).acos();
+    // Too close, or antipodal with no single great circle, to interpolate.
+    if angle < 1e-9 || (std::f64::consts::PI - angle) < 1e-9 {
+        return vec![[from.1, from.0], [to.1, to.0]];
+    }
+    let mut points: Vec<[f64; 2]> = Vec::new();
+    for step in 0..=SEGMENTS {
+        // Spherical linear interpolation between the two unit vectors.
+        let fraction = f64::from(step) / f64::from(SEGMENTS);
+        let from_weight = ((1.0 - fraction) * angle).sin() / angle.sin();
+        let to_weight = (fraction * angle).sin() / angle.sin();
+        let point: Vec<f64> = (0..3)
+            .map(|i| from_weight.mul_add(start[i], to_weight * end[i]))
+            .collect();
+        let lat = point[2].atan2(point[0].hypot(point[1])).to_degrees();
+        let mut lon = point[1].atan2(point[0]).to_degrees();
+        if let Some([previous, _]) = points.last() {
+            lon += ((previous - lon) / 360.0).round() * 360.0;
+        }
+        points.push([lon, lat]);
+    }
+    points
+}
+
+/// Converts a latitude and longitude to a point on the unit sphere.
+fn unit((lat, lon): (f64, f64)) -> [f64; 3] {
+    let (lat, lon) = (lat.to_radians(), lon.to_radians());
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
@@ -0,0 +1,153 @@
+use super::{Columns, Row};
+use crate::manifest::Provenance;
+use arrow_array::builder::{MapBuilder, StringBuilder, UInt32Builder};
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, RecordBatch, StringArray, UInt32Array, UInt64Array,
+};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{ArrowError, Field, Schema};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+
+/// Writes the rows as an Arrow IPC file (Feather v2) holding a single record
+/// batch, with one column per enabled output column, `tags` and
+/// `top_services` as map columns and custom fields as string columns.
+/// Provenance, if given, is stored as JSON under the `metadata` key of the
+/// schema metadata.
+pub fn write<'a>(
+    writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    columns: Columns,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<Row> = rows.collect();
+    let mut fields = Vec::new();
+    let mut arrays: Vec<ArrayRef> = Vec::new();
+    let mut column = |name: &str, array: ArrayRef, nullable: bool| {
+        fields.push(Field::new(name, array.data_type().clone(), nullable));
+        arrays.push(array);
+    };
+    let strings = |value: fn(&Row<'a>) -> Option<&'a str>| -> ArrayRef {
+        Arc::new(rows.iter().map(value).collect::<StringArray>())
+    };
+    let floats = |value: fn(&Row<'a>) -> Option<f64>| -> ArrayRef {
+        Arc::new(rows.iter().map(value).collect::<Float64Array>())
+    };
+
+    column("city_name", strings(|row| Some(row.city_name)), false);
+    column("country_name", strings(|row| Some(row.country_name)), false);
+    let counts = UInt32Array::from_iter_values(rows.iter().map(|row| row.count));
+    column("count", Arc::new(counts), false);
+    column("lat", floats(|row| Some(row.lat)), false);
+    column("lon", floats(|row| Some(row.lon)), false);
+    if columns.weighted {
+        column("weighted_count", floats(|row| row.weighted_count), true);
+    }
+    if columns.count_error.is_some() {
+        let errors: UInt64Array = rows.iter().map(|row| row.count_error).collect();
+        column("count_error", Arc::new(errors), true);
+    }
+    if columns.feed {
+        column("feed", strings(|row| row.feed), true);
+    }
+    if columns.service {
+        column("service", strings(|row| row.service), true);
+    }
+    if columns.tag {
+        column("tag", strings(|row| row.tag), true);
+    }
+    if columns.per_capita {
+        column("threats_per_100k", floats(|row| row.threats_per_100k), true);
+    }
+    if columns.risk {
+        column(
+            "risk_weighted_count",
+            floats(|row| row.risk_weighted_count),
+            true,
+        );
+    }
+    if columns.known_scanner {
+        column("known_scanner", strings(|row| row.known_scanner), true);
+    }
+    if columns.cloud_provider {
+        column("cloud_provider", strings(|row| row.cloud_provider), true);
+    }
+    if columns.rdap {
+        column("netblock_owner", strings(|row| row.netblock_owner), true);
+        column("abuse_contact", strings(|row| row.abuse_contact), true);
+    }
+    if columns.tags {
+        column("tags", tags(&rows)?, false);
+    }
+    if columns.label {
+        let labels: StringArray = rows.iter().map(|row| row.label.as_deref()).collect();
+        column("label", Arc::new(labels), true);
+    }
+    if columns.top_services.is_some() {
+        column("top_services", top_services(&rows)?, false);
+    }
+    if columns.anomaly {
+        let anomalies: BooleanArray = rows.iter().map(|row| row.anomaly).collect();
+        column("anomaly", Arc::new(anomalies), false);
+    }
+    if columns.seen {
+        column("first_seen", strings(|row| row.first_seen), true);
+        let new: BooleanArray = rows.iter().map(|row| row.new).collect();
+        column("new", Arc::new(new), false);
+    }
+    let custom: BTreeSet<&str> = rows
+        .iter()
+        .flat_map(|row| row.fields.keys().map(String::as_str))
+        .collect();
+    for name in custom {
+        let values: StringArray = rows
+            .iter()
+            .map(|row| match row.fields.get(name) {
+                Some(Value::String(value)) => Some(value.clone()),
+                Some(Value::Null) | None => None,
+                Some(value) => Some(value.to_string()),
+            })
+            .collect();
+        column(name, Arc::new(values), true);
+    }
+
+    let mut metadata = HashMap::new();
+    if let Some(provenance) = provenance {
+        metadata.insert("metadata".to_string(), serde_json::to_string(provenance)?);
+    }
+    let schema = Arc::new(Schema::new_with_metadata(fields, metadata));
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+    let mut writer = FileWriter::try_new(writer, &schema)?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Builds the `tags` map column from label name to value.
+fn tags(rows: &[Row]) -> Result<ArrayRef, ArrowError> {
+    let mut tags = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for row in rows {
+        for (key, value) in row.tags.into_iter().flatten() {
+            tags.keys().append_value(key);
+            tags.values().append_value(value);
+        }
+        tags.append(true)?;
+    }
+    Ok(Arc::new(tags.finish()))
+}
+
+/// Builds the `top_services` map column from service to count.
+fn top_services(rows: &[Row]) -> Result<ArrayRef, ArrowError> {
+    let mut services = MapBuilder::new(None, StringBuilder::new(), UInt32Builder::new());
+    for row in rows {
+        for service in row.top_services.iter().flatten() {
+            services.keys().append_value(service.service);
+            services.values().append_value(service.count);
+        }
+        services.append(true)?;
+    }
+    Ok(Arc::new(services.finish()))
+}
@@ -0,0 +1,76 @@
+use super::tiles;
+use super::Row;
+use crate::manifest::Provenance;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// The locations falling into one tile, clustered into a single point.
+#[derive(Serialize)]
+struct Cluster {
+    x: u32,
+    y: u32,
+    count: u64,
+    /// Number of output rows in the cluster.
+    locations: usize,
+    /// Count-weighted centroid of the locations.
+    lat: f64,
+    lon: f64,
+}
+
+/// Writes the rows clustered per Web Mercator tile at each `zoom` level, as
+/// `{"zooms": [{"zoom": 0, "clusters": [...]}, ...]}` with clusters ordered
+/// by tile, so a web map can draw the clusters of its current zoom level
+/// without clustering the locations itself. Provenance, if given, is added
+/// as a top-level `metadata` object.
+pub fn write<'a>(
+    writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    zoom: RangeInclusive<u8>,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<Row> = rows.collect();
+    let zooms: Vec<_> = zoom
+        .map(|zoom| json!({ "zoom": zoom, "clusters": cluster(&rows, zoom) }))
+        .collect();
+    let mut document = json!({ "zooms": zooms });
+    if let Some(metadata) = provenance {
+        document["metadata"] = serde_json::to_value(metadata)?;
+    }
+    serde_json::to_writer(writer, &document)?;
+    Ok(())
+}
+
+/// Clusters `rows` per tile of `zoom`.
+#[allow(clippy::cast_precision_loss)] // Counts are far below 2^52.
+fn cluster(rows: &[Row], zoom: u8) -> Vec<Cluster> {
+    let mut tiles: BTreeMap<(u32, u32), Cluster> = BTreeMap::new();
+    for row in rows {
+        let (x, y) = tiles::tile(row.lat, row.lon, zoom);
+        let cluster = tiles.entry((x, y)).or_insert(Cluster {
+            x,
+            y,
+            count: 0,
+            locations: 0,
+            lat: 0.0,
+            lon: 0.0,
+        });
+        // Sums the weighted coordinates, divided by the count below.
+        cluster.count += u64::from(row.count);
+        cluster.locations += 1;
+        cluster.lat += row.lat * f64::from(row.count);
+        cluster.lon += row.lon * f64::from(row.count);
+    }
+    tiles
+        .into_values()
+        .map(|mut cluster| {
+            let weight = cluster.count.max(1) as f64;
+            cluster.lat /= weight;
+            cluster.lon /= weight;
+            cluster
+        })
+        .collect()
+}
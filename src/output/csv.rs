@@ -0,0 +1,170 @@
+use super::{Columns, Row, ServiceCount};
+use crate::manifest::Provenance;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::io::Write;
+
+/// Writes the rows as CSV.
+///
+/// Optional `columns` are appended after the original five so existing
+/// consumers keep working, followed by the custom fields of any row.
+/// Provenance, if given, is written as `#` comment lines ahead of the header.
+pub fn write<'a>(
+    mut writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    columns: Columns,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(provenance) = provenance {
+        writeln!(writer, "# tool: {} {}", provenance.tool, provenance.version)?;
+        writeln!(writer, "# generated_at: {}", provenance.generated_at)?;
+        writeln!(
+            writer,
+            "# database: {} built {} (epoch {})",
+            provenance.database.kind,
+            provenance
+                .database
+                .build_date
+                .as_deref()
+                .unwrap_or("unknown"),
+            provenance.database.build_epoch
+        )?;
+    }
+
+    let mut wtr = csv::Writer::from_writer(writer);
+    let mut header = header(columns);
+    let rows: Vec<Row> = rows.collect();
+    let fields: BTreeSet<&str> = rows
+        .iter()
+        .flat_map(|row| row.fields.keys().map(String::as_str))
+        .collect();
+    header.extend(&fields);
+    wtr.write_record(&header)?;
+
+    for row in rows {
+        let mut record = vec![
+            row.city_name.to_string(),
+            row.country_name.to_string(),
+            row.count.to_string(),
+            format!("{:.5}", row.lat),
+            format!("{:.5}", row.lon),
+        ];
+        if let Some(weighted_count) = row.weighted_count {
+            record.push(weighted_count.to_string());
+        }
+        if let Some(count_error) = row.count_error {
+            record.push(count_error.to_string());
+        }
+        if columns.feed {
+            record.push(row.feed.unwrap_or_default().to_string());
+        }
+        if columns.service {
+            record.push(row.service.unwrap_or_default().to_string());
+        }
+        if columns.tag {
+            record.push(row.tag.unwrap_or_default().to_string());
+        }
+        if columns.per_capita {
+            let per_100k = row.threats_per_100k;
+            record.push(per_100k.map_or_else(String::new, |value| value.to_string()));
+        }
+        if let Some(risk_weighted_count) = row.risk_weighted_count {
+            record.push(risk_weighted_count.to_string());
+        }
+        if columns.known_scanner {
+            record.push(row.known_scanner.unwrap_or_default().to_string());
+        }
+        if columns.cloud_provider {
+            record.push(row.cloud_provider.unwrap_or_default().to_string());
+        }
+        if columns.rdap {
+            record.push(row.netblock_owner.unwrap_or_default().to_string());
+            record.push(row.abuse_contact.unwrap_or_default().to_string());
+        }
+        if let Some(tags) = row.tags {
+            let tags: Vec<String> = tags
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            record.push(tags.join("; "));
+        }
+        if let Some(label) = row.label {
+            record.push(label);
+        }
+        if let Some(services) = row.top_services {
+            let services: Vec<String> = services
+                .iter()
+                .map(|ServiceCount { service, count }| format!("{service}={count}"))
+                .collect();
+            record.push(services.join("; "));
+        }
+        if let Some(anomaly) = row.anomaly {
+            record.push(anomaly.to_string());
+        }
+        if columns.seen {
+            record.push(row.first_seen.unwrap_or_default().to_string());
+            record.push(row.new.unwrap_or_default().to_string());
+        }
+        record.extend(fields.iter().map(|field| match row.fields.get(*field) {
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        }));
+        wtr.write_record(&record)?;
+    }
+
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Returns the names of the regular and enabled optional columns.
+fn header(columns: Columns) -> Vec<&'static str> {
+    let mut header = vec!["City Name", "Country Name", "Count", "Lat", "Lon"];
+    if columns.weighted {
+        header.push("Weighted Count");
+    }
+    if columns.count_error.is_some() {
+        header.push("Count Error");
+    }
+    if columns.feed {
+        header.push("Feed");
+    }
+    if columns.service {
+        header.push("Service");
+    }
+    if columns.tag {
+        header.push("Tag");
+    }
+    if columns.per_capita {
+        header.push("Threats per 100k");
+    }
+    if columns.risk {
+        header.push("Risk Weighted Count");
+    }
+    if columns.known_scanner {
+        header.push("Known Scanner");
+    }
+    if columns.cloud_provider {
+        header.push("Cloud Provider");
+    }
+    if columns.rdap {
+        header.extend(["Netblock Owner", "Abuse Contact"]);
+    }
+    if columns.tags {
+        header.push("Tags");
+    }
+    if columns.label {
+        header.push("Label");
+    }
+    if columns.top_services.is_some() {
+        header.push("Top Services");
+    }
+    if columns.anomaly {
+        header.push("Anomaly");
+    }
+    if columns.seen {
+        header.extend(["First Seen", "New"]);
+    }
+    header
+}
@@ -0,0 +1,30 @@
+use super::Row;
+use crate::manifest::Provenance;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::io::Write;
+
+/// Writes the rows as a GeoJSON `FeatureCollection` of `Point` features, with
+/// every column as a feature property. Provenance, if given, is added as a
+/// `metadata` foreign member of the collection.
+pub fn write<'a>(
+    writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let features: Vec<Value> = rows
+        .map(|row| {
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [row.lon, row.lat] },
+                "properties": row,
+            })
+        })
+        .collect();
+    let mut collection = json!({ "type": "FeatureCollection", "features": features });
+    if let Some(metadata) = provenance {
+        collection["metadata"] = serde_json::to_value(metadata)?;
+    }
+    serde_json::to_writer(writer, &collection)?;
+    Ok(())
+}
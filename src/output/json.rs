@@ -0,0 +1,23 @@
+use super::Row;
+use crate::manifest::Provenance;
+use serde_json::json;
+use std::error::Error;
+use std::io::Write;
+
+/// Writes the rows as a JSON array of objects, wrapped as
+/// `{"metadata": ..., "locations": [...]}` when provenance is given.
+pub fn write<'a>(
+    writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let locations: Vec<Row> = rows.collect();
+    match provenance {
+        Some(metadata) => serde_json::to_writer(
+            writer,
+            &json!({ "metadata": metadata, "locations": locations }),
+        )?,
+        None => serde_json::to_writer(writer, &locations)?,
+    }
+    Ok(())
+}
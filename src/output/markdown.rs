@@ -0,0 +1,115 @@
+use super::summary::{Summary, TOP_LOCATIONS};
+use super::{Report, Row};
+use crate::manifest::Provenance;
+use std::error::Error;
+use std::io::Write;
+
+/// Writes a human-readable Markdown report: summary statistics, the top
+/// locations by count, the top offending IPs if looked up and a per-country
+/// breakdown, under the report's title.
+/// Provenance, if given, is noted under the title.
+pub fn write<'a>(
+    mut writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    report: &Report,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let summary = Summary::new(rows);
+
+    writeln!(writer, "# {}", report.title)?;
+    writeln!(writer)?;
+    if let Some(provenance) = provenance {
+        writeln!(
+            writer,
+            "_Generated {} by {} {} using {} built {}._",
+            provenance.generated_at,
+            provenance.tool,
+            provenance.version,
+            provenance.database.kind,
+            provenance
+                .database
+                .build_date
+                .as_deref()
+                .unwrap_or("unknown"),
+        )?;
+        writeln!(writer)?;
+    }
+
+    writeln!(writer, "## Summary")?;
+    writeln!(writer)?;
+    writeln!(writer, "- Total threats: {}", summary.total)?;
+    writeln!(writer, "- Locations: {}", summary.rows.len())?;
+    writeln!(writer, "- Countries: {}", summary.countries.len())?;
+    if let Some(top) = summary.rows.first() {
+        writeln!(
+            writer,
+            "- Top location: {}, {} ({} threats, {:.1}%)",
+            escape(top.city_name),
+            escape(top.country_name),
+            top.count,
+            summary.share(top.count.into()),
+        )?;
+    }
+    writeln!(writer)?;
+
+    writeln!(writer, "## Top {TOP_LOCATIONS} Locations")?;
+    writeln!(writer)?;
+    writeln!(writer, "| # | City | Country | Count | Share |")?;
+    writeln!(writer, "|--:|------|---------|------:|------:|")?;
+    for (rank, row) in summary.rows.iter().take(TOP_LOCATIONS).enumerate() {
+        writeln!(
+            writer,
+            "| {} | {} | {} | {} | {:.1}% |",
+            rank + 1,
+            escape(row.city_name),
+            escape(row.country_name),
+            row.count,
+            summary.share(row.count.into()),
+        )?;
+    }
+    writeln!(writer)?;
+
+    if let Some(offenders) = report.offenders {
+        writeln!(writer, "## Top Offenders")?;
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "Source IPs with the most threats, with their open ports and tags from {}.",
+            offenders.service,
+        )?;
+        writeln!(writer)?;
+        writeln!(writer, "| IP | Count | Open Ports | Tags |")?;
+        writeln!(writer, "|----|------:|------------|------|")?;
+        for host in &offenders.hosts {
+            let (ports, tags) = host.info.as_ref().map_or_else(
+                || ("_not found_".to_string(), String::new()),
+                |info| (info.ports_list(), escape(&info.tags.join(", "))),
+            );
+            writeln!(
+                writer,
+                "| {} | {} | {ports} | {tags} |",
+                host.ip, host.count
+            )?;
+        }
+        writeln!(writer)?;
+    }
+
+    writeln!(writer, "## By Country")?;
+    writeln!(writer)?;
+    writeln!(writer, "| Country | Locations | Count | Share |")?;
+    writeln!(writer, "|---------|----------:|------:|------:|")?;
+    for &(country, locations, count) in &summary.countries {
+        writeln!(
+            writer,
+            "| {} | {locations} | {count} | {:.1}% |",
+            escape(country),
+            summary.share(count),
+        )?;
+    }
+    Ok(())
+}
+
+/// Escapes characters that would break a Markdown table cell.
+fn escape(text: &str) -> String {
+    text.replace('|', "\\|")
+}
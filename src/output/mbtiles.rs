@@ -0,0 +1,72 @@
+use super::mvt::{self, LAYER};
+use super::Row;
+use crate::manifest::Provenance;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rusqlite::{params, Connection, MAIN_DB};
+use serde_json::json;
+use std::error::Error;
+use std::io::Write;
+use std::ops::RangeInclusive;
+
+/// Tables of the MBTiles 1.3 format.
+const SCHEMA: &str = "
+CREATE TABLE metadata (name TEXT, value TEXT);
+CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB);
+CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+";
+
+/// Writes the rows as gzipped vector tiles for each `zoom` level, packaged as
+/// an MBTiles file, see [`mvt::encode`]. Provenance, if given, is stored as
+/// JSON under the `metadata` name of the metadata table.
+pub fn write<'a>(
+    mut writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    zoom: RangeInclusive<u8>,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let tileset = mvt::encode(rows, zoom.clone())?;
+    let mut connection = Connection::open_in_memory()?;
+    connection.execute_batch(SCHEMA)?;
+    let transaction = connection.transaction()?;
+    {
+        let mut insert = transaction.prepare("INSERT INTO metadata VALUES (?, ?)")?;
+        let layers = json!({
+            "vector_layers": [{
+                "id": LAYER,
+                "minzoom": zoom.start(),
+                "maxzoom": zoom.end(),
+                "fields": tileset.fields,
+            }],
+        });
+        for (name, value) in [
+            ("name", env!("CARGO_PKG_NAME").to_string()),
+            ("format", "pbf".to_string()),
+            ("type", "overlay".to_string()),
+            ("minzoom", zoom.start().to_string()),
+            ("maxzoom", zoom.end().to_string()),
+            (
+                "bounds",
+                tileset.bounds.map(|bound| bound.to_string()).join(","),
+            ),
+            ("json", layers.to_string()),
+        ] {
+            insert.execute(params![name, value])?;
+        }
+        if let Some(metadata) = provenance {
+            insert.execute(params!["metadata", serde_json::to_string(metadata)?])?;
+        }
+
+        let mut insert = transaction.prepare("INSERT INTO tiles VALUES (?, ?, ?, ?)")?;
+        for ((zoom, column, row), tile) in tileset.tiles {
+            let mut gzipped = GzEncoder::new(Vec::new(), Compression::default());
+            gzipped.write_all(&tile)?;
+            // MBTiles numbers rows from the bottom, as in TMS.
+            let row = (1_u32 << zoom) - 1 - row;
+            insert.execute(params![zoom, column, row, gzipped.finish()?])?;
+        }
+    }
+    transaction.commit()?;
+    writer.write_all(&connection.serialize(MAIN_DB)?)?;
+    Ok(())
+}
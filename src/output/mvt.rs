@@ -0,0 +1,210 @@
+use super::tiles;
+use super::Row;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::ops::RangeInclusive;
+
+/// Name of the layer holding the locations.
+pub const LAYER: &str = "locations";
+
+/// Resolution of tile coordinates.
+const EXTENT: u32 = 4096;
+
+/// A tile by zoom level, column and row, with rows counted from the top.
+pub type TileId = (u8, u32, u32);
+
+/// Encoded vector tiles and the properties their features carry.
+pub struct Tileset {
+    pub tiles: BTreeMap<TileId, Vec<u8>>,
+    /// Type of each property, as `String`, `Number` or `Boolean`, for the
+    /// TileJSON `vector_layers` description.
+    pub fields: BTreeMap<String, &'static str>,
+    /// Minimum longitude and latitude and maximum longitude and latitude of
+    /// the rows, or of the whole map if there are none.
+    pub bounds: [f64; 4],
+}
+
+/// Encodes the rows as a Mapbox Vector Tile point layer for each tile holding
+/// any of them at each `zoom` level. Every column but the coordinates is a
+/// feature property; tags and other structured values are JSON strings.
+pub fn encode<'a>(
+    rows: impl Iterator<Item = Row<'a>>,
+    zoom: RangeInclusive<u8>,
+) -> Result<Tileset, Box<dyn Error>> {
+    let features = rows
+        .map(|row| {
+            let Value::Object(mut properties) = serde_json::to_value(&row)? else {
+                return Err("rows serialize as objects".into());
+            };
+            properties.remove("lat");
+            properties.remove("lon");
+            Ok((row.lat, row.lon, properties))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let mut fields = BTreeMap::new();
+    for (key, value) in features.iter().flat_map(|(.., properties)| properties) {
+        let kind = match value {
+            Value::Null => continue,
+            Value::Number(_) => "Number",
+            Value::Bool(_) => "Boolean",
+            _ => "String",
+        };
+        fields.insert(key.clone(), kind);
+    }
+    let bounds = features.iter().fold(None, |bounds, &(lat, lon, _)| {
+        let [west, south, east, north] = bounds.unwrap_or([lon, lat, lon, lat]);
+        Some([west.min(lon), south.min(lat), east.max(lon), north.max(lat)])
+    });
+    let mut tiles = BTreeMap::new();
+    for zoom in zoom {
+        let mut layers: BTreeMap<(u32, u32), Layer> = BTreeMap::new();
+        for (lat, lon, properties) in &features {
+            let (x, y) = tiles::project(*lat, *lon, zoom);
+            let (column, row) = tiles::tile(*lat, *lon, zoom);
+            #[allow(clippy::cast_possible_truncation)] // Clamped to the tile.
+            let position = |coordinate: f64, tile: u32| {
+                let offset = (coordinate - f64::from(tile)) * f64::from(EXTENT);
+                offset.round().clamp(0.0, f64::from(EXTENT - 1)) as i64
+            };
+            layers.entry((column, row)).or_default().add(
+                position(x, column),
+                position(y, row),
+                properties,
+            );
+        }
+        for ((column, row), layer) in layers {
+            tiles.insert((zoom, column, row), layer.encode());
+        }
+    }
+    Ok(Tileset {
+        tiles,
+        fields,
+        bounds: bounds.unwrap_or([-180.0, -tiles::MAX_LATITUDE, 180.0, tiles::MAX_LATITUDE]),
+    })
+}
+
+/// A layer being built, with its property keys and values interned.
+#[derive(Default)]
+struct Layer {
+    features: Vec<Vec<u8>>,
+    keys: Vec<String>,
+    key_indices: HashMap<String, u32>,
+    values: Vec<Vec<u8>>,
+    value_indices: HashMap<Vec<u8>, u32>,
+}
+
+impl Layer {
+    /// Adds a point feature at tile coordinates `x` and `y`.
+    fn add(&mut self, x: i64, y: i64, properties: &Map<String, Value>) {
+        let mut tags = Vec::new();
+        for (key, value) in properties {
+            let Some(value) = encode_value(value) else {
+                continue;
+            };
+            tags.push(intern(&mut self.keys, &mut self.key_indices, key.clone()));
+            tags.push(intern(&mut self.values, &mut self.value_indices, value));
+        }
+        let mut feature = Vec::new();
+        field_varint(&mut feature, 1, self.features.len() as u64 + 1);
+        field_packed(&mut feature, 2, tags.into_iter().map(u64::from));
+        // GeomType POINT.
+        field_varint(&mut feature, 3, 1);
+        // A single MoveTo command followed by the zigzag encoded position.
+        field_packed(&mut feature, 4, [9, zigzag(x), zigzag(y)]);
+        self.features.push(feature);
+    }
+
+    /// Encodes the layer as a complete tile.
+    fn encode(self) -> Vec<u8> {
+        let mut layer = Vec::new();
+        field_varint(&mut layer, 15, 2);
+        field_bytes(&mut layer, 1, LAYER.as_bytes());
+        for feature in &self.features {
+            field_bytes(&mut layer, 2, feature);
+        }
+        for key in &self.keys {
+            field_bytes(&mut layer, 3, key.as_bytes());
+        }
+        for value in &self.values {
+            field_bytes(&mut layer, 4, value);
+        }
+        field_varint(&mut layer, 5, u64::from(EXTENT));
+        let mut tile = Vec::new();
+        field_bytes(&mut tile, 3, &layer);
+        tile
+    }
+}
+
+/// Returns the index of `item` in `items`, adding it if it's new.
+fn intern<T: Clone + Eq + std::hash::Hash>(
+    items: &mut Vec<T>,
+    indices: &mut HashMap<T, u32>,
+    item: T,
+) -> u32 {
+    *indices.entry(item).or_insert_with_key(|item| {
+        items.push(item.clone());
+        u32::try_from(items.len() - 1).unwrap_or(u32::MAX)
+    })
+}
+
+/// Encodes a property value as a vector tile `Value` message, or `None` for
+/// nulls, which are left out.
+fn encode_value(value: &Value) -> Option<Vec<u8>> {
+    let mut encoded = Vec::new();
+    match value {
+        Value::Null => return None,
+        Value::String(text) => field_bytes(&mut encoded, 1, text.as_bytes()),
+        Value::Bool(flag) => field_varint(&mut encoded, 7, u64::from(*flag)),
+        Value::Number(number) => {
+            if let Some(unsigned) = number.as_u64() {
+                field_varint(&mut encoded, 5, unsigned);
+            } else if let Some(signed) = number.as_i64() {
+                field_varint(&mut encoded, 6, zigzag(signed));
+            } else {
+                let double = number.as_f64().unwrap_or_default();
+                encoded.push(3 << 3 | 1);
+                encoded.extend_from_slice(&double.to_le_bytes());
+            }
+        }
+        structured => field_bytes(&mut encoded, 1, structured.to_string().as_bytes()),
+    }
+    Some(encoded)
+}
+
+/// Maps a signed integer to an unsigned one, small magnitudes first.
+#[allow(clippy::cast_sign_loss)] // Reinterprets the bits by design.
+const fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Appends `value` as a Protocol Buffers base 128 varint.
+pub fn varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        // Low seven bits with the continuation bit set.
+        #[allow(clippy::cast_possible_truncation)]
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    buffer.push(value as u8);
+}
+
+fn field_varint(buffer: &mut Vec<u8>, field: u64, value: u64) {
+    varint(buffer, field << 3);
+    varint(buffer, value);
+}
+
+fn field_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    varint(buffer, field << 3 | 2);
+    varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn field_packed(buffer: &mut Vec<u8>, field: u64, values: impl IntoIterator<Item = u64>) {
+    let mut packed = Vec::new();
+    for value in values {
+        varint(&mut packed, value);
+    }
+    field_bytes(buffer, field, &packed);
+}
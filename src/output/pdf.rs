@@ -0,0 +1,437 @@
+// Page coordinates are `f32` points; counts and coordinates lose nothing
+// visible in the conversion.
+#![allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+
+use super::summary::{Summary, TOP_LOCATIONS};
+use super::{Report, Row};
+use crate::intel::Offenders;
+use crate::manifest::Provenance;
+use pdf_writer::{Content, Filter, Finish, Name, Pdf, Rect, Ref, Str};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// A4 page size and margin, in points.
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 40.0;
+const CONTENT_WIDTH: f32 = PAGE_WIDTH - 2.0 * MARGIN;
+
+/// Resource names of the fonts and the logo.
+const REGULAR: Name = Name(b"F1");
+const BOLD: Name = Name(b"F2");
+const LOGO: Name = Name(b"Im1");
+
+/// Height the logo is drawn at, in points.
+const LOGO_HEIGHT: f32 = 40.0;
+
+/// A JPEG image shown in the report header.
+pub struct Logo {
+    data: Vec<u8>,
+    width: u16,
+    height: u16,
+    /// 1 for grayscale, 3 for RGB.
+    components: u8,
+}
+
+impl Logo {
+    /// Loads the JPEG image at `path`.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read(path)?;
+        let (width, height, components) = jpeg_frame(&data)
+            .filter(|&(width, height, components)| {
+                width > 0 && height > 0 && matches!(components, 1 | 3)
+            })
+            .ok_or_else(|| format!("{} is not a grayscale or RGB JPEG image", path.display()))?;
+        Ok(Self {
+            data,
+            width,
+            height,
+            components,
+        })
+    }
+}
+
+/// Reads the width, height and number of color components from the frame
+/// header of a JPEG image.
+fn jpeg_frame(data: &[u8]) -> Option<(u16, u16, u8)> {
+    if data.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut offset = 2;
+    loop {
+        let marker = *data.get(offset + 1)?;
+        if data.get(offset) != Some(&0xFF) {
+            return None;
+        }
+        let length = usize::from(u16::from_be_bytes([
+            *data.get(offset + 2)?,
+            *data.get(offset + 3)?,
+        ]));
+        // Start-of-frame markers, excluding DHT, JPG and DAC.
+        if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+            let frame = data.get(offset + 4..offset + 10)?;
+            let height = u16::from_be_bytes([frame[1], frame[2]]);
+            let width = u16::from_be_bytes([frame[3], frame[4]]);
+            return Some((width, height, frame[5]));
+        }
+        offset += 2 + length;
+    }
+}
+
+/// Content streams of the pages written so far and the vertical position on
+/// the current page.
+struct Pages {
+    done: Vec<Content>,
+    current: Content,
+    y: f32,
+}
+
+impl Pages {
+    fn new() -> Self {
+        Self {
+            done: Vec::new(),
+            current: Content::new(),
+            y: PAGE_HEIGHT - MARGIN,
+        }
+    }
+
+    /// Moves down by `height` points, starting a new page first if that
+    /// would run into the bottom margin, and returns the new position.
+    fn advance(&mut self, height: f32) -> f32 {
+        if self.y - height < MARGIN {
+            self.done
+                .push(std::mem::replace(&mut self.current, Content::new()));
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+        self.y -= height;
+        self.y
+    }
+
+    /// Writes `text` with its baseline at `x`, `y`.
+    fn text(&mut self, x: f32, y: f32, font: Name, size: f32, text: &str) {
+        self.current
+            .begin_text()
+            .set_font(font, size)
+            .next_line(x, y)
+            .show(Str(&encode(text)))
+            .end_text();
+    }
+
+    /// Writes a line of `text` below the current position.
+    fn line(&mut self, font: Name, size: f32, text: &str) {
+        let y = self.advance(size * 1.5);
+        self.text(MARGIN, y, font, size, text);
+    }
+
+    /// Writes a table row with cells starting at the given offsets from the
+    /// left margin.
+    fn row(&mut self, font: Name, cells: &[(f32, &str)]) {
+        let y = self.advance(14.0);
+        for &(x, cell) in cells {
+            self.text(MARGIN + x, y, font, 9.0, cell);
+        }
+    }
+
+    fn finish(mut self) -> Vec<Content> {
+        self.done.push(self.current);
+        self.done
+    }
+}
+
+/// Writes a PDF report for management reporting: the report's title and
+/// logo, summary statistics, a map of the locations, the top locations by
+/// count, the top offending IPs if looked up and a per-country breakdown.
+/// Provenance, if given, is noted under the title.
+pub fn write<'a>(
+    mut writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    report: &Report,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let summary = Summary::new(rows);
+    let mut pages = Pages::new();
+
+    if let Some(logo) = report.logo {
+        let width = LOGO_HEIGHT * f32::from(logo.width) / f32::from(logo.height);
+        let (x, y) = (
+            PAGE_WIDTH - MARGIN - width,
+            PAGE_HEIGHT - MARGIN - LOGO_HEIGHT,
+        );
+        pages
+            .current
+            .save_state()
+            .transform([width, 0.0, 0.0, LOGO_HEIGHT, x, y])
+            .x_object(LOGO)
+            .restore_state();
+    }
+    pages.line(BOLD, 20.0, report.title);
+    if let Some(provenance) = provenance {
+        pages.line(
+            REGULAR,
+            9.0,
+            &format!(
+                "Generated {} by {} {} using {} built {}",
+                provenance.generated_at,
+                provenance.tool,
+                provenance.version,
+                provenance.database.kind,
+                provenance
+                    .database
+                    .build_date
+                    .as_deref()
+                    .unwrap_or("unknown"),
+            ),
+        );
+    }
+    pages.advance(10.0);
+
+    pages.line(BOLD, 14.0, "Summary");
+    pages.line(REGULAR, 10.0, &format!("Total threats: {}", summary.total));
+    pages.line(REGULAR, 10.0, &format!("Locations: {}", summary.rows.len()));
+    pages.line(
+        REGULAR,
+        10.0,
+        &format!("Countries: {}", summary.countries.len()),
+    );
+    if let Some(top) = summary.rows.first() {
+        pages.line(
+            REGULAR,
+            10.0,
+            &format!(
+                "Top location: {}, {} ({} threats, {:.1}%)",
+                top.city_name,
+                top.country_name,
+                top.count,
+                summary.share(top.count.into()),
+            ),
+        );
+    }
+    pages.advance(10.0);
+
+    draw_map(&mut pages, &summary.rows);
+    pages.advance(10.0);
+
+    pages.line(BOLD, 14.0, &format!("Top {TOP_LOCATIONS} Locations"));
+    let columns = [0.0, 25.0, 215.0, 395.0, 455.0];
+    let header = ["#", "City", "Country", "Count", "Share"];
+    pages.row(BOLD, &cells(&columns, &header.map(String::from)));
+    for (rank, row) in summary.rows.iter().take(TOP_LOCATIONS).enumerate() {
+        let values = [
+            (rank + 1).to_string(),
+            truncate(row.city_name, 36),
+            truncate(row.country_name, 34),
+            row.count.to_string(),
+            format!("{:.1}%", summary.share(row.count.into())),
+        ];
+        pages.row(REGULAR, &cells(&columns, &values));
+    }
+    pages.advance(10.0);
+
+    if let Some(offenders) = report.offenders {
+        draw_offenders(&mut pages, offenders);
+        pages.advance(10.0);
+    }
+
+    pages.line(BOLD, 14.0, "By Country");
+    let columns = [0.0, 215.0, 305.0, 395.0];
+    let header = ["Country", "Locations", "Count", "Share"];
+    pages.row(BOLD, &cells(&columns, &header.map(String::from)));
+    for &(country, locations, count) in &summary.countries {
+        let values = [
+            truncate(country, 40),
+            locations.to_string(),
+            count.to_string(),
+            format!("{:.1}%", summary.share(count)),
+        ];
+        pages.row(REGULAR, &cells(&columns, &values));
+    }
+
+    writer.write_all(&assemble(pages.finish(), report.logo))?;
+    Ok(())
+}
+
+/// Lists the top offending IPs with their open ports and tags.
+fn draw_offenders(pages: &mut Pages, offenders: &Offenders) {
+    pages.line(BOLD, 14.0, "Top Offenders");
+    pages.line(
+        REGULAR,
+        9.0,
+        &format!("Open ports and tags from {}", offenders.service),
+    );
+    let columns = [0.0, 175.0, 235.0, 385.0];
+    let header = ["IP", "Count", "Open Ports", "Tags"];
+    pages.row(BOLD, &cells(&columns, &header.map(String::from)));
+    for host in &offenders.hosts {
+        let (ports, tags) = host.info.as_ref().map_or_else(
+            || ("not found".to_string(), String::new()),
+            |info| (info.ports_list(), info.tags.join(", ")),
+        );
+        let values = [
+            host.ip.to_string(),
+            host.count.to_string(),
+            truncate(&ports, 28),
+            truncate(&tags, 28),
+        ];
+        pages.row(REGULAR, &cells(&columns, &values));
+    }
+}
+
+/// Pairs each cell value with its column offset.
+fn cells<'a>(columns: &[f32], values: &'a [String]) -> Vec<(f32, &'a str)> {
+    columns
+        .iter()
+        .zip(values)
+        .map(|(&x, value)| (x, value.as_str()))
+        .collect()
+}
+
+/// Draws the locations as circles sized by count on an equirectangular
+/// world grid spanning the content width.
+fn draw_map(pages: &mut Pages, rows: &[Row]) {
+    let (width, height) = (CONTENT_WIDTH, CONTENT_WIDTH / 2.0);
+    let bottom = pages.advance(height);
+    let project = |lat: f64, lon: f64| {
+        (
+            (((lon + 180.0) / 360.0) as f32).mul_add(width, MARGIN),
+            (((lat + 90.0) / 180.0) as f32).mul_add(height, bottom),
+        )
+    };
+    let map = &mut pages.current;
+    map.set_fill_rgb(0.93, 0.95, 0.98)
+        .rect(MARGIN, bottom, width, height)
+        .fill_nonzero();
+
+    // Graticule every 30 degrees.
+    map.set_stroke_rgb(0.8, 0.83, 0.88).set_line_width(0.5);
+    for lon in (-150..=150).step_by(30) {
+        let (x, _) = project(0.0, f64::from(lon));
+        map.move_to(x, bottom).line_to(x, bottom + height);
+    }
+    for lat in (-60..=60).step_by(30) {
+        let (_, y) = project(f64::from(lat), 0.0);
+        map.move_to(MARGIN, y).line_to(MARGIN + width, y);
+    }
+    map.stroke();
+    map.set_stroke_rgb(0.6, 0.63, 0.68)
+        .rect(MARGIN, bottom, width, height)
+        .stroke();
+
+    // Largest first, so smaller circles stay visible on top.
+    let max = rows.first().map_or(1, |row| row.count.max(1));
+    map.set_fill_rgb(0.85, 0.15, 0.15)
+        .set_stroke_rgb(1.0, 1.0, 1.0)
+        .set_line_width(0.5);
+    for row in rows {
+        let (x, y) = project(row.lat, row.lon);
+        let radius = 10.0f32.mul_add((row.count as f32 / max as f32).sqrt(), 2.0);
+        circle(map, x, y, radius);
+        map.fill_nonzero_and_stroke();
+    }
+
+    let caption = pages.advance(12.0);
+    pages.text(
+        MARGIN,
+        caption,
+        REGULAR,
+        8.0,
+        "Threat locations, circle area proportional to count (equirectangular projection)",
+    );
+}
+
+/// Adds a circle path approximated by four Bézier curves.
+fn circle(content: &mut Content, x: f32, y: f32, radius: f32) {
+    // Control point distance for a quarter circle.
+    let k = 0.552_284_8 * radius;
+    content
+        .move_to(x + radius, y)
+        .cubic_to(x + radius, y + k, x + k, y + radius, x, y + radius)
+        .cubic_to(x - k, y + radius, x - radius, y + k, x - radius, y)
+        .cubic_to(x - radius, y - k, x - k, y - radius, x, y - radius)
+        .cubic_to(x + k, y - radius, x + radius, y - k, x + radius, y)
+        .close_path();
+}
+
+/// Assembles the document from the page contents, with the standard
+/// Helvetica fonts and the logo, if any, as shared page resources.
+fn assemble(pages: Vec<Content>, logo: Option<&Logo>) -> Vec<u8> {
+    let catalog = Ref::new(1);
+    let tree = Ref::new(2);
+    let regular = Ref::new(3);
+    let bold = Ref::new(4);
+    let image = Ref::new(5);
+    let mut next = 6;
+    let mut ids = Vec::new();
+    for _ in &pages {
+        ids.push((Ref::new(next), Ref::new(next + 1)));
+        next += 2;
+    }
+
+    let mut pdf = Pdf::new();
+    pdf.catalog(catalog).pages(tree);
+    pdf.pages(tree)
+        .kids(ids.iter().map(|&(page, _)| page))
+        .count(i32::try_from(ids.len()).unwrap_or(i32::MAX));
+    for (content, &(page_id, content_id)) in pages.into_iter().zip(&ids) {
+        let mut page = pdf.page(page_id);
+        page.parent(tree)
+            .media_box(Rect::new(0.0, 0.0, PAGE_WIDTH, PAGE_HEIGHT))
+            .contents(content_id);
+        let mut resources = page.resources();
+        resources.fonts().pair(REGULAR, regular).pair(BOLD, bold);
+        if logo.is_some() {
+            resources.x_objects().pair(LOGO, image);
+        }
+        resources.finish();
+        page.finish();
+        pdf.stream(content_id, &content.finish());
+    }
+    for (id, font) in [(regular, "Helvetica"), (bold, "Helvetica-Bold")] {
+        pdf.type1_font(id)
+            .base_font(Name(font.as_bytes()))
+            .encoding_predefined(Name(b"WinAnsiEncoding"));
+    }
+    if let Some(logo) = logo {
+        let mut xobject = pdf.image_xobject(image, &logo.data);
+        xobject.filter(Filter::DctDecode);
+        xobject.width(logo.width.into());
+        xobject.height(logo.height.into());
+        xobject.bits_per_component(8);
+        if logo.components == 1 {
+            xobject.color_space().device_gray();
+        } else {
+            xobject.color_space().device_rgb();
+        }
+    }
+    pdf.finish()
+}
+
+/// Encodes `text` for the standard fonts' `WinAnsiEncoding`, which agrees
+/// with Latin-1 for printable characters and adds common typographic ones;
+/// anything else becomes `?`.
+fn encode(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match (c, u8::try_from(u32::from(c))) {
+            ('€', _) => 0x80,
+            ('‘', _) => 0x91,
+            ('’', _) => 0x92,
+            ('“', _) => 0x93,
+            ('”', _) => 0x94,
+            ('•', _) => 0x95,
+            ('–', _) => 0x96,
+            ('—', _) => 0x97,
+            (_, Ok(byte)) if byte >= 0x20 && !(0x7F..0xA0).contains(&byte) => byte,
+            _ => b'?',
+        })
+        .collect()
+}
+
+/// Shortens `text` to at most `max` characters, marking the cut with `...`.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let kept: String = text.chars().take(max.saturating_sub(3)).collect();
+    format!("{kept}...")
+}
@@ -0,0 +1,212 @@
+use super::mvt::{self, varint, LAYER};
+use super::Row;
+use crate::manifest::Provenance;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+/// Size of the fixed header of the PMTiles 3 format.
+const HEADER_LEN: usize = 127;
+
+/// Readers fetch the header and root directory with a single request of
+/// this many bytes, so the root directory has to fit in what's left.
+const ROOT_LEN: usize = 16_384 - HEADER_LEN;
+
+/// Number of entries in each leaf directory, doubled until the root
+/// directory of them fits.
+const LEAF_ENTRIES: usize = 4096;
+
+/// The `gzip` compression and `mvt` tile type codes of the header.
+const GZIP: u8 = 2;
+const MVT: u8 = 1;
+
+/// A directory entry for a run of tiles with the same content, or for a
+/// leaf directory if `run_length` is 0.
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u64,
+    run_length: u64,
+}
+
+/// Writes the rows as gzipped vector tiles for each `zoom` level, packaged as
+/// a single PMTiles archive that web maps read straight from a static file
+/// host with HTTP range requests, see [`mvt::encode`]. Provenance, if given,
+/// is stored under the `metadata` key of the archive's JSON metadata.
+pub fn write<'a>(
+    mut writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+    zoom: RangeInclusive<u8>,
+    provenance: Option<&Provenance>,
+) -> Result<(), Box<dyn Error>> {
+    let tileset = mvt::encode(rows, zoom.clone())?;
+
+    // Tiles ordered by ID, with identical ones stored once.
+    let mut tiles = tileset
+        .tiles
+        .into_iter()
+        .map(|((zoom, x, y), tile)| (tile_id(zoom, x, y), tile))
+        .collect::<Vec<_>>();
+    tiles.sort_unstable_by_key(|&(id, _)| id);
+    let mut data = Vec::new();
+    let mut offsets: HashMap<Vec<u8>, (u64, u64)> = HashMap::new();
+    let mut entries: Vec<Entry> = Vec::new();
+    for (tile_id, tile) in &tiles {
+        let gzipped = gzip(tile)?;
+        let (offset, length) = *offsets.entry(gzipped).or_insert_with_key(|gzipped| {
+            data.extend_from_slice(gzipped);
+            let length = gzipped.len() as u64;
+            (data.len() as u64 - length, length)
+        });
+        match entries.last_mut() {
+            Some(last) if last.offset == offset && last.tile_id + last.run_length == *tile_id => {
+                last.run_length += 1;
+            }
+            _ => entries.push(Entry {
+                tile_id: *tile_id,
+                offset,
+                length,
+                run_length: 1,
+            }),
+        }
+    }
+    let (root, leaves) = directories(&entries)?;
+
+    let mut metadata = json!({
+        "name": env!("CARGO_PKG_NAME"),
+        "type": "overlay",
+        "vector_layers": [{
+            "id": LAYER,
+            "minzoom": zoom.start(),
+            "maxzoom": zoom.end(),
+            "fields": tileset.fields,
+        }],
+    });
+    if let Some(provenance) = provenance {
+        metadata["metadata"] = serde_json::to_value(provenance)?;
+    }
+    let metadata = gzip(&serde_json::to_vec(&metadata)?)?;
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(b"PMTiles\x03");
+    let mut offset = HEADER_LEN as u64;
+    for section in [&root, &metadata, &leaves, &data] {
+        let length = section.len() as u64;
+        header.extend_from_slice(&offset.to_le_bytes());
+        header.extend_from_slice(&length.to_le_bytes());
+        offset += length;
+    }
+    let contents = offsets.len() as u64;
+    for count in [tiles.len() as u64, entries.len() as u64, contents] {
+        header.extend_from_slice(&count.to_le_bytes());
+    }
+    // Clustered, as tile data is in tile ID order.
+    header.extend_from_slice(&[1, GZIP, GZIP, MVT, *zoom.start(), *zoom.end()]);
+    let [west, south, east, north] = tileset.bounds;
+    for bound in [west, south, east, north] {
+        header.extend_from_slice(&e7(bound).to_le_bytes());
+    }
+    header.push(*zoom.start());
+    for center in [(west + east) / 2.0, (south + north) / 2.0] {
+        header.extend_from_slice(&e7(center).to_le_bytes());
+    }
+
+    for section in [&header, &root, &metadata, &leaves, &data] {
+        writer.write_all(section)?;
+    }
+    Ok(())
+}
+
+/// Returns the ID of a tile: its position along a Hilbert curve over its
+/// zoom level, after the tiles of all lower zoom levels.
+fn tile_id(zoom: u8, mut x: u32, mut y: u32) -> u64 {
+    let mut id = ((1_u64 << (2 * u32::from(zoom))) - 1) / 3;
+    let size = 1_u32 << zoom;
+    let mut half = size / 2;
+    while half > 0 {
+        let (right, bottom) = (x & half > 0, y & half > 0);
+        id += u64::from(half)
+            * u64::from(half)
+            * u64::from((3 * u32::from(right)) ^ u32::from(bottom));
+        if !bottom {
+            if right {
+                (x, y) = (size - 1 - x, size - 1 - y);
+            }
+            (x, y) = (y, x);
+        }
+        half /= 2;
+    }
+    id
+}
+
+/// Serializes the entries into a gzipped root directory, moving them into
+/// leaf directories if they don't fit. Returns the root and the leaves.
+fn directories(entries: &[Entry]) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let root = directory(entries)?;
+    if root.len() <= ROOT_LEN {
+        return Ok((root, Vec::new()));
+    }
+    let mut leaf_entries = LEAF_ENTRIES;
+    loop {
+        let mut leaves = Vec::new();
+        let mut root = Vec::new();
+        for chunk in entries.chunks(leaf_entries) {
+            let leaf = directory(chunk)?;
+            root.push(Entry {
+                tile_id: chunk[0].tile_id,
+                offset: leaves.len() as u64,
+                length: leaf.len() as u64,
+                run_length: 0,
+            });
+            leaves.extend(leaf);
+        }
+        let root = directory(&root)?;
+        if root.len() <= ROOT_LEN {
+            return Ok((root, leaves));
+        }
+        leaf_entries *= 2;
+    }
+}
+
+/// Serializes the entries as a gzipped directory: their count, then each
+/// field of all entries in turn as varints, with tile IDs delta encoded and
+/// offsets as 0 if the tile directly follows the previous one.
+fn directory(entries: &[Entry]) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    varint(&mut buffer, entries.len() as u64);
+    let mut last_id = 0;
+    for entry in entries {
+        varint(&mut buffer, entry.tile_id - last_id);
+        last_id = entry.tile_id;
+    }
+    for entry in entries {
+        varint(&mut buffer, entry.run_length);
+    }
+    for entry in entries {
+        varint(&mut buffer, entry.length);
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        let follows = i > 0 && {
+            let previous = &entries[i - 1];
+            entry.offset == previous.offset + previous.length
+        };
+        varint(&mut buffer, if follows { 0 } else { entry.offset + 1 });
+    }
+    gzip(&buffer)
+}
+
+fn gzip(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+/// Converts degrees to the fixed-point units of the header.
+#[allow(clippy::cast_possible_truncation)] // Within ±180 degrees.
+fn e7(degrees: f64) -> i32 {
+    (degrees * 1e7).round() as i32
+}
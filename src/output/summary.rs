@@ -0,0 +1,57 @@
+use super::Row;
+use std::collections::HashMap;
+
+/// Number of locations listed in the top locations table of reports.
+pub const TOP_LOCATIONS: usize = 20;
+
+/// Totals shared by the human-readable report formats.
+pub struct Summary<'a> {
+    /// Rows by count, highest first.
+    pub rows: Vec<Row<'a>>,
+    /// Sum of all counts.
+    pub total: u64,
+    /// Country name, number of locations and count per country, highest
+    /// count first.
+    pub countries: Vec<(&'a str, usize, u64)>,
+}
+
+impl<'a> Summary<'a> {
+    /// Summarizes the rows.
+    pub fn new(rows: impl Iterator<Item = Row<'a>>) -> Self {
+        let mut rows: Vec<Row> = rows.collect();
+        rows.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.country_name.cmp(b.country_name))
+                .then_with(|| a.city_name.cmp(b.city_name))
+        });
+        let total = rows.iter().map(|row| u64::from(row.count)).sum();
+        let mut countries: HashMap<&str, (usize, u64)> = HashMap::new();
+        for row in &rows {
+            let country = countries.entry(row.country_name).or_default();
+            country.0 += 1;
+            country.1 += u64::from(row.count);
+        }
+        let mut countries: Vec<(&str, usize, u64)> = countries
+            .into_iter()
+            .map(|(name, (locations, count))| (name, locations, count))
+            .collect();
+        countries.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.cmp(b.0)));
+        Self {
+            rows,
+            total,
+            countries,
+        }
+    }
+
+    /// Returns `count` as a percentage of all threats.
+    pub fn share(&self, count: u64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        // Counts are far below 2^52, so the conversion is exact.
+        #[allow(clippy::cast_precision_loss)]
+        let share = count as f64 * 100.0 / self.total as f64;
+        share
+    }
+}
@@ -0,0 +1,69 @@
+use super::Row;
+use crate::manifest::Provenance;
+use handlebars::Handlebars;
+use serde_json::json;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Name the user's template is registered under.
+const NAME: &str = "report";
+
+/// A user-supplied Handlebars template rendering the aggregated locations
+/// into a custom report.
+pub struct Template {
+    registry: Handlebars<'static>,
+    content_type: &'static str,
+}
+
+impl Template {
+    /// Loads and compiles the template at `path`. The MIME type of the
+    /// rendered report is guessed from the extension preceding `.hbs`, e.g.
+    /// `report.html.hbs`, or of the template itself.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut registry = Handlebars::new();
+        registry
+            .register_template_string(NAME, fs::read_to_string(path)?)
+            .map_err(|e| format!("{}: {e}", path.display()))?;
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let name = name.strip_suffix(".hbs").unwrap_or(&name);
+        let content_type = match Path::new(name).extension().and_then(|e| e.to_str()) {
+            Some("html" | "htm") => "text/html",
+            Some("md" | "markdown") => "text/markdown",
+            Some("xml") => "application/xml",
+            Some("json") => "application/json",
+            Some("csv") => "text/csv",
+            _ => "text/plain",
+        };
+        Ok(Self {
+            registry,
+            content_type,
+        })
+    }
+
+    /// The MIME type of the rendered report, used when uploading it.
+    pub const fn content_type(&self) -> &'static str {
+        self.content_type
+    }
+
+    /// Renders the rows with the template. The template sees `locations`, an
+    /// array of rows with the same fields as JSON output, `total_count`, the
+    /// sum of their counts, and the run's `metadata`.
+    pub fn write<'a>(
+        &self,
+        writer: impl Write,
+        rows: impl Iterator<Item = Row<'a>>,
+        provenance: &Provenance,
+    ) -> Result<(), Box<dyn Error>> {
+        let locations: Vec<Row> = rows.collect();
+        let total_count: u64 = locations.iter().map(|row| u64::from(row.count)).sum();
+        let data = json!({
+            "metadata": provenance,
+            "locations": locations,
+            "total_count": total_count,
+        });
+        self.registry.render_to_write(NAME, &data, writer)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use super::Row;
+use crate::tui;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use ratatui::widgets::Widget;
+use std::error::Error;
+use std::io::Write;
+
+/// Size of the map in terminal cells; Braille gives 2x4 dots per cell, so
+/// this keeps the 2:1 aspect of the projection.
+const WIDTH: u16 = 100;
+const HEIGHT: u16 = 25;
+
+/// Writes a Braille world map with the locations marked in hotter colors the
+/// more threats they count, using ANSI colors, followed by a legend. Meant
+/// for a quick look with `cat` or `less -R`.
+pub fn write<'a>(
+    mut writer: impl Write,
+    rows: impl Iterator<Item = Row<'a>>,
+) -> Result<(), Box<dyn Error>> {
+    let points: Vec<(f64, f64, u32)> = rows.map(|row| (row.lon, row.lat, row.count)).collect();
+    let area = Rect::new(0, 0, WIDTH, HEIGHT);
+    let mut buffer = Buffer::empty(area);
+    tui::heatmap(&points).render(area, &mut buffer);
+
+    for y in 0..HEIGHT {
+        let mut color = Color::Reset;
+        for x in 0..WIDTH {
+            let cell = &buffer[(x, y)];
+            if cell.fg != color {
+                color = cell.fg;
+                write!(writer, "\x1b[{}m", ansi(color))?;
+            }
+            write!(writer, "{}", cell.symbol())?;
+        }
+        writeln!(writer, "\x1b[0m")?;
+    }
+
+    let total: u64 = points.iter().map(|&(.., count)| u64::from(count)).sum();
+    writeln!(writer, "{total} threats at {} locations", points.len())?;
+    let max = points.iter().map(|&(.., count)| count).max().unwrap_or(1);
+    for (color, range) in tui::intensities(max) {
+        if !range.is_empty() {
+            let (low, high) = (range.start().max(&1), range.end());
+            writeln!(writer, "\x1b[{}m⣿\x1b[0m {low}-{high}", ansi(color))?;
+        }
+    }
+    Ok(())
+}
+
+/// Maps the colors the heatmap uses to ANSI foreground color codes.
+const fn ansi(color: Color) -> u8 {
+    match color {
+        Color::DarkGray => 90,
+        Color::Yellow => 33,
+        Color::LightRed => 91,
+        Color::Red => 31,
+        _ => 39,
+    }
+}
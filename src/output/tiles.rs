@@ -0,0 +1,26 @@
+use std::f64::consts::PI;
+
+/// Latitude limit of the Web Mercator projection, beyond which it diverges.
+pub const MAX_LATITUDE: f64 = 85.051_128_78;
+
+/// Projects a location onto the Web Mercator tile grid of `zoom`, returning
+/// its `x` and `y` in tile units: the integer part is the tile, the
+/// fraction the position within it.
+pub fn project(lat: f64, lon: f64, zoom: u8) -> (f64, f64) {
+    let tiles = f64::from(1_u32 << zoom);
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = (lon + 180.0) / 360.0;
+    let y = (1.0 - lat.tan().asinh() / PI) / 2.0;
+    (x * tiles, y * tiles)
+}
+
+/// Returns the `x` and `y` of the tile of `zoom` holding a location.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Clamped to the grid.
+pub fn tile(lat: f64, lon: f64, zoom: u8) -> (u32, u32) {
+    let (x, y) = project(lat, lon, zoom);
+    let edge = f64::from((1_u32 << zoom) - 1);
+    (
+        x.floor().clamp(0.0, edge) as u32,
+        y.floor().clamp(0.0, edge) as u32,
+    )
+}
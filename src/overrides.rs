@@ -0,0 +1,60 @@
+use crate::geo::Location;
+use crate::input::open_text;
+use crate::ranges::RangeMap;
+use ipnetwork::IpNetwork;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Loads fixed locations from `network,city,country,lat,lon` CSV lines, where
+/// `network` is an IP address or CIDR. Lines starting with `#` and a header
+/// line are skipped; any other malformed line is an error.
+pub fn load(path: &Path) -> Result<RangeMap<Location>, Box<dyn Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_reader(open_text(path)?);
+    let mut networks = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        let record = record?;
+        let line = record.position().map_or(i as u64 + 1, csv::Position::line);
+        let malformed = || {
+            format!(
+                "{}:{line}: expected `network,city,country,lat,lon`",
+                path.display()
+            )
+        };
+        if record.len() != 5 {
+            return Err(malformed().into());
+        }
+        let (network, city, country) = (&record[0], &record[1], &record[2]);
+        let Ok(network) = network.parse::<IpNetwork>() else {
+            if i == 0 {
+                continue;
+            }
+            return Err(malformed().into());
+        };
+        let (Ok(lat), Ok(lon)) = (record[3].parse(), record[4].parse()) else {
+            return Err(malformed().into());
+        };
+        let location = Location {
+            city_name: city.to_string(),
+            country_name: country.to_string(),
+            lat,
+            lon,
+            geoname_id: None,
+            population: None,
+            country_code: None,
+            country_weight: None,
+            known_scanner: None,
+            cloud_provider: None,
+            netblock_owner: None,
+            abuse_contact: None,
+            tags: BTreeMap::new(),
+        };
+        networks.push((network, location));
+    }
+    Ok(networks.into_iter().collect())
+}
@@ -0,0 +1,54 @@
+use crate::input::open_text;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Column of the population in the GeoNames `cities*.txt` / `allCountries.txt`
+/// dumps.
+const GEONAMES_POPULATION: usize = 14;
+
+/// City populations keyed by GeoNames ID, the `geoname_id` `MaxMind` records
+/// carry.
+#[derive(Default)]
+pub struct Populations {
+    by_geoname_id: HashMap<u32, u32>,
+}
+
+impl Populations {
+    /// Loads populations from a GeoNames dump such as `cities500.txt`, or a
+    /// simpler file of `geoname_id,population` (or tab separated) lines.
+    /// Gzipped files are decompressed transparently.
+    ///
+    /// Header lines, malformed lines and zero populations are skipped.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut by_geoname_id = HashMap::new();
+        for line in open_text(path)?.lines() {
+            let line = line?;
+            // GeoNames names may contain commas, so only split on them for
+            // the two-column layout.
+            let geonames: Vec<&str> = line.split('\t').collect();
+            let (id, population) = if geonames.len() > GEONAMES_POPULATION {
+                (geonames[0], geonames[GEONAMES_POPULATION])
+            } else if let Some(pair) = line.split_once([',', '\t']) {
+                pair
+            } else {
+                continue;
+            };
+            if let (Ok(id), Ok(population)) = (id.trim().parse(), population.trim().parse()) {
+                if population > 0 {
+                    by_geoname_id.insert(id, population);
+                }
+            }
+        }
+        if by_geoname_id.is_empty() {
+            return Err(format!("no populations found in {}", path.display()).into());
+        }
+        Ok(Self { by_geoname_id })
+    }
+
+    /// Returns the population of the city with the given GeoNames ID.
+    pub fn get(&self, geoname_id: u32) -> Option<u32> {
+        self.by_geoname_id.get(&geoname_id).copied()
+    }
+}
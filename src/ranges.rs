@@ -0,0 +1,183 @@
+use ipnetwork::IpNetwork;
+use std::cmp::Reverse;
+use std::net::IpAddr;
+
+/// A labeled IP range.
+struct Range<T> {
+    first: u128,
+    last: u128,
+    /// Highest `last` of this and all preceding ranges, bounding how far back
+    /// a lookup has to search through overlapping ranges.
+    reach: u128,
+    label: T,
+}
+
+/// IP networks mapped to labels, such as the scanner or cloud provider they
+/// belong to or a fixed location, with logarithmic lookups.
+pub struct RangeMap<T = String> {
+    v4: Vec<Range<T>>,
+    v6: Vec<Range<T>>,
+}
+
+impl<T> Default for RangeMap<T> {
+    fn default() -> Self {
+        Self {
+            v4: Vec::new(),
+            v6: Vec::new(),
+        }
+    }
+}
+
+impl<T> RangeMap<T> {
+    /// Returns the label of the range containing `ip`, preferring the most
+    /// specific one starting closest to it if ranges overlap.
+    pub fn get(&self, ip: IpAddr) -> Option<&T> {
+        let (ranges, address) = match ip {
+            IpAddr::V4(v4) => (&self.v4, u128::from(u32::from(v4))),
+            IpAddr::V6(v6) => (&self.v6, u128::from(v6)),
+        };
+        let end = ranges.partition_point(|range| range.first <= address);
+        ranges[..end]
+            .iter()
+            .rev()
+            .take_while(|range| range.reach >= address)
+            .find(|range| range.last >= address)
+            .map(|range| &range.label)
+    }
+}
+
+impl<T> FromIterator<(IpNetwork, T)> for RangeMap<T> {
+    fn from_iter<I: IntoIterator<Item = (IpNetwork, T)>>(networks: I) -> Self {
+        let mut map = Self::default();
+        for (network, label) in networks {
+            let (ranges, first, last) = match network {
+                IpNetwork::V4(v4) => (
+                    &mut map.v4,
+                    u32::from(v4.network()).into(),
+                    u32::from(v4.broadcast()).into(),
+                ),
+                IpNetwork::V6(v6) => {
+                    let first = u128::from(v6.network());
+                    let host_mask = u128::MAX.checked_shr(v6.prefix().into()).unwrap_or(0);
+                    (&mut map.v6, first, first | host_mask)
+                }
+            };
+            ranges.push(Range {
+                first,
+                last,
+                reach: last,
+                label,
+            });
+        }
+        for ranges in [&mut map.v4, &mut map.v6] {
+            ranges.sort_by_key(|range| (range.first, Reverse(range.last)));
+            let mut reach = 0;
+            for range in ranges.iter_mut() {
+                reach = reach.max(range.last);
+                range.reach = reach;
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RangeMap;
+    use std::net::IpAddr;
+
+    /// Returns a map of each of `networks` to its label.
+    fn map(networks: &[(&str, &'static str)]) -> RangeMap<&'static str> {
+        networks
+            .iter()
+            .map(|&(network, label)| (network.parse().expect("valid network"), label))
+            .collect()
+    }
+
+    /// Returns the label of `ip` in `map`.
+    fn get(map: &RangeMap<&'static str>, ip: &str) -> Option<&'static str> {
+        map.get(ip.parse::<IpAddr>().expect("valid IP")).copied()
+    }
+
+    #[test]
+    fn prefers_the_most_specific_nested_range() {
+        let map = map(&[
+            ("10.0.0.0/8", "outer"),
+            ("10.1.2.0/24", "inner"),
+            ("10.1.0.0/16", "middle"),
+            ("10.1.2.3/32", "host"),
+        ]);
+        assert_eq!(get(&map, "10.1.2.3"), Some("host"));
+        assert_eq!(get(&map, "10.1.2.4"), Some("inner"));
+        assert_eq!(get(&map, "10.1.3.0"), Some("middle"));
+        assert_eq!(get(&map, "10.200.0.1"), Some("outer"));
+        assert_eq!(get(&map, "10.255.255.255"), Some("outer"));
+        assert_eq!(get(&map, "11.0.0.0"), None);
+    }
+
+    #[test]
+    fn finds_a_wide_range_behind_later_narrower_ones() {
+        // The /8 has to be found past the ranges starting after it, which the
+        // reach of those ranges allows.
+        let map = map(&[
+            ("10.0.0.0/8", "wide"),
+            ("10.0.0.0/24", "a"),
+            ("10.0.1.0/24", "b"),
+            ("10.0.2.0/24", "c"),
+            ("10.9.0.0/16", "d"),
+        ]);
+        assert_eq!(get(&map, "10.0.3.1"), Some("wide"));
+        assert_eq!(get(&map, "10.10.0.1"), Some("wide"));
+        assert_eq!(get(&map, "10.0.1.1"), Some("b"));
+        assert_eq!(get(&map, "10.9.255.255"), Some("d"));
+    }
+
+    #[test]
+    fn prefers_the_range_starting_closest_when_ranges_overlap() {
+        let map = map(&[("192.0.2.0/24", "first"), ("192.0.2.0/25", "half")]);
+        assert_eq!(get(&map, "192.0.2.1"), Some("half"));
+        assert_eq!(get(&map, "192.0.2.200"), Some("first"));
+    }
+
+    #[test]
+    fn tells_adjacent_ranges_apart() {
+        let map = map(&[("192.0.2.0/25", "low"), ("192.0.2.128/25", "high")]);
+        assert_eq!(get(&map, "192.0.2.0"), Some("low"));
+        assert_eq!(get(&map, "192.0.2.127"), Some("low"));
+        assert_eq!(get(&map, "192.0.2.128"), Some("high"));
+        assert_eq!(get(&map, "192.0.2.255"), Some("high"));
+        assert_eq!(get(&map, "192.0.1.255"), None);
+        assert_eq!(get(&map, "192.0.3.0"), None);
+    }
+
+    #[test]
+    fn misses_between_ranges() {
+        let map = map(&[
+            ("198.51.100.0/24", "low"),
+            ("203.0.113.0/24", "high"),
+            ("0.0.0.0/32", "zero"),
+        ]);
+        assert_eq!(get(&map, "200.0.0.1"), None);
+        assert_eq!(get(&map, "0.0.0.1"), None);
+        assert_eq!(get(&map, "255.255.255.255"), None);
+        assert_eq!(
+            RangeMap::<&str>::default().get("192.0.2.1".parse().expect("valid IP")),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_ipv4_and_ipv6_ranges_apart() {
+        let map = map(&[
+            ("192.0.2.0/24", "v4"),
+            ("::c000:200/120", "v6 with the same bits"),
+            ("2001:db8::/32", "documentation"),
+            ("::/0", "any v6"),
+        ]);
+        assert_eq!(get(&map, "192.0.2.1"), Some("v4"));
+        assert_eq!(get(&map, "::c000:201"), Some("v6 with the same bits"));
+        assert_eq!(get(&map, "2001:db8::1"), Some("documentation"));
+        assert_eq!(get(&map, "2001:db9::1"), Some("any v6"));
+        assert_eq!(get(&map, "198.51.100.1"), None);
+    }
+}
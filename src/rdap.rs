@@ -0,0 +1,208 @@
+use crate::api_cache::ApiCache;
+use crate::logging;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Default RDAP service, which redirects each query to the regional
+/// registry responsible for the IP.
+const DEFAULT_URL: &str = "https://rdap.org";
+
+/// Service RDAP answers are cached under, by the first address of their
+/// netblock.
+pub const CACHE_NAME: &str = "rdap";
+
+/// Registration details of the netblock an IP belongs to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Registration {
+    /// The netblock's address range, e.g. `9.0.0.0 - 9.255.255.255`.
+    pub range: String,
+    /// Organization the netblock is registered to, or else its name.
+    pub owner: Option<String>,
+    /// Email address to report abuse from the netblock to.
+    pub abuse_contact: Option<String>,
+}
+
+/// A netblock as cached: its last address and registration.
+#[derive(Serialize, Deserialize)]
+struct Cached {
+    last: IpAddr,
+    #[serde(flatten)]
+    registration: Registration,
+}
+
+/// Netblocks looked up so far and when the last request was made.
+#[derive(Default)]
+struct State {
+    /// Registrations by the first and last address of their netblock.
+    netblocks: BTreeMap<IpAddr, (IpAddr, Registration)>,
+    /// IPs whose lookup failed, so they aren't retried.
+    failed: HashSet<IpAddr>,
+    last_request: Option<Instant>,
+}
+
+/// Looks up netblock registrations over RDAP, at most `rate` requests per
+/// second, remembering each netblock for the rest of the run, and in the
+/// `--cache` if given, so IPs in a netblock already looked up don't cost a
+/// request.
+pub struct Rdap {
+    url: String,
+    interval: Duration,
+    cache: Option<Arc<ApiCache>>,
+    state: Mutex<State>,
+}
+
+impl Rdap {
+    /// Creates a client of the RDAP service at `url`, or `rdap.org`,
+    /// starting from the netblocks still fresh in `cache`.
+    pub fn new(url: Option<String>, rate: u32, cache: Option<Arc<ApiCache>>) -> Self {
+        let netblocks = cache
+            .iter()
+            .flat_map(|cache| cache.fresh(CACHE_NAME))
+            .filter_map(|(first, value)| {
+                let cached: Cached = serde_json::from_value(value).ok()?;
+                Some((first.parse().ok()?, (cached.last, cached.registration)))
+            })
+            .collect();
+        Self {
+            url: url.map_or_else(
+                || DEFAULT_URL.to_string(),
+                |url| url.trim_end_matches('/').to_string(),
+            ),
+            interval: Duration::from_secs(1) / rate.max(1),
+            cache,
+            state: Mutex::new(State {
+                netblocks,
+                ..State::default()
+            }),
+        }
+    }
+
+    /// Returns how many of `ips` aren't in a netblock looked up already, and
+    /// would cost a request each at most.
+    pub fn pending(&self, ips: impl IntoIterator<Item = IpAddr>) -> usize {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        ips.into_iter()
+            .filter(|&ip| find_netblock(&state, ip).is_none())
+            .count()
+    }
+
+    /// Returns the registration of the netblock `ip` belongs to, or `None`
+    /// if it couldn't be looked up.
+    ///
+    /// Lookups wait for each other, to keep to the rate limit and not look
+    /// up the same netblock twice.
+    pub fn registration(&self, ip: IpAddr) -> Option<Registration> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(registration) = find_netblock(&state, ip) {
+            return Some(registration.clone());
+        }
+        if state.failed.contains(&ip) {
+            return None;
+        }
+        if let Some(last_request) = state.last_request {
+            thread::sleep((last_request + self.interval).saturating_duration_since(Instant::now()));
+        }
+        state.last_request = Some(Instant::now());
+        self.look_up(ip, state)
+    }
+
+    /// Queries the registration of `ip` and records the answer in `state`.
+    fn look_up(&self, ip: IpAddr, mut state: MutexGuard<State>) -> Option<Registration> {
+        match self.fetch(ip) {
+            Ok((first, last, registration)) => {
+                state.netblocks.insert(first, (last, registration.clone()));
+                if let Some(cache) = &self.cache {
+                    let cached = Cached {
+                        last,
+                        registration: registration.clone(),
+                    };
+                    cache.insert(CACHE_NAME, &first.to_string(), json!(cached));
+                }
+                Some(registration)
+            }
+            Err(e) => {
+                state.failed.insert(ip);
+                drop(state);
+                logging::warn(
+                    "RDAP lookup failed",
+                    &[("ip", json!(ip)), ("error", json!(e.to_string()))],
+                );
+                None
+            }
+        }
+    }
+
+    /// Fetches the RDAP record of the netblock `ip` belongs to, returning
+    /// its first and last address along with its registration.
+    fn fetch(&self, ip: IpAddr) -> Result<(IpAddr, IpAddr, Registration), Box<dyn Error>> {
+        let response = ureq::get(&format!("{}/ip/{ip}", self.url))
+            .set("Accept", "application/rdap+json")
+            .call()?;
+        let network: Value = serde_json::from_reader(response.into_reader())?;
+        let address = |field: &str| -> Result<IpAddr, Box<dyn Error>> {
+            let address = network[field]
+                .as_str()
+                .ok_or_else(|| format!("the RDAP record has no `{field}`"))?;
+            Ok(address.parse()?)
+        };
+        let (first, last) = (address("startAddress")?, address("endAddress")?);
+        let entities = network["entities"]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice);
+        let owner = find_entity(entities, "registrant")
+            .and_then(|registrant| vcard(registrant, "fn"))
+            .or_else(|| network["name"].as_str())
+            .map(str::to_string);
+        let abuse_contact = find_entity(entities, "abuse")
+            .and_then(|abuse| vcard(abuse, "email"))
+            .map(str::to_string);
+        let registration = Registration {
+            range: format!("{first} - {last}"),
+            owner,
+            abuse_contact,
+        };
+        Ok((first, last, registration))
+    }
+}
+
+/// Finds the registration of the netblock looked up so far that `ip`
+/// belongs to.
+fn find_netblock(state: &State, ip: IpAddr) -> Option<&Registration> {
+    state
+        .netblocks
+        .range(..=ip)
+        .rev()
+        .find(|(_, (last, _))| ip <= *last)
+        .map(|(_, (_, registration))| registration)
+}
+
+/// Finds the first entity with `role` among `entities` and the entities
+/// nested in them, such as the abuse contact of the registrant.
+fn find_entity<'a>(entities: &'a [Value], role: &str) -> Option<&'a Value> {
+    entities.iter().find_map(|entity| {
+        let roles = entity["roles"].as_array().map_or(&[][..], Vec::as_slice);
+        if roles.iter().any(|r| r.as_str() == Some(role)) {
+            return Some(entity);
+        }
+        find_entity(
+            entity["entities"].as_array().map_or(&[][..], Vec::as_slice),
+            role,
+        )
+    })
+}
+
+/// Returns the text value of the `property` of an entity's jCard, such as
+/// its formatted name, `fn`, or `email`.
+fn vcard<'a>(entity: &'a Value, property: &str) -> Option<&'a str> {
+    entity["vcardArray"][1]
+        .as_array()?
+        .iter()
+        .find(|field| field[0].as_str() == Some(property))?[3]
+        .as_str()
+}
@@ -0,0 +1,111 @@
+use crate::geo::{Details, Location};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::net::IpAddr;
+
+/// Everything known about a single IP, as printed by the `lookup` command.
+#[derive(Serialize)]
+pub struct Record {
+    pub ip: IpAddr,
+    pub city: String,
+    pub country: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_code: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub subdivisions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub postal_code: Option<String>,
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub accuracy_radius_km: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time_zone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geoname_id: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub population: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country_weight: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub known_scanner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_provider: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netblock_owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub abuse_contact: Option<String>,
+    /// Labels added by `--enricher`s, such as `asn`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Record {
+    /// Combines the enriched location of `ip` with the details of its record.
+    pub fn new(ip: IpAddr, location: Location, details: Details) -> Self {
+        Self {
+            ip,
+            city: location.city_name,
+            country: location.country_name,
+            country_code: location.country_code,
+            subdivisions: details.subdivisions,
+            postal_code: details.postal_code,
+            lat: location.lat,
+            lon: location.lon,
+            accuracy_radius_km: details.accuracy_radius,
+            time_zone: details.time_zone,
+            geoname_id: location.geoname_id,
+            population: location.population,
+            country_weight: location.country_weight,
+            known_scanner: location.known_scanner,
+            cloud_provider: location.cloud_provider,
+            netblock_owner: location.netblock_owner,
+            abuse_contact: location.abuse_contact,
+            tags: location.tags,
+        }
+    }
+
+    /// Writes the record as pretty-printed JSON, or as a `field: value` line
+    /// per field that has a value.
+    pub fn write(&self, json: bool, mut out: impl Write) -> Result<(), Box<dyn Error>> {
+        if json {
+            serde_json::to_writer_pretty(&mut out, self)?;
+            writeln!(out)?;
+            return Ok(());
+        }
+        let subdivisions = self.subdivisions.join(", ");
+        let fields = [
+            ("ip", Some(self.ip.to_string())),
+            ("city", Some(self.city.clone())),
+            ("country", Some(self.country.clone())),
+            ("country code", self.country_code.clone()),
+            ("subdivisions", Some(subdivisions).filter(|s| !s.is_empty())),
+            ("postal code", self.postal_code.clone()),
+            ("coordinates", Some(format!("{}, {}", self.lat, self.lon))),
+            (
+                "accuracy radius",
+                self.accuracy_radius_km.map(|r| format!("{r} km")),
+            ),
+            ("time zone", self.time_zone.clone()),
+            ("geoname id", self.geoname_id.map(|id| id.to_string())),
+            ("population", self.population.map(|p| p.to_string())),
+            ("country weight", self.country_weight.map(|w| w.to_string())),
+            ("known scanner", self.known_scanner.clone()),
+            ("cloud provider", self.cloud_provider.clone()),
+            ("netblock owner", self.netblock_owner.clone()),
+            ("abuse contact", self.abuse_contact.clone()),
+        ];
+        let tags = self
+            .tags
+            .iter()
+            .map(|(tag, value)| (tag.as_str(), Some(value.clone())));
+        for (field, value) in fields.into_iter().chain(tags) {
+            if let Some(value) = value {
+                writeln!(out, "{:<17}{value}", format!("{field}:"))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use std::borrow::Cow;
+
+/// Defanged spellings and what they stand for, matched case insensitively.
+const DEFANGED: [(&str, &str); 10] = [
+    ("[.]", "."),
+    ("(.)", "."),
+    ("{.}", "."),
+    ("[dot]", "."),
+    ("(dot)", "."),
+    ("{dot}", "."),
+    ("[:]", ":"),
+    ("[://]", "://"),
+    ("[/]", "/"),
+    ("hxxp", "http"),
+];
+
+/// Undoes the usual defanging of shared indicators, such as `192[.]0[.]2[.]1`,
+/// `2001[:]db8[:][:]1` or `hxxp://192.0.2.1/`, so they can be looked up.
+/// Text without any of them is returned as is.
+pub fn refang(text: &str) -> Cow<'_, str> {
+    if !text.contains(['[', '(', '{', 'x', 'X']) {
+        return Cow::Borrowed(text);
+    }
+    let mut refanged = String::with_capacity(text.len());
+    let mut rest = text;
+    'outer: while let Some(c) = rest.chars().next() {
+        for (defanged, fanged) in DEFANGED {
+            let matches = rest
+                .get(..defanged.len())
+                .is_some_and(|prefix| prefix.eq_ignore_ascii_case(defanged));
+            if matches {
+                refanged.push_str(fanged);
+                rest = &rest[defanged.len()..];
+                continue 'outer;
+            }
+        }
+        refanged.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    Cow::Owned(refanged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::refang;
+    use std::borrow::Cow;
+
+    #[test]
+    fn refangs_schemes() {
+        assert_eq!(refang("hxxps://203.0.113.5/x"), "https://203.0.113.5/x");
+        assert_eq!(refang("HXXP[://]example[.]com[/]a"), "http://example.com/a");
+    }
+
+    #[test]
+    fn refangs_dots_in_any_brackets() {
+        for defanged in [
+            "203[.]0[.]113[.]5",
+            "203(.)0(.)113(.)5",
+            "203{.}0{.}113{.}5",
+            "203[dot]0(DOT)113{Dot}5",
+        ] {
+            assert_eq!(refang(defanged), "203.0.113.5", "{defanged}");
+        }
+    }
+
+    #[test]
+    fn refangs_ipv6_colons() {
+        assert_eq!(refang("2001[:]db8[:][:]1"), "2001:db8::1");
+        assert_eq!(refang("[2001[:]db8[:][:]1]"), "[2001:db8::1]");
+    }
+
+    #[test]
+    fn leaves_other_text_alone() {
+        assert!(matches!(
+            refang("203.0.113.5"),
+            Cow::Borrowed("203.0.113.5")
+        ));
+        assert_eq!(refang("[x] (a) {b} xx"), "[x] (a) {b} xx");
+        assert_eq!(refang("[ü](.)ü"), "[ü].ü");
+    }
+}
@@ -0,0 +1,54 @@
+use maxminddb::{MaxMindDBError, Reader};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the database file is checked for changes.
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Watches the `MaxMind` database file of a long-running collector so a
+/// replaced database (e.g. by the monthly `geoipupdate`) is picked up without
+/// a restart.
+pub struct DatabaseWatcher {
+    path: PathBuf,
+    /// Modification time and size of the file as last loaded or attempted.
+    version: Option<(SystemTime, u64)>,
+    next_check: Instant,
+}
+
+impl DatabaseWatcher {
+    /// Starts watching the database at `path`, which was just loaded.
+    pub fn new(path: PathBuf) -> Self {
+        let version = file_version(&path);
+        Self {
+            path,
+            version,
+            next_check: Instant::now() + CHECK_INTERVAL,
+        }
+    }
+
+    /// Returns a reader for the database if the file changed since it was
+    /// last loaded, checking at most every few seconds.
+    ///
+    /// A file that fails to open, e.g. because it is still being written, is
+    /// reported once and retried when it changes again.
+    pub fn poll(&mut self) -> Result<Option<Reader<Vec<u8>>>, MaxMindDBError> {
+        if Instant::now() < self.next_check {
+            return Ok(None);
+        }
+        self.next_check = Instant::now() + CHECK_INTERVAL;
+        let version = file_version(&self.path);
+        if version.is_none() || version == self.version {
+            return Ok(None);
+        }
+        self.version = version;
+        Reader::open_readfile(&self.path).map(Some)
+    }
+}
+
+/// Returns the modification time and size of the file at `path`, if it
+/// exists.
+fn file_version(path: &PathBuf) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
@@ -0,0 +1,135 @@
+mod s3;
+
+use clap::ValueEnum;
+use std::error::Error;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+/// HTTP methods outputs can be uploaded with.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UploadMethod {
+    #[default]
+    Put,
+    Post,
+}
+
+/// How outputs are uploaded to a remote location.
+#[derive(Debug, Default)]
+pub struct Upload {
+    /// HTTP method for `http(s)://` destinations; S3 always uses `PUT`.
+    pub method: UploadMethod,
+    /// Extra headers sent with HTTP uploads.
+    pub headers: Vec<(String, String)>,
+    /// How often a failed upload is retried before giving up.
+    pub retries: u32,
+}
+
+/// An input or output location outside the local file system.
+pub enum Remote<'a> {
+    /// An `http://` or `https://` URL.
+    Http(&'a str),
+    /// An `s3://bucket/key` object.
+    S3 { bucket: &'a str, key: &'a str },
+}
+
+impl<'a> Remote<'a> {
+    /// Parses `location` as a remote location, returning `None` for local paths.
+    pub fn parse(location: &'a str) -> Option<Self> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            return Some(Self::Http(location));
+        }
+        let (bucket, key) = location.strip_prefix("s3://")?.split_once('/')?;
+        Some(Self::S3 { bucket, key })
+    }
+
+    /// The last path segment of the location, used in place of a file name.
+    pub fn file_name(&self) -> &'a str {
+        let path = match self {
+            Self::Http(url) => url.split(['?', '#']).next().unwrap_or(url),
+            Self::S3 { key, .. } => key,
+        };
+        path.rsplit('/').next().unwrap_or(path)
+    }
+
+    /// Downloads the location's contents.
+    ///
+    /// `headers` are sent with HTTP requests, e.g. `Authorization` for
+    /// protected feeds. S3 requests are signed with the credentials from the
+    /// standard `AWS_*` environment variables, or sent unsigned for public
+    /// buckets when none are set. Downloads larger than `max_size` bytes are
+    /// aborted.
+    pub fn fetch(
+        &self,
+        headers: &[(String, String)],
+        max_size: Option<u64>,
+    ) -> Result<Vec<u8>, Box<dyn Error>> {
+        let request = match self {
+            Self::Http(url) => headers
+                .iter()
+                .fold(ureq::get(url), |request, (name, value)| {
+                    request.set(name, value)
+                }),
+            Self::S3 { bucket, key } => s3::request("GET", bucket, key, &[]),
+        };
+        // Read one byte past the limit to tell a full-size body from a larger one.
+        let mut body = Vec::new();
+        request
+            .call()?
+            .into_reader()
+            .take(max_size.map_or(u64::MAX, |limit| limit + 1))
+            .read_to_end(&mut body)?;
+        if let Some(limit) = max_size.filter(|&limit| body.len() as u64 > limit) {
+            return Err(format!("download exceeds --max-input-size of {limit} bytes").into());
+        }
+        Ok(body)
+    }
+
+    /// Uploads `body` to the location, retrying with exponential backoff
+    /// (1s, 2s, 4s, ...) on connection errors and `429` or `5xx` responses.
+    pub fn upload(
+        &self,
+        body: &[u8],
+        content_type: &str,
+        upload: &Upload,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            let request = match self {
+                Self::Http(url) => {
+                    let method = match upload.method {
+                        UploadMethod::Put => "PUT",
+                        UploadMethod::Post => "POST",
+                    };
+                    upload
+                        .headers
+                        .iter()
+                        .fold(ureq::request(method, url), |request, (name, value)| {
+                            request.set(name, value)
+                        })
+                }
+                Self::S3 { bucket, key } => s3::request("PUT", bucket, key, body),
+            };
+            let Err(error) = request.set("Content-Type", content_type).send_bytes(body) else {
+                return Ok(());
+            };
+            let retryable = match &error {
+                ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+                ureq::Error::Transport(_) => true,
+            };
+            if !retryable || attempt >= upload.retries {
+                return Err(error.into());
+            }
+            thread::sleep(Duration::from_secs(1 << attempt.min(6)));
+            attempt += 1;
+        }
+    }
+}
+
+/// Parses a `Name: value` HTTP header.
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    header
+        .split_once(':')
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .ok_or_else(|| format!("expected `Name: value`, got `{header}`"))
+}
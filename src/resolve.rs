@@ -0,0 +1,77 @@
+use crate::geo::Locator;
+use crate::refang::refang;
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::net::IpAddr;
+
+/// Header of the `resolve` command's CSV output.
+const HEADER: [&str; 12] = [
+    "IP",
+    "City Name",
+    "Country Name",
+    "Country Code",
+    "Lat",
+    "Lon",
+    "Time Zone",
+    "Known Scanner",
+    "Cloud Provider",
+    "Netblock Owner",
+    "Abuse Contact",
+    "Tags",
+];
+
+/// Looks up every IP listed in `list`, one per line, and writes a CSV row
+/// for each with its location and enrichments, in the order listed.
+///
+/// Blank lines and `#` comments are skipped, and defanged IPs refanged.
+/// IPs that are invalid or not in the database keep their row, with the
+/// other columns left empty, so rows match the list one to one. Returns the
+/// number of IPs and how many of them were located.
+pub fn write<S: AsRef<[u8]>>(
+    locator: &Locator<S>,
+    list: impl BufRead,
+    out: impl Write,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(HEADER)?;
+    let (mut listed, mut located) = (0, 0);
+    for line in list.lines() {
+        let line = line?;
+        let source = line.trim();
+        if source.is_empty() || source.starts_with('#') {
+            continue;
+        }
+        listed += 1;
+        let located_ip = refang(source)
+            .parse::<IpAddr>()
+            .ok()
+            .and_then(|ip| Some((ip, locator.locate(ip)?)));
+        let Some((ip, location)) = located_ip else {
+            writer.write_record([source, "", "", "", "", "", "", "", "", "", "", ""])?;
+            continue;
+        };
+        located += 1;
+        let details = locator.details(ip);
+        let tags: Vec<String> = location
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect();
+        writer.write_record([
+            source,
+            &location.city_name,
+            &location.country_name,
+            location.country_code.as_deref().unwrap_or_default(),
+            &location.lat.to_string(),
+            &location.lon.to_string(),
+            details.time_zone.as_deref().unwrap_or_default(),
+            location.known_scanner.as_deref().unwrap_or_default(),
+            location.cloud_provider.as_deref().unwrap_or_default(),
+            location.netblock_owner.as_deref().unwrap_or_default(),
+            location.abuse_contact.as_deref().unwrap_or_default(),
+            &tags.join("; "),
+        ])?;
+    }
+    writer.flush()?;
+    Ok((listed, located))
+}
@@ -0,0 +1,56 @@
+use crate::input::open_text;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// Risk multipliers per ISO 3166-1 alpha-2 country code, e.g. to emphasize
+/// sanctioned countries.
+#[derive(Default)]
+pub struct CountryWeights {
+    by_country: HashMap<String, f64>,
+    /// Multiplier for countries not listed, 1 unless the file sets `*`.
+    default: f64,
+}
+
+impl CountryWeights {
+    /// Loads `CC,multiplier` lines (comma or tab separated). A `*` country
+    /// sets the multiplier for unlisted countries. Blank lines, `#` comments
+    /// and a header line are skipped; any other malformed line is an error.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut weights = Self {
+            by_country: HashMap::new(),
+            default: 1.0,
+        };
+        for (i, line) in open_text(path)?.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let malformed = || format!("{}:{}: expected `CC,multiplier`", path.display(), i + 1);
+            let (country, weight) = line.split_once([',', '\t']).ok_or_else(malformed)?;
+            let Ok(weight) = weight.trim().parse::<f64>() else {
+                if i == 0 {
+                    continue;
+                }
+                return Err(malformed().into());
+            };
+            match country.trim() {
+                "*" => weights.default = weight,
+                code => {
+                    weights.by_country.insert(code.to_ascii_uppercase(), weight);
+                }
+            }
+        }
+        Ok(weights)
+    }
+
+    /// Returns the multiplier for the country with the given ISO code.
+    pub fn get(&self, country_code: Option<&str>) -> f64 {
+        country_code
+            .and_then(|code| self.by_country.get(code))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
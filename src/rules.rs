@@ -0,0 +1,292 @@
+use crate::aggregate::{Aggregator, CityData};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// A `--rules` file: a list of `[[rule]]` tables.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+/// An alerting rule, checked against the aggregated counts every time the
+/// output is written.
+///
+/// A rule fires either once the count of the matching `country` and `city`,
+/// or of all threats, rises `above` a threshold, or with `new_country` when
+/// threats come from a country not seen before. Alerts go to the rule's own
+/// webhooks if it has any, or to `--slack-webhook` and `--teams-webhook`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    /// ISO code or name of the country to count, case insensitively.
+    country: Option<String>,
+    /// Name of the city to count, case insensitively.
+    city: Option<String>,
+    above: Option<u64>,
+    #[serde(default)]
+    new_country: bool,
+    /// Countries, by ISO code or name, that are never new.
+    #[serde(default)]
+    known_countries: Vec<String>,
+    pub slack: Option<String>,
+    pub teams: Option<String>,
+    /// URL the alert is posted to as JSON.
+    pub webhook: Option<String>,
+}
+
+/// A rule that fired, and why.
+pub struct Alert<'a> {
+    pub rule: &'a Rule,
+    pub message: String,
+}
+
+/// The loaded rules and what they've seen so far.
+pub struct Rules {
+    list: Vec<Rule>,
+    /// Whether each threshold rule was above its threshold when last checked,
+    /// so it only fires again after dropping back below it.
+    above: Vec<bool>,
+    /// Countries each `new_country` rule has seen, by lowercase name or
+    /// ISO code.
+    seen: Vec<BTreeSet<String>>,
+    checked: bool,
+}
+
+impl Rules {
+    /// Loads the rules from a TOML file of `[[rule]]` tables, as
+    /// [`Rules::parse`] does.
+    pub fn load(path: &Path, collector: bool) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?, collector)
+            .map_err(|error| format!("{}: {error}", path.display()).into())
+    }
+
+    /// Parses the rules from TOML `[[rule]]` tables, rejecting rules that
+    /// don't set exactly one of `above` and `new_country`, and outside the
+    /// `collector`, `new_country` rules without `known_countries`, as
+    /// nothing would be known before their only check.
+    fn parse(text: &str, collector: bool) -> Result<Self, String> {
+        let file: RulesFile = toml::from_str(text).map_err(|error| error.to_string())?;
+        for rule in &file.rules {
+            if rule.above.is_some() == rule.new_country {
+                return Err(format!(
+                    "rule `{}` must set exactly one of `above` and `new_country`",
+                    rule.name
+                ));
+            }
+            if rule.new_country && rule.known_countries.is_empty() && !collector {
+                return Err(format!(
+                    "rule `{}` needs `known_countries` outside collector mode (--listen)",
+                    rule.name
+                ));
+            }
+        }
+        let seen = file
+            .rules
+            .iter()
+            .map(|rule| {
+                rule.known_countries
+                    .iter()
+                    .map(|c| c.to_lowercase())
+                    .collect()
+            })
+            .collect();
+        Ok(Self {
+            above: vec![false; file.rules.len()],
+            seen,
+            list: file.rules,
+            checked: false,
+        })
+    }
+
+    /// Checks every rule against the aggregated counts, returning the alerts
+    /// of those that fired.
+    ///
+    /// In the collector, `new_country` rules without `known_countries` take
+    /// the countries of their first check as known, so they only fire for
+    /// countries that show up later.
+    pub fn check(&mut self, aggregator: &Aggregator) -> Vec<Alert<'_>> {
+        // Threats per country, by lowercase name, along with its name and
+        // lowercase code.
+        let mut countries: BTreeMap<String, (&str, String, u64)> = BTreeMap::new();
+        for (_, data) in aggregator.entries() {
+            let code = data.country_code.as_deref().unwrap_or_default();
+            let country = countries
+                .entry(data.country_name.to_lowercase())
+                .or_insert_with(|| (&data.country_name, code.to_lowercase(), 0));
+            country.2 += u64::from(data.total_count);
+        }
+        let mut alerts = Vec::new();
+        for (i, rule) in self.list.iter().enumerate() {
+            if let Some(threshold) = rule.above {
+                let count: u64 = aggregator
+                    .entries()
+                    .filter(|(_, data)| rule.matches(data))
+                    .map(|(_, data)| u64::from(data.total_count))
+                    .sum();
+                let was_above = std::mem::replace(&mut self.above[i], count > threshold);
+                if count > threshold && !was_above {
+                    alerts.push(Alert {
+                        rule,
+                        message: format!("{}: {count} threats, above {threshold}", rule.scope()),
+                    });
+                }
+                continue;
+            }
+            let seen = &mut self.seen[i];
+            let baseline = !self.checked && rule.known_countries.is_empty();
+            let mut new = Vec::new();
+            for (name, (country, code, count)) in &countries {
+                if seen.contains(name) || (!code.is_empty() && seen.contains(code)) {
+                    continue;
+                }
+                seen.insert(name.clone());
+                if !baseline {
+                    new.push(format!("{country} ({count} threats)"));
+                }
+            }
+            if !new.is_empty() {
+                alerts.push(Alert {
+                    rule,
+                    message: format!("new countries: {}", new.join(", ")),
+                });
+            }
+        }
+        self.checked = true;
+        alerts
+    }
+}
+
+impl Rule {
+    /// Returns `true` if the group's threats count towards the rule.
+    fn matches(&self, data: &CityData) -> bool {
+        let country = self.country.as_ref().map_or(true, |country| {
+            data.country_name.eq_ignore_ascii_case(country)
+                || data
+                    .country_code
+                    .as_ref()
+                    .is_some_and(|code| code.eq_ignore_ascii_case(country))
+        });
+        let city = self
+            .city
+            .as_ref()
+            .map_or(true, |city| data.city_name.eq_ignore_ascii_case(city));
+        country && city
+    }
+
+    /// Describes what the rule counts.
+    fn scope(&self) -> String {
+        match (&self.city, &self.country) {
+            (Some(city), Some(country)) => format!("{city}, {country}"),
+            (Some(place), None) | (None, Some(place)) => place.clone(),
+            (None, None) => "all locations".to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rules;
+    use crate::aggregate::tests::berlin;
+    use crate::aggregate::{Aggregator, Group};
+    use crate::geo::Location;
+
+    /// Returns a location in Paris.
+    fn paris() -> Location {
+        Location {
+            city_name: "Paris".into(),
+            country_name: "France".into(),
+            country_code: Some("FR".into()),
+            lat: 48.8566,
+            lon: 2.3522,
+            ..berlin()
+        }
+    }
+
+    /// Returns an aggregator of `count` threats at each of `locations`.
+    fn counts(locations: &[(Location, u32)]) -> Aggregator {
+        let mut aggregator = Aggregator::default();
+        for (location, count) in locations {
+            aggregator.add(location, Group::default(), None, *count, None);
+        }
+        aggregator
+    }
+
+    /// Returns the messages of the alerts `rules` fire for `locations`.
+    fn check(rules: &mut Rules, locations: &[(Location, u32)]) -> Vec<String> {
+        rules
+            .check(&counts(locations))
+            .into_iter()
+            .map(|alert| format!("{}: {}", alert.rule.name, alert.message))
+            .collect()
+    }
+
+    #[test]
+    fn fires_again_only_after_dropping_back_below_the_threshold() {
+        let mut rules = Rules::parse(
+            "[[rule]]\nname = \"German surge\"\ncountry = \"de\"\nabove = 5\n",
+            false,
+        )
+        .expect("valid rules");
+        let surge = ["German surge: de: 6 threats, above 5"];
+        assert_eq!(check(&mut rules, &[(berlin(), 6), (paris(), 100)]), surge);
+        assert!(check(&mut rules, &[(berlin(), 9)]).is_empty());
+        assert!(check(&mut rules, &[(berlin(), 5)]).is_empty());
+        assert_eq!(check(&mut rules, &[(berlin(), 6)]), surge);
+    }
+
+    #[test]
+    fn counts_all_threats_without_a_country_or_city() {
+        let mut rules =
+            Rules::parse("[[rule]]\nname = \"Total\"\nabove = 10\n", false).expect("valid rules");
+        assert_eq!(
+            check(&mut rules, &[(berlin(), 6), (paris(), 5)]),
+            ["Total: all locations: 11 threats, above 10"]
+        );
+    }
+
+    #[test]
+    fn fires_once_for_countries_not_known() {
+        let mut rules = Rules::parse(
+            "[[rule]]\nname = \"New\"\nnew_country = true\nknown_countries = [\"DE\"]\n",
+            false,
+        )
+        .expect("valid rules");
+        assert!(check(&mut rules, &[(berlin(), 3)]).is_empty());
+        assert_eq!(
+            check(&mut rules, &[(berlin(), 3), (paris(), 2)]),
+            ["New: new countries: France (2 threats)"]
+        );
+        assert!(check(&mut rules, &[(paris(), 4)]).is_empty());
+    }
+
+    #[test]
+    fn takes_the_first_check_as_known_in_the_collector() {
+        let mut rules = Rules::parse("[[rule]]\nname = \"New\"\nnew_country = true\n", true)
+            .expect("valid rules");
+        assert!(check(&mut rules, &[(berlin(), 3)]).is_empty());
+        assert_eq!(
+            check(&mut rules, &[(paris(), 1)]),
+            ["New: new countries: France (1 threats)"]
+        );
+    }
+
+    #[test]
+    fn rejects_rules_that_could_never_fire() {
+        for (rules, collector) in [
+            ("[[rule]]\nname = \"New\"\nnew_country = true\n", false),
+            (
+                "[[rule]]\nname = \"Both\"\nnew_country = true\nabove = 1\n",
+                true,
+            ),
+            ("[[rule]]\nname = \"Neither\"\n", true),
+        ] {
+            assert!(Rules::parse(rules, collector).is_err(), "{rules}");
+        }
+    }
+}
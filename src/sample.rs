@@ -0,0 +1,113 @@
+use crate::refang::refang;
+
+/// Keeps a deterministic fraction of records, chosen by a hash of their
+/// source, and scales the counts of those kept to make up for the rest.
+///
+/// As the choice depends on the source alone, every record of a source is
+/// either kept or dropped, the same ones on every run, so sampled maps are
+/// reproducible and a source's counts are never split.
+#[derive(Clone, Copy)]
+pub struct Sampler {
+    rate: f64,
+    /// Sources hashing below this are kept.
+    threshold: u64,
+}
+
+impl Sampler {
+    /// Creates a sampler keeping `rate`, between 0 and 1, of the sources.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )] // Saturates at the full range for a rate of 1.
+    pub fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            threshold: (rate * u64::MAX as f64) as u64,
+        }
+    }
+
+    /// Returns whether records of `source` are kept. Defanged sources are
+    /// refanged first, so they're kept along with the plain spelling.
+    pub fn keeps(&self, source: &str) -> bool {
+        self.rate >= 1.0 || fnv1a(refang(source.trim()).as_bytes()) < self.threshold
+    }
+
+    /// Scales the count of a kept record up by the inverse of the rate,
+    /// rounded and saturating at `u32::MAX`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Float to integer casts saturate.
+    pub fn scale(&self, count: u32) -> u32 {
+        (f64::from(count) / self.rate).round() as u32
+    }
+}
+
+/// The 64 bit FNV-1a hash, which unlike the standard library's hasher is the
+/// same across runs, builds and platforms.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sampler;
+
+    #[test]
+    fn keeps_the_same_sources_every_time() {
+        let sampler = Sampler::new(0.5);
+        let sources: Vec<String> = (0..=255).map(|i| format!("198.51.100.{i}")).collect();
+        let kept: Vec<bool> = sources.iter().map(|source| sampler.keeps(source)).collect();
+        let again: Vec<bool> = sources
+            .iter()
+            .map(|source| Sampler::new(0.5).keeps(source))
+            .collect();
+        assert_eq!(kept, again);
+        assert!(kept.contains(&true) && kept.contains(&false));
+    }
+
+    #[test]
+    fn keeps_defanged_sources_with_their_plain_spelling() {
+        let sampler = Sampler::new(0.5);
+        for i in 0..=255 {
+            let plain = format!("198.51.100.{i}");
+            let defanged = format!(" 198[.]51[.]100[.]{i} ");
+            assert_eq!(sampler.keeps(&plain), sampler.keeps(&defanged), "{plain}");
+        }
+    }
+
+    #[test]
+    fn keeps_everything_at_a_rate_of_one() {
+        let sampler = Sampler::new(1.0);
+        assert!((0..=255).all(|i| sampler.keeps(&format!("203.0.113.{i}"))));
+        assert_eq!(sampler.scale(7), 7);
+    }
+
+    #[test]
+    fn keeps_about_the_rate_of_sources() {
+        for rate in [0.01, 0.1, 0.5, 0.9] {
+            let sampler = Sampler::new(rate);
+            let kept = (0..100_000)
+                .filter(|i| {
+                    sampler.keeps(&format!("10.{}.{}.{}", i >> 16, (i >> 8) & 255, i & 255))
+                })
+                .count();
+            #[allow(clippy::cast_precision_loss)] // Exact for counts this small.
+            let fraction = kept as f64 / 100_000.0;
+            assert!(
+                (fraction - rate).abs() < 0.01,
+                "kept {fraction} at a rate of {rate}"
+            );
+        }
+    }
+
+    #[test]
+    fn scales_counts_rounded_and_saturating() {
+        let sampler = Sampler::new(0.3);
+        assert_eq!(sampler.scale(1), 3);
+        assert_eq!(sampler.scale(2), 7);
+        assert_eq!(sampler.scale(0), 0);
+        assert_eq!(Sampler::new(1e-6).scale(5_000), u32::MAX);
+        assert_eq!(Sampler::new(0.5).scale(u32::MAX), u32::MAX);
+    }
+}
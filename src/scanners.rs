@@ -0,0 +1,49 @@
+use crate::input::open_text;
+use crate::ranges::RangeMap;
+use ipnetwork::IpNetwork;
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+
+/// The bundled list of known benign internet scanners.
+const BUNDLED: &str = include_str!("../data/known_scanners.txt");
+
+/// Loads the known scanner ranges from `path`, or the bundled list if `path`
+/// is `None`.
+pub fn load(path: Option<&Path>) -> Result<RangeMap, Box<dyn Error>> {
+    let Some(path) = path else {
+        return parse(
+            BUNDLED.lines().map(|line| Ok(line.to_string())),
+            "bundled list",
+        );
+    };
+    let name = path.display().to_string();
+    parse(
+        open_text(path)?
+            .lines()
+            .map(|line| line.map_err(Into::into)),
+        &name,
+    )
+}
+
+/// Parses `CIDR name` lines; the name is optional and defaults to `scanner`.
+/// Blank lines and `#` comments are skipped.
+fn parse(
+    lines: impl Iterator<Item = Result<String, Box<dyn Error>>>,
+    name: &str,
+) -> Result<RangeMap, Box<dyn Error>> {
+    let mut networks = Vec::new();
+    for (i, line) in lines.enumerate() {
+        let line = line?;
+        let mut fields = line.split_whitespace();
+        let Some(cidr) = fields.next().filter(|cidr| !cidr.starts_with('#')) else {
+            continue;
+        };
+        let network: IpNetwork = cidr
+            .parse()
+            .map_err(|e| format!("{name}:{}: invalid network `{cidr}`: {e}", i + 1))?;
+        let scanner = fields.next().unwrap_or("scanner");
+        networks.push((network, scanner.to_string()));
+    }
+    Ok(networks.into_iter().collect())
+}
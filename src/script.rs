@@ -0,0 +1,67 @@
+use rhai::{CallFnOptions, Dynamic, Engine, Scope, AST};
+use std::error::Error;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+/// Upper bound on the operations of one call, so a runaway loop fails
+/// instead of hanging the run.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A user-supplied Rhai script defining the one-argument function it is
+/// called through.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    path: PathBuf,
+    function: &'static str,
+}
+
+impl Script {
+    /// Compiles the script at `path`, which must define `function`, and runs
+    /// its top-level statements.
+    pub fn load(path: &Path, function: &'static str) -> Result<Self, Box<dyn Error>> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let mut script = Self {
+            ast: AST::empty(),
+            engine,
+            path: path.to_path_buf(),
+            function,
+        };
+        script.ast = script
+            .engine
+            .compile_file(script.path.clone())
+            .map_err(|e| script.error(e))?;
+        if !script
+            .ast
+            .iter_functions()
+            .any(|f| f.name == function && f.params.len() == 1)
+        {
+            let missing = format!("doesn't define a `{function}` function with one parameter");
+            return Err(script.error(missing).into());
+        }
+        script
+            .engine
+            .run_ast(&script.ast)
+            .map_err(|e| script.error(e))?;
+        Ok(script)
+    }
+
+    /// Calls the script's function with `argument`.
+    pub fn call(&self, argument: Dynamic) -> Result<Dynamic, Box<dyn Error>> {
+        let options = CallFnOptions::new().eval_ast(false);
+        let result = self.engine.call_fn_with_options(
+            options,
+            &mut Scope::new(),
+            &self.ast,
+            self.function,
+            (argument,),
+        );
+        Ok(result.map_err(|e| self.error(e))?)
+    }
+
+    /// Prefixes `error` with the path of the script.
+    pub fn error(&self, error: impl Display) -> String {
+        format!("{}: {error}", self.path.display())
+    }
+}
@@ -0,0 +1,207 @@
+use crate::aggregate::Aggregator;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// When something was first and last observed, as RFC 3339 UTC times.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Sighting {
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/// Numbers of locations, countries and source IPs seen for the first time in
+/// an update of the state.
+#[derive(Default)]
+pub struct NewSightings {
+    pub locations: usize,
+    pub countries: usize,
+    pub ips: usize,
+}
+
+/// The `--seen-state` file: when each location, by its coordinates, each
+/// country and, with `--track-ips`, each source IP was first and last
+/// observed, across runs.
+#[derive(Default, Serialize, Deserialize)]
+pub struct SeenState {
+    #[serde(default)]
+    locations: BTreeMap<String, Sighting>,
+    #[serde(default)]
+    countries: BTreeMap<String, Sighting>,
+    #[serde(default)]
+    ips: BTreeMap<IpAddr, Sighting>,
+}
+
+impl SeenState {
+    /// Loads the state from `path`, or starts an empty one if it doesn't
+    /// exist yet.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match fs::read(path) {
+            Ok(bytes) => {
+                Ok(serde_json::from_slice(&bytes)
+                    .map_err(|e| format!("{}: {e}", path.display()))?)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Records everything `aggregator` holds as seen at `now`, and the
+    /// source IPs it tracked, and stamps each group with when its location
+    /// was first seen, flagging it as new if that was `now`.
+    pub fn update(&mut self, aggregator: &mut Aggregator, now: &str) -> NewSightings {
+        let mut new = NewSightings::default();
+        for (key, data) in aggregator.entries_mut() {
+            let location = format!("{:.5},{:.5}", key.location.lat(), key.location.lon());
+            let sighting = see(&mut self.locations, location, now, &mut new.locations);
+            data.first_seen = Some(sighting.first_seen.clone());
+            data.new = sighting.first_seen == now;
+            let country = data.country_code.as_ref().unwrap_or(&data.country_name);
+            see(
+                &mut self.countries,
+                country.clone(),
+                now,
+                &mut new.countries,
+            );
+        }
+        for ip in aggregator.take_ips() {
+            see(&mut self.ips, ip, now, &mut new.ips);
+        }
+        new
+    }
+
+    /// Writes the state to `path`, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut temporary = path.as_os_str().to_os_string();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_vec(self)?)?;
+        Ok(fs::rename(&temporary, path)?)
+    }
+}
+
+/// Records `key` as seen at `now`, counting it in `new` if it never was
+/// before, and returns its sighting.
+fn see<'a, K: Ord>(
+    sightings: &'a mut BTreeMap<K, Sighting>,
+    key: K,
+    now: &str,
+    new: &mut usize,
+) -> &'a Sighting {
+    let sighting = sightings.entry(key).or_insert_with(|| {
+        *new += 1;
+        Sighting {
+            first_seen: now.to_string(),
+            last_seen: now.to_string(),
+        }
+    });
+    now.clone_into(&mut sighting.last_seen);
+    sighting
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SeenState;
+    use crate::aggregate::tests::berlin;
+    use crate::aggregate::{Aggregator, Group};
+    use crate::geo::Location;
+    use std::net::IpAddr;
+    use std::{env, fs, process};
+
+    /// Returns an aggregator of one threat at each of `locations`, from
+    /// each of `ips`.
+    fn aggregator(locations: &[Location], ips: &[&str]) -> Aggregator {
+        let mut aggregator = Aggregator::default();
+        for location in locations {
+            aggregator.add(location, Group::default(), None, 1, None);
+        }
+        for ip in ips {
+            aggregator.add_ip(ip.parse::<IpAddr>().expect("valid IP"));
+        }
+        aggregator
+    }
+
+    /// Returns a location in Hamburg.
+    fn hamburg() -> Location {
+        Location {
+            city_name: "Hamburg".into(),
+            lat: 53.5511,
+            lon: 9.9937,
+            ..berlin()
+        }
+    }
+
+    /// Returns the first sighting and new flag of each group of `aggregator`,
+    /// by city.
+    fn flags(aggregator: &Aggregator) -> Vec<(String, Option<String>, bool)> {
+        let mut flags: Vec<_> = aggregator
+            .entries()
+            .map(|(_, data)| (data.city_name.clone(), data.first_seen.clone(), data.new))
+            .collect();
+        flags.sort();
+        flags
+    }
+
+    #[test]
+    fn flags_locations_seen_for_the_first_time() {
+        let mut seen = SeenState::default();
+        let mut first = aggregator(&[berlin()], &["192.0.2.1"]);
+        let new = seen.update(&mut first, "2026-01-01T00:00:00Z");
+        assert_eq!((new.locations, new.countries, new.ips), (1, 1, 1));
+        assert_eq!(
+            flags(&first),
+            [("Berlin".into(), Some("2026-01-01T00:00:00Z".into()), true)]
+        );
+
+        let mut second = aggregator(&[berlin(), hamburg()], &["192.0.2.1", "192.0.2.2"]);
+        let new = seen.update(&mut second, "2026-01-02T00:00:00Z");
+        assert_eq!((new.locations, new.countries, new.ips), (1, 0, 1));
+        assert_eq!(
+            flags(&second),
+            [
+                ("Berlin".into(), Some("2026-01-01T00:00:00Z".into()), false),
+                ("Hamburg".into(), Some("2026-01-02T00:00:00Z".into()), true),
+            ]
+        );
+        let berlin = &seen.locations["52.52000,13.40500"];
+        assert_eq!(berlin.first_seen, "2026-01-01T00:00:00Z");
+        assert_eq!(berlin.last_seen, "2026-01-02T00:00:00Z");
+        assert_eq!(seen.countries["DE"].last_seen, "2026-01-02T00:00:00Z");
+    }
+
+    #[test]
+    fn saves_and_loads_the_state() {
+        let dir = env::temp_dir().join(format!("dashboard_location_mapper-{}-seen", process::id()));
+        fs::create_dir_all(&dir).expect("the temporary directory is created");
+        let path = dir.join("seen.json");
+        let missing = SeenState::load(&path).expect("a missing state is empty");
+        assert!(missing.locations.is_empty());
+
+        let mut seen = SeenState::default();
+        seen.update(
+            &mut aggregator(&[berlin()], &["192.0.2.1"]),
+            "2026-01-01T00:00:00Z",
+        );
+        seen.save(&path).expect("the state is saved");
+        let entries: Vec<_> = fs::read_dir(&dir)
+            .expect("the directory is listed")
+            .map(|entry| entry.expect("an entry").file_name())
+            .collect();
+        assert_eq!(entries, ["seen.json"]);
+
+        let mut loaded = SeenState::load(&path).expect("the state loads");
+        let mut again = aggregator(&[berlin()], &["192.0.2.1"]);
+        let new = loaded.update(&mut again, "2026-01-02T00:00:00Z");
+        assert_eq!((new.locations, new.countries, new.ips), (0, 0, 0));
+
+        fs::write(&path, "not json").expect("the state is overwritten");
+        let error = SeenState::load(&path)
+            .err()
+            .expect("an invalid state fails");
+        let _ = fs::remove_dir_all(&dir);
+        assert!(error.to_string().starts_with(&path.display().to_string()));
+    }
+}
@@ -0,0 +1,26 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once SIGINT or SIGTERM has been received.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a SIGINT/SIGTERM handler requesting a graceful shutdown, so the
+/// run can stop at the next chunk and flush what it has aggregated so far.
+/// A second signal exits immediately.
+pub fn install() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+    })
+}
+
+/// Returns `true` once a shutdown has been requested.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Requests a graceful shutdown, as if SIGINT had been received.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
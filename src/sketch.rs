@@ -0,0 +1,143 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A count-min sketch: approximate counts of any number of keys in a fixed
+/// `depth` by `width` table of counters.
+///
+/// Each key is counted in one counter of every row, picked by a hash of the
+/// key, and its estimate is the smallest of them. Collisions only ever add to
+/// a counter, so estimates never fall short of the true count, and exceed it
+/// by at most [`Self::error_bound`] with a probability of at least
+/// `1 - e^-depth`.
+pub struct CountMinSketch {
+    width: usize,
+    depth: usize,
+    counts: Vec<u64>,
+    /// Severity-weighted counts, sharing the counters' positions.
+    weighted: Vec<f64>,
+    /// Total count of every key added.
+    total: u64,
+}
+
+impl CountMinSketch {
+    /// Creates an empty sketch of `depth` rows of `width` counters each.
+    pub fn new(width: usize, depth: usize) -> Self {
+        let (width, depth) = (width.max(1), depth.max(1));
+        Self {
+            width,
+            depth,
+            counts: vec![0; width * depth],
+            weighted: vec![0.0; width * depth],
+            total: 0,
+        }
+    }
+
+    /// Adds `count` and `weighted_count` to `key`, and returns its estimated
+    /// count and weighted count so far.
+    pub fn add<K: Hash>(&mut self, key: &K, count: u32, weighted_count: f64) -> (u64, f64) {
+        self.total += u64::from(count);
+        let mut estimate = (u64::MAX, f64::INFINITY);
+        for cell in self.cells(key) {
+            self.counts[cell] += u64::from(count);
+            self.weighted[cell] += weighted_count;
+            estimate.0 = estimate.0.min(self.counts[cell]);
+            estimate.1 = estimate.1.min(self.weighted[cell]);
+        }
+        estimate
+    }
+
+    /// Returns the total count of every key added.
+    pub const fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns how far estimates may exceed true counts: `e / width` of the
+    /// total count, rounded up.
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_precision_loss,
+        clippy::cast_sign_loss
+    )] // Non-negative, and at most the total.
+    pub fn error_bound(&self) -> u64 {
+        (std::f64::consts::E * self.total as f64 / self.width as f64).ceil() as u64
+    }
+
+    /// Returns the index of the counter of `key` in each row.
+    ///
+    /// The rows' hash functions are derived from two halves of a single
+    /// hash, which is as good as independent hashes for a count-min sketch.
+    fn cells<K: Hash>(&self, key: &K) -> Vec<usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (first, second) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let width = self.width as u64;
+        #[allow(clippy::cast_possible_truncation)] // Below the width.
+        (0..self.depth)
+            .map(|row| {
+                let column = first.wrapping_add(second.wrapping_mul(row as u64)) % width;
+                row * self.width + column as usize
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CountMinSketch;
+
+    #[test]
+    fn counts_few_keys_exactly() {
+        let mut sketch = CountMinSketch::new(4096, 5);
+        sketch.add(&"Berlin", 3, 1.5);
+        sketch.add(&"Paris", 2, 4.0);
+        assert_eq!(sketch.add(&"Berlin", 4, 2.0), (7, 3.5));
+        assert_eq!(sketch.add(&"Paris", 0, 0.0), (2, 4.0));
+        assert_eq!(sketch.add(&"Tokyo", 0, 0.0), (0, 0.0));
+        assert_eq!(sketch.total(), 9);
+    }
+
+    #[test]
+    fn estimates_stay_within_the_error_bound() {
+        let (width, depth) = (64, 5);
+        let mut sketch = CountMinSketch::new(width, depth);
+        let counts: Vec<u32> = (0..2000).map(|key| key % 17 + 1).collect();
+        for (key, &count) in counts.iter().enumerate() {
+            sketch.add(&key, count, 0.0);
+        }
+        let total: u64 = counts.iter().map(|&count| u64::from(count)).sum();
+        assert_eq!(sketch.total(), total);
+        let bound = sketch.error_bound();
+        let mut beyond = 0;
+        for (key, &count) in counts.iter().enumerate() {
+            let (estimate, _) = sketch.add(&key, 0, 0.0);
+            assert!(estimate >= u64::from(count), "{key} underestimated");
+            if estimate - u64::from(count) > bound {
+                beyond += 1;
+            }
+        }
+        // Each estimate is beyond the bound with a probability of at most
+        // e^-depth, under 1%.
+        assert!(
+            beyond * 100 <= counts.len(),
+            "{beyond} estimates beyond {bound}"
+        );
+    }
+
+    #[test]
+    fn rounds_the_error_bound_up() {
+        let mut sketch = CountMinSketch::new(100, 1);
+        assert_eq!(sketch.error_bound(), 0);
+        sketch.add(&1, 100, 0.0);
+        // e * 100 / 100 = 2.718...
+        assert_eq!(sketch.error_bound(), 3);
+    }
+
+    #[test]
+    fn clamps_empty_dimensions_to_one_counter() {
+        let mut sketch = CountMinSketch::new(0, 0);
+        sketch.add(&"Berlin", 3, 0.0);
+        assert_eq!(sketch.add(&"Paris", 2, 0.0), (5, 0.0));
+        assert_eq!(sketch.error_bound(), 14);
+    }
+}
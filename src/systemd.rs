@@ -0,0 +1,126 @@
+use serde_json::Value;
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
+
+/// Socket journald receives native protocol entries on.
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Sends `state` (e.g. `READY=1`) to the service manager when running as a
+/// systemd `Type=notify` service, and does nothing otherwise.
+pub fn notify(state: &str) {
+    if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        // Notifications are advisory; systemd times out if they're lost.
+        let _ = send(&path, state.as_bytes());
+    }
+}
+
+/// Pings the systemd watchdog at half the service's `WatchdogSec=`, if
+/// enabled for this process.
+pub struct Watchdog {
+    interval: Option<Duration>,
+    next: Instant,
+}
+
+impl Watchdog {
+    /// Reads the watchdog interval systemd passed in `WATCHDOG_USEC`.
+    pub fn new() -> Self {
+        let pid = env::var("WATCHDOG_PID").ok();
+        let interval = env::var("WATCHDOG_USEC")
+            .ok()
+            .filter(|_| pid.map_or(true, |pid| pid == process::id().to_string()))
+            .and_then(|usec| usec.parse().ok())
+            .map(|usec: u64| Duration::from_micros(usec / 2));
+        Self {
+            interval,
+            next: Instant::now(),
+        }
+    }
+
+    /// Pings the watchdog if it's due. Called from the main loop, so a stuck
+    /// loop gets the service restarted.
+    pub fn ping(&mut self) {
+        if let Some(interval) = self.interval.filter(|_| Instant::now() >= self.next) {
+            notify("WATCHDOG=1");
+            self.next = Instant::now() + interval;
+        }
+    }
+}
+
+/// Writes a log event to journald as a native entry, with `fields` as
+/// structured fields named in upper case, e.g. `PATH=` for `path`.
+pub fn journal(priority: u8, message: &str, fields: &[(&str, Value)]) -> io::Result<()> {
+    let mut entry = Vec::new();
+    append(&mut entry, "PRIORITY", &priority.to_string());
+    append(&mut entry, "SYSLOG_IDENTIFIER", env!("CARGO_PKG_NAME"));
+    append(&mut entry, "MESSAGE", message);
+    for (key, value) in fields {
+        let name = field_name(key);
+        match value {
+            Value::String(text) => append(&mut entry, &name, text),
+            other => append(&mut entry, &name, &other.to_string()),
+        }
+    }
+    send(OsStr::new(JOURNAL_SOCKET), &entry)
+}
+
+/// Appends a `NAME=value` field to a journal entry, using the
+/// length-prefixed form for values spanning several lines.
+fn append(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Maps a log field name to a valid journal field name: upper case letters,
+/// digits and underscores, not starting with an underscore or digit.
+fn field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if name.starts_with(|c: char| c == '_' || c.is_ascii_digit()) {
+        format!("F{name}")
+    } else {
+        name
+    }
+}
+
+/// Sends a datagram to the Unix socket at `path`, or in the abstract
+/// namespace for paths starting with `@`.
+#[cfg(unix)]
+fn send(path: &OsStr, datagram: &[u8]) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.as_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        socket.send_to_addr(datagram, &addr)?;
+        return Ok(());
+    }
+    socket.send_to(datagram, path)?;
+    Ok(())
+}
+
+/// systemd only exists on Unix.
+#[cfg(not(unix))]
+fn send(_path: &OsStr, _datagram: &[u8]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
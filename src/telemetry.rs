@@ -0,0 +1,326 @@
+use crate::logging;
+use crate::remote::{Remote, Upload, UploadMethod};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Prefix of the metric names.
+const METRIC_PREFIX: &str = "dashboard_location_mapper";
+
+/// The OTLP exporter state; telemetry is off until it is set.
+static EXPORTER: OnceLock<Mutex<Exporter>> = OnceLock::new();
+
+/// Collects spans and metrics and sends them to an OTLP/HTTP collector as
+/// JSON.
+struct Exporter {
+    /// Base URL of the collector, e.g. `http://localhost:4318`.
+    endpoint: String,
+    headers: Vec<(String, String)>,
+    resource: Value,
+    /// Trace of the current run or collector flush interval.
+    trace_id: String,
+    /// Span covering the whole trace, parent of every recorded span.
+    root_id: String,
+    trace_start: SystemTime,
+    spans: Vec<Value>,
+    /// Time the cumulative metrics started counting.
+    metrics_start: SystemTime,
+    /// Records per aggregation outcome.
+    records: BTreeMap<&'static str, u64>,
+    lookups: Lookups,
+    /// Collector lookup cache hits and misses.
+    cache: BTreeMap<&'static str, u64>,
+}
+
+/// Cumulative database lookup latency.
+#[derive(Default)]
+pub struct Lookups {
+    pub count: u64,
+    pub total: Duration,
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+}
+
+impl Lookups {
+    /// Records the latency of one lookup.
+    pub fn observe(&mut self, latency: Duration) {
+        self.count += 1;
+        self.total += latency;
+        self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+        self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+    }
+
+    /// Adds the lookups counted in `other`.
+    pub fn merge(&mut self, other: &Self) {
+        self.count += other.count;
+        self.total += other.total;
+        for latency in other.min.into_iter().chain(other.max) {
+            self.min = Some(self.min.map_or(latency, |min| min.min(latency)));
+            self.max = Some(self.max.map_or(latency, |max| max.max(latency)));
+        }
+    }
+}
+
+/// Enables telemetry export to the OTLP/HTTP collector at `endpoint`, falling
+/// back to the standard `OTEL_EXPORTER_OTLP_ENDPOINT` environment variable.
+/// Telemetry stays off if neither is set.
+///
+/// `OTEL_EXPORTER_OTLP_HEADERS` (`key=value,...`) adds request headers and
+/// `OTEL_SERVICE_NAME` overrides the reported service name.
+pub fn init(endpoint: Option<String>) {
+    let Some(endpoint) = endpoint.or_else(|| env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()) else {
+        return;
+    };
+    let headers = env::var("OTEL_EXPORTER_OTLP_HEADERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|header| header.split_once('='))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let service = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| METRIC_PREFIX.to_string());
+    let now = SystemTime::now();
+    let exporter = Exporter {
+        endpoint: endpoint.trim_end_matches('/').to_string(),
+        headers,
+        resource: json!({
+            "attributes": attributes(&[
+                ("service.name", json!(service)),
+                ("service.version", json!(env!("CARGO_PKG_VERSION"))),
+            ]),
+        }),
+        trace_id: random_id(16),
+        root_id: random_id(8),
+        trace_start: now,
+        spans: Vec::new(),
+        metrics_start: now,
+        records: BTreeMap::new(),
+        lookups: Lookups::default(),
+        cache: BTreeMap::new(),
+    };
+    let _ = EXPORTER.set(Mutex::new(exporter));
+}
+
+/// Records a span named `name` that started at `start` and ends now.
+pub fn span(name: &str, start: Instant, fields: &[(&str, Value)]) {
+    with_exporter(|exporter| {
+        let end = SystemTime::now();
+        let start = end.checked_sub(start.elapsed()).unwrap_or(end);
+        let span = json!({
+            "traceId": exporter.trace_id,
+            "spanId": random_id(8),
+            "parentSpanId": exporter.root_id,
+            "name": name,
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(start),
+            "endTimeUnixNano": unix_nanos(end),
+            "attributes": attributes(fields),
+        });
+        exporter.spans.push(span);
+    });
+}
+
+/// Adds `count` records handled with `outcome` (e.g. `located`) to the
+/// records counter.
+pub fn count_records(outcome: &'static str, count: u64) {
+    with_exporter(|exporter| *exporter.records.entry(outcome).or_default() += count);
+}
+
+/// Adds `count` lookup cache queries with `result` (`hit` or `miss`) to the
+/// cache counter.
+pub fn count_cache_lookups(result: &'static str, count: u64) {
+    with_exporter(|exporter| *exporter.cache.entry(result).or_default() += count);
+}
+
+/// Adds database lookups to the lookup latency histogram.
+pub fn record_lookups(lookups: &Lookups) {
+    with_exporter(|exporter| exporter.lookups.merge(lookups));
+}
+
+/// Ends the current trace with a root span named `name` and sends it, along
+/// with the cumulative metrics, to the collector. A new trace starts for any
+/// spans recorded afterwards.
+///
+/// Export failures are logged rather than failing the run.
+pub fn export(name: &str) {
+    with_exporter(|exporter| {
+        let end = SystemTime::now();
+        let root = json!({
+            "traceId": exporter.trace_id,
+            "spanId": exporter.root_id,
+            "name": name,
+            "kind": 1,
+            "startTimeUnixNano": unix_nanos(exporter.trace_start),
+            "endTimeUnixNano": unix_nanos(end),
+        });
+        exporter.spans.push(root);
+        let traces = json!({
+            "resourceSpans": [{
+                "resource": exporter.resource,
+                "scopeSpans": [{ "scope": scope(), "spans": exporter.spans }],
+            }],
+        });
+        let metrics = json!({
+            "resourceMetrics": [{
+                "resource": exporter.resource,
+                "scopeMetrics": [{ "scope": scope(), "metrics": exporter.metrics(end) }],
+            }],
+        });
+        exporter.send("/v1/traces", &traces);
+        exporter.send("/v1/metrics", &metrics);
+
+        exporter.trace_id = random_id(16);
+        exporter.root_id = random_id(8);
+        exporter.trace_start = end;
+        exporter.spans.clear();
+    });
+}
+
+impl Exporter {
+    /// The cumulative metrics as of `now`.
+    fn metrics(&self, now: SystemTime) -> Value {
+        let start = unix_nanos(self.metrics_start);
+        let time = unix_nanos(now);
+        let counts = |counts: &BTreeMap<&str, u64>, attribute: &str| -> Vec<Value> {
+            counts
+                .iter()
+                .map(|(value, count)| {
+                    json!({
+                        "attributes": attributes(&[(attribute, json!(value))]),
+                        "startTimeUnixNano": start,
+                        "timeUnixNano": time,
+                        "asInt": count.to_string(),
+                    })
+                })
+                .collect()
+        };
+        let millis = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        let mut lookups = json!({
+            "startTimeUnixNano": start,
+            "timeUnixNano": time,
+            "count": self.lookups.count.to_string(),
+            "sum": millis(self.lookups.total),
+            "bucketCounts": [self.lookups.count.to_string()],
+            "explicitBounds": [],
+        });
+        if let (Some(min), Some(max)) = (self.lookups.min, self.lookups.max) {
+            lookups["min"] = json!(millis(min));
+            lookups["max"] = json!(millis(max));
+        }
+        json!([
+            {
+                "name": format!("{METRIC_PREFIX}.records"),
+                "description": "Threat records aggregated, by outcome.",
+                "unit": "{record}",
+                "sum": {
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                    "dataPoints": counts(&self.records, "outcome"),
+                },
+            },
+            {
+                "name": format!("{METRIC_PREFIX}.lookup.cache"),
+                "description": "Collector lookup cache queries, by result.",
+                "unit": "{lookup}",
+                "sum": {
+                    "aggregationTemporality": 2,
+                    "isMonotonic": true,
+                    "dataPoints": counts(&self.cache, "result"),
+                },
+            },
+            {
+                "name": format!("{METRIC_PREFIX}.lookup.duration"),
+                "description": "Latency of geolocation database lookups.",
+                "unit": "ms",
+                "histogram": {
+                    "aggregationTemporality": 2,
+                    "dataPoints": [lookups],
+                },
+            },
+        ])
+    }
+
+    /// Posts an OTLP/HTTP JSON payload to `path` on the collector.
+    fn send(&self, path: &str, payload: &Value) {
+        let url = format!("{}{path}", self.endpoint);
+        let upload = Upload {
+            method: UploadMethod::Post,
+            headers: self.headers.clone(),
+            retries: 1,
+        };
+        let result = serde_json::to_vec(payload)
+            .map_err(Into::into)
+            .and_then(|body| Remote::Http(&url).upload(&body, "application/json", &upload));
+        if let Err(error) = result {
+            logging::warn(
+                "telemetry export failed",
+                &[("url", json!(url)), ("error", json!(error.to_string()))],
+            );
+        }
+    }
+}
+
+/// Runs `f` on the exporter if telemetry is enabled.
+fn with_exporter(f: impl FnOnce(&mut Exporter)) {
+    if let Some(exporter) = EXPORTER.get() {
+        // A panic while holding the lock can't leave the telemetry in a state
+        // worth refusing to report.
+        let mut exporter = exporter.lock().unwrap_or_else(PoisonError::into_inner);
+        f(&mut exporter);
+    }
+}
+
+/// The instrumentation scope reported with spans and metrics.
+fn scope() -> Value {
+    json!({ "name": METRIC_PREFIX, "version": env!("CARGO_PKG_VERSION") })
+}
+
+/// Converts fields to OTLP key-value attributes.
+fn attributes(fields: &[(&str, Value)]) -> Vec<Value> {
+    fields
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Bool(flag) => json!({ "boolValue": flag }),
+                Value::Number(n) if n.is_f64() => json!({ "doubleValue": n }),
+                Value::Number(n) => json!({ "intValue": n.to_string() }),
+                Value::String(text) => json!({ "stringValue": text }),
+                other => json!({ "stringValue": other.to_string() }),
+            };
+            json!({ "key": key, "value": value })
+        })
+        .collect()
+}
+
+/// Nanoseconds since the Unix epoch, as the decimal string OTLP JSON uses
+/// for 64-bit integers.
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Returns a hex-encoded pseudo-random trace or span ID of `bytes` bytes.
+///
+/// IDs only need to be unique, so hashing the process ID, the current time
+/// and a counter is enough.
+fn random_id(bytes: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut hasher = Sha256::new();
+    hasher.update(process::id().to_be_bytes());
+    hasher.update(unix_nanos(SystemTime::now()));
+    hasher.update(counter.to_be_bytes());
+    hasher.finalize()[..bytes]
+        .iter()
+        .fold(String::new(), |mut id, byte| {
+            let _ = write!(id, "{byte:02x}");
+            id
+        })
+}
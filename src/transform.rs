@@ -0,0 +1,166 @@
+use crate::aggregate::{CityData, GroupKey, LocationKey};
+use crate::script::Script;
+use rhai::serde::{from_dynamic, to_dynamic};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+/// A user-defined output transformation: a Rhai script defining
+/// `transform(rows)`, called with the aggregated rows before they are
+/// written and returning the rows to write instead.
+///
+/// Rows are maps of `city`, `country`, `count`, `weighted_count`, `lat`,
+/// `lon`, `population`, `country_weight`, `feed`, `service`, `tag`,
+/// `known_scanner`, `cloud_provider`, `netblock_owner`, `abuse_contact`,
+/// `tags` and `services`. Rows returned
+/// with the same location and grouping dimensions are merged, and any other
+/// field a row is given is written as a custom field.
+pub struct Transform {
+    script: Script,
+}
+
+/// An aggregated row as the script sees it.
+#[derive(Serialize, Deserialize)]
+struct ScriptRow {
+    city: String,
+    country: String,
+    #[serde(default)]
+    country_code: Option<String>,
+    count: u32,
+    #[serde(default)]
+    weighted_count: Option<f64>,
+    lat: f64,
+    lon: f64,
+    #[serde(default)]
+    population: Option<u32>,
+    #[serde(default)]
+    country_weight: Option<f64>,
+    #[serde(default)]
+    feed: Option<String>,
+    #[serde(default)]
+    service: Option<String>,
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    known_scanner: Option<String>,
+    #[serde(default)]
+    cloud_provider: Option<String>,
+    #[serde(default)]
+    netblock_owner: Option<String>,
+    #[serde(default)]
+    abuse_contact: Option<String>,
+    #[serde(default)]
+    tags: BTreeMap<String, String>,
+    #[serde(default)]
+    services: BTreeMap<String, u32>,
+    #[serde(default)]
+    anomaly: bool,
+    #[serde(default)]
+    first_seen: Option<String>,
+    #[serde(default)]
+    new: bool,
+    #[serde(flatten)]
+    fields: BTreeMap<String, Value>,
+}
+
+impl Transform {
+    /// Compiles the script at `path` and runs its top-level statements.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let script = Script::load(path, "transform")?;
+        Ok(Self { script })
+    }
+
+    /// Runs `transform` on the aggregated `entries`, returning the
+    /// transformed entries in the order the script returned them.
+    pub fn apply<'a>(
+        &self,
+        entries: impl Iterator<Item = (&'a GroupKey, &'a CityData)>,
+    ) -> Result<Vec<(GroupKey, CityData)>, Box<dyn Error>> {
+        let rows: Vec<ScriptRow> = entries
+            .map(|(key, data)| ScriptRow {
+                city: data.city_name.clone(),
+                country: data.country_name.clone(),
+                country_code: data.country_code.clone(),
+                count: data.total_count,
+                weighted_count: Some(data.weighted_count),
+                lat: key.location.lat(),
+                lon: key.location.lon(),
+                population: data.population,
+                country_weight: data.country_weight,
+                feed: key.feed.clone(),
+                service: key.service.clone(),
+                tag: key.tag.clone(),
+                known_scanner: key.known_scanner.clone(),
+                cloud_provider: key.cloud_provider.clone(),
+                netblock_owner: key.netblock_owner.clone(),
+                abuse_contact: key.abuse_contact.clone(),
+                tags: key.tags.clone(),
+                services: data.services.clone(),
+                anomaly: data.anomaly,
+                first_seen: data.first_seen.clone(),
+                new: data.new,
+                fields: data.fields.clone(),
+            })
+            .collect();
+        let result = self.script.call(to_dynamic(rows)?)?;
+        // Going through JSON accepts integers where floats are expected.
+        let rows: Vec<ScriptRow> = from_dynamic::<Value>(&result)
+            .map_err(Into::into)
+            .and_then(|rows| serde_json::from_value(rows).map_err(Into::into))
+            .map_err(|e: Box<dyn Error>| {
+                self.script
+                    .error(format!("`transform` must return an array of rows: {e}"))
+            })?;
+        let mut entries: Vec<(GroupKey, CityData)> = Vec::with_capacity(rows.len());
+        let mut positions = HashMap::new();
+        for row in rows {
+            let key = GroupKey {
+                location: LocationKey::new(row.lat, row.lon),
+                feed: row.feed,
+                service: row.service,
+                tag: row.tag,
+                known_scanner: row.known_scanner,
+                cloud_provider: row.cloud_provider,
+                netblock_owner: row.netblock_owner,
+                abuse_contact: row.abuse_contact,
+                tags: row.tags,
+            };
+            let weighted_count = row.weighted_count.unwrap_or_else(|| row.count.into());
+            match positions.entry(key) {
+                Entry::Occupied(position) => {
+                    let (_, data): &mut (GroupKey, CityData) = &mut entries[*position.get()];
+                    data.total_count = data.total_count.saturating_add(row.count);
+                    data.weighted_count += weighted_count;
+                    for (service, count) in row.services {
+                        let services = data.services.entry(service).or_default();
+                        *services = services.saturating_add(count);
+                    }
+                    data.anomaly |= row.anomaly;
+                    data.new |= row.new;
+                }
+                Entry::Vacant(position) => {
+                    let data = CityData {
+                        city_name: row.city,
+                        country_name: row.country,
+                        country_code: row.country_code,
+                        total_count: row.count,
+                        weighted_count,
+                        population: row.population,
+                        country_weight: row.country_weight,
+                        services: row.services,
+                        anomaly: row.anomaly,
+                        first_seen: row.first_seen,
+                        new: row.new,
+                        fields: row.fields,
+                    };
+                    entries.push((position.key().clone(), data));
+                    position.insert(entries.len() - 1);
+                }
+            }
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,191 @@
+use crate::geo::{distance_km, Locator};
+use crate::input::Login;
+use chrono::{DateTime, SecondsFormat};
+use serde::Serialize;
+use std::error::Error;
+use std::io::Write;
+use std::net::IpAddr;
+
+/// Limits beyond which consecutive logins of a user are impossible travel.
+pub struct Limits {
+    /// Fastest plausible travel in km/h.
+    pub max_speed: f64,
+    /// Distance in km below which logins are never flagged, as geolocation
+    /// is too coarse to tell nearby places apart.
+    pub min_distance: f64,
+}
+
+/// A pair of consecutive logins of a user too far apart for the time
+/// between them.
+#[derive(Serialize)]
+pub struct Finding {
+    pub user: String,
+    pub from_time: String,
+    pub from_ip: String,
+    pub from_city: String,
+    pub from_country: String,
+    pub to_time: String,
+    pub to_ip: String,
+    pub to_city: String,
+    pub to_country: String,
+    pub distance_km: f64,
+    pub hours: f64,
+    /// Speed needed to make the trip, or `None` for simultaneous logins.
+    pub speed_kmh: Option<f64>,
+}
+
+/// A login along with where it came from.
+struct Located {
+    login: Login,
+    city: String,
+    country: String,
+    position: (f64, f64),
+}
+
+/// Geolocates every login and compares each user's consecutive logins in
+/// time order, returning those that would have needed travel faster than
+/// the limits allow. Logins whose IP can't be located are skipped, so the
+/// next one is compared against the last located login.
+pub fn find<S: AsRef<[u8]>>(
+    locator: &Locator<S>,
+    mut logins: Vec<Login>,
+    limits: &Limits,
+) -> Vec<Finding> {
+    logins.sort_by(|a, b| a.user.cmp(&b.user).then(a.time.total_cmp(&b.time)));
+    let mut findings = Vec::new();
+    let mut last: Option<Located> = None;
+    for login in logins {
+        let Some(location) = login
+            .source
+            .trim()
+            .parse::<IpAddr>()
+            .ok()
+            .and_then(|ip| locator.locate(ip))
+        else {
+            continue;
+        };
+        let current = Located {
+            login,
+            city: location.city_name,
+            country: location.country_name,
+            position: (location.lat, location.lon),
+        };
+        if let Some(previous) = last.filter(|last| last.login.user == current.login.user) {
+            findings.extend(check(&previous, &current, limits));
+        }
+        last = Some(current);
+    }
+    findings
+}
+
+/// Returns a finding if getting from one login to the next breaks the limits.
+fn check(from: &Located, to: &Located, limits: &Limits) -> Option<Finding> {
+    let distance = distance_km(from.position, to.position);
+    let hours = (to.login.time - from.login.time) / 3600.0;
+    let speed = (hours > 0.0).then(|| distance / hours);
+    if distance < limits.min_distance || speed.is_some_and(|speed| speed <= limits.max_speed) {
+        return None;
+    }
+    Some(Finding {
+        user: to.login.user.clone(),
+        from_time: time(from.login.time),
+        from_ip: from.login.source.clone(),
+        from_city: from.city.clone(),
+        from_country: from.country.clone(),
+        to_time: time(to.login.time),
+        to_ip: to.login.source.clone(),
+        to_city: to.city.clone(),
+        to_country: to.country.clone(),
+        distance_km: distance.round(),
+        hours: (hours * 100.0).round() / 100.0,
+        speed_kmh: speed.map(f64::round),
+    })
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC time.
+#[allow(clippy::cast_possible_truncation)] // Login times are well within range.
+fn time(seconds: f64) -> String {
+    DateTime::from_timestamp_millis((seconds * 1000.0).round() as i64)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Writes the findings as CSV with a header row, or as JSON lines.
+pub fn write(findings: &[Finding], json: bool, out: impl Write) -> Result<(), Box<dyn Error>> {
+    if json {
+        let mut out = out;
+        for finding in findings {
+            serde_json::to_writer(&mut out, finding)?;
+            writeln!(out)?;
+        }
+        return Ok(out.flush()?);
+    }
+    let mut writer = csv::Writer::from_writer(out);
+    for finding in findings {
+        writer.serialize(finding)?;
+    }
+    Ok(writer.flush()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, time, Limits, Located, Login};
+
+    /// Limits of an airliner's speed and city-level accuracy.
+    const LIMITS: Limits = Limits {
+        max_speed: 900.0,
+        min_distance: 100.0,
+    };
+
+    /// Returns a login of `alice` at `position`, `hours` after the epoch.
+    fn login(city: &str, position: (f64, f64), hours: f64) -> Located {
+        Located {
+            login: Login {
+                user: "alice".to_string(),
+                source: "203.0.113.5".to_string(),
+                time: hours * 3600.0,
+            },
+            city: city.to_string(),
+            country: "Testland".to_string(),
+            position,
+        }
+    }
+
+    #[test]
+    fn measures_travel_over_the_antimeridian_the_short_way() {
+        let suva = login("Suva", (-18.14, 178.44), 0.0);
+        let apia = login("Apia", (-13.83, -171.76), 1.0);
+        let finding = check(&suva, &apia, &LIMITS).expect("1,150 km in an hour is flagged");
+        assert!((1100.0..1200.0).contains(&finding.distance_km));
+        assert_eq!(finding.speed_kmh, Some(finding.distance_km));
+        // The flight takes about two hours, not the other way around the
+        // world.
+        assert!(check(&suva, &login("Apia", (-13.83, -171.76), 2.0), &LIMITS).is_none());
+    }
+
+    #[test]
+    fn flags_simultaneous_logins_far_apart() {
+        let berlin = login("Berlin", (52.52, 13.40), 0.0);
+        let tokyo = login("Tokyo", (35.68, 139.69), 0.0);
+        let finding = check(&berlin, &tokyo, &LIMITS).expect("simultaneous logins are flagged");
+        assert_eq!(finding.speed_kmh, None);
+        assert!(finding.hours.abs() < f64::EPSILON);
+        assert_eq!(
+            (finding.from_city.as_str(), finding.to_city.as_str()),
+            ("Berlin", "Tokyo")
+        );
+    }
+
+    #[test]
+    fn ignores_nearby_logins_however_fast() {
+        let berlin = login("Berlin", (52.52, 13.40), 0.0);
+        let potsdam = login("Potsdam", (52.39, 13.06), 0.0);
+        assert!(check(&berlin, &potsdam, &LIMITS).is_none());
+    }
+
+    #[test]
+    fn formats_times_as_utc() {
+        assert_eq!(time(0.0), "1970-01-01T00:00:00Z");
+        assert_eq!(time(1_700_000_000.4), "2023-11-14T22:13:20Z");
+    }
+}
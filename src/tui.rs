@@ -0,0 +1,217 @@
+use crate::aggregate::Aggregator;
+use crate::shutdown;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Stylize};
+use ratatui::symbols::Marker;
+use ratatui::text::Line;
+use ratatui::widgets::canvas::{Canvas, Context, Map, MapResolution, Points};
+use ratatui::widgets::{Block, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::cmp::Reverse;
+use std::io;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// Number of locations listed in the table.
+const TOP_LOCATIONS: usize = 20;
+
+/// A live terminal dashboard of the aggregation: the top locations, the
+/// handling counts of the current input or flush interval, and a world
+/// heatmap of all locations.
+///
+/// Takes over the terminal until dropped. Pressing `q`, `Esc` or `Ctrl-C`
+/// requests a shutdown, like SIGINT.
+pub struct Dashboard {
+    terminal: DefaultTerminal,
+    snapshot: Snapshot,
+}
+
+/// What the dashboard shows, kept to redraw it after a resize.
+#[derive(Default)]
+struct Snapshot {
+    phase: String,
+    total: u64,
+    /// City, country and count of every location, by descending count.
+    locations: Vec<(String, String, u32)>,
+    /// Longitude, latitude and count of every location.
+    points: Vec<(f64, f64, u32)>,
+    counts: Vec<(&'static str, usize)>,
+}
+
+impl Dashboard {
+    /// Switches the terminal to the dashboard.
+    pub fn start() -> io::Result<Self> {
+        Ok(Self {
+            terminal: ratatui::try_init()?,
+            snapshot: Snapshot::default(),
+        })
+    }
+
+    /// Shows the state of `aggregator` during `phase`, along with how the
+    /// records of the phase were handled, then handles pending key presses.
+    pub fn show(
+        &mut self,
+        phase: String,
+        aggregator: &Aggregator,
+        counts: Vec<(&'static str, usize)>,
+    ) -> io::Result<()> {
+        let mut locations: Vec<_> = aggregator.entries().collect();
+        locations.sort_by_key(|(_, data)| Reverse(data.total_count));
+        self.snapshot = Snapshot {
+            phase,
+            total: aggregator.total_count(),
+            points: locations
+                .iter()
+                .map(|(key, data)| (key.location.lon(), key.location.lat(), data.total_count))
+                .collect(),
+            locations: locations
+                .into_iter()
+                .take(TOP_LOCATIONS)
+                .map(|(_, data)| {
+                    let (city, country) = (data.city_name.clone(), data.country_name.clone());
+                    (city, country, data.total_count)
+                })
+                .collect(),
+            counts,
+        };
+        self.draw()?;
+        self.poll(Duration::ZERO).map(|_| ())
+    }
+
+    /// Shows the final state as `phase` and waits for the user to quit.
+    pub fn wait(&mut self, phase: String) -> io::Result<()> {
+        self.snapshot.phase = phase;
+        self.draw()?;
+        while !self.poll(Duration::from_secs(1))? {}
+        Ok(())
+    }
+
+    /// Handles the key presses and resizes arriving within `timeout`.
+    /// Returns `true` if the user asked to quit.
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        let mut quit = false;
+        let mut timeout = timeout;
+        while event::poll(timeout)? {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                        shutdown::request();
+                        quit = true;
+                    }
+                }
+                Event::Resize(..) => self.draw()?,
+                _ => {}
+            }
+            timeout = Duration::ZERO;
+        }
+        Ok(quit)
+    }
+
+    fn draw(&mut self) -> io::Result<()> {
+        let snapshot = &self.snapshot;
+        self.terminal
+            .draw(|frame| render(frame, snapshot))
+            .map(|_| ())
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+/// Lays out the table and counts on the left and the map on the right.
+fn render(frame: &mut Frame, snapshot: &Snapshot) {
+    let title = Line::from(format!(
+        " {} threats at {} locations, {} ",
+        snapshot.total,
+        snapshot.points.len(),
+        snapshot.phase
+    ))
+    .bold();
+    let outer = Block::bordered()
+        .title(title)
+        .title_bottom(Line::from(" q quit ").right_aligned());
+    let area = outer.inner(frame.area());
+    frame.render_widget(outer, frame.area());
+
+    let [left, map] = Layout::horizontal([Constraint::Length(48), Constraint::Fill(1)]).areas(area);
+    let [table, counts] = Layout::vertical([
+        Constraint::Fill(1),
+        Constraint::Length(u16::try_from(snapshot.counts.len() + 2).unwrap_or(u16::MAX)),
+    ])
+    .areas(left);
+
+    let rows = snapshot
+        .locations
+        .iter()
+        .map(|(city, country, count)| Row::new([city.clone(), country.clone(), count.to_string()]));
+    let widths = [
+        Constraint::Fill(1),
+        Constraint::Fill(1),
+        Constraint::Length(8),
+    ];
+    frame.render_widget(
+        Table::new(rows, widths)
+            .header(Row::new(["City", "Country", "Count"]).bold())
+            .block(Block::bordered().title(" Top locations ")),
+        table,
+    );
+
+    let rows = snapshot
+        .counts
+        .iter()
+        .map(|(outcome, count)| Row::new([(*outcome).to_string(), count.to_string()]));
+    let widths = [Constraint::Fill(1), Constraint::Length(10)];
+    frame.render_widget(
+        Table::new(rows, widths).block(Block::bordered().title(" Records ")),
+        counts,
+    );
+
+    let heatmap = heatmap(&snapshot.points).block(Block::bordered().title(" Map "));
+    frame.render_widget(heatmap, map);
+}
+
+/// Draws `points`, given as longitude, latitude and count, onto a world
+/// map, in hotter colors the more threats they count relative to the top
+/// location.
+pub fn heatmap(points: &[(f64, f64, u32)]) -> Canvas<'_, impl Fn(&mut Context<'_>) + '_> {
+    let max = points.iter().map(|&(.., count)| count).max().unwrap_or(1);
+    Canvas::default()
+        .marker(Marker::Braille)
+        .x_bounds([-180.0, 180.0])
+        .y_bounds([-90.0, 90.0])
+        .paint(move |ctx| {
+            ctx.draw(&Map {
+                color: Color::DarkGray,
+                resolution: MapResolution::High,
+            });
+            ctx.layer();
+            for (color, range) in intensities(max) {
+                let coords: Vec<(f64, f64)> = points
+                    .iter()
+                    .filter(|(.., count)| range.contains(count))
+                    .map(|&(lon, lat, _)| (lon, lat))
+                    .collect();
+                ctx.draw(&Points {
+                    coords: &coords,
+                    color,
+                });
+            }
+        })
+}
+
+/// Splits counts up to `max` into thirds, each with the color locations in
+/// it are drawn in.
+pub const fn intensities(max: u32) -> [(Color, RangeInclusive<u32>); 3] {
+    let (third, two_thirds) = (max / 3, max - max / 3);
+    [
+        (Color::Yellow, 0..=third),
+        (Color::LightRed, third + 1..=two_thirds),
+        (Color::Red, two_thirds + 1..=max),
+    ]
+}
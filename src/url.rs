@@ -0,0 +1,61 @@
+/// Returns the host of `url`, such as `evil.example.com` for
+/// `https://user@evil.example.com:8443/login?id=1`, without any brackets
+/// around an IPv6 address, or `None` if `url` doesn't start with a scheme.
+pub fn host(url: &str) -> Option<&str> {
+    let (scheme, rest) = url.trim().split_once("://")?;
+    let valid_scheme = scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    if !valid_scheme {
+        return None;
+    }
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']')?.0,
+        None => authority
+            .split_once(':')
+            .map_or(authority, |(host, _)| host),
+    };
+    Some(host).filter(|host| !host.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::host;
+
+    #[test]
+    fn finds_hosts_of_urls() {
+        let url = "https://user@evil.example.com:8443/login?id=1";
+        assert_eq!(host(url), Some("evil.example.com"));
+        assert_eq!(host(" http://203.0.113.5 "), Some("203.0.113.5"));
+        assert_eq!(host("svn+ssh://a:b@example.org#x"), Some("example.org"));
+        assert_eq!(host("ftp://example.org?x=1"), Some("example.org"));
+    }
+
+    #[test]
+    fn strips_brackets_from_ipv6_hosts() {
+        assert_eq!(host("http://[2001:db8::1]:8080/x"), Some("2001:db8::1"));
+        assert_eq!(host("https://user@[2001:db8::1]"), Some("2001:db8::1"));
+        assert_eq!(host("http://[2001:db8::1/x"), None);
+    }
+
+    #[test]
+    fn rejects_urls_without_a_scheme() {
+        for url in [
+            "example.com/login",
+            "//example.com",
+            "203.0.113.5:80",
+            "1http://example.com",
+            "ht tp://example.com",
+            "http://",
+            "http:///path",
+            "http://user@:80",
+        ] {
+            assert_eq!(host(url), None, "{url}");
+        }
+    }
+}
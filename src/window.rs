@@ -0,0 +1,132 @@
+use crate::aggregate::Aggregator;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// A time window over the collector's running totals, so they cover the
+/// last `--window` rather than everything since the collector started.
+///
+/// Flows are aggregated into buckets of `--slide` each. Once a window's worth
+/// of buckets has been filled, the oldest is subtracted from the totals as
+/// each new one starts, so the window slides forward a bucket at a time, or
+/// starts over when the slide equals the window.
+pub struct Window {
+    slide: Duration,
+    /// Number of buckets the window spans.
+    size: usize,
+    /// The buckets from oldest to newest, the last one being filled.
+    buckets: VecDeque<Aggregator>,
+    /// When the newest bucket started.
+    started: Instant,
+}
+
+impl Window {
+    /// Creates a window of `length`, moving forward by `slide` at a time.
+    pub fn new(length: Duration, slide: Duration, now: Instant) -> Self {
+        let slide = slide.max(Duration::from_secs(1));
+        let size = usize::try_from(length.as_secs() / slide.as_secs()).unwrap_or(usize::MAX);
+        Self {
+            slide,
+            size: size.max(1),
+            buckets: VecDeque::from([Aggregator::default()]),
+            started: now,
+        }
+    }
+
+    /// Adds a batch of aggregated flows to the newest bucket and `totals`.
+    pub fn add(&mut self, batch: &Aggregator, totals: &mut Aggregator) {
+        totals.merge(batch);
+        if let Some(bucket) = self.buckets.back_mut() {
+            bucket.merge(batch);
+        }
+    }
+
+    /// Starts a new bucket for every `slide` elapsed by `now`, subtracting
+    /// the buckets that fall out of the window from `totals`.
+    pub fn advance(&mut self, now: Instant, totals: &mut Aggregator) {
+        while now.duration_since(self.started) >= self.slide {
+            self.started += self.slide;
+            self.buckets.push_back(Aggregator::default());
+            while self.buckets.len() > self.size {
+                if let Some(expired) = self.buckets.pop_front() {
+                    totals.subtract(&expired);
+                }
+            }
+            // After a long quiet spell, skip straight to an empty window.
+            if self.buckets.iter().all(|bucket| bucket.len() == 0)
+                && now.duration_since(self.started) >= self.slide
+            {
+                let skipped = now.duration_since(self.started).as_secs() / self.slide.as_secs();
+                self.started += self.slide * u32::try_from(skipped).unwrap_or(u32::MAX);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Window;
+    use crate::aggregate::tests::berlin;
+    use crate::aggregate::{Aggregator, Group};
+    use std::time::{Duration, Instant};
+
+    /// Returns a batch of `count` flows.
+    fn batch(count: u32) -> Aggregator {
+        let mut batch = Aggregator::default();
+        batch.add(&berlin(), Group::default(), None, count, None);
+        batch
+    }
+
+    /// Returns `seconds` after `start`.
+    fn at(start: Instant, seconds: u64) -> Instant {
+        start + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn tumbling_window_starts_over_once_full() {
+        let start = Instant::now();
+        let mut window = Window::new(Duration::from_secs(60), Duration::from_secs(60), start);
+        let mut totals = Aggregator::default();
+        window.add(&batch(3), &mut totals);
+        window.advance(at(start, 59), &mut totals);
+        assert_eq!(totals.total_count(), 3);
+        window.advance(at(start, 60), &mut totals);
+        assert_eq!(totals.total_count(), 0);
+        assert_eq!(totals.len(), 0);
+    }
+
+    #[test]
+    fn sliding_window_subtracts_expired_buckets() {
+        let start = Instant::now();
+        let mut window = Window::new(Duration::from_secs(60), Duration::from_secs(20), start);
+        let mut totals = Aggregator::default();
+        for (seconds, count) in [(0, 1), (20, 2), (40, 4)] {
+            window.advance(at(start, seconds), &mut totals);
+            window.add(&batch(count), &mut totals);
+        }
+        assert_eq!(totals.total_count(), 7);
+        window.advance(at(start, 79), &mut totals);
+        assert_eq!(totals.total_count(), 6);
+        window.advance(at(start, 80), &mut totals);
+        assert_eq!(totals.total_count(), 4);
+        window.advance(at(start, 120), &mut totals);
+        assert_eq!(totals.total_count(), 0);
+    }
+
+    #[test]
+    fn skips_ahead_after_a_quiet_spell() {
+        let start = Instant::now();
+        let mut window = Window::new(Duration::from_secs(60), Duration::from_secs(20), start);
+        let mut totals = Aggregator::default();
+        window.add(&batch(5), &mut totals);
+        window.advance(at(start, 1_010), &mut totals);
+        assert_eq!(totals.total_count(), 0);
+        assert_eq!(window.started, at(start, 1_000));
+        assert!(window.buckets.len() <= window.size);
+        // Buckets still start on the same boundaries after the skip.
+        window.add(&batch(2), &mut totals);
+        window.advance(at(start, 1_019), &mut totals);
+        assert_eq!(totals.total_count(), 2);
+        window.advance(at(start, 1_060), &mut totals);
+        assert_eq!(totals.total_count(), 0);
+    }
+}
@@ -0,0 +1,168 @@
+//! Property tests of the invariants aggregation has to keep however its
+//! inputs are ordered, chunked and spread over threads: no located threat is
+//! lost or counted twice, and the same records always give the same output.
+//! Records are located with the mock provider, in a map of `10.0.N.0/24`
+//! networks with random coordinates.
+
+use proptest::prelude::*;
+use serde_json::{json, Map};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Scale of the coordinates locations are grouped by, 5 decimal places.
+const SCALE: f64 = 100_000.0;
+
+/// Numbers the files of each run, as tests run in parallel.
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// A threat source record: the third octet of its `10.0.N.1` IP, or `None`
+/// for an invalid IP, and its count.
+type Record = (Option<u8>, u32);
+
+/// Returns the path of a new file in the target directory.
+fn temporary(extension: &str) -> PathBuf {
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("aggregation_{run}.{extension}"))
+}
+
+/// Aggregates `records`, located in a mock map of a `10.0.N.0/24` network
+/// at each of `locations`, with the extra `args`, and returns the CSV output.
+fn aggregate(locations: &[(f64, f64)], records: &[Record], args: &[&str]) -> String {
+    let map: Map<String, serde_json::Value> = locations
+        .iter()
+        .enumerate()
+        .map(|(n, (lat, lon))| {
+            let location = json!({ "city": format!("City {n}"), "country": "Testland", "lat": lat, "lon": lon });
+            (format!("10.0.{n}.0/24"), location)
+        })
+        .collect();
+    let sources: Vec<String> = records
+        .iter()
+        .map(|(n, _)| n.map_or_else(|| "not-an-ip".to_string(), |n| format!("10.0.{n}.1")))
+        .collect();
+    let counts: Vec<u32> = records.iter().map(|&(_, count)| count).collect();
+    let input = json!({ "Threat Sources": { "Count": counts, "Source": sources } });
+    let (map_path, input_path, output_path) =
+        (temporary("json"), temporary("json"), temporary("csv"));
+    fs::write(&map_path, json!(map).to_string()).expect("the mock map is written");
+    fs::write(&input_path, input.to_string()).expect("the input is written");
+    let status = Command::new(env!("CARGO_BIN_EXE_dashboard_location_mapper"))
+        .arg("--provider=mock")
+        .arg("--mock-map")
+        .arg(&map_path)
+        .arg("--input")
+        .arg(&input_path)
+        .arg("--output")
+        .arg(&output_path)
+        .args(args)
+        .status()
+        .expect("the binary runs");
+    assert!(
+        status.success(),
+        "aggregating {records:?} with {args:?} failed"
+    );
+    let output = fs::read_to_string(&output_path).expect("the output is written");
+    for path in [map_path, input_path, output_path] {
+        fs::remove_file(path).ok();
+    }
+    output
+}
+
+/// Returns the counts of the rows of a CSV output.
+fn counts(output: &str) -> Vec<u64> {
+    let mut reader = csv::Reader::from_reader(output.as_bytes());
+    let headers = reader.headers().expect("the output has headers").clone();
+    let column = headers
+        .iter()
+        .position(|header| header == "Count")
+        .expect("the output has a Count column");
+    reader
+        .records()
+        .map(|row| {
+            row.expect("rows are valid")[column]
+                .parse()
+                .expect("counts are integers")
+        })
+        .collect()
+}
+
+/// Returns the total count of the records located in a map of `networks`.
+fn located_total(networks: usize, records: &[Record]) -> u64 {
+    records
+        .iter()
+        .filter(|(n, _)| n.is_some_and(|n| usize::from(n) < networks))
+        .map(|&(_, count)| u64::from(count))
+        .sum()
+}
+
+/// Coordinates of up to 8 locations.
+fn locations() -> impl Strategy<Value = Vec<(f64, f64)>> {
+    prop::collection::vec((-89.0..89.0, -179.0..179.0), 1..8)
+}
+
+/// Up to 60 records in up to 10 networks, some outside the map or invalid.
+fn records() -> impl Strategy<Value = Vec<Record>> {
+    prop::collection::vec((prop::option::weighted(0.9, 0..10u8), 1..10_000u32), 0..60)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn total_equals_located_input(locations in locations(), records in records()) {
+        let output = aggregate(&locations, &records, &[]);
+        let total: u64 = counts(&output).iter().sum();
+        prop_assert_eq!(total, located_total(locations.len(), &records));
+    }
+
+    #[test]
+    fn order_chunks_and_threads_dont_matter(
+        (locations, records, shuffled) in (locations(), records()).prop_flat_map(
+            |(locations, records)| (Just(locations), Just(records.clone()), Just(records).prop_shuffle())
+        ),
+        chunk_size in 1..20usize,
+        threads in 1..4usize,
+    ) {
+        let expected = aggregate(&locations, &records, &[]);
+        let chunk_size = chunk_size.to_string();
+        let threads = threads.to_string();
+        let args = ["--chunk-size", &chunk_size, "--threads", &threads];
+        prop_assert_eq!(aggregate(&locations, &shuffled, &args), expected);
+    }
+
+    #[test]
+    fn rounding_merges_without_losing_counts(
+        base in locations(),
+        offsets in prop::collection::vec((-0.00002..0.00002, -0.00002..0.00002), 8),
+        records in records(),
+    ) {
+        // Locations a few hundred-thousandths of a degree apart, many of
+        // which round to the same coordinates.
+        let locations: Vec<(f64, f64)> = base
+            .iter()
+            .chain(&base)
+            .zip(&offsets)
+            .map(|(&(lat, lon), &(dlat, dlon))| (lat + dlat, lon + dlon))
+            .collect();
+        let output = aggregate(&locations, &records, &[]);
+        let counts = counts(&output);
+        let located: HashSet<usize> = records
+            .iter()
+            .filter_map(|&(n, _)| n.map(usize::from))
+            .filter(|&n| n < locations.len())
+            .collect();
+        #[allow(clippy::cast_possible_truncation)] // Coordinates fit in degrees * 10^5.
+        let keys: HashSet<(i64, i64)> = located
+            .iter()
+            .map(|&n| {
+                let (lat, lon) = locations[n];
+                ((lat * SCALE).round() as i64, (lon * SCALE).round() as i64)
+            })
+            .collect();
+        prop_assert_eq!(counts.len(), keys.len());
+        prop_assert_eq!(counts.iter().sum::<u64>(), located_total(locations.len(), &records));
+    }
+}
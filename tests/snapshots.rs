@@ -0,0 +1,169 @@
+//! Golden-file tests of every output format, written from the same fixed
+//! inputs located with the mock provider, so any change to an output shows
+//! up as a snapshot diff. Review and accept changes with `cargo insta
+//! review`, or rerun with `INSTA_UPDATE=always`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Directory of the test inputs.
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+/// Aggregates the fixture threat sources into `--format format` with the
+/// extra `args`, and returns the output.
+fn output(format: &str, args: &[&str]) -> Vec<u8> {
+    let path = output_path(format, args);
+    let fixtures = Path::new(FIXTURES);
+    let status = Command::new(env!("CARGO_BIN_EXE_dashboard_location_mapper"))
+        .arg("--provider=mock")
+        .arg("--mock-map")
+        .arg(fixtures.join("mock_map.json"))
+        .arg("--input")
+        .arg(fixtures.join("threat_sources.json"))
+        .arg("--output")
+        .arg(&path)
+        .args(["--format", format])
+        .args(args)
+        .status()
+        .expect("the binary runs");
+    assert!(status.success(), "--format {format} {args:?} failed");
+    fs::read(&path).expect("the output is written")
+}
+
+/// Returns a path of its own in the target directory for the output of
+/// `format` with `args`, as tests run in parallel.
+fn output_path(format: &str, args: &[&str]) -> PathBuf {
+    let name: String = std::iter::once(&format)
+        .chain(args)
+        .map(|arg| arg.trim_start_matches('-'))
+        .collect::<Vec<_>>()
+        .join("_")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("snapshot_{name}.{format}"))
+}
+
+/// Returns the output as text.
+fn text(format: &str, args: &[&str]) -> String {
+    String::from_utf8(output(format, args)).expect("the output is UTF-8")
+}
+
+#[test]
+fn csv() {
+    insta::assert_snapshot!(text("csv", &[]));
+}
+
+#[test]
+fn csv_by_feed() {
+    insta::assert_snapshot!(text("csv", &["--group-by", "city,feed"]));
+}
+
+#[test]
+fn json() {
+    insta::assert_snapshot!(text("json", &[]));
+}
+
+#[test]
+fn geojson() {
+    insta::assert_snapshot!(text("geojson", &[]));
+}
+
+#[test]
+fn markdown() {
+    insta::assert_snapshot!(text("markdown", &[]));
+}
+
+#[test]
+fn termmap() {
+    insta::assert_snapshot!(text("termmap", &[]));
+}
+
+#[test]
+fn clusters() {
+    insta::assert_snapshot!(text("clusters", &[]));
+}
+
+#[test]
+fn arcs() {
+    insta::assert_snapshot!(text("arcs", &["--target", "50.11,8.68"]));
+}
+
+#[test]
+fn template() {
+    let template = Path::new(FIXTURES).join("report.html.hbs");
+    let template = template.to_str().expect("the path is UTF-8");
+    insta::assert_snapshot!(text("csv", &["--template", template]));
+}
+
+#[test]
+fn pdf() {
+    insta::assert_binary_snapshot!(".pdf", output("pdf", &[]));
+}
+
+#[test]
+fn pmtiles() {
+    insta::assert_binary_snapshot!(".pmtiles", output("pmtiles", &[]));
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow() {
+    insta::assert_binary_snapshot!(".arrow", output("arrow", &[]));
+}
+
+/// Snapshots the metadata and a hash of each tile of the MBTiles output, as
+/// the database file itself differs between `SQLite` versions.
+#[cfg(feature = "mvt")]
+#[test]
+fn mvt() {
+    use flate2::read::GzDecoder;
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write;
+    use std::io::Read;
+
+    let path = output_path("mvt", &[]);
+    output("mvt", &[]);
+    let connection = rusqlite::Connection::open(&path).expect("the output is an SQLite file");
+    let mut summary = String::new();
+    let mut metadata = connection
+        .prepare("SELECT name, value FROM metadata ORDER BY name")
+        .expect("the output has a metadata table");
+    let rows = metadata
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .expect("the metadata is read");
+    for row in rows {
+        let (name, value) = row.expect("the metadata is text");
+        let _ = writeln!(summary, "{name}: {value}");
+    }
+    let mut tiles = connection
+        .prepare("SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles ORDER BY 1, 2, 3")
+        .expect("the output has a tiles table");
+    let rows = tiles
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, u8>(0)?,
+                row.get::<_, u32>(1)?,
+                row.get::<_, u32>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+            ))
+        })
+        .expect("the tiles are read");
+    for row in rows {
+        let (zoom, column, row, gzipped) = row.expect("the tiles are valid");
+        let mut tile = Vec::new();
+        GzDecoder::new(gzipped.as_slice())
+            .read_to_end(&mut tile)
+            .expect("the tiles are gzipped");
+        let hash = format!("{:x}", Sha256::digest(&tile));
+        let _ = writeln!(
+            summary,
+            "{zoom}/{column}/{row}: {} bytes, sha256 {hash}",
+            tile.len()
+        );
+    }
+    insta::assert_snapshot!(summary);
+}